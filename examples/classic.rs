@@ -0,0 +1,30 @@
+extern crate regex;
+
+use regex::dfa::Dfa;
+use regex::monoid::Monoid;
+use regex::nfa::Nfa;
+
+// 古典的な言語の最小 DFA の状態数と遷移モノイドの大きさを表示する
+const CLASSIC: &[(&str, &str)] = &[
+    ("even number of a's", "b*(ab*ab*)*"),
+    ("binary multiples of 3", "(0|1(01*0)*1)*"),
+    ("(ab)*", "(ab)*"),
+    ("a*b*", "a*b*"),
+    ("ends with ab", "(a|b)*ab"),
+];
+
+fn main() {
+    for &(name, pattern) in CLASSIC {
+        let nfa = Nfa::re2nfa(pattern).unwrap();
+        let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+        dfa.minimize();
+        let monoid = Monoid::construct(&dfa);
+        println!(
+            "{:<24} {:<16} states: {:>2}  monoid: {:>3}",
+            name,
+            pattern,
+            dfa.states.len(),
+            monoid.size()
+        );
+    }
+}
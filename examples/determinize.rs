@@ -0,0 +1,29 @@
+extern crate regex;
+use regex::dfa::Dfa;
+use regex::nfa::Nfa;
+use std::time::Instant;
+
+// 部分集合構成で DFA の状態数が指数的に増えるパターンで、決定化にかかる時間を測る
+// cargo run --release --example determinize
+// cargo run --release --example determinize --features rustc-hash
+fn main() {
+    for n in 4..=12 {
+        let patterns = [
+            format!("(a|b)*a(a|b){{{}}}", n),
+            format!("[a-c]*a[a-c]{{{}}}c", n),
+            format!("(.*x.{{{}}})|(.*y.{{{}}})", n / 2, n / 2),
+        ];
+        for pattern in patterns.iter() {
+            let nfa = Nfa::re2nfa(pattern).unwrap();
+            let start = Instant::now();
+            let dfa = Dfa::nfa2dfa(&nfa).unwrap();
+            println!(
+                "{:<28} nfa {:>4}  dfa {:>6}  {:>10?}",
+                pattern,
+                nfa.states.len(),
+                dfa.states.len(),
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -0,0 +1,37 @@
+extern crate regex;
+use regex::dna::{pack, DnaDfa};
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+
+// FASTA の各配列から pattern に一致する箇所を探し、終わる位置を表示する
+// cargo run --example fasta -- 'TA(T|A)A' genome.fa
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let pattern = args.get(1).map(|s| s.as_str()).unwrap_or("TATA(A|T)A");
+    let mut input = String::new();
+    match args.get(2) {
+        Some(path) => File::open(path).and_then(|mut f| f.read_to_string(&mut input)),
+        None => io::stdin().read_to_string(&mut input),
+    }
+    .expect("failed to read FASTA");
+
+    let packed = DnaDfa::searcher(pattern, false).unwrap();
+    let with_n = DnaDfa::searcher(pattern, true).unwrap();
+    let mut records = Vec::new();
+    for line in input.lines() {
+        if let Some(name) = line.strip_prefix('>') {
+            records.push((name.to_string(), Vec::new()));
+        } else if let Some(&mut (_, ref mut seq)) = records.last_mut() {
+            seq.extend(line.trim().bytes().map(|c| c.to_ascii_uppercase()));
+        }
+    }
+    for (name, seq) in records {
+        // N を含まない配列は 2 ビットに詰めて読む
+        let ends = match pack(&seq) {
+            Some(bits) => packed.match_ends_packed(&bits, seq.len()),
+            None => with_n.match_ends_bytes(&seq),
+        };
+        println!("{}: {} bases, {} matches {:?}", name, seq.len(), ends.len(), ends);
+    }
+}
@@ -0,0 +1,29 @@
+extern crate regex;
+use regex::dfa::Dfa;
+use regex::nfa::Nfa;
+use regex::rng::Rng;
+use std::time::Instant;
+
+// 状態数の多い DFA で、訪問回数の順に状態を並べ替える前後の照合時間を比べる
+// cargo run --release --example heat
+fn main() {
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(a|b|c|d)*a(a|b|c|d){10}").unwrap()).unwrap();
+    dfa.minimize();
+
+    let mut rng = Rng::new(1);
+    let corpus: Vec<Vec<u8>> = (0..2000)
+        .map(|_| (0..512).map(|_| b"abcd"[rng.below(4)]).collect())
+        .collect();
+    let profile = dfa.profile(corpus.iter());
+    let hot = profile.hot_states();
+    println!("states {}, hottest {:?}", dfa.states.len(), &hot[..4]);
+
+    let time = |dfa: &Dfa| {
+        let start = Instant::now();
+        let accepted = corpus.iter().filter(|input| dfa.accept_bytes(input)).count();
+        (accepted, start.elapsed())
+    };
+    println!("before reorder: {:?}", time(&dfa));
+    dfa.reorder_by_heat(&profile);
+    println!("after reorder:  {:?}", time(&dfa));
+}
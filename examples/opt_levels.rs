@@ -0,0 +1,39 @@
+extern crate regex;
+use regex::{OptLevel, RegexBuilder};
+use std::time::Instant;
+
+// OptLevel ごとのコンパイル時間、DFA の状態数、照合時間を比べる
+// cargo run --release --example opt_levels
+fn main() {
+    let patterns = [
+        "[a-z_][a-z0-9_]*=[0-9]+",
+        "(GET|POST|PUT|DELETE) /api/v[0-9]+/[a-z]+",
+        "([0-9]{1,3}\\.){3}[0-9]{1,3}",
+        "(a|b)*a(a|b){8}",
+    ];
+    let haystack: String = (0..2000)
+        .map(|i| format!("user_{}={} GET /api/v2/items 10.0.{}.1 abba{} ", i, i * 7, i % 256, i))
+        .collect();
+    for pattern in patterns.iter() {
+        println!("{}", pattern);
+        for level in 0..=3 {
+            let compile = Instant::now();
+            let re = RegexBuilder::new(pattern)
+                .opt_level(OptLevel::from_level(level))
+                .build()
+                .unwrap();
+            let compile = compile.elapsed();
+            let search = Instant::now();
+            let matches = re.find_iter(&haystack).count();
+            let search = search.elapsed();
+            println!(
+                "  O{}: compile {:>10?}  states {:>5}  find_iter {:>10?}  ({} matches)",
+                level,
+                compile,
+                re.dfa().states.len(),
+                search,
+                matches
+            );
+        }
+    }
+}
@@ -0,0 +1,34 @@
+extern crate regex;
+use regex::dfa::Dfa;
+use regex::nfa::Nfa;
+use regex::shiftor::ShiftOr;
+use std::time::Instant;
+
+// 長い入力の最後にだけ一致がある場合の DenseDfa と ShiftOr の探索の速さを測る
+// SIMD 命令で読み飛ばす場合と比べるには simd feature の有無で 2 回実行する
+// cargo run --release --example search_loop [--features simd]
+fn main() {
+    let mut haystack: Vec<u8> = (0..64 << 20).map(|i| b"abcdefgh 0123456"[i % 16]).collect();
+    haystack.extend_from_slice(b"ERR-4242");
+    let mb = haystack.len() as f64 / (1 << 20) as f64;
+    let rounds = 5;
+
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ERR-[0-9]{4}").unwrap().unanchored()).unwrap();
+    dfa.minimize();
+    let dense = dfa.to_dense();
+    let shift_or = ShiftOr::new("ERR-[0-9]{4}").unwrap();
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        assert_eq!(dense.shortest_match(&haystack), Some(haystack.len()));
+    }
+    let elapsed = start.elapsed().as_secs_f64() / rounds as f64;
+    println!("DenseDfa::shortest_match {:>8.1} MB/s", mb / elapsed);
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        assert_eq!(shift_or.find(&haystack).map(|(_, end)| end), Some(haystack.len()));
+    }
+    let elapsed = start.elapsed().as_secs_f64() / rounds as f64;
+    println!("ShiftOr::find            {:>8.1} MB/s", mb / elapsed);
+}
@@ -0,0 +1,71 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+// 型ごとの「状態番号 → 値」の表
+// DFA をスレッド間で共有できるよう、値は Send + Sync に限る
+trait Table: Send + Sync {
+    fn remap(&mut self, new_id: &[usize]);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Send + Sync + 'static> Table for HashMap<usize, T> {
+    fn remap(&mut self, new_id: &[usize]) {
+        let old = mem::take(self);
+        for (id, value) in old {
+            if let Some(&new) = new_id.get(id) {
+                self.insert(new, value);
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// 状態に付けた利用者のデータ
+#[derive(Default)]
+pub struct Annotations {
+    tables: HashMap<TypeId, Box<dyn Table>>,
+}
+
+impl fmt::Debug for Annotations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Annotations {{ {} tables }}", self.tables.len())
+    }
+}
+
+impl Annotations {
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&HashMap<usize, T>> {
+        self.tables
+            .get(&TypeId::of::<T>())
+            .and_then(|table| table.as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> &mut HashMap<usize, T> {
+        self.tables
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(HashMap::<usize, T>::new()))
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+
+    // new_id[古い番号] = 新しい番号
+    pub fn remap(&mut self, new_id: &[usize]) {
+        for table in self.tables.values_mut() {
+            table.remap(new_id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.clear();
+    }
+}
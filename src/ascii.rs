@@ -0,0 +1,99 @@
+use dfa::{Dfa, DfaBuilder};
+
+// ASCII のバイトだけを読む DFA の 128 列の遷移表
+// 0x80 以上のバイトを読んだら必ず失敗とする
+#[derive(Debug, Clone)]
+pub struct AsciiDfa {
+    t: Vec<[Option<usize>; 128]>,
+    accept: Vec<bool>,
+    start: usize,
+}
+
+impl AsciiDfa {
+    // 0x80 以上のバイトで受理状態へ到達できない DFA のときのみ作る
+    // (否定の文字クラスや `(?s).` を含むパターンでは作れない)
+    pub fn from_dfa(dfa: &Dfa) -> Option<Self> {
+        if dfa.states.is_empty() || !dfa.is_ascii_only() {
+            return None;
+        }
+        let mut t = Vec::with_capacity(dfa.states.len());
+        for state in dfa.states.iter() {
+            let mut row = [None; 128];
+            for (slot, (_, next)) in row.iter_mut().zip(dfa.transitions(state.id)) {
+                *slot = next;
+            }
+            t.push(row);
+        }
+        Some(AsciiDfa {
+            t,
+            accept: dfa.states.iter().map(|s| s.accept).collect(),
+            start: dfa.start(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    // 入力全体を受理するか
+    pub fn accept_bytes(&self, s: &[u8]) -> bool {
+        let mut state = self.start;
+        for &c in s {
+            if c >= 0x80 {
+                return false;
+            }
+            match self.t[state][c as usize] {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        self.accept[state]
+    }
+
+    // 0x80 以上のバイトの遷移を持たない 256 列の DFA に戻す
+    pub fn to_dfa(&self) -> Dfa {
+        let mut builder = DfaBuilder::new();
+        for &accept in self.accept.iter() {
+            builder.add_state(accept);
+        }
+        for (from, row) in self.t.iter().enumerate() {
+            for (c, next) in row.iter().enumerate() {
+                if let Some(to) = *next {
+                    builder.set_transition(from, c as u8, to).unwrap();
+                }
+            }
+        }
+        let mut dfa = builder.finish().unwrap();
+        dfa.set_start(self.start);
+        dfa
+    }
+}
+
+#[test]
+fn ascii_reduction() {
+    use nfa::Nfa;
+    let dfa = |regex: &str| {
+        let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa(regex).unwrap()).unwrap();
+        dfa.minimize();
+        dfa
+    };
+    let ascii = AsciiDfa::from_dfa(&dfa("[a-z_][a-z0-9_]*=\\d+")).unwrap();
+    assert!(ascii.accept_bytes(b"max_len=64"));
+    assert!(!ascii.accept_bytes(b"max_len="));
+    assert!(!ascii.accept_bytes("m\u{e9}=1".as_bytes()));
+    let back = ascii.to_dfa();
+    assert_eq!(back.states.len(), ascii.len());
+    assert!(back.accept("x1=2"));
+    assert!(!back.accept_bytes(b"x\xff=2"));
+
+    // 0x80 以上のバイトを受理し得るクラスや `.` を含む場合は縮められない
+    assert!(AsciiDfa::from_dfa(&dfa("[^a]b")).is_none());
+    assert!(AsciiDfa::from_dfa(&dfa("\\W")).is_none());
+    assert!(AsciiDfa::from_dfa(&dfa("a.c")).is_none());
+    assert!(AsciiDfa::from_dfa(&dfa("caf\u{e9}")).is_none());
+    assert!(AsciiDfa::from_dfa(&dfa("\\w+")).is_some());
+}
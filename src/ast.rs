@@ -0,0 +1,752 @@
+use dfa::ByteSet;
+use error::Error;
+use nfa::{syntax_tree, Flags};
+#[cfg(test)]
+use parser::parse;
+use std::fmt;
+
+/// パターンの構文木。
+///
+/// `parser::parse` で作り、`Nfa::from_ast` で NFA にする。
+/// 下流のツールは `Visitor` でたどって解析したり、値を書き換えてからコンパイルしたりできる。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ast {
+    /// 1 バイト
+    Literal(u8),
+    /// `.` (`(?s)` がなければ改行以外の 1 バイト)
+    Dot,
+    /// `[a-z]`, `[^0-9]`, `\d` など
+    Class(Box<Class>),
+    /// 捕獲グループ `(...)` と名前付きの `(?P<name>...)`
+    Group { name: Option<String>, ast: Box<Ast> },
+    /// 捕獲しないグループ `(?:...)` と、中だけで flag を切り替える `(?i-s:...)`
+    NonCapturing { flags: GroupFlags, ast: Box<Ast> },
+    /// 2 個以上の要素の連接
+    Concat(Vec<Ast>),
+    /// 2 個以上の要素の選択
+    Union(Vec<Ast>),
+    /// `*`
+    Star(Box<Ast>),
+    /// `+`
+    Plus(Box<Ast>),
+    /// `?`
+    ZeroOne(Box<Ast>),
+    /// `{m}`, `{m,}`, `{m,n}` (上限なしは None)
+    Repeat {
+        ast: Box<Ast>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+/// 文字クラスの要素と否定の有無。
+///
+/// 大文字と小文字を区別しない場合は否定の前に要素を広げる必要があるため、否定は別に持つ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Class {
+    pub members: ByteSet,
+    pub negated: bool,
+}
+
+impl Class {
+    pub fn new() -> Class {
+        Class {
+            members: [false; 256],
+            negated: false,
+        }
+    }
+
+    /// クラスに一致するバイト
+    pub fn bytes(&self, case_insensitive: bool) -> Vec<u8> {
+        let mut members = self.members;
+        if case_insensitive {
+            for c in b'A'..=b'Z' {
+                let either = members[c as usize] || members[c.to_ascii_lowercase() as usize];
+                members[c as usize] = either;
+                members[c.to_ascii_lowercase() as usize] = either;
+            }
+        }
+        (0..=255u8)
+            .filter(|&c| members[c as usize] != self.negated)
+            .collect()
+    }
+}
+
+impl Default for Class {
+    fn default() -> Self {
+        Class::new()
+    }
+}
+
+/// グループの中だけで切り替える flag。None の flag は外側の設定のまま
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupFlags {
+    /// `i`
+    pub case_insensitive: Option<bool>,
+    /// `s`
+    pub dot_all: Option<bool>,
+}
+
+impl GroupFlags {
+    pub fn is_empty(&self) -> bool {
+        *self == GroupFlags::default()
+    }
+
+    /// 外側の flags にこのグループの設定を重ねる
+    pub fn apply(&self, flags: Flags) -> Flags {
+        Flags {
+            case_insensitive: self.case_insensitive.unwrap_or(flags.case_insensitive),
+            dot_all: self.dot_all.unwrap_or(flags.dot_all),
+            ..flags
+        }
+    }
+}
+
+// `(?i-s:` の `i-s` の部分
+impl fmt::Display for GroupFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [(self.case_insensitive, 'i'), (self.dot_all, 's')];
+        for &(_, c) in flags.iter().filter(|&&(on, _)| on == Some(true)) {
+            write!(f, "{}", c)?;
+        }
+        if flags.iter().any(|&(on, _)| on == Some(false)) {
+            write!(f, "-")?;
+        }
+        for &(_, c) in flags.iter().filter(|&&(on, _)| on == Some(false)) {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// 構文木をたどる訪問者。既定では何もせず全ての子をたどる
+pub trait Visitor {
+    /// ノードに入るときに呼ばれる。false を返すとその子はたどらない
+    fn enter(&mut self, _ast: &Ast) -> bool {
+        true
+    }
+
+    /// ノードの子をたどり終えたときに呼ばれる
+    fn leave(&mut self, _ast: &Ast) {}
+}
+
+impl Ast {
+    /// 直下の部分木
+    pub fn children(&self) -> Vec<&Ast> {
+        match *self {
+            Ast::Literal(_) | Ast::Dot | Ast::Class(_) => Vec::new(),
+            Ast::Group { ref ast, .. }
+            | Ast::NonCapturing { ref ast, .. }
+            | Ast::Star(ref ast)
+            | Ast::Plus(ref ast)
+            | Ast::ZeroOne(ref ast)
+            | Ast::Repeat { ref ast, .. } => vec![ast],
+            Ast::Concat(ref asts) | Ast::Union(ref asts) => asts.iter().collect(),
+        }
+    }
+
+    /// 深さ優先で各ノードを visitor に渡す
+    pub fn walk<V: Visitor>(&self, visitor: &mut V) {
+        if visitor.enter(self) {
+            for child in self.children() {
+                child.walk(visitor);
+            }
+        }
+        visitor.leave(self);
+    }
+
+    /// 回数指定の繰り返しを展開した場合の 1 文字に一致する要素の数
+    pub fn expanded_len(&self) -> usize {
+        match *self {
+            Ast::Literal(_) | Ast::Dot | Ast::Class(_) => 1,
+            Ast::Repeat { ref ast, min, max } => {
+                ast.expanded_len().saturating_mul(max.unwrap_or(min + 1))
+            }
+            _ => self
+                .children()
+                .iter()
+                .fold(0, |len, child| len.saturating_add(child.expanded_len())),
+        }
+    }
+}
+
+// 結合の強さ (大きいほど強い)
+const UNION: u8 = 0;
+const CONCAT: u8 = 1;
+const REPEAT: u8 = 2;
+const ATOM: u8 = 3;
+
+// 連接の先頭のバイトの列が 1 文字の UTF-8 になっていれば、その文字とバイト数を返す
+fn utf8_prefix(asts: &[Ast]) -> Option<(char, usize)> {
+    let len = match asts.first() {
+        Some(&Ast::Literal(c)) if c >= 0xf0 => 4,
+        Some(&Ast::Literal(c)) if c >= 0xe0 => 3,
+        Some(&Ast::Literal(c)) if c >= 0xc0 => 2,
+        _ => return None,
+    };
+    let bytes = asts
+        .get(..len)?
+        .iter()
+        .map(|ast| match *ast {
+            Ast::Literal(c) => Some(c),
+            _ => None,
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    let c = ::std::str::from_utf8(&bytes).ok()?.chars().next()?;
+    Some((c, len))
+}
+
+// groups が true なら捕獲グループを括弧で書く
+fn precedence(ast: &Ast, groups: bool) -> u8 {
+    match *ast {
+        Ast::Group { .. } if groups => ATOM,
+        Ast::Group { ref ast, .. } => precedence(ast, groups),
+        Ast::NonCapturing { flags, .. } if groups || !flags.is_empty() => ATOM,
+        Ast::NonCapturing { ref ast, .. } => precedence(ast, groups),
+        Ast::Union(_) => UNION,
+        // 1 文字の UTF-8 は分けずに書くので一つの要素とみなす
+        Ast::Concat(ref asts) if utf8_prefix(asts).map(|(_, len)| len) == Some(asts.len()) => ATOM,
+        Ast::Concat(_) => CONCAT,
+        Ast::Star(_) | Ast::Plus(_) | Ast::ZeroOne(_) | Ast::Repeat { .. } => REPEAT,
+        Ast::Dot | Ast::Literal(_) | Ast::Class(_) => ATOM,
+    }
+}
+
+fn write_operand(ast: &Ast, min_precedence: u8, groups: bool, out: &mut String) {
+    if precedence(ast, groups) < min_precedence {
+        // グループを残す場合は、括弧を足しても捕獲グループが増えないようにする
+        out.push_str(if groups { "(?:" } else { "(" });
+        write(ast, groups, out);
+        out.push(')');
+    } else {
+        write(ast, groups, out);
+    }
+}
+
+fn write_literal(c: u8, out: &mut String) {
+    match c {
+        b'\\' | b'|' | b'*' | b'+' | b'?' | b'(' | b')' | b'.' | b'[' | b'{' | b'^' | b'$' => {
+            out.push('\\');
+            out.push(c as char);
+        }
+        0x20..=0x7e => out.push(c as char),
+        _ => out.push_str(&format!("\\x{:02x}", c)),
+    }
+}
+
+fn write_class_member(c: u8, out: &mut String) {
+    match c {
+        b'\\' | b']' | b'-' | b'^' => {
+            out.push('\\');
+            out.push(c as char);
+        }
+        0x20..=0x7e => out.push(c as char),
+        _ => out.push_str(&format!("\\x{:02x}", c)),
+    }
+}
+
+// 連続するバイトは範囲にまとめて `[a-z_]` の形で書く
+fn write_class(class: &Class, out: &mut String) {
+    let mut members: Vec<u8> = (0..=255u8).filter(|&c| class.members[c as usize]).collect();
+    let mut negated = class.negated;
+    // `[]` は書けないので全てのバイトの否定として書く
+    if members.is_empty() {
+        members = (0..=255u8).collect();
+        negated = !negated;
+    }
+    out.push('[');
+    if negated {
+        out.push('^');
+    }
+    let mut i = 0;
+    while i < members.len() {
+        let mut j = i;
+        while j + 1 < members.len() && members[j + 1] == members[j] + 1 {
+            j += 1;
+        }
+        write_class_member(members[i], out);
+        if j >= i + 2 {
+            out.push('-');
+            write_class_member(members[j], out);
+        } else if j == i + 1 {
+            write_class_member(members[j], out);
+        }
+        i = j + 1;
+    }
+    out.push(']');
+}
+
+fn write(ast: &Ast, groups: bool, out: &mut String) {
+    match *ast {
+        Ast::Group { ref name, ref ast } if groups => {
+            out.push('(');
+            if let Some(ref name) = *name {
+                out.push_str(&format!("?P<{}>", name));
+            }
+            write(ast, groups, out);
+            out.push(')');
+        }
+        Ast::Group { ref ast, .. } => write(ast, groups, out),
+        Ast::NonCapturing { flags, ref ast } if groups || !flags.is_empty() => {
+            out.push_str(&format!("(?{}:", flags));
+            write(ast, groups, out);
+            out.push(')');
+        }
+        Ast::NonCapturing { ref ast, .. } => write(ast, groups, out),
+        Ast::Union(ref asts) => {
+            for (i, ast) in asts.iter().enumerate() {
+                if i > 0 {
+                    out.push('|');
+                }
+                write_operand(ast, UNION, groups, out);
+            }
+        }
+        Ast::Concat(ref asts) => {
+            let mut i = 0;
+            while i < asts.len() {
+                if let Some((c, len)) = utf8_prefix(&asts[i..]) {
+                    out.push(c);
+                    i += len;
+                } else {
+                    write_operand(&asts[i], CONCAT, groups, out);
+                    i += 1;
+                }
+            }
+        }
+        Ast::Star(ref ast) => {
+            write_operand(ast, REPEAT, groups, out);
+            out.push('*');
+        }
+        Ast::Plus(ref ast) => {
+            write_operand(ast, REPEAT, groups, out);
+            out.push('+');
+        }
+        Ast::ZeroOne(ref ast) => {
+            write_operand(ast, REPEAT, groups, out);
+            out.push('?');
+        }
+        Ast::Repeat { ref ast, min, max } => {
+            write_operand(ast, REPEAT, groups, out);
+            match max {
+                Some(max) if max == min => out.push_str(&format!("{{{}}}", min)),
+                Some(max) => out.push_str(&format!("{{{},{}}}", min, max)),
+                None => out.push_str(&format!("{{{},}}", min)),
+            }
+        }
+        Ast::Dot => out.push('.'),
+        Ast::Literal(c) => write_literal(c, out),
+        Ast::Class(ref class) => write_class(class, out),
+    }
+}
+
+// 捕獲グループを除き、括弧の少ない正規化されたパターン文字列に戻す
+pub fn to_string(ast: &Ast) -> String {
+    let mut out = String::new();
+    write(ast, false, &mut out);
+    out
+}
+
+// 捕獲グループを残し、それ以外は必要な所にだけ括弧を付けたパターン文字列に戻す
+// parse で読み直すと同じ構文木になる (グループのない所に要る括弧は `(?:...)` で書く)
+pub fn to_pattern(ast: &Ast) -> String {
+    let mut out = String::new();
+    write(ast, true, &mut out);
+    out
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&to_pattern(self))
+    }
+}
+
+fn describe_literal(c: u8) -> String {
+    match c {
+        b'a'..=b'z' | b'A'..=b'Z' => format!("letter {}", c as char),
+        b'0'..=b'9' => format!("digit {}", c as char),
+        b' ' => "space".to_owned(),
+        0x21..=0x7e => format!("character '{}'", c as char),
+        _ => format!("byte 0x{:02x}", c),
+    }
+}
+
+// グループで囲まれた同じ種類の選択や連接を一列に並べる
+fn flatten<'a>(asts: &'a [Ast], union: bool, operands: &mut Vec<&'a Ast>) {
+    for ast in asts {
+        let mut inner = ast;
+        loop {
+            match *inner {
+                Ast::Group { ref ast, .. } => inner = ast,
+                Ast::NonCapturing { flags, ref ast } if flags.is_empty() => inner = ast,
+                _ => break,
+            }
+        }
+        match *inner {
+            Ast::Union(ref asts) if union => flatten(asts, union, operands),
+            Ast::Concat(ref asts) if !union => flatten(asts, union, operands),
+            _ => operands.push(inner),
+        }
+    }
+}
+
+fn explain_node(ast: &Ast, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let (title, child) = match *ast {
+        Ast::Group { ref ast, .. } => return explain_node(ast, depth, out),
+        Ast::NonCapturing { flags, ref ast } if flags.is_empty() => {
+            return explain_node(ast, depth, out)
+        }
+        Ast::NonCapturing { flags, ref ast } => (format!("with flags {}:", flags), ast),
+        Ast::Union(ref asts) | Ast::Concat(ref asts) => {
+            let union = matches!(*ast, Ast::Union(_));
+            let mut operands = Vec::new();
+            flatten(asts, union, &mut operands);
+            let title = if union { "one of:" } else { "in sequence:" };
+            out.push_str(&format!("{}{}\n", indent, title));
+            for operand in operands {
+                explain_node(operand, depth + 1, out);
+            }
+            return;
+        }
+        Ast::Star(ref ast) => ("zero or more of:".to_owned(), ast),
+        Ast::Plus(ref ast) => ("one or more of:".to_owned(), ast),
+        Ast::ZeroOne(ref ast) => ("optionally:".to_owned(), ast),
+        Ast::Repeat { ref ast, min, max } => {
+            let title = match max {
+                Some(max) if max == min => format!("exactly {} of:", min),
+                Some(max) => format!("between {} and {} of:", min, max),
+                None => format!("at least {} of:", min),
+            };
+            (title, ast)
+        }
+        Ast::Dot => return out.push_str(&format!("{}any byte\n", indent)),
+        Ast::Literal(c) => return out.push_str(&format!("{}{}\n", indent, describe_literal(c))),
+        Ast::Class(ref class) => {
+            let mut written = String::new();
+            write_class(class, &mut written);
+            return out.push_str(&format!("{}any byte in {}\n", indent, written));
+        }
+    };
+    out.push_str(&format!("{}{}\n", indent, title));
+    explain_node(child, depth + 1, out);
+}
+
+// 構文木を字下げした説明文に変換する
+pub fn explain(ast: &Ast) -> String {
+    let mut out = String::new();
+    explain_node(ast, 0, &mut out);
+    out
+}
+
+pub fn explain_pattern(regex: &str) -> Option<String> {
+    syntax_tree(regex).ok().map(|root| explain(&root))
+}
+
+// 構文木に含まれる捕獲グループの数
+pub fn capture_groups(ast: &Ast) -> usize {
+    struct Groups(usize);
+    impl Visitor for Groups {
+        fn enter(&mut self, ast: &Ast) -> bool {
+            if let Ast::Group { .. } = *ast {
+                self.0 += 1;
+            }
+            true
+        }
+    }
+    let mut groups = Groups(0);
+    ast.walk(&mut groups);
+    groups.0
+}
+
+// 捕獲グループの名前を `(` の順に並べたもの (名前のないグループは None)
+pub fn capture_names(ast: &Ast) -> Vec<Option<String>> {
+    struct Names(Vec<Option<String>>);
+    impl Visitor for Names {
+        fn enter(&mut self, ast: &Ast) -> bool {
+            if let Ast::Group { ref name, .. } = *ast {
+                self.0.push(name.clone());
+            }
+            true
+        }
+    }
+    let mut names = Names(Vec::new());
+    ast.walk(&mut names);
+    names.0
+}
+
+// どの一致でも必ず同じ数だけ参加する捕獲グループの数。一致によって変わるなら None
+pub fn static_capture_groups(ast: &Ast) -> Option<usize> {
+    match *ast {
+        Ast::Group { ref ast, .. } => static_capture_groups(ast).map(|n| n + 1),
+        Ast::NonCapturing { ref ast, .. } => static_capture_groups(ast),
+        Ast::Concat(ref asts) => asts
+            .iter()
+            .try_fold(0, |sum, ast| static_capture_groups(ast).map(|n| sum + n)),
+        Ast::Union(ref asts) => {
+            let first = static_capture_groups(&asts[0])?;
+            for ast in asts[1..].iter() {
+                if static_capture_groups(ast)? != first {
+                    return None;
+                }
+            }
+            Some(first)
+        }
+        Ast::Plus(ref ast) => static_capture_groups(ast),
+        // `{0}` の中のグループは一度も参加しない
+        Ast::Repeat { max: Some(0), .. } => Some(0),
+        Ast::Repeat { ref ast, min, .. } if min > 0 => static_capture_groups(ast),
+        Ast::Star(ref ast) | Ast::ZeroOne(ref ast) | Ast::Repeat { ref ast, .. } => {
+            if capture_groups(ast) == 0 {
+                Some(0)
+            } else {
+                None
+            }
+        }
+        Ast::Literal(_) | Ast::Dot | Ast::Class(_) => Some(0),
+    }
+}
+
+// パターンに書かれた捕獲グループの数と、必ず参加するグループの数
+pub fn capture_counts(regex: &str) -> Result<(usize, Option<usize>), Error> {
+    let root = syntax_tree(regex)?;
+    Ok((capture_groups(&root), static_capture_groups(&root)))
+}
+
+// 一致する各バイト列を逆順にした言語の構文木 (連接の順序を再帰的に逆にする)
+pub fn reverse(ast: &Ast) -> Ast {
+    let boxed = |ast: &Ast| Box::new(reverse(ast));
+    match *ast {
+        Ast::Literal(_) | Ast::Dot | Ast::Class(_) => ast.clone(),
+        Ast::Group { ref name, ref ast } => Ast::Group {
+            name: name.clone(),
+            ast: boxed(ast),
+        },
+        Ast::NonCapturing { flags, ref ast } => Ast::NonCapturing {
+            flags,
+            ast: boxed(ast),
+        },
+        Ast::Concat(ref asts) => Ast::Concat(asts.iter().rev().map(reverse).collect()),
+        Ast::Union(ref asts) => Ast::Union(asts.iter().map(reverse).collect()),
+        Ast::Star(ref ast) => Ast::Star(boxed(ast)),
+        Ast::Plus(ref ast) => Ast::Plus(boxed(ast)),
+        Ast::ZeroOne(ref ast) => Ast::ZeroOne(boxed(ast)),
+        Ast::Repeat { ref ast, min, max } => Ast::Repeat {
+            ast: boxed(ast),
+            min,
+            max,
+        },
+    }
+}
+
+// 構文木を同じ言語を表すより小さな木に書き換える
+// 捕獲グループは NFA の形に影響しないので取り除く
+pub fn simplify(ast: Ast) -> Ast {
+    match ast {
+        Ast::Group { ast, .. } => simplify(*ast),
+        Ast::NonCapturing { flags, ast } if flags.is_empty() => simplify(*ast),
+        Ast::NonCapturing { flags, ast } => Ast::NonCapturing {
+            flags,
+            ast: Box::new(simplify(*ast)),
+        },
+        // 空の連接 (ε) は取り除き、入れ子の連接は一列に並べる
+        Ast::Concat(asts) => {
+            let mut flat = Vec::new();
+            for ast in asts {
+                match simplify(ast) {
+                    Ast::Concat(inner) => flat.extend(inner),
+                    ast => flat.push(ast),
+                }
+            }
+            match flat.len() {
+                1 => flat.pop().unwrap(),
+                _ => Ast::Concat(flat),
+            }
+        }
+        // 入れ子の選択は一列に並べ、同じ選択肢は最初の一つだけ残す
+        Ast::Union(asts) => {
+            let mut flat: Vec<Ast> = Vec::new();
+            for ast in asts {
+                let alternatives = match simplify(ast) {
+                    Ast::Union(inner) => inner,
+                    ast => vec![ast],
+                };
+                for alternative in alternatives {
+                    if !flat.contains(&alternative) {
+                        flat.push(alternative);
+                    }
+                }
+            }
+            match flat.len() {
+                1 => flat.pop().unwrap(),
+                _ => Ast::Union(flat),
+            }
+        }
+        Ast::Star(ast) => closure(simplify(*ast), false, false),
+        Ast::Plus(ast) => closure(simplify(*ast), true, false),
+        Ast::ZeroOne(ast) => closure(simplify(*ast), false, true),
+        Ast::Repeat { ast, min, max } => {
+            let ast = simplify(*ast);
+            match (min, max) {
+                (0, None) => closure(ast, false, false),
+                (1, None) => closure(ast, true, false),
+                (0, Some(1)) => closure(ast, false, true),
+                (1, Some(1)) => ast,
+                (min, max) => Ast::Repeat {
+                    ast: Box::new(ast),
+                    min,
+                    max,
+                },
+            }
+        }
+        ast => ast,
+    }
+}
+
+// ast を最低 1 回 (at_least_once)、最大 1 回 (at_most_once) の繰り返しで囲む
+// ast 自身が `*`, `+`, `?` なら二重の繰り返しを一つにまとめる (`a**` → `a*`, `a?+` → `a*`)
+fn closure(ast: Ast, at_least_once: bool, at_most_once: bool) -> Ast {
+    let (ast, at_least_once, at_most_once) = match ast {
+        Ast::Star(inner) => (*inner, false, false),
+        Ast::Plus(inner) => (*inner, at_least_once, false),
+        Ast::ZeroOne(inner) => (*inner, false, at_most_once),
+        ast => (ast, at_least_once, at_most_once),
+    };
+    match (at_least_once, at_most_once) {
+        (false, false) => Ast::Star(Box::new(ast)),
+        (true, false) => Ast::Plus(Box::new(ast)),
+        (false, true) => Ast::ZeroOne(Box::new(ast)),
+        (true, true) => ast,
+    }
+}
+
+#[test]
+fn explain_pattern_tree() {
+    assert_eq!(
+        explain_pattern("(a|b1)*c?").unwrap(),
+        "in sequence:
+  zero or more of:
+    one of:
+      letter a
+      in sequence:
+        letter b
+        digit 1
+  optionally:
+    letter c
+"
+    );
+}
+
+#[test]
+fn ast_to_string() {
+    for &(regex, expected) in &[
+        ("((a)|(bc))*d?", "(a|bc)*d?"),
+        ("(a|(b|c))", "a|b|c"),
+        ("((ab)c)", "abc"),
+        ("(a(b)+)", "ab+"),
+        ("[a-c_]{2,}x{3}", "[_a-c]{2,}x{3}"),
+        ("[^\\d]|\\s", "[^0-9]|[\\x09-\\x0d ]"),
+    ] {
+        assert_eq!(to_string(&parse(regex).unwrap()), expected);
+    }
+}
+
+#[test]
+fn capture_group_counts() {
+    assert_eq!(capture_counts("a(b)(c|d)").unwrap(), (2, Some(2)));
+    assert_eq!(capture_counts("(a)|b").unwrap(), (1, None));
+    assert_eq!(capture_counts("((a)|(b))").unwrap(), (3, Some(2)));
+    assert_eq!(capture_counts("(a)+(b)?").unwrap(), (2, None));
+    assert_eq!(capture_counts("[a-z]{2,3}(x)").unwrap(), (1, Some(1)));
+    assert_eq!(capture_counts("(\\d){0,2}").unwrap(), (1, None));
+}
+
+#[test]
+fn visitor_walks_tree() {
+    // 深さごとのノードの数を数える
+    struct Depths {
+        depth: usize,
+        counts: Vec<usize>,
+    }
+    impl Visitor for Depths {
+        fn enter(&mut self, _ast: &Ast) -> bool {
+            if self.counts.len() <= self.depth {
+                self.counts.push(0);
+            }
+            self.counts[self.depth] += 1;
+            self.depth += 1;
+            true
+        }
+        fn leave(&mut self, _ast: &Ast) {
+            self.depth -= 1;
+        }
+    }
+    let ast = parse("a(b|c)*d").unwrap();
+    let mut depths = Depths {
+        depth: 0,
+        counts: Vec::new(),
+    };
+    ast.walk(&mut depths);
+    assert_eq!(depths.counts, vec![1, 3, 1, 1, 2]);
+    assert_eq!(
+        ast,
+        Ast::Concat(vec![
+            Ast::Literal(b'a'),
+            Ast::Star(Box::new(Ast::Group {
+                name: None,
+                ast: Box::new(Ast::Union(vec![Ast::Literal(b'b'), Ast::Literal(b'c')])),
+            })),
+            Ast::Literal(b'd'),
+        ])
+    );
+}
+
+#[test]
+fn simplify_tree() {
+    for &(regex, expected) in &[
+        ("(a**)", "a*"),
+        ("(x|x)", "x"),
+        ("a?+b+?(c+)+d??", "a*b*c+d?"),
+        ("(a|(b|a))(c(de))", "(a|b)cde"),
+        ("(ab|ab|c){1}x{0,}y{1,}z{0,1}", "(ab|c)x*y+z?"),
+        ("(a{2,3})*", "a{2,3}*"),
+    ] {
+        assert_eq!(to_string(&simplify(parse(regex).unwrap())), expected);
+    }
+    // 空の連接 (ε) は連接から取り除く
+    let epsilon_x = Ast::Concat(vec![Ast::Concat(Vec::new()), Ast::Literal(b'x')]);
+    assert_eq!(simplify(epsilon_x), Ast::Literal(b'x'));
+
+    let states = |regex: &str| ::nfa::Nfa::re2nfa(regex).unwrap().states.len();
+    assert_eq!(states("((a**)*)+"), states("a*"));
+    assert_eq!(states("(ab|cd|ab|cd)"), states("ab|cd"));
+}
+
+#[test]
+fn pattern_round_trip() {
+    for &(regex, expected) in &[
+        ("((a)|(bc))*d?", "((a)|(bc))*d?"),
+        ("a|(b|c)", "a|(b|c)"),
+        ("[a-c_]{2,}x{3}", "[_a-c]{2,}x{3}"),
+        ("\\[\\{1\\}\\^", "\\[\\{1}\\^"),
+        ("caf\u{e9}+\u{3042}", "caf\u{e9}+\u{3042}"),
+        ("\\x00[\\x80-\\xff]\\n", "\\x00[\\x80-\\xff]n"),
+        ("(a{2})*", "(a{2})*"),
+    ] {
+        let ast = parse(regex).unwrap();
+        let pattern = ast.to_string();
+        assert_eq!(pattern, expected);
+        assert_eq!(parse(&pattern).unwrap(), ast);
+    }
+    // 構文木を書き換えた後でも読み直せる
+    let ast = simplify(parse("(a**|b)(c)").unwrap());
+    assert_eq!(to_pattern(&ast), "(?:a*|b)c");
+    let empty = Ast::Class(Box::new(Class::new()));
+    assert_eq!(to_pattern(&empty), "[^\\x00-\\xff]");
+    assert_eq!(
+        parse(&to_pattern(&empty)).unwrap(),
+        Ast::Class(Box::new(Class {
+            members: [true; 256],
+            negated: true,
+        }))
+    );
+}
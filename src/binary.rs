@@ -0,0 +1,385 @@
+use dfa::{Anchors, Automaton, Dfa, DfaBuilder};
+use error::{Error, FormatError};
+
+// DFA のバイナリ形式 (数値は全てリトルエンディアン)
+//
+// ヘッダ
+//   magic     4 バイト  b"RDFA"
+//   version   u16      FORMAT_VERSION
+//   flags     u16      bit 0: 先頭のアンカー, bit 1: 末尾のアンカー, bit 2: 複数行, bit 3: 128 列
+//   patterns  u32      パターンの数
+//   states    u32      状態の数
+//   checksum  u32      本体の FNV-1a
+// 本体
+//   状態ごとに受理なら 1 の u8 と、各列の遷移先の u32 (遷移なしは NONE)
+//   ASCII だけを読む DFA は 0x80 以上の列を省いて 128 列で書く
+
+const MAGIC: &[u8; 4] = b"RDFA";
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_LEN: usize = 20;
+const NONE: u32 = u32::MAX;
+
+const ANCHOR_START: u16 = 1;
+const ANCHOR_END: u16 = 1 << 1;
+const MULTI_LINE: u16 = 1 << 2;
+const ASCII: u16 = 1 << 3;
+
+// コンパイル時の設定のうち、読み込む側と一致していなければならないもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub anchors: Anchors,
+    pub patterns: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            anchors: Anchors::default(),
+            patterns: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub options: Options,
+    // 遷移表が 128 列か
+    pub ascii: bool,
+    pub states: u32,
+    pub checksum: u32,
+}
+
+impl Header {
+    fn columns(&self) -> usize {
+        if self.ascii {
+            0x80
+        } else {
+            0x100
+        }
+    }
+
+    fn body_len(&self) -> usize {
+        self.states as usize * (1 + 4 * self.columns())
+    }
+}
+
+// FNV-1a
+pub fn checksum(body: &[u8]) -> u32 {
+    body.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from(bytes[at]) | u16::from(bytes[at + 1]) << 8
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    (0..4).fold(0, |n, i| n | u32::from(bytes[at + i]) << (8 * i))
+}
+
+// ヘッダだけを読む。本体は検査しない
+pub fn read_header(bytes: &[u8]) -> Result<Header, Error> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(Error::Format(FormatError::NotDfa));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::Format(FormatError::Truncated));
+    }
+    let version = u16_at(bytes, 4);
+    if version != FORMAT_VERSION {
+        return Err(Error::Format(FormatError::Version(version)));
+    }
+    let flags = u16_at(bytes, 6);
+    Ok(Header {
+        version,
+        options: Options {
+            anchors: Anchors {
+                start: flags & ANCHOR_START != 0,
+                end: flags & ANCHOR_END != 0,
+                multi_line: flags & MULTI_LINE != 0,
+            },
+            patterns: u32_at(bytes, 8),
+        },
+        ascii: flags & ASCII != 0,
+        states: u32_at(bytes, 12),
+        checksum: u32_at(bytes, 16),
+    })
+}
+
+impl Dfa {
+    // options はパターンから DFA を作ったときの設定で、読み込む側は同じ設定を指定する
+    pub fn to_bytes(&self, options: &Options) -> Vec<u8> {
+        let ascii = self.is_ascii_only();
+        let header = Header {
+            version: FORMAT_VERSION,
+            options: *options,
+            ascii,
+            states: self.states.len() as u32,
+            checksum: 0,
+        };
+        // 読み込んだ側では 0 番が開始状態になるよう、開始状態と 0 番を入れ替えて書く
+        let start = self.start();
+        let swapped = |id: usize| match id {
+            id if id == start => 0,
+            0 => start,
+            id => id,
+        };
+        let mut body = Vec::with_capacity(header.body_len());
+        for id in (0..self.states.len()).map(swapped) {
+            let state = &self.states[id];
+            body.push(state.accept as u8);
+            for (_, next) in self.transitions(state.id).take(header.columns()) {
+                let next = next.map_or(NONE, |next| swapped(next) as u32);
+                body.extend_from_slice(&next.to_le_bytes());
+            }
+        }
+
+        let anchors = options.anchors;
+        let flags = [
+            (anchors.start, ANCHOR_START),
+            (anchors.end, ANCHOR_END),
+            (anchors.multi_line, MULTI_LINE),
+            (ascii, ASCII),
+        ]
+        .iter()
+        .filter(|&&(set, _)| set)
+        .fold(0, |flags, &(_, bit)| flags | bit);
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&options.patterns.to_le_bytes());
+        bytes.extend_from_slice(&header.states.to_le_bytes());
+        bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    // 版、設定、チェックサムを確かめてから DFA を読み込む
+    pub fn from_bytes(bytes: &[u8], expected: &Options) -> Result<Dfa, Error> {
+        DfaRef::new(bytes, expected)?.to_dfa()
+    }
+}
+
+// to_bytes で書いたバイト列をそのまま遷移表として使う DFA
+// mmap したファイルなどから、状態を組み立て直さずに照合できる
+#[derive(Debug, Clone, Copy)]
+pub struct DfaRef<'a> {
+    header: Header,
+    body: &'a [u8],
+}
+
+impl<'a> DfaRef<'a> {
+    // from_bytes と同じ検査に加えて、全ての遷移先が存在することを一度だけ確かめる
+    pub fn new(bytes: &'a [u8], expected: &Options) -> Result<DfaRef<'a>, Error> {
+        let header = read_header(bytes)?;
+        if header.options != *expected {
+            return Err(Error::Format(FormatError::Options {
+                expected: *expected,
+                found: header.options,
+            }));
+        }
+        let body = &bytes[HEADER_LEN..];
+        if body.len() != header.body_len() {
+            return Err(Error::Format(FormatError::Truncated));
+        }
+        let found = checksum(body);
+        if found != header.checksum {
+            return Err(Error::Format(FormatError::Checksum {
+                expected: header.checksum,
+                found,
+            }));
+        }
+        if header.states == 0 {
+            return Err(Error::InvalidAutomaton("no start state".to_owned()));
+        }
+
+        let dfa = DfaRef { header, body };
+        for from in 0..dfa.len() {
+            for c in 0..header.columns() {
+                let next = dfa.raw_next(from, c);
+                if next != NONE && next >= header.states {
+                    return Err(Error::InvalidAutomaton(format!(
+                        "state {} has a transition to missing state {}",
+                        from, next
+                    )));
+                }
+            }
+        }
+        Ok(dfa)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.states as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 書き出すときに開始状態を 0 番にしている
+    pub fn start(&self) -> usize {
+        0
+    }
+
+    fn row(&self, state: usize) -> usize {
+        state * (1 + 4 * self.header.columns())
+    }
+
+    fn raw_next(&self, state: usize, c: usize) -> u32 {
+        u32_at(self.body, self.row(state) + 1 + 4 * c)
+    }
+
+    pub fn is_accept(&self, state: usize) -> bool {
+        self.body[self.row(state)] != 0
+    }
+
+    // 128 列の表では 0x80 以上のバイトに遷移はない
+    pub fn next(&self, state: usize, c: u8) -> Option<usize> {
+        if c as usize >= self.header.columns() {
+            return None;
+        }
+        match self.raw_next(state, c as usize) {
+            NONE => None,
+            next => Some(next as usize),
+        }
+    }
+
+    pub fn accept(&self, s: &str) -> bool {
+        self.accept_bytes(s.as_bytes())
+    }
+
+    pub fn accept_bytes(&self, s: &[u8]) -> bool {
+        let mut state = self.start();
+        for &c in s {
+            match self.next(state, c) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        self.is_accept(state)
+    }
+
+    pub fn to_dfa(&self) -> Result<Dfa, Error> {
+        let mut builder = DfaBuilder::new();
+        for state in 0..self.len() {
+            builder.add_state(self.is_accept(state));
+        }
+        for from in 0..self.len() {
+            for c in 0..self.header.columns() {
+                if let Some(next) = self.next(from, c as u8) {
+                    builder.set_transition(from, c as u8, next)?;
+                }
+            }
+        }
+        builder.finish()
+    }
+}
+
+impl<'a> Automaton for DfaRef<'a> {
+    fn start(&self) -> usize {
+        DfaRef::start(self)
+    }
+
+    fn next(&self, state: usize, c: u8) -> Option<usize> {
+        DfaRef::next(self, state, c)
+    }
+
+    fn is_accept(&self, state: usize) -> bool {
+        DfaRef::is_accept(self, state)
+    }
+
+    fn num_states(&self) -> usize {
+        self.len()
+    }
+}
+
+#[test]
+fn binary_round_trip() {
+    use nfa::Nfa;
+    let compile = |regex: &str| {
+        let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa(regex).unwrap()).unwrap();
+        dfa.minimize();
+        dfa
+    };
+    let options = Options::default();
+    let dfa = compile("(a|b)*abb");
+    let bytes = dfa.to_bytes(&options);
+    // ASCII だけを読むので 128 列で書く
+    assert_eq!(bytes.len(), HEADER_LEN + dfa.states.len() * (1 + 4 * 128));
+    let loaded = Dfa::from_bytes(&bytes, &options).unwrap();
+    assert_eq!(loaded.states.len(), dfa.states.len());
+    for s in &["abb", "babb", "ab", "abbx"] {
+        assert_eq!(loaded.accept(s), dfa.accept(s));
+    }
+    let dfa = compile("\\W+");
+    let loaded = Dfa::from_bytes(&dfa.to_bytes(&options), &options).unwrap();
+    assert!(loaded.accept_bytes(b"\xff-"));
+    assert!(!read_header(&dfa.to_bytes(&options)).unwrap().ascii);
+}
+
+#[test]
+fn binary_errors() {
+    use nfa::Nfa;
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab").unwrap()).unwrap();
+    let options = Options {
+        anchors: Anchors {
+            start: true,
+            ..Anchors::default()
+        },
+        patterns: 1,
+    };
+    let bytes = dfa.to_bytes(&options);
+    let error = |bytes: &[u8], expected: &Options| match Dfa::from_bytes(bytes, expected) {
+        Err(Error::Format(e)) => e,
+        other => panic!("{:?}", other),
+    };
+
+    assert_eq!(error(b"DFA?", &options), FormatError::NotDfa);
+    assert_eq!(error(&bytes[..10], &options), FormatError::Truncated);
+    assert_eq!(error(&bytes[..bytes.len() - 1], &options), FormatError::Truncated);
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(error(&newer, &options), FormatError::Version(2));
+    match error(&bytes, &Options::default()) {
+        FormatError::Options { found, .. } => assert_eq!(found, options),
+        e => panic!("{:?}", e),
+    }
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    match error(&corrupted, &options) {
+        FormatError::Checksum { expected, found } => assert_ne!(expected, found),
+        e => panic!("{:?}", e),
+    }
+}
+
+#[test]
+fn zero_copy_view() {
+    use nfa::Nfa;
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[0-9]+(\\.[0-9]+)?").unwrap()).unwrap();
+    dfa.minimize();
+    let options = Options::default();
+    let bytes = dfa.to_bytes(&options);
+    let view = DfaRef::new(&bytes, &options).unwrap();
+    assert_eq!(view.len(), dfa.states.len());
+    for s in &["12", "3.14", "3.", ".5", "", "1\u{e9}"] {
+        assert_eq!(view.accept(s), dfa.accept(s));
+    }
+    assert_eq!(view.next(view.start(), 0xff), None);
+
+    // チェックサムを合わせても、存在しない状態への遷移は弾く
+    let mut broken = bytes.clone();
+    broken[HEADER_LEN + 1..HEADER_LEN + 5].copy_from_slice(&100u32.to_le_bytes());
+    let sum = checksum(&broken[HEADER_LEN..]);
+    broken[16..20].copy_from_slice(&sum.to_le_bytes());
+    match DfaRef::new(&broken, &options) {
+        Err(Error::InvalidAutomaton(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
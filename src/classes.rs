@@ -0,0 +1,129 @@
+use dfa::Dfa;
+use nfa::Nfa;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+// どの状態からも同じ遷移をするバイトをまとめた同値類
+// 類の番号は、その類で最も小さいバイトの順に 0 から振る
+#[derive(Clone, PartialEq, Eq)]
+pub struct ByteClasses {
+    classes: [u8; 256],
+    len: usize,
+}
+
+impl ByteClasses {
+    // 全てのバイトを別々の類にする
+    pub fn singletons() -> Self {
+        let mut classes = [0; 256];
+        for (c, class) in classes.iter_mut().enumerate() {
+            *class = c as u8;
+        }
+        ByteClasses { classes, len: 256 }
+    }
+
+    // column(c) が等しいバイトを同じ類にまとめる
+    fn from_columns<K: Hash + Eq, F: Fn(usize) -> K>(column: F) -> Self {
+        let mut ids: HashMap<K, u8> = HashMap::new();
+        let mut classes = [0; 256];
+        for (c, class) in classes.iter_mut().enumerate() {
+            let next = ids.len() as u8;
+            *class = *ids.entry(column(c)).or_insert(next);
+        }
+        ByteClasses {
+            classes,
+            len: ids.len(),
+        }
+    }
+
+    pub fn from_dfa(dfa: &Dfa) -> Self {
+        ByteClasses::from_columns(|c| {
+            dfa.states
+                .iter()
+                .map(|s| dfa.transition(s.id, c as u8))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    // ε遷移は入力によらないので、バイトの遷移だけを比べる
+    pub fn from_nfa(nfa: &Nfa) -> Self {
+        ByteClasses::from_columns(|c| {
+            nfa.states
+                .iter()
+                .map(|s| s.transition[c].as_ref().map(|next| next.sorted()))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    pub fn get(&self, c: u8) -> usize {
+        self.classes[c as usize] as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 各類で最も小さいバイト。類の番号の順に並ぶ
+    pub fn representatives(&self) -> Vec<u8> {
+        let mut representatives = Vec::with_capacity(self.len);
+        for c in 0..=255u8 {
+            if self.get(c) == representatives.len() {
+                representatives.push(c);
+            }
+        }
+        representatives
+    }
+
+    // 各類に含まれるバイトの数。類の番号の順に並ぶ
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.len];
+        for &class in self.classes.iter() {
+            sizes[class as usize] += 1;
+        }
+        sizes
+    }
+
+    pub fn members(&self, class: usize) -> Vec<u8> {
+        (0..=255u8).filter(|&c| self.get(c) == class).collect()
+    }
+}
+
+impl fmt::Debug for ByteClasses {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ByteClasses({} classes)", self.len)
+    }
+}
+
+impl Dfa {
+    pub fn byte_classes(&self) -> ByteClasses {
+        ByteClasses::from_dfa(self)
+    }
+}
+
+#[test]
+fn byte_classes() {
+    let nfa = Nfa::re2nfa("[a-c]+x|[0-9]").unwrap();
+    let classes = ByteClasses::from_nfa(&nfa);
+    // [a-c], x, [0-9], それ以外
+    assert_eq!(classes.len(), 4);
+    assert_eq!(classes.get(b'a'), classes.get(b'c'));
+    assert_ne!(classes.get(b'a'), classes.get(b'x'));
+    assert_eq!(classes.get(b'0'), classes.get(b'9'));
+    assert_eq!(classes.get(b'z'), classes.get(0));
+    assert_eq!(classes.members(classes.get(b'b')), b"abc".to_vec());
+    assert_eq!(classes.representatives(), vec![0, b'0', b'a', b'x']);
+
+    let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    dfa.minimize();
+    assert_eq!(dfa.byte_classes(), classes);
+    // 遷移表の列はバイトの類ごとに一つ
+    assert_eq!(dfa.classes(), &classes);
+    assert!(dfa.states.iter().all(|s| s.t.len() == classes.len()));
+    assert_eq!(dfa.transition(dfa.start(), b'b'), dfa.transition(dfa.start(), b'a'));
+    assert!(dfa.accept("abx") && dfa.accept("7") && !dfa.accept("x"));
+    assert_eq!(ByteClasses::singletons().len(), 256);
+}
@@ -0,0 +1,162 @@
+use dfa::Dfa;
+
+// 遷移先が同じ連続したバイトの範囲 (from, to, 遷移先)
+fn byte_ranges(t: &[Option<usize>]) -> Vec<(u8, u8, usize)> {
+    let mut ranges: Vec<(u8, u8, usize)> = Vec::new();
+    for (c, next) in t.iter().enumerate() {
+        let (c, next) = match *next {
+            Some(next) => (c as u8, next),
+            None => continue,
+        };
+        match ranges.last_mut() {
+            Some(last) if last.1 as usize + 1 == c as usize && last.2 == next => last.1 = c,
+            _ => ranges.push((c, c, next)),
+        }
+    }
+    ranges
+}
+
+fn rust_byte(c: u8) -> String {
+    match c {
+        b'\'' | b'\\' => format!("b'\\{}'", c as char),
+        0x20..=0x7e => format!("b'{}'", c as char),
+        _ => format!("0x{:02x}", c),
+    }
+}
+
+impl Dfa {
+    /// 受理判定を行う依存のない Rust の関数 `pub fn name(input: &[u8]) -> bool` のソースを返す。
+    /// 遷移は状態とバイトの範囲の `match` で書く。
+    pub fn to_rust_source(&self, name: &str) -> String {
+        let mut out = String::new();
+        out.push_str(&format!(
+            "// {} 状態の DFA から生成した照合関数\npub fn {}(input: &[u8]) -> bool {{\n",
+            self.states.len(),
+            name
+        ));
+        if self.states.is_empty() {
+            out.push_str("    let _ = input;\n    false\n}\n");
+            return out;
+        }
+        out.push_str(&format!("    let mut state: usize = {};\n", self.start()));
+        out.push_str("    for &b in input {\n        state = match (state, b) {\n");
+        for state in self.states.iter() {
+            let row: Vec<_> = self.transitions(state.id).map(|(_, next)| next).collect();
+            for (from, to, next) in byte_ranges(&row) {
+                let pattern = if from == to {
+                    rust_byte(from)
+                } else {
+                    format!("{}..={}", rust_byte(from), rust_byte(to))
+                };
+                out.push_str(&format!("            ({}, {}) => {},\n", state.id, pattern, next));
+            }
+        }
+        out.push_str("            _ => return false,\n        };\n    }\n");
+        let accepting: Vec<String> = self.accepting_states().map(|id| id.to_string()).collect();
+        if accepting.is_empty() {
+            out.push_str("    let _ = state;\n    false\n}\n");
+        } else {
+            out.push_str(&format!(
+                "    match state {{\n        {} => true,\n        _ => false,\n    }}\n}}\n",
+                accepting.join(" | ")
+            ));
+        }
+        out
+    }
+
+    /// 遷移表 `static const uint32_t table[][256]` と、それを引く C の関数
+    /// `int dfa_accept(const unsigned char *input, size_t len)` のソースを返す。
+    /// 遷移のないところは `DFA_DEAD` (0xffffffff) にする。
+    pub fn to_c_source(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!(
+            "/* {} 状態の DFA から生成した遷移表 */\n#include <stddef.h>\n#include <stdint.h>\n\n",
+            self.states.len()
+        ));
+        out.push_str("#define DFA_DEAD 0xffffffffu\n\n");
+        if self.states.is_empty() {
+            out.push_str("int dfa_accept(const unsigned char *input, size_t len)\n{\n");
+            out.push_str("    (void)input;\n    (void)len;\n    return 0;\n}\n");
+            return out;
+        }
+        out.push_str(&format!(
+            "static const uint32_t table[{}][256] = {{\n",
+            self.states.len()
+        ));
+        for state in self.states.iter() {
+            out.push_str(&format!("    /* {} */\n    {{\n", state.id));
+            let row: Vec<_> = self.transitions(state.id).map(|(_, next)| next).collect();
+            for row in row.chunks(16) {
+                let cells: Vec<String> = row
+                    .iter()
+                    .map(|next| next.map_or("DFA_DEAD".to_owned(), |next| format!("{}", next)))
+                    .collect();
+                out.push_str(&format!("        {},\n", cells.join(", ")));
+            }
+            out.push_str("    },\n");
+        }
+        out.push_str("};\n\n");
+        let accept: Vec<&str> = self
+            .states
+            .iter()
+            .map(|s| if s.accept { "1" } else { "0" })
+            .collect();
+        out.push_str(&format!(
+            "static const uint8_t accept[{}] = {{{}}};\n\n",
+            self.states.len(),
+            accept.join(", ")
+        ));
+        out.push_str("int dfa_accept(const unsigned char *input, size_t len)\n{\n");
+        out.push_str(&format!("    uint32_t state = {};\n", self.start()));
+        out.push_str("    size_t i;\n    for (i = 0; i < len; i++) {\n");
+        out.push_str("        state = table[state][input[i]];\n");
+        out.push_str("        if (state == DFA_DEAD) {\n            return 0;\n        }\n    }\n");
+        out.push_str("    return accept[state];\n}\n");
+        out
+    }
+}
+
+#[test]
+fn rust_source() {
+    use nfa::Nfa;
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[a-z]+'?[0-9]").unwrap()).unwrap();
+    dfa.minimize();
+    assert_eq!(
+        dfa.to_rust_source("is_word"),
+        "// 4 状態の DFA から生成した照合関数
+pub fn is_word(input: &[u8]) -> bool {
+    let mut state: usize = 0;
+    for &b in input {
+        state = match (state, b) {
+            (0, b'a'..=b'z') => 1,
+            (1, b'\\'') => 2,
+            (1, b'0'..=b'9') => 3,
+            (1, b'a'..=b'z') => 1,
+            (2, b'0'..=b'9') => 3,
+            _ => return false,
+        };
+    }
+    match state {
+        3 => true,
+        _ => false,
+    }
+}
+"
+    );
+    assert!(Dfa::new().to_rust_source("never").contains("    false\n}"));
+}
+
+#[test]
+fn c_source() {
+    use nfa::Nfa;
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab*").unwrap()).unwrap();
+    dfa.minimize();
+    let source = dfa.to_c_source();
+    assert!(source.contains("static const uint32_t table[2][256] = {\n    /* 0 */\n    {\n"));
+    assert!(source.contains("static const uint8_t accept[2] = {0, 1};"));
+    assert!(source.contains("    uint32_t state = 0;\n"));
+    // 0 番の状態の 0x60 から 0x6f の行 ('a' は 0x61)
+    assert!(source.contains("        DFA_DEAD, 1, DFA_DEAD,"));
+    assert_eq!(source.matches("DFA_DEAD").count(), 2 * 256 - 2 + 2);
+    assert!(Dfa::new().to_c_source().contains("return 0;"));
+}
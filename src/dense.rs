@@ -0,0 +1,345 @@
+use classes::ByteClasses;
+use dfa::{Anchors, Automaton, Dfa, Match, Matches, Search};
+use simd;
+use std::ops::Range;
+
+// 遷移表を 1 本の Vec<u32> に詰めた DFA
+// 行は状態ごとにバイトの同値類の数だけ並び、遷移先は行の先頭位置 (状態番号 × stride) で持つ
+// 0 番は自分に戻るだけの死状態で、遷移のないバイトはここへ行く。照合の内側のループに分岐がない
+// 死状態の次に受理状態を並べるので、行の先頭位置が special 以下かを比べるだけで止まる状態か分かる
+#[derive(Debug, Clone)]
+pub struct DenseDfa {
+    table: Vec<u32>,
+    classes: ByteClasses,
+    stride: usize,
+    accept: Vec<bool>,
+    // 行の先頭位置
+    start: u32,
+    // 最後の受理状態の行の先頭位置
+    special: u32,
+    // 開始状態から他の状態へ進むバイトが 3 個以下ならその一覧
+    // shortest_match は開始状態にいる間、次にこのどれかが現れるところまで読み飛ばす
+    start_bytes: Option<Vec<u8>>,
+}
+
+impl DenseDfa {
+    // dfa の受理状態、受理しない状態の順に、それぞれ元の番号の順で 1 番から並べる
+    pub fn from_dfa(dfa: &Dfa) -> DenseDfa {
+        let classes = dfa.classes().clone();
+        let stride = classes.len();
+        let order: Vec<usize> = (0..dfa.states.len())
+            .filter(|&i| dfa.states[i].accept)
+            .chain((0..dfa.states.len()).filter(|&i| !dfa.states[i].accept))
+            .collect();
+        let mut row = vec![0; dfa.states.len()];
+        for (k, &i) in order.iter().enumerate() {
+            row[i] = ((k + 1) * stride) as u32;
+        }
+        let mut table = vec![0; (dfa.states.len() + 1) * stride];
+        let mut accept = vec![false; dfa.states.len() + 1];
+        for (k, &i) in order.iter().enumerate() {
+            let state = &dfa.states[i];
+            accept[k + 1] = state.accept;
+            for (class, next) in state.t.iter().enumerate() {
+                if let Some(next) = *next {
+                    table[(k + 1) * stride + class] = row[next];
+                }
+            }
+        }
+        let start = if dfa.states.is_empty() {
+            0
+        } else {
+            row[dfa.start()]
+        };
+        let accepting = dfa.states.iter().filter(|state| state.accept).count();
+        let leaving: Vec<u8> = (0..=255u8)
+            .filter(|&c| table[start as usize + classes.get(c)] != start)
+            .collect();
+        let start_bytes = if start != 0 && leaving.len() <= 3 {
+            Some(leaving)
+        } else {
+            None
+        };
+        DenseDfa {
+            table,
+            classes,
+            stride,
+            accept,
+            start,
+            special: (accepting * stride) as u32,
+            start_bytes,
+        }
+    }
+
+    pub fn classes(&self) -> &ByteClasses {
+        &self.classes
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    // 遷移表のバイト数
+    pub fn table_size(&self) -> usize {
+        self.table.len() * 4
+    }
+
+    pub fn accept(&self, s: &str) -> bool {
+        Automaton::accept_bytes(self, s.as_bytes())
+    }
+
+    // Dfa::find_bounded と同じ一致を、状態を行の先頭位置で持って探す
+    // 行の先頭位置が special 以下の状態は受理状態 (0 番の死状態は候補に入れない)
+    pub fn find_bounded(
+        &self,
+        haystack: &[u8],
+        range: Range<usize>,
+        anchors: Anchors,
+        max_len: Option<usize>,
+    ) -> Option<Match> {
+        if self.start == 0 {
+            return None;
+        }
+        let stride = self.stride;
+        // 各状態に到達した一致候補のうち最も左の開始位置 (状態の番号で引く)
+        let mut starts: Vec<Option<usize>> = vec![None; self.accept.len()];
+        let mut active: Vec<u32> = Vec::new();
+        let mut best: Option<Match> = None;
+        let start = self.start as usize / stride;
+        for end in range.start..=haystack.len() {
+            if best.is_none()
+                && end < range.end
+                && starts[start].is_none()
+                && anchors.start_ok(haystack, end)
+            {
+                starts[start] = Some(end);
+                active.push(self.start);
+            }
+            for &row in active.iter() {
+                let start = starts[row as usize / stride].unwrap();
+                let better = match best {
+                    Some(m) => start < m.start || (start == m.start && end > m.end),
+                    None => true,
+                };
+                if row <= self.special && better && anchors.end_ok(haystack, end) {
+                    best = Some(Match { start, end });
+                }
+            }
+            if let Some(m) = best {
+                active.retain(|&row| starts[row as usize / stride].unwrap() <= m.start);
+            }
+            if let Some(max_len) = max_len {
+                active.retain(|&row| end - starts[row as usize / stride].unwrap() < max_len);
+            }
+            let exhausted = active.is_empty() && (best.is_some() || end + 1 >= range.end);
+            if end == haystack.len() || exhausted {
+                break;
+            }
+
+            let class = self.classes.get(haystack[end]);
+            let mut next_starts: Vec<Option<usize>> = vec![None; self.accept.len()];
+            let mut next_active = Vec::new();
+            for &row in active.iter() {
+                let next = self.table[row as usize + class];
+                if next == 0 {
+                    continue;
+                }
+                let start = starts[row as usize / stride].unwrap();
+                match next_starts[next as usize / stride] {
+                    None => {
+                        next_starts[next as usize / stride] = Some(start);
+                        next_active.push(next);
+                    }
+                    Some(s) if start < s => next_starts[next as usize / stride] = Some(start),
+                    _ => {}
+                }
+            }
+            starts = next_starts;
+            active = next_active;
+        }
+        best
+    }
+
+    pub fn find_anchored(&self, haystack: &[u8], from: usize, anchors: Anchors) -> Option<Match> {
+        self.find_bounded(haystack, from..haystack.len() + 1, anchors, None)
+    }
+
+    // 一致の長さの下限と上限は元の DFA で求めておいたものを渡す
+    pub fn find_iter_bounded<'h>(
+        &self,
+        haystack: &'h [u8],
+        anchors: Anchors,
+        min_len: Option<usize>,
+        max_len: Option<usize>,
+    ) -> Matches<'_, 'h> {
+        Matches::new(self, haystack, anchors, min_len, max_len)
+    }
+
+    // special 以下の行で止まったときの結果
+    fn stop(&self, state: u32, at: usize) -> Option<usize> {
+        if state == 0 {
+            None
+        } else {
+            Some(at)
+        }
+    }
+
+    // 先頭から読んで最初に受理状態になる位置。入力の途中からの一致を探す DFA なら最短の一致の終わり
+    pub fn shortest_match(&self, haystack: &[u8]) -> Option<usize> {
+        let mut state = self.start;
+        let mut at = 0;
+        while at < haystack.len() {
+            if state <= self.special {
+                return self.stop(state, at);
+            }
+            if state == self.start {
+                if let Some(ref bytes) = self.start_bytes {
+                    at += simd::find_any(bytes, &haystack[at..])?;
+                }
+            }
+            state = self.table[state as usize + self.classes.get(haystack[at])];
+            at += 1;
+        }
+        if state <= self.special {
+            return self.stop(state, haystack.len());
+        }
+        None
+    }
+}
+
+impl Search for DenseDfa {
+    fn find_bounded(
+        &self,
+        haystack: &[u8],
+        range: Range<usize>,
+        anchors: Anchors,
+        max_len: Option<usize>,
+    ) -> Option<Match> {
+        DenseDfa::find_bounded(self, haystack, range, anchors, max_len)
+    }
+}
+
+impl Automaton for DenseDfa {
+    fn start(&self) -> usize {
+        self.start as usize / self.stride
+    }
+
+    fn next(&self, state: usize, c: u8) -> Option<usize> {
+        match self.table[state * self.stride + self.classes.get(c)] as usize / self.stride {
+            0 => None,
+            next => Some(next),
+        }
+    }
+
+    fn is_accept(&self, state: usize) -> bool {
+        self.accept[state]
+    }
+
+    fn num_states(&self) -> usize {
+        self.accept.len()
+    }
+
+    // 死状態に落ちても止まらずに最後まで読む
+    fn accept_bytes(&self, s: &[u8]) -> bool {
+        let mut state = self.start as usize;
+        for &c in s {
+            state = self.table[state + self.classes.get(c)] as usize;
+        }
+        self.accept[state / self.stride]
+    }
+}
+
+impl Dfa {
+    pub fn to_dense(&self) -> DenseDfa {
+        DenseDfa::from_dfa(self)
+    }
+}
+
+#[test]
+fn dense_matches_struct_of_states() {
+    use nfa::Nfa;
+
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(ab|cd)*e[0-9]?").unwrap()).unwrap();
+    dfa.minimize();
+    let dense = dfa.to_dense();
+    // a, b, c, d, e, [0-9], その他
+    assert_eq!(dense.stride(), 7);
+    assert_eq!(dense.num_states(), dfa.states.len() + 1);
+    for s in &["e", "abcde7", "abe", "ab", "cde99", "", "e\u{ff}"] {
+        assert_eq!(dense.accept(s), dfa.accept(s), "{}", s);
+        let generic = |a: &dyn Automaton| {
+            let mut state = Some(a.start());
+            for &c in s.as_bytes() {
+                state = state.and_then(|state| a.next(state, c));
+            }
+            state.is_some_and(|state| a.is_accept(state))
+        };
+        assert_eq!(generic(&dense), dfa.accept(s));
+    }
+    assert!(!Dfa::new().to_dense().accept(""));
+}
+
+#[test]
+fn dense_shortest_match() {
+    use nfa::Nfa;
+
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[0-9]+x|yy").unwrap().unanchored()).unwrap();
+    dfa.minimize();
+    let dense = dfa.to_dense();
+    assert_eq!(dense.shortest_match(b"abc12x"), Some(6));
+    assert_eq!(dense.shortest_match(b"ayyb1x"), Some(3));
+    assert_eq!(dense.shortest_match(b"abcdefgh9x"), Some(10));
+    assert_eq!(dense.shortest_match(b"12345678"), None);
+    assert_eq!(dense.shortest_match(b""), None);
+
+    // 死状態に落ちたら読むのをやめる
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(ab)?").unwrap()).unwrap();
+    dfa.minimize();
+    let dense = dfa.to_dense();
+    assert_eq!(dense.shortest_match(b"xab"), Some(0));
+    let dense = Dfa::nfa2dfa(&Nfa::re2nfa("abc").unwrap()).unwrap().to_dense();
+    assert_eq!(dense.shortest_match(b"abcabc"), Some(3));
+    assert_eq!(dense.shortest_match(b"abxabcab"), None);
+    assert_eq!(Dfa::new().to_dense().shortest_match(b"a"), None);
+
+    // 開始状態から出ていくバイト (E) まで読み飛ばし、途中で開始状態に戻ってもまた読み飛ばす
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ERR-[0-9]").unwrap().unanchored()).unwrap();
+    dfa.minimize();
+    let dense = dfa.to_dense();
+    assert_eq!(dense.start_bytes, Some(b"E".to_vec()));
+    for at in 0..70 {
+        let haystack = format!("{}ERx E ERR-x ERR-7", ".".repeat(at));
+        assert_eq!(dense.shortest_match(haystack.as_bytes()), Some(at + 17), "{}", haystack);
+        assert_eq!(dense.shortest_match(&haystack.as_bytes()[..at + 16]), None);
+    }
+}
+
+#[test]
+fn dense_find_matches_dfa() {
+    use nfa::Nfa;
+
+    let haystack = b"xx abab\nab cd12 abcd\nabab";
+    for pattern in &["(ab)+", "a|b|", "[a-d]+[0-9]*", "b.", "zz"] {
+        let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa(pattern).unwrap()).unwrap();
+        dfa.minimize();
+        let dense = dfa.to_dense();
+        let (min_len, max_len) = (dfa.min_match_len(), dfa.max_match_len());
+        let cases = [(false, false, false), (true, true, true), (false, true, false)];
+        for &(start, end, multi_line) in cases.iter() {
+            let anchors = Anchors {
+                start,
+                end,
+                multi_line,
+            };
+            let expected: Vec<Match> = dfa.find_iter_anchored(haystack, anchors).collect();
+            let found: Vec<Match> =
+                dense.find_iter_bounded(haystack, anchors, min_len, max_len).collect();
+            assert_eq!(found, expected, "{} {:?}", pattern, anchors);
+            assert_eq!(
+                dense.find_anchored(haystack, 3, anchors),
+                dfa.find_anchored(haystack, 3, anchors)
+            );
+        }
+    }
+    assert_eq!(Dfa::new().to_dense().find_anchored(b"a", 0, Anchors::default()), None);
+}
@@ -1,239 +1,1936 @@
-use nfa::{Nfa, StateSet};
+use annotation::Annotations;
+use classes::ByteClasses;
+use elimination;
+use error::Error;
+use nfa::{Nfa, StateSet, SubsetMap};
+use rng::Rng;
+#[cfg(feature = "serde")]
+use serde::de::Error as _;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::fs;
-use std::io::{BufWriter, Write};
-use std::mem;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use walk::{walk_dfs, Visitor};
 
 #[derive(Clone)]
 pub struct State {
-    pub t: [Option<usize>; 256],
+    // バイトの類ごとの遷移先 (Dfa::classes の類の番号で引く)
+    pub t: Vec<Option<usize>>,
     pub id: usize,
     pub accept: bool,
+    // 複数のパターンから作った DFA で、この状態で一致するパターンの番号 (昇順)
+    pub patterns: Vec<usize>,
 }
 
 impl fmt::Debug for State {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut output = "State { t: [".to_string();
-        for i in 0..256 {
-            let s = &match self.t[i] {
-                Some(n) => format!("{}: {}, ", i, n),
-                _ => "".to_string(),
-            };
-            output += s;
+        for (class, next) in self.t.iter().enumerate() {
+            if let Some(n) = next {
+                output += &format!("{}: {}, ", class, n);
+            }
         }
         output += "], ";
         output += &format!("id: {}, accept: {}", self.id, self.accept);
+        if !self.patterns.is_empty() {
+            output += &format!(", patterns: {:?}", self.patterns);
+        }
         write!(f, "({})", output)
     }
 }
 
 impl State {
-    fn new(id: usize, accept: bool) -> Self {
+    fn new(id: usize, accept: bool, alphabet_len: usize) -> Self {
         State {
-            t: [None; 256],
-            id: id,
-            accept: accept,
+            t: vec![None; alphabet_len],
+            id,
+            accept,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+pub type ByteSet = [bool; 256];
+
+/// 64 ビットの語 4 つで表したバイトの集合。`quick_reject` に渡す必須バイトに使う。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteMask([u64; 4]);
+
+impl ByteMask {
+    pub fn from_set(set: &ByteSet) -> Self {
+        let mut words = [0u64; 4];
+        for (b, _) in set.iter().enumerate().filter(|&(_, &m)| m) {
+            words[b >> 6] |= 1 << (b & 63);
+        }
+        ByteMask(words)
+    }
+
+    pub fn contains(&self, b: u8) -> bool {
+        self.0[(b >> 6) as usize] & (1 << (b & 63)) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == [0; 4]
+    }
+}
+
+/// `Dfa::required_bytes` で求めた必須バイトのどれかが欠けているため、部分一致もあり得ない場合に
+/// true を返す。必須バイトは照合のたびに求めると重いので、一度求めたものを渡す。
+/// 必須バイトを全て見つけた時点で読むのをやめる。
+pub fn quick_reject(required: &ByteMask, haystack: &[u8]) -> bool {
+    let mut remaining = required.len();
+    if remaining == 0 {
+        return false;
+    }
+    let mut missing = required.0;
+    for &b in haystack {
+        let (word, bit) = ((b >> 6) as usize, 1u64 << (b & 63));
+        if missing[word] & bit != 0 {
+            missing[word] &= !bit;
+            remaining -= 1;
+            if remaining == 0 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// 遷移表の持ち方によらず、1 バイトずつ状態を進めて照合できる決定性オートマトン。
+/// `Dfa` (密な表)、`SparseDfa` (遷移の区間の列)、`DfaRef` (バイナリ形式) が実装する。
+pub trait Automaton {
+    fn start(&self) -> usize;
+    fn next(&self, state: usize, c: u8) -> Option<usize>;
+    fn is_accept(&self, state: usize) -> bool;
+    fn num_states(&self) -> usize;
+
+    /// `s` 全体が受理されるか。
+    fn accept_bytes(&self, s: &[u8]) -> bool {
+        if self.num_states() == 0 {
+            return false;
+        }
+        let mut state = self.start();
+        for &c in s {
+            match self.next(state, c) {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        self.is_accept(state)
+    }
+}
+
+impl Automaton for Dfa {
+    fn start(&self) -> usize {
+        self.start
+    }
+
+    fn next(&self, state: usize, c: u8) -> Option<usize> {
+        self.transition(state, c)
+    }
+
+    fn is_accept(&self, state: usize) -> bool {
+        self.states[state].accept
+    }
+
+    fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    fn accept_bytes(&self, s: &[u8]) -> bool {
+        Dfa::accept_bytes(self, s)
+    }
+}
+
+// 照合に渡したスライスの中でのバイト位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Match {
+    // ストリームの base バイト目から始まる断片の中での一致を、ストリーム上の位置に直す
+    pub fn at_offset(self, base: u64) -> StreamSpan {
+        StreamSpan {
+            start: base + self.start as u64,
+            end: base + self.end as u64,
         }
     }
 }
 
+// ストリーム全体でのバイト位置 (32 ビット環境でも 4 GiB を超えて数えられるよう u64 で持つ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSpan {
+    pub start: u64,
+    pub end: u64,
+}
+
+// パターン両端の `^` と `$` が一致の位置に課す制約
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Anchors {
+    // 一致は入力の先頭から始まる
+    pub start: bool,
+    // 一致は入力の末尾で終わる
+    pub end: bool,
+    // 行の先頭と末尾も入力の先頭と末尾として扱う
+    pub multi_line: bool,
+}
+
+impl Anchors {
+    pub fn start_ok(&self, haystack: &[u8], pos: usize) -> bool {
+        !self.start || pos == 0 || (self.multi_line && haystack[pos - 1] == b'\n')
+    }
+
+    pub fn end_ok(&self, haystack: &[u8], pos: usize) -> bool {
+        !self.end || pos == haystack.len() || (self.multi_line && haystack[pos] == b'\n')
+    }
+}
+
+/// 開始位置を範囲に限って、最も左で最長の一致を探せる照合器。
+/// `Dfa` と `DenseDfa` が実装し、`Matches` はこれを繰り返し呼ぶ。
+pub trait Search {
+    fn find_bounded(
+        &self,
+        haystack: &[u8],
+        range: Range<usize>,
+        anchors: Anchors,
+        max_len: Option<usize>,
+    ) -> Option<Match>;
+}
+
+impl Search for Dfa {
+    fn find_bounded(
+        &self,
+        haystack: &[u8],
+        range: Range<usize>,
+        anchors: Anchors,
+        max_len: Option<usize>,
+    ) -> Option<Match> {
+        Dfa::find_bounded(self, haystack, range, anchors, max_len)
+    }
+}
+
+// 重ならない一致を左から順に返すイテレータ
+// 空の一致の後は少なくとも 1 バイト進めてから次を探す
+// 位置は haystack の中での添字なので、ストリーム上の位置は Match::at_offset で求める
+pub struct Matches<'d, 'h> {
+    dfa: &'d dyn Search,
+    haystack: &'h [u8],
+    pos: usize,
+    anchors: Anchors,
+    // 一致の長さの下限と上限 (下限が None なら一致はない、上限が None なら上限はない)
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+}
+
+impl<'d, 'h> Matches<'d, 'h> {
+    // 一致の長さの下限と上限は求めておいたものを渡す
+    pub fn new(
+        dfa: &'d dyn Search,
+        haystack: &'h [u8],
+        anchors: Anchors,
+        min_len: Option<usize>,
+        max_len: Option<usize>,
+    ) -> Self {
+        Matches {
+            dfa,
+            haystack,
+            pos: 0,
+            anchors,
+            min_len,
+            max_len,
+        }
+    }
+}
+
+impl<'d, 'h> Iterator for Matches<'d, 'h> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        // 残りが最短の一致より短ければ探さない
+        if self.pos + self.min_len? > self.haystack.len() {
+            return None;
+        }
+        let starts = self.pos..self.haystack.len() + 1;
+        let m = self.dfa.find_bounded(self.haystack, starts, self.anchors, self.max_len)?;
+        self.pos = if m.start == m.end { m.end + 1 } else { m.end };
+        Some(m)
+    }
+}
+
+// 部分集合構成で生成した状態の番号付け
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeterminizationOrder {
+    // 幅優先探索で発見した順
+    Discovery,
+    // NFA 状態の集合の辞書順 (開始状態は常に 0 番)
+    Lexicographic,
+}
+
+// 部分集合構成で作る DFA の状態数の上限
+pub const STATE_LIMIT: usize = 1 << 16;
+
 #[derive(Debug)]
 pub struct Dfa {
     pub states: Vec<State>,
     state_num: usize,
+    // 開始状態の番号 (最小化で番号が付け替わっても追いかける)
+    start: usize,
+    // 遷移表の列になるバイトの類。各状態の t は類の番号で引き、長さは類の数
+    classes: ByteClasses,
+    annotations: Annotations,
 }
 
 impl Dfa {
     pub fn new() -> Self {
+        Dfa::with_classes(ByteClasses::singletons())
+    }
+
+    fn with_classes(classes: ByteClasses) -> Self {
         Self {
             states: Vec::new(),
             state_num: 0,
+            start: 0,
+            classes,
+            annotations: Annotations::default(),
         }
     }
 
-    pub fn nfa2dfa(nfa: &Nfa) -> Self {
-        Dfa::construct(nfa)
+    /// 遷移表の列になるバイトの類。
+    pub fn classes(&self) -> &ByteClasses {
+        &self.classes
     }
 
-    fn construct(nfa: &Nfa) -> Self {
-        let mut dfa = Dfa::new();
+    /// 状態 `id` からバイト `c` で進む先。
+    pub fn transition(&self, id: usize, c: u8) -> Option<usize> {
+        self.states[id].t[self.classes.get(c)]
+    }
+
+    /// 状態 `id` の遷移をバイトの順に並べたもの。
+    pub fn transitions(
+        &self,
+        id: usize,
+    ) -> impl DoubleEndedIterator<Item = (u8, Option<usize>)> + '_ {
+        let t = &self.states[id].t;
+        (0..=255u8).map(move |c| (c, t[self.classes.get(c)]))
+    }
+
+    // 遷移先が全ての状態で同じになるバイトを一つの類にまとめ、遷移表の列を減らす
+    fn compact_classes(&mut self) {
+        let classes = ByteClasses::from_dfa(self);
+        if classes.len() == self.classes.len() {
+            return;
+        }
+        let representatives = classes.representatives();
+        let old = &self.classes;
+        for state in self.states.iter_mut() {
+            state.t = representatives.iter().map(|&c| state.t[old.get(c)]).collect();
+        }
+        self.classes = classes;
+    }
+
+    // 全てのバイトを別々の列にした遷移表に戻す (バイトごとに遷移を書き換える前に使う)
+    fn expand_classes(&mut self) {
+        let old = &self.classes;
+        for state in self.states.iter_mut() {
+            state.t = (0..=255u8).map(|c| state.t[old.get(c)]).collect();
+        }
+        self.classes = ByteClasses::singletons();
+    }
+
+    pub fn nfa2dfa(nfa: &Nfa) -> Result<Self, Error> {
+        Dfa::nfa2dfa_with_order(nfa, DeterminizationOrder::Discovery)
+    }
+
+    pub fn nfa2dfa_with_order(nfa: &Nfa, order: DeterminizationOrder) -> Result<Self, Error> {
+        let (mut dfa, subset_to_state) = Dfa::construct(nfa)?;
+        if order == DeterminizationOrder::Lexicographic {
+            let mut subsets: Vec<(Vec<usize>, usize)> = subset_to_state
+                .into_iter()
+                .map(|(subset, id)| (subset.sorted(), id))
+                .collect();
+            // 開始状態は常に 0 番に置く
+            subsets.sort_by_key(|&(ref subset, id)| (id != 0, subset.clone()));
+            let order: Vec<usize> = subsets.into_iter().map(|(_, id)| id).collect();
+            dfa.renumber(&order);
+        }
+        debug_validate!(dfa, "determinization");
+        Ok(dfa)
+    }
+
+    // order[新しい番号] = 古い番号 となるように状態を並べ替える
+    pub fn renumber(&mut self, order: &[usize]) {
+        let mut new_id = vec![0; self.states.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_id[old] = new;
+        }
+        let mut states: Vec<State> = order.iter().map(|&old| self.states[old].clone()).collect();
+        for (id, state) in states.iter_mut().enumerate() {
+            state.id = id;
+            for t in state.t.iter_mut() {
+                if let Some(next) = *t {
+                    *t = Some(new_id[next]);
+                }
+            }
+        }
+        self.states = states;
+        self.start = new_id[self.start];
+        self.annotations.remap(&new_id);
+    }
+
+    /// 状態番号をキーとする型 `T` の注釈表。状態の並べ替えでは引き継がれ、最小化で破棄される。
+    pub fn annotations<T: Send + Sync + 'static>(&self) -> Option<&HashMap<usize, T>> {
+        self.annotations.get()
+    }
+
+    pub fn annotations_mut<T: Send + Sync + 'static>(&mut self) -> &mut HashMap<usize, T> {
+        self.annotations.get_mut()
+    }
+
+    fn construct(nfa: &Nfa) -> Result<(Self, SubsetMap<usize>), Error> {
+        let _span = trace_span!(DEBUG, "determinize", nfa_states = nfa.states.len());
+        // 同じ類のバイトは遷移先も同じなので、類ごとに 1 バイトだけ調べ、遷移表も類ごとに持つ
+        let classes = ByteClasses::from_nfa(nfa);
+        let representatives = classes.representatives();
+        let mut dfa = Dfa::with_classes(classes.clone());
         let mut state_num = 0;
         let mut queue: VecDeque<StateSet> = VecDeque::new();
-        let mut subset_to_state: HashMap<StateSet, usize> = HashMap::new();
+        let mut subset_to_state: SubsetMap<usize> = SubsetMap::default();
         queue.push_back(nfa.start_states());
         subset_to_state.insert(nfa.start_states(), state_num);
         state_num += 1;
 
         while !queue.is_empty() {
             let mut accept = false;
+            let mut patterns = Vec::new();
             let subset: StateSet = queue.pop_front().unwrap();
-            let mut transitions: Vec<Option<StateSet>> = vec![None; 256];
-
-            for iter in subset.iter() {
-                accept |= nfa.states[*iter as usize].accept;
-                for c in 0..256 {
-                    if let Some(nfa_t) = nfa.t(*iter as usize, c as u8) {
-                        if let Some(ref t) = transitions[c].take() {
-                            transitions[c] = Some(nfa_t.union(t).cloned().collect());
+            let mut transitions: Vec<Option<StateSet>> = vec![None; classes.len()];
+
+            for &id in subset.iter() {
+                accept |= nfa.states[id].accept;
+                if nfa.states[id].accept {
+                    patterns.extend(nfa.states[id].pattern);
+                }
+                for (class, &c) in representatives.iter().enumerate() {
+                    if let Some(nfa_t) = nfa.t(id, c) {
+                        if let Some(ref t) = transitions[class].take() {
+                            transitions[class] = Some(nfa_t.union(t).cloned().collect());
                         } else {
-                            transitions[c] = Some(nfa_t);
+                            transitions[class] = Some(nfa_t);
                         }
                     }
                 }
             }
+            patterns.sort();
+            patterns.dedup();
             let state = dfa.new_state(accept);
+            state.patterns = patterns;
 
-            for c in 0..256 {
-                if let Some(ref next) = transitions[c] {
-                    if !subset_to_state.contains_key(&next) {
+            for (class, next) in transitions.iter().enumerate() {
+                if let Some(ref next) = *next {
+                    if !subset_to_state.contains_key(next) {
+                        if state_num == STATE_LIMIT {
+                            return Err(Error::SizeLimit(STATE_LIMIT));
+                        }
                         subset_to_state.insert(next.clone(), state_num);
                         state_num += 1;
                         queue.push_back(next.clone());
                     }
-                    state.t[c] = Some(*subset_to_state.get(next).unwrap());
+                    state.t[class] = Some(*subset_to_state.get(next).unwrap());
                 }
             }
         }
         dfa.state_num = state_num;
-        dfa
+        trace_event!("determinized", states = dfa.states.len());
+        Ok((dfa, subset_to_state))
+    }
+
+    // 模倣される NFA 状態を刈り込みながら部分集合構成を行う
+    pub fn nfa2dfa_pruned(nfa: &Nfa) -> Result<Self, Error> {
+        let sim = nfa.simulation_preorder();
+        let (accepts, moves) = nfa.closure_moves();
+        let classes = ByteClasses::from_nfa(nfa);
+        let representatives = classes.representatives();
+        let mut dfa = Dfa::with_classes(classes);
+        let mut state_num = 0;
+        let mut queue: VecDeque<StateSet> = VecDeque::new();
+        let mut subset_to_state: SubsetMap<usize> = SubsetMap::default();
+        let start = nfa.prune_simulated(&nfa.start_states(), &sim);
+        queue.push_back(start.clone());
+        subset_to_state.insert(start, state_num);
+        state_num += 1;
+
+        while let Some(subset) = queue.pop_front() {
+            let accept = subset.iter().any(|&id| accepts[id]);
+            let state = dfa.new_state(accept);
+
+            for (t, &c) in state.t.iter_mut().zip(representatives.iter()) {
+                let mut next = StateSet::new();
+                for id in subset.iter() {
+                    next.extend(moves[*id][c as usize].iter().cloned());
+                }
+                if next.is_empty() {
+                    continue;
+                }
+                let next = nfa.prune_simulated(&next, &sim);
+                if !subset_to_state.contains_key(&next) {
+                    if state_num == STATE_LIMIT {
+                        return Err(Error::SizeLimit(STATE_LIMIT));
+                    }
+                    subset_to_state.insert(next.clone(), state_num);
+                    state_num += 1;
+                    queue.push_back(next.clone());
+                }
+                *t = Some(subset_to_state[&next]);
+            }
+        }
+        dfa.state_num = state_num;
+        debug_validate!(dfa, "pruned determinization");
+        Ok(dfa)
     }
 
     fn new_state(&mut self, accept: bool) -> &mut State {
         let id = self.state_num;
-        self.states.push(State::new(id, accept));
+        self.states.push(State::new(id, accept, self.classes.len()));
         self.state_num += 1;
         &mut self.states[id]
     }
 
+    // 開始状態があり、状態番号が並びの位置と一致し、全ての遷移先が存在することを確かめる
+    // debug ビルドでは構成と最小化の後に呼ばれる
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.states.is_empty() {
+            return Err(Error::InvalidAutomaton("no start state".to_owned()));
+        }
+        if self.start >= self.states.len() {
+            return Err(Error::InvalidAutomaton(format!(
+                "start state {} does not exist",
+                self.start
+            )));
+        }
+        for (i, state) in self.states.iter().enumerate() {
+            if state.id != i {
+                return Err(Error::InvalidAutomaton(format!(
+                    "state at index {} has id {}",
+                    i, state.id
+                )));
+            }
+            if state.t.len() != self.classes.len() {
+                return Err(Error::InvalidAutomaton(format!(
+                    "state {} has {} columns for {} byte classes",
+                    state.id,
+                    state.t.len(),
+                    self.classes.len()
+                )));
+            }
+            for next in state.t.iter().flatten() {
+                if *next >= self.states.len() {
+                    return Err(Error::InvalidAutomaton(format!(
+                        "state {} has a transition to missing state {}",
+                        state.id, next
+                    )));
+                }
+            }
+        }
+        if self.state_num != self.states.len() {
+            return Err(Error::InvalidAutomaton(format!(
+                "state count {} does not match {} states",
+                self.state_num,
+                self.states.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// 開始状態の番号。
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// 開始状態と 0 番の状態を入れ替え、開始状態を 0 番にする。
+    pub fn normalize_start(&mut self) {
+        let start = self.start;
+        if start == 0 {
+            return;
+        }
+        let swapped = |id: usize| match id {
+            id if id == start => 0,
+            0 => start,
+            id => id,
+        };
+        self.states.swap(0, start);
+        for (id, state) in self.states.iter_mut().enumerate() {
+            state.id = id;
+            for next in state.t.iter_mut().flatten() {
+                *next = swapped(*next);
+            }
+        }
+        self.start = 0;
+        self.annotations.clear();
+    }
+
+    pub fn accepting_states<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        self.states.iter().filter(|s| s.accept).map(|s| s.id)
+    }
+
+    pub fn set_accept(&mut self, id: usize, accept: bool) {
+        self.states[id].accept = accept;
+    }
+
+    pub fn set_start(&mut self, id: usize) {
+        self.start = id;
+    }
+
     pub fn accept(&self, s: &str) -> bool {
-        let mut state = &self.states[0];
-        for c in s.to_string().chars() {
-            if let Some(next) = state.t[c as usize] {
-                state = &self.states[next];
-            } else {
-                return false;
+        self.accept_bytes(s.as_bytes())
+    }
+
+    pub fn accept_bytes(&self, s: &[u8]) -> bool {
+        self.accept_iter(s.iter().cloned())
+    }
+
+    /// 遅延して作られるバイト列をバッファに集めずに照合する。遷移がなくなった時点で読むのをやめる。
+    pub fn accept_iter<I: IntoIterator<Item = u8>>(&self, input: I) -> bool {
+        let mut state = match self.states.get(self.start) {
+            Some(state) => state,
+            None => return false,
+        };
+        for c in input {
+            match state.t[self.classes.get(c)] {
+                Some(next) => state = &self.states[next],
+                None => return false,
+            }
+        }
+        state.accept
+    }
+
+    /// 最も左から始まる一致のうち最長のものを返す。
+    pub fn find(&self, haystack: &str) -> Option<Match> {
+        self.find_bytes(haystack.as_bytes())
+    }
+
+    pub fn find_bytes(&self, haystack: &[u8]) -> Option<Match> {
+        self.find_anchored(haystack, 0, Anchors::default())
+    }
+
+    // haystack の from バイト目以降で、anchors の制約を満たす最も左で最長の一致を返す
+    pub fn find_anchored(&self, haystack: &[u8], from: usize, anchors: Anchors) -> Option<Match> {
+        self.find_bounded(haystack, from..haystack.len() + 1, anchors, None)
+    }
+
+    // find_anchored と同じだが、一致の開始位置を range の中に限る
+    // 一致の長さが max_len 以下と分かっていれば、それより長く読んだ候補を捨てる
+    pub fn find_bounded(
+        &self,
+        haystack: &[u8],
+        range: Range<usize>,
+        anchors: Anchors,
+        max_len: Option<usize>,
+    ) -> Option<Match> {
+        if self.states.is_empty() {
+            return None;
+        }
+        // 各状態に到達した一致候補のうち最も左の開始位置
+        let mut starts: Vec<Option<usize>> = vec![None; self.states.len()];
+        let mut active: Vec<usize> = Vec::new();
+        let mut best: Option<Match> = None;
+        for end in range.start..=haystack.len() {
+            if best.is_none()
+                && end < range.end
+                && starts[self.start].is_none()
+                && anchors.start_ok(haystack, end)
+            {
+                starts[self.start] = Some(end);
+                active.push(self.start);
+            }
+            for &id in active.iter() {
+                let start = starts[id].unwrap();
+                let better = match best {
+                    Some(m) => start < m.start || (start == m.start && end > m.end),
+                    None => true,
+                };
+                if self.states[id].accept && better && anchors.end_ok(haystack, end) {
+                    best = Some(Match { start, end });
+                }
+            }
+            if let Some(m) = best {
+                active.retain(|&id| starts[id].unwrap() <= m.start);
+            }
+            if let Some(max_len) = max_len {
+                active.retain(|&id| end - starts[id].unwrap() < max_len);
+            }
+            // 一致が見つかるか、range の外まで来て読んでいる候補がなくなったら終わる
+            let exhausted = active.is_empty() && (best.is_some() || end + 1 >= range.end);
+            if end == haystack.len() || exhausted {
+                break;
+            }
+
+            let mut next_starts: Vec<Option<usize>> = vec![None; self.states.len()];
+            let mut next_active = Vec::new();
+            for &id in active.iter() {
+                if let Some(next) = self.transition(id, haystack[end]) {
+                    let start = starts[id].unwrap();
+                    match next_starts[next] {
+                        None => {
+                            next_starts[next] = Some(start);
+                            next_active.push(next);
+                        }
+                        Some(s) if start < s => next_starts[next] = Some(start),
+                        _ => {}
+                    }
+                }
+            }
+            starts = next_starts;
+            active = next_active;
+        }
+        best
+    }
+
+    pub fn find_iter<'h>(&self, haystack: &'h str) -> Matches<'_, 'h> {
+        self.find_iter_bytes(haystack.as_bytes())
+    }
+
+    pub fn find_iter_bytes<'h>(&self, haystack: &'h [u8]) -> Matches<'_, 'h> {
+        self.find_iter_anchored(haystack, Anchors::default())
+    }
+
+    pub fn find_iter_anchored<'h>(&self, haystack: &'h [u8], anchors: Anchors) -> Matches<'_, 'h> {
+        self.find_iter_bounded(haystack, anchors, self.min_match_len(), self.max_match_len())
+    }
+
+    // 一致の長さの下限と上限を求めずに、求めておいたものを使う find_iter_anchored
+    pub fn find_iter_bounded<'h>(
+        &self,
+        haystack: &'h [u8],
+        anchors: Anchors,
+        min_len: Option<usize>,
+        max_len: Option<usize>,
+    ) -> Matches<'_, 'h> {
+        Matches::new(self, haystack, anchors, min_len, max_len)
+    }
+
+    // 先頭に最長一致させ、一致範囲と残りの入力を返す
+    pub fn strip_prefix_match<'a>(&self, input: &'a str) -> Option<(Match, &'a str)> {
+        let mut state = self.states.get(self.start)?;
+        let mut longest = if state.accept { Some(0) } else { None };
+        for (i, &c) in input.as_bytes().iter().enumerate() {
+            match state.t[self.classes.get(c)] {
+                Some(next) => state = &self.states[next],
+                None => break,
+            }
+            if state.accept && input.is_char_boundary(i + 1) {
+                longest = Some(i + 1);
             }
         }
-        true
+        longest.map(|end| (Match { start: 0, end }, &input[end..]))
+    }
+
+    #[cfg(feature = "petgraph")]
+    pub fn to_petgraph(&self) -> ::petgraph::Graph<bool, Option<u8>> {
+        ::walk::to_petgraph(self)
     }
 
     pub fn dot(&self) -> String {
-        let mut dot = r###"
+        let mut dot = Vec::new();
+        self.write_dot(&mut dot).unwrap();
+        String::from_utf8(dot).unwrap()
+    }
+
+    // dot 形式で out に書き出す
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(
+            r###"
             digraph G {
             rankdir=LR;
             empty [label = "" shape = plaintext];
         "###
-        .to_owned();
-        let mut ac_state_dot = "\nnode [shape = doublecircle]".to_owned();
-
+            .as_bytes(),
+        )?;
+        out.write_all(b"\nnode [shape = doublecircle]")?;
         for ac_state in self.states.iter().filter(|&s| s.accept) {
-            ac_state_dot.push_str(&("s".to_owned() + &ac_state.id.to_string() + " "));
+            write!(out, "s{} ", ac_state.id)?;
         }
-        ac_state_dot.push_str(";\n");
-        dot.push_str(&ac_state_dot);
-        dot.push_str("node [shape = circle];\nempty -> s0 [label = \"start\"]\n");
+        out.write_all(b";\n")?;
+        out.write_all(b"node [shape = circle];\n\n")?;
+        writeln!(out, "empty -> s{} [label = \"start\"]", self.start)?;
+        self.write_dot_edges(out, "")?;
+        out.write_all(b"}")
+    }
+
+    fn write_dot_edges<W: Write>(&self, out: &mut W, prefix: &str) -> io::Result<()> {
         for s in self.states.iter() {
-            for (label, t_state) in s.t.iter().enumerate() {
+            for (label, t_state) in self.transitions(s.id) {
                 if let Some(t_state) = t_state {
-                    dot.push_str(&format!(
-                        "s{} -> s{} [label = \"{}\"]\n",
-                        s.id, t_state, label
-                    ));
+                    writeln!(
+                        out,
+                        "{}s{} -> {}s{} [label = \"{}\"]",
+                        prefix, s.id, prefix, t_state, label
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 他のグラフと一つの dot ファイルに並べるための subgraph を書く
+    // 状態の名前には name を前に付けて区別する
+    pub fn write_dot_cluster<W: Write>(
+        &self,
+        out: &mut W,
+        name: &str,
+        label: &str,
+    ) -> io::Result<()> {
+        writeln!(out, "subgraph cluster_{} {{\nlabel = \"{}\";", name, label)?;
+        writeln!(out, "{}_start [label = \"\" shape = plaintext];", name)?;
+        out.write_all(b"node [shape = doublecircle]")?;
+        for ac_state in self.states.iter().filter(|&s| s.accept) {
+            write!(out, " {}_s{}", name, ac_state.id)?;
+        }
+        out.write_all(b";\nnode [shape = circle];\n")?;
+        writeln!(out, "{}_start -> {}_s{} [label = \"start\"]", name, name, self.start)?;
+        self.write_dot_edges(out, &format!("{}_", name))?;
+        out.write_all(b"}\n")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = BufWriter::new(fs::File::create(path)?);
+        self.write_dot(&mut f)?;
+        f.flush()
+    }
+}
+
+// serde feature での保存形式。遷移は 256 要素の表ではなく (入力, 遷移先) の組で持つ
+// 状態番号は並びの位置なので書き出さず、注釈は保存しない
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SerdeState {
+    accept: bool,
+    patterns: Vec<usize>,
+    t: Vec<(u8, usize)>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SerdeDfa {
+    start: usize,
+    states: Vec<SerdeState>,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Dfa {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let states = self
+            .states
+            .iter()
+            .map(|state| SerdeState {
+                accept: state.accept,
+                patterns: state.patterns.clone(),
+                t: self
+                    .transitions(state.id)
+                    .filter_map(|(c, next)| next.map(|next| (c, next)))
+                    .collect(),
+            })
+            .collect();
+        SerdeDfa {
+            start: self.start,
+            states,
+        }
+        .serialize(serializer)
+    }
+}
+
+// 読み込んだ DFA は validate を通してから返す
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Dfa {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = SerdeDfa::deserialize(deserializer)?;
+        let mut dfa = Dfa::new();
+        for (id, raw_state) in raw.states.into_iter().enumerate() {
+            let state = dfa.new_state(raw_state.accept);
+            state.patterns = raw_state.patterns;
+            for (c, next) in raw_state.t {
+                state.t[c as usize] = Some(next);
+            }
+            debug_assert_eq!(state.id, id);
+        }
+        dfa.start = raw.start;
+        dfa.validate().map_err(D::Error::custom)?;
+        dfa.compact_classes();
+        Ok(dfa)
+    }
+}
+
+// 遷移や状態を直接編集して DFA を組み立てる
+// 組み立てている間は全てのバイトを別々の列に持ち、finish でバイトの類にまとめる
+#[derive(Debug, Default)]
+pub struct DfaBuilder {
+    states: Vec<State>,
+}
+
+impl From<Dfa> for DfaBuilder {
+    fn from(mut dfa: Dfa) -> Self {
+        dfa.normalize_start();
+        dfa.expand_classes();
+        DfaBuilder { states: dfa.states }
+    }
+}
+
+impl DfaBuilder {
+    pub fn new() -> Self {
+        DfaBuilder { states: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    fn check_state(&self, id: usize) -> Result<(), Error> {
+        if id < self.states.len() {
+            Ok(())
+        } else {
+            Err(Error::InvalidAutomaton(format!("state {} does not exist", id)))
+        }
+    }
+
+    pub fn add_state(&mut self, accept: bool) -> usize {
+        let id = self.states.len();
+        self.states.push(State::new(id, accept, 256));
+        id
+    }
+
+    // 状態を削除し、それより後ろの状態の番号を詰める (削除した状態への遷移も消える)
+    pub fn remove_state(&mut self, id: usize) -> Result<(), Error> {
+        self.check_state(id)?;
+        self.states.remove(id);
+        for (new_id, state) in self.states.iter_mut().enumerate() {
+            state.id = new_id;
+            for t in state.t.iter_mut() {
+                *t = match *t {
+                    Some(next) if next == id => None,
+                    Some(next) if next > id => Some(next - 1),
+                    t => t,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_accept(&mut self, id: usize, accept: bool) -> Result<(), Error> {
+        self.check_state(id)?;
+        self.states[id].accept = accept;
+        Ok(())
+    }
+
+    pub fn set_transition(&mut self, from: usize, c: u8, to: usize) -> Result<(), Error> {
+        self.check_state(from)?;
+        self.check_state(to)?;
+        self.states[from].t[c as usize] = Some(to);
+        Ok(())
+    }
+
+    pub fn remove_transition(&mut self, from: usize, c: u8) -> Result<(), Error> {
+        self.check_state(from)?;
+        self.states[from].t[c as usize] = None;
+        Ok(())
+    }
+
+    // 開始状態があり、全ての遷移先が存在することを確かめて DFA を返す
+    pub fn finish(self) -> Result<Dfa, Error> {
+        let mut dfa = Dfa::new();
+        dfa.state_num = self.states.len();
+        dfa.states = self.states;
+        dfa.validate()?;
+        dfa.compact_classes();
+        Ok(dfa)
+    }
+}
+
+impl Dfa {
+    // 遷移を逆向きにして部分集合構成をした DFA。元の受理状態の集合が開始状態になる
+    // 開始状態を NFA の ε 遷移で表すと開始状態の集合にだけ余分な状態が入るので、直接集合で作る
+    fn reverse_determinize(&self) -> Result<Dfa, Error> {
+        let classes = self.classes.len();
+        let mut inverse = vec![vec![Vec::new(); classes]; self.states.len()];
+        for (p, state) in self.states.iter().enumerate() {
+            for (class, next) in state.t.iter().enumerate() {
+                if let Some(next) = *next {
+                    inverse[next][class].push(p);
+                }
+            }
+        }
+
+        let mut dfa = Dfa::with_classes(self.classes.clone());
+        let start: Vec<usize> = self.accepting_states().collect();
+        let mut set_to_state: HashMap<Vec<usize>, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        set_to_state.insert(start.clone(), 0);
+        queue.push_back(start);
+        while let Some(set) = queue.pop_front() {
+            let id = dfa.new_state(set.binary_search(&self.start).is_ok()).id;
+            let mut targets = vec![None; classes];
+            for (class, target) in targets.iter_mut().enumerate() {
+                let mut next: Vec<usize> =
+                    set.iter().flat_map(|&q| inverse[q][class].iter().cloned()).collect();
+                if next.is_empty() {
+                    continue;
+                }
+                next.sort_unstable();
+                next.dedup();
+                let next_id = match set_to_state.get(&next) {
+                    Some(&next_id) => next_id,
+                    None => {
+                        if set_to_state.len() == STATE_LIMIT {
+                            return Err(Error::SizeLimit(STATE_LIMIT));
+                        }
+                        let next_id = set_to_state.len();
+                        set_to_state.insert(next.clone(), next_id);
+                        queue.push_back(next);
+                        next_id
+                    }
+                };
+                *target = Some(next_id);
+            }
+            dfa.states[id].t = targets;
+        }
+        Ok(dfa)
+    }
+
+    /// 逆向きにして決定化することを 2 回繰り返す Brzozowski の最小化。
+    /// 到達できない状態と受理状態へ行けない状態は残らないため、`minimize` の結果からそれらを
+    /// 除いたものと同じ形になる。注釈と、状態ごとのパターンの番号は引き継がない。
+    pub fn minimize_brzozowski(&mut self) -> Result<(), Error> {
+        let _span = trace_span!(DEBUG, "minimize_brzozowski", states = self.states.len());
+        self.annotations.clear();
+        if self.states.is_empty() {
+            return Ok(());
+        }
+        let mut dfa = self.reverse_determinize()?.reverse_determinize()?;
+        dfa.compact_classes();
+        self.states = dfa.states;
+        self.state_num = dfa.state_num;
+        self.start = dfa.start;
+        self.classes = dfa.classes;
+        debug_validate!(self, "Brzozowski minimization");
+        Ok(())
+    }
+
+    /// `self` を変えずに、`minimize` で同じ言語を受理する状態をまとめた DFA を返す。
+    /// 注釈は引き継がない。
+    pub fn minimized(&self) -> Dfa {
+        let mut dfa = Dfa {
+            states: self.states.clone(),
+            state_num: self.state_num,
+            start: self.start,
+            classes: self.classes.clone(),
+            annotations: Annotations::default(),
+        };
+        dfa.minimize();
+        dfa
+    }
+
+    /// Hopcroft の分割の細分化で、同じ言語を受理する状態を一つにまとめる。
+    /// 各同値類は番号の最も小さい状態が代表し、新しい番号は代表の番号の順に振る。
+    ///
+    /// 遷移がないことと、受理状態へ行けない状態へ遷移することは区別したままにする。
+    /// そのため受理状態へ行けない状態が残っていると、結果は状態数最小とは限らない。
+    pub fn minimize(&mut self) {
+        let _span = trace_span!(DEBUG, "minimize", states = self.states.len());
+        self.annotations.clear();
+        if self.states.len() < 2 {
+            return;
+        }
+        let n = self.states.len();
+        // 遷移のないところは n 番の吸い込み状態へ行くものとして全域の DFA にする
+        // 遷移がないことと、受理状態へ行けない状態へ遷移することは区別するので、吸い込み状態は
+        // 初めから他の状態と別の類に置く
+        let sink = n;
+        let next = |q: usize, a: usize| -> usize {
+            if q == sink {
+                return sink;
+            }
+            self.states[q].t[a].unwrap_or(sink)
+        };
+        let mut inverse = vec![vec![Vec::new(); n + 1]; self.classes.len()];
+        for (a, inverse) in inverse.iter_mut().enumerate() {
+            for q in 0..=n {
+                inverse[next(q, a)].push(q);
+            }
+        }
+
+        // 受理の有無と一致するパターンで最初の分割を作る
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut initial: HashMap<(bool, &[usize]), usize> = HashMap::new();
+        for (q, state) in self.states.iter().enumerate() {
+            let key = (state.accept, &state.patterns[..]);
+            let group = *initial.entry(key).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(q);
+        }
+        groups.push(vec![sink]);
+
+        // 各類の状態は elems の連続した範囲 blocks[block] に置き、状態の位置を pos に持つ
+        // 類を分けるときは分ける状態を範囲の先頭へ寄せるだけなので、手間はその数に比例する
+        let mut block_of = vec![0; n + 1];
+        let mut elems = Vec::with_capacity(n + 1);
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        for (block, group) in groups.into_iter().enumerate() {
+            for &q in group.iter() {
+                block_of[q] = block;
+            }
+            let start = elems.len();
+            elems.extend(group);
+            blocks.push((start, elems.len()));
+        }
+        let mut pos = vec![0; n + 1];
+        for (i, &q) in elems.iter().enumerate() {
+            pos[q] = i;
+        }
+
+        // (類, バイトの類) が作業列にあるかを in_work[類 * classes + バイトの類] に持つ
+        let classes = self.classes.len();
+        let mut work: VecDeque<(usize, usize)> = VecDeque::new();
+        let mut in_work = vec![true; blocks.len() * classes];
+        for block in 0..blocks.len() {
+            work.extend((0..classes).map(|a| (block, a)));
+        }
+        while let Some((splitter, a)) = work.pop_front() {
+            in_work[splitter * classes + a] = false;
+            // splitter へ a で遷移する状態を、属する類ごとに集める
+            let mut touched: HashMap<usize, Vec<usize>> = HashMap::new();
+            let (start, end) = blocks[splitter];
+            for &q in elems[start..end].iter() {
+                for &p in inverse[a][q].iter() {
+                    touched.entry(block_of[p]).or_default().push(p);
+                }
+            }
+            let mut touched: Vec<(usize, Vec<usize>)> = touched.into_iter().collect();
+            touched.sort_unstable_by_key(|&(block, _)| block);
+            for (block, members) in touched {
+                let (start, end) = blocks[block];
+                if members.len() == end - start {
+                    continue;
+                }
+                let new_block = blocks.len();
+                for (i, &p) in members.iter().enumerate() {
+                    let (from, to) = (pos[p], start + i);
+                    elems.swap(from, to);
+                    pos[elems[from]] = from;
+                    pos[p] = to;
+                    block_of[p] = new_block;
+                }
+                let middle = start + members.len();
+                blocks[block] = (middle, end);
+                blocks.push((start, middle));
+                in_work.resize(blocks.len() * classes, false);
+                let new_is_smaller = middle - start <= end - middle;
+                for b in 0..classes {
+                    let smaller = if in_work[block * classes + b] || new_is_smaller {
+                        new_block
+                    } else {
+                        block
+                    };
+                    if !in_work[smaller * classes + b] {
+                        in_work[smaller * classes + b] = true;
+                        work.push_back((smaller, b));
+                    }
+                }
+            }
+        }
+
+        // 代表 (類の中で最も小さい番号) の順に新しい番号を振る
+        let mut new_id: Vec<Option<usize>> = vec![None; blocks.len()];
+        let mut states = Vec::new();
+        for q in 0..n {
+            if new_id[block_of[q]].is_none() {
+                new_id[block_of[q]] = Some(states.len());
+                let mut state = self.states[q].clone();
+                state.id = states.len();
+                states.push(state);
+            }
+        }
+        if states.len() == n {
+            self.compact_classes();
+            return;
+        }
+        for state in states.iter_mut() {
+            for t in state.t.iter_mut() {
+                if let Some(next) = *t {
+                    *t = new_id[block_of[next]];
+                }
+            }
+        }
+        self.start = new_id[block_of[self.start]].unwrap();
+        self.states = states;
+        self.state_num = self.states.len();
+        // 状態をまとめると遷移先の同じバイトが増えることがある
+        self.compact_classes();
+        debug_validate!(self, "minimization");
+        trace_event!("minimized", states = self.states.len());
+    }
+
+    // 状態の対ごとに区別できるかを表に書き込んでいく以前の最小化 (O(n²·Σ))
+    // minimize と同じ結果になることをテストで確かめる
+    #[cfg(test)]
+    fn minimize_pairwise(&mut self) {
+        self.annotations.clear();
+        if self.states.len() < 2 {
+            return;
+        }
+        let mut distinction_table = vec![Vec::new(); self.states.len()];
+        for (i, row) in distinction_table.iter_mut().enumerate().take(self.states.len() - 1) {
+            for j in ((i + 1)..self.states.len()).rev() {
+                let (p, q) = (&self.states[i], &self.states[j]);
+                row.push(p.accept != q.accept || p.patterns != q.patterns);
+            }
+        }
+
+        let mut distinction_flag = true;
+        while distinction_flag {
+            distinction_flag = false;
+            for i in 0..(self.states.len() - 1) {
+                for j in (i + 1)..self.states.len() {
+                    if !distinction_table[i][self.states.len() - j - 1] {
+                        for c in 0..self.classes.len() {
+                            let mut n1 = self.states[i].t[c];
+                            let mut n2 = self.states[j].t[c];
+                            if n1 != n2 {
+                                if n1 > n2 {
+                                    ::std::mem::swap(&mut n1, &mut n2);
+                                };
+                                if n1.is_none() || n2.is_none() || distinction_table[n1.unwrap()]
+                                    [self.states.len() - n2.unwrap() - 1]
+                                {
+                                    distinction_flag = true;
+                                    distinction_table[i][self.states.len() - j - 1] = true;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut swap_map: HashMap<usize, usize> = HashMap::new();
+        for (i, row) in distinction_table.iter().enumerate() {
+            for j in (i + 1)..self.states.len() {
+                if !row[self.states.len() - j - 1] {
+                    swap_map.entry(j).or_insert(i);
+                }
+            }
+        }
+
+        if swap_map.is_empty() {
+            return;
+        }
+
+        let minimum_size = self.states.len() - swap_map.len();
+        let mut replace_map = vec![0; self.states.len()];
+        let mut d = 0;
+        for s in 0..self.states.len() {
+            if !swap_map.contains_key(&s) {
+                replace_map[s] = d;
+                d += 1;
+                if s != replace_map[s] {
+                    self.states.swap(replace_map[s], s);
+                    self.states[replace_map[s]].id = replace_map[s];
+                }
+            } else {
+                replace_map[s] = replace_map[swap_map[&s]];
+            }
+        }
+
+        for state in self.states.iter_mut().take(minimum_size) {
+            for next in state.t.iter_mut().flatten() {
+                *next = replace_map[*next];
+            }
+        }
+        drop(self.states.drain(minimum_size..));
+        self.start = replace_map[self.start];
+        self.state_num = self.states.len();
+        debug_validate!(self, "minimization");
+        trace_event!("minimized", states = self.states.len());
+    }
+}
+
+impl Dfa {
+    /// `other` から `self` への準同型写像を探す。
+    /// 見つかった場合は `other` の各状態の写像先を返す (到達不能な状態は `None`)。
+    pub fn homomorphism_from(&self, other: &Dfa) -> Option<Vec<Option<usize>>> {
+        if self.states.is_empty() || other.states.is_empty() {
+            return None;
+        }
+        let mut map: Vec<Option<usize>> = vec![None; other.states.len()];
+        let mut queue = VecDeque::new();
+        map[other.start] = Some(self.start);
+        queue.push_back(other.start);
+
+        while let Some(q) = queue.pop_front() {
+            let h = map[q].unwrap();
+            if other.states[q].accept != self.states[h].accept {
+                return None;
+            }
+            for c in 0..=255u8 {
+                match (other.transition(q, c), self.transition(h, c)) {
+                    (None, None) => {}
+                    (Some(next), Some(h_next)) => match map[next] {
+                        Some(mapped) if mapped != h_next => return None,
+                        Some(_) => {}
+                        None => {
+                            map[next] = Some(h_next);
+                            queue.push_back(next);
+                        }
+                    },
+                    _ => return None,
+                }
+            }
+        }
+        Some(map)
+    }
+
+    /// 全ての状態が全ての入力バイトについて遷移先を持つかを判定する。
+    pub fn is_total(&self) -> bool {
+        self.states.iter().all(|s| s.t.iter().all(|t| t.is_some()))
+    }
+
+    /// 受理されない最短の文字列を返す。全ての文字列を受理する場合は `None`。
+    pub fn non_universal_witness(&self) -> Option<Vec<u8>> {
+        if self.states.is_empty() {
+            return Some(Vec::new());
+        }
+        // 遷移が無いところは番号 states.len() の受理しない状態へ行くものとして探す
+        // 同じ類のバイトは最も小さいものだけを使う
+        let dead = self.states.len();
+        let representatives = self.classes.representatives();
+        let mut prev: Vec<Option<(usize, u8)>> = vec![None; dead + 1];
+        let mut visited = vec![false; dead + 1];
+        let mut queue = VecDeque::new();
+        visited[self.start] = true;
+        queue.push_back(self.start);
+
+        while let Some(id) = queue.pop_front() {
+            // 取り出した状態が受理しないなら、そこまでの経路が最短の文字列になる
+            if id == dead || !self.states[id].accept {
+                let mut witness = Vec::new();
+                let mut cur = id;
+                while let Some((from, c)) = prev[cur] {
+                    witness.push(c);
+                    cur = from;
+                }
+                witness.reverse();
+                return Some(witness);
+            }
+            for (next, &c) in self.states[id].t.iter().zip(representatives.iter()) {
+                let next = next.unwrap_or(dead);
+                if !visited[next] {
+                    visited[next] = true;
+                    prev[next] = Some((id, c));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// 全ての文字列を受理するかを判定する。
+    pub fn is_universal(&self) -> bool {
+        self.non_universal_witness().is_none()
+    }
+
+    // byte を使わずに受理状態へ到達できるか
+    fn accepts_without(&self, byte: u8) -> bool {
+        if self.states.is_empty() {
+            return false;
+        }
+        // byte の類に他のバイトがあれば、その類の遷移は byte を使わずに通れる
+        let class = self.classes.get(byte);
+        let blocked = self.classes.members(class).len() == 1;
+        let mut visited = vec![false; self.states.len()];
+        let mut stack = vec![self.start];
+        visited[self.start] = true;
+        while let Some(id) = stack.pop() {
+            if self.states[id].accept {
+                return true;
+            }
+            for (c, next) in self.states[id].t.iter().enumerate() {
+                if let Some(next) = *next {
+                    if (!blocked || c != class) && !visited[next] {
+                        visited[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// 受理される全ての文字列に必ず含まれるバイトの集合を返す。
+    pub fn required_bytes(&self) -> ByteSet {
+        let mut required = [false; 256];
+        for (c, r) in required.iter_mut().enumerate() {
+            *r = !self.accepts_without(c as u8);
+        }
+        required
+    }
+
+    /// 開始状態から到達可能で、かつ受理状態へ到達可能な状態に true を立てた表を返す。
+    pub fn live_states(&self) -> Vec<bool> {
+        struct Reachability {
+            reachable: Vec<bool>,
+            reverse: Vec<Vec<usize>>,
+        }
+
+        impl Visitor for Reachability {
+            fn enter_state(&mut self, id: usize) {
+                self.reachable[id] = true;
+            }
+
+            fn on_transition(&mut self, from: usize, _label: Option<u8>, to: usize) {
+                self.reverse[to].push(from);
+            }
+        }
+
+        let n = self.states.len();
+        let mut reachability = Reachability {
+            reachable: vec![false; n],
+            reverse: vec![Vec::new(); n],
+        };
+        walk_dfs(self, &mut reachability);
+        let Reachability { reachable, reverse } = reachability;
+
+        let mut live = vec![false; n];
+        let mut stack: Vec<usize> = (0..n)
+            .filter(|&id| reachable[id] && self.states[id].accept)
+            .collect();
+        for id in stack.iter() {
+            live[*id] = true;
+        }
+        while let Some(id) = stack.pop() {
+            for prev in reverse[id].iter() {
+                if !live[*prev] {
+                    live[*prev] = true;
+                    stack.push(*prev);
+                }
+            }
+        }
+        live
+    }
+
+    /// 状態除去法で受理言語を表すパターンを返す。受理言語が空か空文字列だけなら `None`。
+    /// 表示可能な ASCII 以外のバイトは `\xHH` と書くため、そのままでは構文解析できない。
+    pub fn to_pattern(&self) -> Option<String> {
+        elimination::to_pattern(self)
+    }
+
+    /// 長さ `len` のバイト列のうち受理されるものの割合。
+    /// 一様に選んだバイト列が受理される確率として、開始状態から 1 バイトずつ確率を配る。
+    pub fn density(&self, len: usize) -> f64 {
+        if self.states.is_empty() {
+            return 0.0;
+        }
+        let live = self.live_states();
+        let sizes = self.classes.sizes();
+        let mut probability = vec![0.0; self.states.len()];
+        probability[self.start] = 1.0;
+        for _ in 0..len {
+            let mut next = vec![0.0; self.states.len()];
+            for (id, &p) in probability.iter().enumerate().filter(|&(_, &p)| p > 0.0) {
+                for (to, &size) in self.states[id].t.iter().zip(sizes.iter()) {
+                    match *to {
+                        Some(to) if live[to] => next[to] += p * size as f64 / 256.0,
+                        _ => {}
+                    }
+                }
+            }
+            probability = next;
+        }
+        (0..self.states.len())
+            .filter(|&id| self.states[id].accept)
+            .map(|id| probability[id])
+            .sum()
+    }
+
+    // counts[r][id] = 状態 id から読んで受理される長さ r のバイト列の数 (r <= len)
+    // 数えきれないほど多いときは None
+    fn suffix_counts(&self, len: usize) -> Option<Vec<Vec<u128>>> {
+        let sizes = self.classes.sizes();
+        let mut counts = Vec::with_capacity(len + 1);
+        counts.push(self.states.iter().map(|s| s.accept as u128).collect::<Vec<_>>());
+        for r in 1..=len {
+            let mut row = Vec::with_capacity(self.states.len());
+            for state in self.states.iter() {
+                let mut n: u128 = 0;
+                for (next, &size) in state.t.iter().zip(sizes.iter()) {
+                    if let Some(next) = *next {
+                        n = n.checked_add(counts[r - 1][next].checked_mul(size as u128)?)?;
+                    }
+                }
+                row.push(n);
+            }
+            counts.push(row);
+        }
+        Some(counts)
+    }
+
+    /// 受理される長さ `len` のバイト列の数。`u128` に収まらなければ `None`。
+    pub fn count_accepted(&self, len: usize) -> Option<u128> {
+        if self.states.is_empty() {
+            return Some(0);
+        }
+        Some(self.suffix_counts(len)?[len][self.start])
+    }
+
+    /// 受理される長さ `len` のバイト列を辞書順に並べたときの `k` 番目 (0 から数える)。
+    /// `k` が数以上か、数が `u128` に収まらなければ `None`。
+    pub fn unrank(&self, len: usize, k: u128) -> Option<Vec<u8>> {
+        if self.states.is_empty() {
+            return None;
+        }
+        let counts = self.suffix_counts(len)?;
+        if k >= counts[len][self.start] {
+            return None;
+        }
+        let mut k = k;
+        let mut word = Vec::with_capacity(len);
+        let mut state = self.start;
+        for r in (0..len).rev() {
+            for (c, next) in self.transitions(state) {
+                if let Some(next) = next {
+                    if k < counts[r][next] {
+                        word.push(c);
+                        state = next;
+                        break;
+                    }
+                    k -= counts[r][next];
+                }
+            }
+        }
+        Some(word)
+    }
+
+    /// `unrank` の逆。`word` が受理される同じ長さのバイト列の中で辞書順に何番目か。
+    /// 受理されなければ `None`。
+    pub fn rank(&self, word: &[u8]) -> Option<u128> {
+        if self.states.is_empty() {
+            return None;
+        }
+        let counts = self.suffix_counts(word.len())?;
+        let mut rank: u128 = 0;
+        let mut state = self.start;
+        for (i, &c) in word.iter().enumerate() {
+            let r = word.len() - i - 1;
+            for (_, next) in self.transitions(state).take(c as usize) {
+                if let Some(next) = next {
+                    rank += counts[r][next];
+                }
+            }
+            state = self.transition(state, c)?;
+        }
+        if self.states[state].accept {
+            Some(rank)
+        } else {
+            None
+        }
+    }
+
+    /// 受理される文字列の数が長さに対して増える速さ (1 バイトあたりのビット数)。
+    /// 生きている状態の間の遷移の数を並べた行列のスペクトル半径の対数で、
+    /// 有限の言語なら 0、`.*` のように全てを受理するなら 8 になる。
+    pub fn entropy_rate(&self) -> f64 {
+        let live = self.live_states();
+        let ids: Vec<usize> = (0..self.states.len()).filter(|&id| live[id]).collect();
+        if ids.is_empty() {
+            return 0.0;
+        }
+        // A + I のべき乗法で A のスペクトル半径を求める (I を足すと周期的な閉路でも収束する)
+        let sizes = self.classes.sizes();
+        let mut vector = vec![1.0; self.states.len()];
+        let mut radius = 0.0;
+        for _ in 0..10_000 {
+            let mut next = vec![0.0; self.states.len()];
+            for &id in ids.iter() {
+                next[id] = vector[id];
+                for (to, &size) in self.states[id].t.iter().zip(sizes.iter()) {
+                    match *to {
+                        Some(to) if live[to] => next[id] += vector[to] * size as f64,
+                        _ => {}
+                    }
+                }
+            }
+            let norm = ids.iter().map(|&id| next[id]).fold(0.0, f64::max);
+            for value in next.iter_mut() {
+                *value /= norm;
+            }
+            let converged = (norm - 1.0 - radius).abs() < 1e-12;
+            radius = norm - 1.0;
+            vector = next;
+            if converged {
+                break;
+            }
+        }
+        if radius < 1.0 {
+            return 0.0;
+        }
+        radius.log2()
+    }
+
+    /// 受理される文字列を乱数で一つ選ぶ。受理状態ではコイン投げで止まり、
+    /// 長さが `max_len` に達した後は受理状態への最短経路をたどる。受理言語が空なら `None`。
+    pub fn sample(&self, rng: &mut Rng, max_len: usize) -> Option<Vec<u8>> {
+        let live = self.live_states();
+        if self.states.is_empty() || !live[self.start] {
+            return None;
+        }
+        // 受理状態までの距離 (受理状態から逆向きの幅優先探索)
+        let mut reverse: Vec<Vec<usize>> = vec![Vec::new(); self.states.len()];
+        for (id, state) in self.states.iter().enumerate() {
+            for next in state.t.iter().flatten() {
+                reverse[*next].push(id);
+            }
+        }
+        let mut distance: Vec<Option<usize>> = vec![None; self.states.len()];
+        let mut queue = VecDeque::new();
+        for id in self.accepting_states() {
+            distance[id] = Some(0);
+            queue.push_back(id);
+        }
+        while let Some(id) = queue.pop_front() {
+            for &prev in reverse[id].iter() {
+                if distance[prev].is_none() {
+                    distance[prev] = Some(distance[id].unwrap() + 1);
+                    queue.push_back(prev);
+                }
+            }
+        }
+
+        let mut word = Vec::new();
+        let mut state = self.start;
+        loop {
+            if self.states[state].accept && (word.len() >= max_len || rng.coin()) {
+                return Some(word);
+            }
+            let candidates: Vec<(u8, usize)> = self
+                .transitions(state)
+                .filter_map(|(c, next)| match next {
+                    Some(next) if live[next] => Some((c, next)),
+                    _ => None,
+                })
+                .filter(|&(_, next)| {
+                    word.len() < max_len || distance[next] < distance[state]
+                })
+                .collect();
+            if candidates.is_empty() {
+                return Some(word);
+            }
+            let &(c, next) = rng.choose(&candidates);
+            word.push(c);
+            state = next;
+        }
+    }
+
+    /// 受理される最短の文字列 (同じ長さの中では辞書順で最小のもの)。受理言語が空なら `None`。
+    pub fn shortest_accepted(&self) -> Option<Vec<u8>> {
+        if self.states.is_empty() {
+            return None;
+        }
+        // 同じ類のバイトは最も小さいものだけを使う
+        let representatives = self.classes.representatives();
+        let mut prev: Vec<Option<(usize, u8)>> = vec![None; self.states.len()];
+        let mut visited = vec![false; self.states.len()];
+        let mut queue = VecDeque::new();
+        visited[self.start] = true;
+        queue.push_back(self.start);
+        while let Some(id) = queue.pop_front() {
+            if self.states[id].accept {
+                let mut word = Vec::new();
+                let mut cur = id;
+                while let Some((from, c)) = prev[cur] {
+                    word.push(c);
+                    cur = from;
+                }
+                word.reverse();
+                return Some(word);
+            }
+            for (next, &c) in self.states[id].t.iter().zip(representatives.iter()) {
+                if let Some(next) = *next {
+                    if !visited[next] {
+                        visited[next] = true;
+                        prev[next] = Some((id, c));
+                        queue.push_back(next);
+                    }
                 }
             }
         }
-        dot.push_str("}");
-        dot
+        None
     }
 
-    pub fn write(&self, file_name: &str) {
-        let dot = self.dot();
-        let mut f = BufWriter::new(fs::File::create(file_name).unwrap());
-        f.write(dot.as_bytes()).ok();
+    /// 受理される最短の文字列の長さ。受理言語が空なら `None`。
+    pub fn min_match_len(&self) -> Option<usize> {
+        if self.states.is_empty() {
+            return None;
+        }
+        let mut dist = vec![None; self.states.len()];
+        let mut queue = VecDeque::new();
+        dist[self.start] = Some(0);
+        queue.push_back(self.start);
+        while let Some(id) = queue.pop_front() {
+            let d = dist[id].unwrap();
+            if self.states[id].accept {
+                return Some(d);
+            }
+            for next in self.states[id].t.iter().flatten() {
+                if dist[*next].is_none() {
+                    dist[*next] = Some(d + 1);
+                    queue.push_back(*next);
+                }
+            }
+        }
+        None
     }
-}
 
-impl Dfa {
-    pub fn minimize(&mut self) {
-        let mut distinction_table = vec![Vec::new(); self.states.len()];
-        for i in 0..self.states.len() - 1 {
-            for j in ((i + 1)..self.states.len()).rev() {
-                distinction_table[i].push(self.states[i].accept != self.states[j].accept);
+    /// 受理される最長の文字列の長さ。長さに上限がない場合と受理言語が空の場合は `None`。
+    pub fn max_match_len(&self) -> Option<usize> {
+        let live = self.live_states();
+        if live.is_empty() || !live[self.start] {
+            return None;
+        }
+        // 0: 未訪問, 1: 探索中, 2: 完了
+        let mut mark = vec![0u8; self.states.len()];
+        let mut longest: Vec<Option<usize>> = vec![None; self.states.len()];
+        let mut stack = vec![(self.start, false)];
+        while let Some((id, done)) = stack.pop() {
+            if done {
+                let mut best = if self.states[id].accept { Some(0) } else { None };
+                for next in self.states[id].t.iter().flatten() {
+                    if let Some(l) = longest[*next] {
+                        best = Some(best.map_or(l + 1, |b: usize| b.max(l + 1)));
+                    }
+                }
+                longest[id] = best;
+                mark[id] = 2;
+                continue;
+            }
+            if mark[id] != 0 {
+                continue;
+            }
+            mark[id] = 1;
+            stack.push((id, true));
+            for next in self.states[id].t.iter().flatten() {
+                if !live[*next] {
+                    continue;
+                }
+                match mark[*next] {
+                    0 => stack.push((*next, false)),
+                    1 => return None,
+                    _ => {}
+                }
             }
         }
+        longest[self.start]
+    }
 
-        let mut distinction_flag = true;
-        while distinction_flag {
-            distinction_flag = false;
-            for i in 0..(self.states.len() - 1) {
-                for j in (i + 1)..self.states.len() {
-                    if !distinction_table[i][self.states.len() - j - 1] {
-                        for c in 0..=255 {
-                            let mut n1 = self.states[i].t[c];
-                            let mut n2 = self.states[j].t[c];
-                            if n1 != n2 {
-                                if n1 > n2 {
-                                    mem::swap(&mut n1, &mut n2);
-                                };
-                                if n1.is_none() || n2.is_none() || distinction_table[n1.unwrap()]
-                                    [self.states.len() - n2.unwrap() - 1]
-                                {
-                                    distinction_flag = true;
-                                    distinction_table[i][self.states.len() - j - 1] = true;
-                                    break;
-                                }
-                            }
-                        }
+    /// 受理言語が `limit` 個以下の文字列からなる有限集合ならば、その全ての文字列を辞書順で返す。
+    pub fn finite_language(&self, limit: usize) -> Option<Vec<Vec<u8>>> {
+        let live = self.live_states();
+        if live.is_empty() || !live[self.start] {
+            return Some(Vec::new());
+        }
+        self.max_match_len()?;
+        let mut words = Vec::new();
+        let mut stack = vec![(self.start, Vec::new())];
+        while let Some((id, word)) = stack.pop() {
+            if self.states[id].accept {
+                if words.len() == limit {
+                    return None;
+                }
+                words.push(word.clone());
+            }
+            for (c, next) in self.transitions(id).rev() {
+                if let Some(next) = next {
+                    if live[next] {
+                        let mut next_word = word.clone();
+                        next_word.push(c);
+                        stack.push((next, next_word));
                     }
                 }
             }
         }
+        Some(words)
+    }
 
-        let mut swap_map: HashMap<usize, usize> = HashMap::new();
-        for i in 0..self.states.len() {
-            for j in (i + 1)..self.states.len() {
-                if !swap_map.contains_key(&j) {
-                    if !distinction_table[i][self.states.len() - j - 1] {
-                        swap_map.insert(j, i);
+    /// 受理される文字列が全て ASCII のみからなるかを判定する。
+    pub fn is_ascii_only(&self) -> bool {
+        let live = self.live_states();
+        self.states.iter().filter(|s| live[s.id]).all(|s| {
+            self.transitions(s.id)
+                .skip(0x80)
+                .all(|(_, next)| next.is_none_or(|next| !live[next]))
+        })
+    }
+
+    // bytes の遷移を ε 遷移とみなした閉包
+    fn erased_closure(&self, state_set: &StateSet, bytes: &ByteSet) -> StateSet {
+        let mut closure = state_set.clone();
+        let mut stack: Vec<usize> = state_set.iter().cloned().collect();
+        while let Some(id) = stack.pop() {
+            for (c, next) in self.transitions(id) {
+                if let Some(next) = next {
+                    if bytes[c as usize] && closure.insert(next) {
+                        stack.push(next);
                     }
                 }
             }
         }
+        closure
+    }
 
-        if swap_map.is_empty() {
-            return;
+    /// 受理される文字列から bytes に含まれるバイトを全て取り除いた言語の DFA を返す。
+    pub fn project_away(&self, bytes: &ByteSet) -> Dfa {
+        let mut dfa = Dfa::new();
+        if self.states.is_empty() {
+            return dfa;
         }
+        let mut queue = VecDeque::new();
+        let mut subset_to_state: SubsetMap<usize> = SubsetMap::default();
+        let start: StateSet = [self.start].iter().cloned().collect();
+        let start = self.erased_closure(&start, bytes);
+        subset_to_state.insert(start.clone(), 0);
+        queue.push_back(start);
 
-        let minimum_size = self.states.len() - swap_map.len();
-        let mut replace_map = vec![0; self.states.len()];
-        let mut d = 0;
-        for s in 0..self.states.len() {
-            if !swap_map.contains_key(&s) {
-                replace_map[s] = d;
-                d += 1;
-                if s != replace_map[s] {
-                    self.states.swap(replace_map[s], s);
-                    self.states[replace_map[s]].id = replace_map[s];
+        while let Some(subset) = queue.pop_front() {
+            let accept = subset.iter().any(|&id| self.states[id].accept);
+            let id = dfa.new_state(accept).id;
+            for c in (0..256).filter(|&c| !bytes[c]) {
+                let next: StateSet =
+                    subset.iter().filter_map(|&s| self.transition(s, c as u8)).collect();
+                if next.is_empty() {
+                    continue;
                 }
-            } else {
-                replace_map[s] = replace_map[swap_map[&s]];
+                let next = self.erased_closure(&next, bytes);
+                let next_id = match subset_to_state.get(&next) {
+                    Some(&next_id) => next_id,
+                    None => {
+                        let next_id = subset_to_state.len();
+                        subset_to_state.insert(next.clone(), next_id);
+                        queue.push_back(next);
+                        next_id
+                    }
+                };
+                dfa.states[id].t[c] = Some(next_id);
             }
         }
+        dfa.compact_classes();
+        dfa
+    }
 
-        {
-            let mut i = 0;
+    /// 2 つの言語の文字列を交互に織り交ぜた文字列全体 (シャッフル積) の DFA を返す。
+    pub fn shuffle(&self, other: &Dfa) -> Dfa {
+        let mut dfa = Dfa::new();
+        if self.states.is_empty() || other.states.is_empty() {
+            return dfa;
+        }
+        // 状態の組 (p, q) を p * m + q で表す
+        let m = other.states.len();
+        let mut queue = VecDeque::new();
+        let mut subset_to_state: SubsetMap<usize> = SubsetMap::default();
+        let start: StateSet = [self.start * m + other.start].iter().cloned().collect();
+        subset_to_state.insert(start.clone(), 0);
+        queue.push_back(start);
 
-            while self.states[i].id < minimum_size {
-                for c in 0..=255 {
-                    if let Some(n) = self.states[i].t[c] {
-                        self.states[i].t[c] = Some(replace_map[n]);
+        while let Some(subset) = queue.pop_front() {
+            let accept = subset
+                .iter()
+                .any(|&pq| self.states[pq / m].accept && other.states[pq % m].accept);
+            let id = dfa.new_state(accept).id;
+            for c in 0..256 {
+                let mut next = StateSet::new();
+                for &pq in subset.iter() {
+                    let (p, q) = (pq / m, pq % m);
+                    if let Some(p_next) = self.transition(p, c as u8) {
+                        next.insert(p_next * m + q);
                     }
+                    if let Some(q_next) = other.transition(q, c as u8) {
+                        next.insert(p * m + q_next);
+                    }
+                }
+                if next.is_empty() {
+                    continue;
                 }
-                i += 1;
+                let next_id = match subset_to_state.get(&next) {
+                    Some(&next_id) => next_id,
+                    None => {
+                        let next_id = subset_to_state.len();
+                        subset_to_state.insert(next.clone(), next_id);
+                        queue.push_back(next);
+                        next_id
+                    }
+                };
+                dfa.states[id].t[c] = Some(next_id);
             }
         }
-        drop(self.states.drain(minimum_size..));
+        dfa.compact_classes();
+        dfa
+    }
+
+    /// 両方の言語に含まれる文字列全体 (積集合) の DFA を返す。
+    pub fn intersection(&self, other: &Dfa) -> Dfa {
+        let mut dfa = Dfa::new();
+        if self.states.is_empty() || other.states.is_empty() {
+            return dfa;
+        }
+        let mut queue = VecDeque::new();
+        let mut pair_to_state: HashMap<(usize, usize), usize> = HashMap::new();
+        let start = (self.start, other.start);
+        pair_to_state.insert(start, 0);
+        queue.push_back(start);
+
+        while let Some((p, q)) = queue.pop_front() {
+            let accept = self.states[p].accept && other.states[q].accept;
+            let id = dfa.new_state(accept).id;
+            for c in 0..256 {
+                let next = match (self.transition(p, c as u8), other.transition(q, c as u8)) {
+                    (Some(p_next), Some(q_next)) => (p_next, q_next),
+                    _ => continue,
+                };
+                let next_id = match pair_to_state.get(&next) {
+                    Some(&next_id) => next_id,
+                    None => {
+                        let next_id = pair_to_state.len();
+                        pair_to_state.insert(next, next_id);
+                        queue.push_back(next);
+                        next_id
+                    }
+                };
+                dfa.states[id].t[c] = Some(next_id);
+            }
+        }
+        dfa.compact_classes();
+        dfa
+    }
+
+    /// `self` が `other` の準同型像 (商オートマトン) になっているかを判定する。
+    pub fn is_homomorphic_image_of(&self, other: &Dfa) -> bool {
+        if let Some(map) = self.homomorphism_from(other) {
+            let image: HashSet<usize> = map.into_iter().flatten().collect();
+            image.len() == self.states.len()
+        } else {
+            false
+        }
     }
 }
 
@@ -241,16 +1938,16 @@ impl Dfa {
 fn regex_accept_char() {
     let regex = "a";
     let s = "a";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
-    assert!(dfa.accept(s);)
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    assert!(dfa.accept(s));
 }
 
 #[test]
 fn regex_accept_union() {
     let regex = "a|b";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "a";
     assert!(dfa.accept(s));
     let s = "b";
@@ -260,8 +1957,8 @@ fn regex_accept_union() {
 #[test]
 fn regex_accept_concat() {
     let regex = "ab";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "ab";
     assert!(dfa.accept(s));
 }
@@ -269,8 +1966,8 @@ fn regex_accept_concat() {
 #[test]
 fn regex_accept_star() {
     let regex = "a*";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "";
     assert!(dfa.accept(s));
     let s = "a";
@@ -282,8 +1979,8 @@ fn regex_accept_star() {
 #[test]
 fn regex_accept_dot() {
     let regex = "(a.*bc|bd)";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "bd";
     assert!(dfa.accept(s));
     let s = "abc";
@@ -295,8 +1992,8 @@ fn regex_accept_dot() {
 #[test]
 fn regex_accept_01() {
     let regex = "(a|b)c";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "ac";
     assert!(dfa.accept(s));
     let s = "bc";
@@ -306,8 +2003,8 @@ fn regex_accept_01() {
 #[test]
 fn regex_accept_02() {
     let regex = "a*c";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "c";
     assert!(dfa.accept(s));
     let s = "ac";
@@ -319,8 +2016,8 @@ fn regex_accept_02() {
 #[test]
 fn regex_accept_03() {
     let regex = "(a|c)*";
-    let nfa = Nfa::re2nfa(regex);
-    let dfa = Dfa::nfa2dfa(&nfa);
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
     let s = "";
     assert!(dfa.accept(s));
     let s = "accccc";
@@ -330,3 +2027,734 @@ fn regex_accept_03() {
     let s = "aaaaaaaaaaaaaaaa";
     assert!(dfa.accept(s));
 }
+
+#[test]
+fn minimized_dfa_is_homomorphic_image() {
+    let regex = "(a|ba)*";
+    let nfa = Nfa::re2nfa(regex).unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    let mut minimized = Dfa::nfa2dfa(&nfa).unwrap();
+    minimized.minimize();
+    assert!(minimized.is_homomorphic_image_of(&dfa));
+    assert!(dfa.is_homomorphic_image_of(&dfa));
+
+    let other = Dfa::nfa2dfa(&Nfa::re2nfa("a*").unwrap()).unwrap();
+    assert!(!other.is_homomorphic_image_of(&dfa));
+}
+
+#[test]
+fn simulation_pruned_dfa_is_equivalent() {
+    for regex in &["(a|ab)*", "(a|b)*ab", "a*(a|b)"] {
+        let nfa = Nfa::re2nfa(regex).unwrap();
+        let sim = nfa.simulation_preorder();
+        for (p, row) in sim.iter().enumerate() {
+            assert!(row[p]);
+        }
+        let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+        let mut pruned = Dfa::nfa2dfa_pruned(&nfa).unwrap();
+        assert!(pruned.states.len() <= dfa.states.len());
+        dfa.minimize();
+        pruned.minimize();
+        assert!(pruned.is_homomorphic_image_of(&dfa));
+        assert!(dfa.is_homomorphic_image_of(&pruned));
+    }
+}
+
+#[test]
+fn reduced_nfa_is_equivalent() {
+    for regex in &["(a|ab)*", "(a|b)*ab", "(a.*bc|bd)"] {
+        let nfa = Nfa::re2nfa(regex).unwrap();
+        let reduced = nfa.reduce();
+        assert!(reduced.states.len() <= nfa.states.len());
+        let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+        let mut reduced_dfa = Dfa::nfa2dfa(&reduced).unwrap();
+        dfa.minimize();
+        reduced_dfa.minimize();
+        assert!(reduced_dfa.is_homomorphic_image_of(&dfa));
+        assert!(dfa.is_homomorphic_image_of(&reduced_dfa));
+    }
+}
+
+#[test]
+fn universality() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(?s).*").unwrap()).unwrap();
+    assert!(dfa.is_total());
+    assert!(dfa.is_universal());
+
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(a|b)*").unwrap()).unwrap();
+    assert!(!dfa.is_total());
+    let witness = dfa.non_universal_witness().unwrap();
+    assert_eq!(witness, vec![0]);
+
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("..*").unwrap()).unwrap();
+    assert_eq!(dfa.non_universal_witness(), Some(vec![]));
+    assert!(!dfa.accept(""));
+
+    // 遷移の欠けた状態より浅いところにある受理しない状態を先に見つける
+    let mut builder = DfaBuilder::new();
+    let (s0, s1, s2) = (builder.add_state(true), builder.add_state(true), builder.add_state(false));
+    for c in 0..=255u8 {
+        builder.set_transition(s0, c, if c == b'b' { s2 } else { s1 }).unwrap();
+    }
+    let dfa = builder.finish().unwrap();
+    assert_eq!(dfa.non_universal_witness(), Some(b"b".to_vec()));
+    assert!(!dfa.is_universal());
+}
+
+#[test]
+fn required_bytes() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(a|b)c*d").unwrap()).unwrap();
+    let required = dfa.required_bytes();
+    assert!(required[b'd' as usize]);
+    assert!(!required[b'a' as usize]);
+    assert!(!required[b'c' as usize]);
+    let mask = ByteMask::from_set(&required);
+    assert_eq!(mask.len(), required.iter().filter(|&&m| m).count());
+    assert!(mask.contains(b'd') && !mask.contains(b'a'));
+    assert!(quick_reject(&mask, b"abcc"));
+    assert!(!quick_reject(&mask, b"xxadxx"));
+    assert!(!quick_reject(&ByteMask::default(), b""));
+}
+
+#[test]
+fn match_len_bounds() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab(c|de)").unwrap()).unwrap();
+    assert_eq!(dfa.min_match_len(), Some(3));
+    assert_eq!(dfa.max_match_len(), Some(4));
+    // 長さの上限を超えて読んだ候補と、最短の一致より短い残りは探さない
+    let found: Vec<Match> = dfa.find_iter("xabdeabcab").collect();
+    assert_eq!(found, vec![Match { start: 1, end: 5 }, Match { start: 5, end: 8 }]);
+    assert_eq!(dfa.find_iter("ab").count(), 0);
+
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a(b|c)*d").unwrap()).unwrap();
+    assert_eq!(dfa.min_match_len(), Some(2));
+    assert_eq!(dfa.max_match_len(), None);
+}
+
+#[test]
+fn finite_language() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(ab|a)(c|d)").unwrap()).unwrap();
+    let words = dfa.finite_language(16).unwrap();
+    assert_eq!(
+        words,
+        vec![
+            b"abc".to_vec(),
+            b"abd".to_vec(),
+            b"ac".to_vec(),
+            b"ad".to_vec(),
+        ]
+    );
+    assert!(dfa.finite_language(3).is_none());
+    assert!(Dfa::nfa2dfa(&Nfa::re2nfa("ab*").unwrap()).unwrap().finite_language(16).is_none());
+}
+
+#[test]
+fn strip_prefix_match() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(a|b)*c").unwrap()).unwrap();
+    assert_eq!(
+        dfa.strip_prefix_match("abcabcd"),
+        Some((Match { start: 0, end: 3 }, "abcd"))
+    );
+    assert_eq!(dfa.strip_prefix_match("dabc"), None);
+}
+
+#[test]
+fn regex_accept_verbose() {
+    let regex = "(?x)
+        (a | b)*  # prefix
+        c d       # suffix
+    ";
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa(regex).unwrap()).unwrap();
+    assert!(dfa.accept("abacd"));
+    assert!(!dfa.accept("abac d"));
+}
+
+#[test]
+fn ascii_only() {
+    assert!(Dfa::nfa2dfa(&Nfa::re2nfa("(a|b)*c").unwrap()).unwrap().is_ascii_only());
+    assert!(!Dfa::nfa2dfa(&Nfa::re2nfa("a.b").unwrap()).unwrap().is_ascii_only());
+}
+
+#[test]
+fn project_away_bytes() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a-(b|c)-d").unwrap()).unwrap();
+    let mut bytes = [false; 256];
+    bytes[b'-' as usize] = true;
+    let projected = dfa.project_away(&bytes);
+    assert!(projected.accept("abd"));
+    assert!(projected.accept("acd"));
+    assert!(!projected.accept("a-bd"));
+    assert!(!projected.accept("ad"));
+}
+
+#[test]
+fn shuffle_product() {
+    let ab = Dfa::nfa2dfa(&Nfa::re2nfa("ab").unwrap()).unwrap();
+    let xy = Dfa::nfa2dfa(&Nfa::re2nfa("xy").unwrap()).unwrap();
+    let shuffled = ab.shuffle(&xy);
+    for s in &["abxy", "axby", "axyb", "xaby", "xayb", "xyab"] {
+        assert!(shuffled.accept(s));
+    }
+    assert!(!shuffled.accept("baxy"));
+    assert!(!shuffled.accept("abx"));
+}
+
+#[test]
+fn lexicographic_determinization_order() {
+    let nfa = Nfa::re2nfa("(a|b)*ab").unwrap();
+    let discovery = Dfa::nfa2dfa(&nfa).unwrap();
+    let lexicographic = Dfa::nfa2dfa_with_order(&nfa, DeterminizationOrder::Lexicographic).unwrap();
+    assert_eq!(discovery.states.len(), lexicographic.states.len());
+    assert!(lexicographic.is_homomorphic_image_of(&discovery));
+    for s in &["ab", "aab", "bab"] {
+        assert!(lexicographic.accept(s));
+    }
+    assert_eq!(
+        format!("{:?}", lexicographic.states),
+        format!(
+            "{:?}",
+            Dfa::nfa2dfa_with_order(&nfa, DeterminizationOrder::Lexicographic).unwrap().states
+        )
+    );
+}
+
+#[test]
+fn prefix_closure_by_set_accept() {
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("abc|bd").unwrap()).unwrap();
+    let accepting: Vec<usize> = dfa.accepting_states().collect();
+    assert_eq!(accepting.len(), 2);
+    let live = dfa.live_states();
+    for (id, _) in live.iter().enumerate().filter(|&(_, &live)| live) {
+        dfa.set_accept(id, true);
+    }
+    for s in &["", "a", "ab", "abc", "b", "bd"] {
+        assert!(dfa.accept(s));
+    }
+    assert!(!dfa.accept("ac"));
+}
+
+#[test]
+fn dfa_builder_edits() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab").unwrap()).unwrap();
+    let mut builder = DfaBuilder::from(dfa);
+    let extra = builder.add_state(true);
+    builder.set_transition(0, b'c', extra).unwrap();
+    assert!(builder.set_transition(0, b'd', extra + 1).is_err());
+    let dfa = builder.finish().unwrap();
+    assert!(dfa.accept("ab"));
+    assert!(dfa.accept("c"));
+
+    let mut builder = DfaBuilder::from(dfa);
+    builder.remove_state(extra).unwrap();
+    builder.remove_transition(0, b'a').unwrap();
+    let dfa = builder.finish().unwrap();
+    assert!(!dfa.accept("c"));
+    assert!(!dfa.accept("ab"));
+    assert!(DfaBuilder::new().finish().is_err());
+}
+
+#[test]
+fn dot_clusters() {
+    let nfa = Nfa::re2nfa("ab").unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    let mut out = Vec::new();
+    nfa.write_dot_cluster(&mut out, "nfa", "NFA").unwrap();
+    dfa.write_dot_cluster(&mut out, "dfa", "DFA").unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("subgraph cluster_nfa {\nlabel = \"NFA\";"));
+    assert!(out.contains("subgraph cluster_dfa {"));
+    assert!(out.contains("dfa_s0 -> dfa_s1 [label = \"97\"]"));
+    assert!(out.contains("nfa_start -> nfa_s0"));
+    assert!(!out.contains(" s0"));
+}
+
+#[test]
+fn nfa_builder() {
+    use nfa::NfaBuilder;
+    // [0-9]+ (\.[0-9]+)? を直接組み立てる
+    let mut builder = NfaBuilder::new();
+    let start = builder.add_state(false);
+    let integer = builder.add_state(true);
+    let dot = builder.add_state(false);
+    let fraction = builder.add_state(true);
+    builder.add_range(start, b'0'..=b'9', integer).unwrap();
+    builder.add_epsilon(integer, start).unwrap();
+    builder.add_transition(integer, b'.', dot).unwrap();
+    builder.add_range(dot, b'0'..=b'9', fraction).unwrap();
+    builder.add_epsilon(fraction, dot).unwrap();
+    assert!(builder.add_epsilon(fraction, 4).is_err());
+    assert!(builder.set_accept(4, true).is_err());
+    let dfa = Dfa::nfa2dfa(&builder.finish().unwrap()).unwrap();
+    for s in &["0", "42", "3.14", "1.0"] {
+        assert!(dfa.accept(s));
+    }
+    for s in &["", ".5", "1.", "1.2.3"] {
+        assert!(!dfa.accept(s));
+    }
+    assert!(NfaBuilder::new().finish().is_err());
+}
+
+#[test]
+fn annotations_follow_renumbering() {
+    let nfa = Nfa::re2nfa("(a|b)*ab").unwrap();
+    let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    let accepting: Vec<usize> = dfa.accepting_states().collect();
+    for id in accepting.iter() {
+        dfa.annotations_mut::<&str>().insert(*id, "end");
+    }
+    let order: Vec<usize> = (0..dfa.states.len()).rev().collect();
+    dfa.renumber(&order);
+    let annotations = dfa.annotations::<&str>().unwrap();
+    let accepting: Vec<usize> = dfa.accepting_states().collect();
+    assert_eq!(annotations.len(), accepting.len());
+    for id in accepting {
+        assert_eq!(annotations[&id], "end");
+    }
+    assert!(dfa.annotations::<usize>().is_none());
+    dfa.minimize();
+    assert!(dfa.annotations::<&str>().is_none());
+}
+
+#[test]
+fn find_leftmost_longest() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a(b|c)*d").unwrap()).unwrap();
+    assert_eq!(dfa.find("xxabcbdyy"), Some(Match { start: 2, end: 7 }));
+    assert_eq!(dfa.find("abab"), None);
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("b*").unwrap()).unwrap();
+    assert_eq!(dfa.find("abb"), Some(Match { start: 0, end: 0 }));
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab|bcde").unwrap()).unwrap();
+    assert_eq!(dfa.find("xbcdab"), Some(Match { start: 4, end: 6 }));
+    assert_eq!(dfa.find("abcde"), Some(Match { start: 0, end: 2 }));
+}
+
+#[test]
+fn find_iter_non_overlapping() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab|bcde").unwrap()).unwrap();
+    let matches: Vec<Match> = dfa.find_iter("abcdexbcdeab").collect();
+    assert_eq!(
+        matches,
+        vec![
+            Match { start: 0, end: 2 },
+            Match { start: 6, end: 10 },
+            Match { start: 10, end: 12 },
+        ]
+    );
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a*").unwrap()).unwrap();
+    let matches: Vec<Match> = dfa.find_iter("baa").collect();
+    assert_eq!(
+        matches,
+        vec![
+            Match { start: 0, end: 0 },
+            Match { start: 1, end: 3 },
+            Match { start: 3, end: 3 },
+        ]
+    );
+    assert_eq!(dfa.find_iter("").count(), 1);
+}
+
+#[test]
+fn plus_and_question() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab+c?").unwrap()).unwrap();
+    for s in ["ab", "abc", "abbb", "abbbc"].iter() {
+        assert!(dfa.accept(s));
+    }
+    for s in ["a", "ac", "abcc", "abcb"].iter() {
+        assert!(!dfa.accept(s));
+    }
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(ab)?c").unwrap()).unwrap();
+    assert!(dfa.accept("c"));
+    assert!(dfa.accept("abc"));
+    assert!(!dfa.accept("ac"));
+    assert!(!dfa.accept("ababc"));
+}
+
+#[test]
+fn counted_repetition() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a{3,5}b{2}").unwrap()).unwrap();
+    for s in ["aaabb", "aaaabb", "aaaaabb"].iter() {
+        assert!(dfa.accept(s));
+    }
+    for s in ["aabb", "aaaaaabb", "aaab", "aaabbb"].iter() {
+        assert!(!dfa.accept(s));
+    }
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(ab){2,}|c*{2}").unwrap()).unwrap();
+    assert!(dfa.accept("abab"));
+    assert!(dfa.accept("ababab"));
+    assert!(!dfa.accept("ab"));
+    assert!(dfa.accept("ccc"));
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a{x}").unwrap()).unwrap();
+    assert!(dfa.accept("a{x}"));
+    match Nfa::re2nfa_with_limit("(a{100}){100}", 1000) {
+        Err(Error::RepeatLimit(1000)) => {}
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    assert!(Nfa::re2nfa("a{2,1}").is_err());
+}
+
+#[test]
+fn intersection_of_languages() {
+    let even = Dfa::nfa2dfa(&Nfa::re2nfa("((a|b)(a|b))*").unwrap()).unwrap();
+    let has_b = Dfa::nfa2dfa(&Nfa::re2nfa("a*b(a|b)*").unwrap()).unwrap();
+    let dfa = even.intersection(&has_b);
+    assert!(dfa.accept("ab"));
+    assert!(dfa.accept("aaab"));
+    assert!(!dfa.accept("aa"));
+    assert!(!dfa.accept("aab"));
+}
+
+#[test]
+fn character_classes() {
+    let nfa = Nfa::re2nfa("[a-c_][0-9]*").unwrap();
+    let dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    for s in ["a", "c12", "_9", "b0123456789"].iter() {
+        assert!(dfa.accept(s));
+    }
+    for s in ["d", "1a", "", "a-"].iter() {
+        assert!(!dfa.accept(s));
+    }
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[]|-]{2}").unwrap()).unwrap();
+    assert!(dfa.accept("]-"));
+    assert!(dfa.accept("||"));
+    assert!(!dfa.accept("]"));
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[^a-c]x").unwrap()).unwrap();
+    assert!(dfa.accept("dx"));
+    assert!(dfa.accept_bytes(b"\xffx"));
+    assert!(!dfa.accept("bx"));
+    assert!(Nfa::re2nfa("[ab").is_err());
+}
+
+#[test]
+fn state_elimination_round_trip() {
+    for regex in ["a(b|c)*d", "(ab)*c?", "[0-9]+x", "a|bc|bd"].iter() {
+        let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa(regex).unwrap()).unwrap();
+        dfa.minimize();
+        let pattern = dfa.to_pattern().unwrap();
+        let mut round_trip = Dfa::nfa2dfa(&Nfa::re2nfa(&pattern).unwrap()).unwrap();
+        round_trip.minimize();
+        assert!(dfa.is_homomorphic_image_of(&round_trip), "{} -> {}", regex, pattern);
+        assert!(round_trip.is_homomorphic_image_of(&dfa), "{} -> {}", regex, pattern);
+    }
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a*").unwrap()).unwrap();
+    dfa.minimize();
+    assert_eq!(dfa.to_pattern(), Some("a*".to_owned()));
+}
+
+#[test]
+fn write_dot_to_file() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a(b|c)").unwrap()).unwrap();
+    let mut streamed = Vec::new();
+    dfa.write_dot(&mut streamed).unwrap();
+    assert_eq!(String::from_utf8(streamed).unwrap(), dfa.dot());
+    let path = ::std::env::temp_dir().join(format!("re-write-dot-{}.dot", ::std::process::id()));
+    dfa.write(&path).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), dfa.dot());
+    fs::remove_file(&path).unwrap();
+    assert!(dfa.write(path.join("missing").join("dfa.dot")).is_err());
+}
+
+#[test]
+fn shorthand_classes() {
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("\\d+\\s*\\w+").unwrap()).unwrap();
+    for s in ["1 a", "42\tfoo_9", "7x", "12 \n\r_"].iter() {
+        assert!(dfa.accept(s));
+    }
+    for s in ["a1", "1 ", " 1a", "1-a"].iter() {
+        assert!(!dfa.accept(s));
+    }
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[\\d.]+").unwrap()).unwrap();
+    assert!(dfa.accept("3.14"));
+    assert!(!dfa.accept("3,14"));
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("\\D[\\W]").unwrap()).unwrap();
+    assert!(dfa.accept("a-"));
+    assert!(!dfa.accept("1-"));
+    assert!(!dfa.accept("a_"));
+}
+
+#[test]
+fn seeded_samples() {
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a(b|c)*d").unwrap()).unwrap();
+    dfa.minimize();
+    let mut rng = Rng::new(11);
+    for _ in 0..50 {
+        let sample = dfa.sample(&mut rng, 5).unwrap();
+        assert!(dfa.accept_bytes(&sample));
+        assert!(sample.len() <= 7);
+    }
+    let first = dfa.sample(&mut Rng::new(5), 10);
+    assert_eq!(first, dfa.sample(&mut Rng::new(5), 10));
+    let empty = Dfa::nfa2dfa(&Nfa::re2nfa("a").unwrap())
+        .unwrap()
+        .intersection(&Dfa::nfa2dfa(&Nfa::re2nfa("b").unwrap()).unwrap());
+    assert_eq!(empty.sample(&mut rng, 3), None);
+}
+
+#[test]
+fn match_at_stream_offset() {
+    let m = Match { start: 2, end: 5 };
+    let base = u64::from(u32::MAX) + 10;
+    assert_eq!(
+        m.at_offset(base),
+        StreamSpan {
+            start: base + 2,
+            end: base + 5,
+        }
+    );
+}
+
+#[test]
+fn validate_invariants() {
+    let mut nfa = Nfa::re2nfa("(a|b)*abb").unwrap();
+    nfa.validate().unwrap();
+    let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    dfa.minimize();
+    dfa.validate().unwrap();
+
+    let mut broken = Dfa::nfa2dfa(&nfa).unwrap();
+    let class = broken.classes().get(b'a');
+    broken.states[1].t[class] = Some(99);
+    let e = broken.validate().unwrap_err();
+    assert!(e.to_string().contains("missing state 99"));
+    dfa.states[0].id = 3;
+    assert_eq!(
+        dfa.validate().unwrap_err().to_string(),
+        "invalid automaton: state at index 0 has id 3"
+    );
+
+    nfa.states[0].transition[256] = Some(vec![nfa.states.len()].into_iter().collect());
+    assert!(nfa.validate().is_err());
+    assert!(Dfa::new().validate().is_err());
+}
+
+#[test]
+fn language_density() {
+    let dfa = |regex: &str| Dfa::nfa2dfa(&Nfa::re2nfa(regex).unwrap()).unwrap();
+    let any = dfa(".*");
+    assert!((any.entropy_rate() - (255f64).log2()).abs() < 1e-9);
+    assert_eq!(dfa("(?s).*").entropy_rate(), 8.0);
+    assert_eq!(dfa("(?s).*").density(5), 1.0);
+    assert_eq!(dfa("abc|de").entropy_rate(), 0.0);
+    assert_eq!(dfa("abc|de").density(3), 1.0 / 256f64.powi(3));
+    assert!((dfa("[01]*").entropy_rate() - 1.0).abs() < 1e-9);
+    assert!((dfa("(ab)*").entropy_rate()).abs() < 1e-9);
+    // 4 桁の PIN は長さ 4 の列のうち 10^4 / 256^4 だけを受理する
+    assert_eq!(dfa("[0-9]{4}").density(4), 1e4 / 256f64.powi(4));
+    assert_eq!(dfa("[0-9]{4}").density(5), 0.0);
+    // フィボナッチ数列の増え方 (1 が続かない 0 と 1 の列) は黄金比になる
+    let golden = (1.0 + 5f64.sqrt()) / 2.0;
+    assert!((dfa("(0|10)*1?").entropy_rate() - golden.log2()).abs() < 1e-9);
+}
+
+#[test]
+fn minimize_separates_pattern_ids() {
+    // "ab" と "cb" の後の受理状態は遷移も受理も同じだが、一致するパターンが違うので併合しない
+    let nfas = [Nfa::re2nfa("ab|cb").unwrap(), Nfa::re2nfa("cb").unwrap()];
+    let mut dfa = Dfa::nfa2dfa(&Nfa::union_tagged(&nfas)).unwrap();
+    dfa.minimize();
+    let patterns = |s: &str| {
+        let mut state = dfa.start();
+        for &c in s.as_bytes() {
+            state = dfa.transition(state, c).unwrap();
+        }
+        dfa.states[state].patterns.clone()
+    };
+    assert_eq!(patterns("ab"), vec![0]);
+    assert_eq!(patterns("cb"), vec![0, 1]);
+    assert_eq!(dfa.states.len(), 5);
+
+    // パターンの番号がなければ従来どおり併合する
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab|cb").unwrap()).unwrap();
+    dfa.minimize();
+    assert_eq!(dfa.states.len(), 3);
+}
+
+#[test]
+fn minimize_remapped_start() {
+    // 開始状態 3 は 1 と区別できず、最小化で 1 に併合される
+    let mut builder = DfaBuilder::new();
+    for accept in [false, false, true, false].iter() {
+        builder.add_state(*accept);
+    }
+    builder.set_transition(0, b'x', 0).unwrap();
+    builder.set_transition(1, b'a', 2).unwrap();
+    builder.set_transition(3, b'a', 2).unwrap();
+    builder.set_transition(2, b'b', 2).unwrap();
+    let mut dfa = builder.finish().unwrap();
+    dfa.set_start(3);
+    assert!(dfa.accept("abb"));
+    dfa.minimize();
+    assert_eq!(dfa.states.len(), 3);
+    assert_eq!(dfa.start(), 1);
+    assert!(dfa.validate().is_ok());
+    assert!(dfa.accept("abb"));
+    assert!(!dfa.accept("x"));
+    assert_eq!(dfa.shortest_accepted(), Some(b"a".to_vec()));
+    assert_eq!(dfa.find("xxab"), Some(Match { start: 2, end: 4 }));
+    assert!(dfa.dot().contains("empty -> s1"));
+
+    let loaded = Dfa::from_bytes(&dfa.to_bytes(&Default::default()), &Default::default()).unwrap();
+    assert_eq!(loaded.start(), 0);
+    assert!(loaded.accept("abb") && !loaded.accept("x"));
+    let mut normalized = DfaBuilder::from(dfa).finish().unwrap();
+    assert!(normalized.accept("ab"));
+    normalized.set_start(5);
+    assert!(normalized.validate().is_err());
+
+    // 状態のない DFA と空文字列を受理する DFA も最小化できる
+    let mut empty = Dfa::new();
+    empty.minimize();
+    assert!(empty.states.is_empty());
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a?").unwrap()).unwrap();
+    dfa.minimize();
+    assert!(dfa.accept("") && dfa.accept("a") && !dfa.accept("aa"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    use monoid::Monoid;
+    use serde_json;
+
+    let nfa = Nfa::re2nfa("(a|b)*abb").unwrap();
+    let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    dfa.minimize();
+    let json = serde_json::to_string(&dfa).unwrap();
+    let loaded: Dfa = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.start(), dfa.start());
+    for input in &["abb", "aabb", "babb", "ab", ""] {
+        assert_eq!(loaded.accept(input), dfa.accept(input));
+    }
+
+    let json = serde_json::to_string(&nfa).unwrap();
+    let loaded: Nfa = serde_json::from_str(&json).unwrap();
+    let states = |nfa: &Nfa| Dfa::nfa2dfa(nfa).unwrap().states.len();
+    assert_eq!(states(&loaded), states(&nfa));
+
+    let monoid = Monoid::construct(&dfa);
+    let json = serde_json::to_string(&monoid).unwrap();
+    let loaded: Monoid = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+    // 存在しない状態への遷移は読み込み時に弾く
+    let broken = r#"{"start":0,"states":[{"accept":false,"patterns":[],"t":[[97,3]]}]}"#;
+    assert!(serde_json::from_str::<Dfa>(broken).is_err());
+}
+
+#[test]
+fn rank_and_unrank() {
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[a-c][0-9]|zz?").unwrap()).unwrap();
+    dfa.minimize();
+    assert_eq!(dfa.count_accepted(2), Some(31));
+    assert_eq!(dfa.count_accepted(1), Some(1));
+    assert_eq!(dfa.unrank(2, 0), Some(b"a0".to_vec()));
+    assert_eq!(dfa.unrank(2, 29), Some(b"c9".to_vec()));
+    assert_eq!(dfa.unrank(2, 30), Some(b"zz".to_vec()));
+    assert_eq!(dfa.unrank(2, 31), None);
+    assert_eq!(dfa.rank(b"b3"), Some(13));
+    assert_eq!(dfa.rank(b"b"), None);
+    for k in 0..31 {
+        assert_eq!(dfa.rank(&dfa.unrank(2, k).unwrap()), Some(k));
+    }
+
+    let mut builder = DfaBuilder::new();
+    builder.add_state(true);
+    for c in 0..=255 {
+        builder.set_transition(0, c, 0).unwrap();
+    }
+    let any = builder.finish().unwrap();
+    assert_eq!(any.count_accepted(15), Some(1 << 120));
+    assert_eq!(any.count_accepted(16), None);
+    assert_eq!(any.rank(b"\x01\x00"), Some(256));
+}
+
+#[test]
+fn minimize_keeps_pattern_ids() {
+    // 受理する言語が同じでも、一致するパターンが違う状態はまとめない
+    let nfas = [Nfa::re2nfa("ab|cb|d").unwrap(), Nfa::re2nfa("cb|d").unwrap()];
+    let dfa = Dfa::nfa2dfa(&Nfa::union_tagged(&nfas)).unwrap();
+    let patterns_after = |dfa: &Dfa, input: &str| {
+        let mut id = dfa.start;
+        for &c in input.as_bytes() {
+            id = dfa.transition(id, c).unwrap();
+        }
+        dfa.states[id].patterns.clone()
+    };
+    let mut pairwise = Dfa {
+        states: dfa.states.clone(),
+        state_num: dfa.state_num,
+        start: dfa.start,
+        classes: dfa.classes.clone(),
+        annotations: Annotations::default(),
+    };
+    pairwise.minimize_pairwise();
+    for minimized in &[dfa.minimized(), pairwise] {
+        assert!(minimized.states.len() < dfa.states.len());
+        assert_eq!(patterns_after(minimized, "ab"), vec![0]);
+        assert_eq!(patterns_after(minimized, "cb"), vec![0, 1]);
+        assert_eq!(patterns_after(minimized, "d"), vec![0, 1]);
+    }
+}
+
+#[test]
+fn hopcroft_matches_pairwise() {
+    let patterns = [
+        "(a|b)*abb",
+        "(a|b)*a(a|b){4}",
+        "[0-9]+(\\.[0-9]+)?(e[+-]?[0-9]+)?",
+        "(ab|a)(bc|c)*",
+        "x*|y*|(xy)*",
+        "(0|1(01*0)*1)*",
+        "[^a]*a[^b]*b",
+    ];
+    for pattern in patterns.iter() {
+        for order in &[DeterminizationOrder::Discovery, DeterminizationOrder::Lexicographic] {
+            let mut hopcroft =
+                Dfa::nfa2dfa_with_order(&Nfa::re2nfa(pattern).unwrap(), *order).unwrap();
+            // 開始状態が 0 番でない場合も確かめる
+            hopcroft.start = 3 % hopcroft.states.len();
+            let mut pairwise = Dfa {
+                states: hopcroft.states.clone(),
+                state_num: hopcroft.state_num,
+                start: hopcroft.start,
+                classes: hopcroft.classes.clone(),
+                annotations: Annotations::default(),
+            };
+            pairwise.minimize_pairwise();
+            hopcroft.minimize();
+            assert_eq!(hopcroft.start, pairwise.start, "{}", pattern);
+            assert_eq!(hopcroft.states.len(), pairwise.states.len(), "{}", pattern);
+            for (h, p) in hopcroft.states.iter().zip(pairwise.states.iter()) {
+                assert_eq!(h.accept, p.accept);
+                let h: Vec<_> = hopcroft.transitions(h.id).collect();
+                let p: Vec<_> = pairwise.transitions(p.id).collect();
+                assert_eq!(h, p, "{}", pattern);
+            }
+        }
+    }
+}
+
+#[test]
+fn brzozowski_agrees_with_hopcroft() {
+    let patterns = ["(a|b)*abb", "(a|b)*a(a|b){3}", "(ab|a)(bc|c)*", "x*|y*|(xy)*", "a+b+|ab"];
+    for pattern in patterns.iter() {
+        let mut brzozowski = Dfa::nfa2dfa(&Nfa::re2nfa(pattern).unwrap()).unwrap();
+        let hopcroft = brzozowski.minimized();
+        brzozowski.minimize_brzozowski().unwrap();
+        assert_eq!(brzozowski.states.len(), hopcroft.states.len(), "{}", pattern);
+        assert!(brzozowski.homomorphism_from(&hopcroft).is_some(), "{}", pattern);
+        assert!(hopcroft.homomorphism_from(&brzozowski).is_some(), "{}", pattern);
+    }
+
+    // 開始状態が 0 番でなく、受理状態へ行けない状態を持つ DFA
+    let mut builder = DfaBuilder::new();
+    let dead = builder.add_state(false);
+    let start = builder.add_state(false);
+    let accept = builder.add_state(true);
+    builder.set_transition(start, b'a', accept).unwrap();
+    builder.set_transition(start, b'b', dead).unwrap();
+    builder.set_transition(accept, b'a', accept).unwrap();
+    let mut dfa = builder.finish().unwrap();
+    dfa.set_start(start);
+    dfa.minimize_brzozowski().unwrap();
+    assert_eq!(dfa.states.len(), 2);
+    assert!(dfa.accept("aaa") && !dfa.accept("ab") && !dfa.accept(""));
+}
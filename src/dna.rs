@@ -0,0 +1,154 @@
+use dfa::Dfa;
+use error::Error;
+use nfa::Nfa;
+
+// 2 ビットの符号の順に並べた塩基。N は 2 ビットに詰められないので 5 列の表でだけ読む
+pub const BASES: &[u8; 5] = b"ACGTN";
+
+fn base_index(c: u8) -> Option<usize> {
+    BASES.iter().position(|&base| base == c)
+}
+
+// 塩基の列を 1 バイトに 4 塩基ずつ、先頭の塩基を上位ビットにして詰める
+// ACGT 以外を含む場合は None
+pub fn pack(bases: &[u8]) -> Option<Vec<u8>> {
+    let mut packed = vec![0u8; bases.len().div_ceil(4)];
+    for (i, &c) in bases.iter().enumerate() {
+        let code = base_index(c).filter(|&code| code < 4)?;
+        packed[i / 4] |= (code as u8) << (6 - 2 * (i % 4));
+    }
+    Some(packed)
+}
+
+// pack で詰めた先頭の len 塩基を取り出す
+pub fn unpack(packed: &[u8], len: usize) -> Vec<u8> {
+    (0..len).map(|i| BASES[packed_code(packed, i)]).collect()
+}
+
+fn packed_code(packed: &[u8], i: usize) -> usize {
+    (packed[i / 4] >> (6 - 2 * (i % 4)) & 0b11) as usize
+}
+
+// 塩基だけを読む DFA の 4 列 (ACGT) または 5 列 (ACGTN) の遷移表
+#[derive(Debug, Clone)]
+pub struct DnaDfa {
+    // 状態 s の塩基 b の遷移先は t[s * columns + b]
+    t: Vec<Option<usize>>,
+    accept: Vec<bool>,
+    columns: usize,
+    start: usize,
+}
+
+impl DnaDfa {
+    // 塩基以外の列は捨てる。入力が塩基だけなら元の DFA と同じ判定になる
+    pub fn from_dfa(dfa: &Dfa, with_n: bool) -> Self {
+        let columns = if with_n { 5 } else { 4 };
+        let mut t = Vec::with_capacity(dfa.states.len() * columns);
+        for state in dfa.states.iter() {
+            t.extend(BASES[..columns].iter().map(|&c| dfa.transition(state.id, c)));
+        }
+        DnaDfa {
+            t,
+            accept: dfa.states.iter().map(|s| s.accept).collect(),
+            columns,
+            start: dfa.start(),
+        }
+    }
+
+    // 配列のどこかに現れる pattern を探すための DFA
+    pub fn searcher(pattern: &str, with_n: bool) -> Result<Self, Error> {
+        let nfa = Nfa::re2nfa(pattern)?.unanchored();
+        let mut dfa = Dfa::nfa2dfa(&nfa)?;
+        dfa.minimize();
+        Ok(DnaDfa::from_dfa(&dfa, with_n))
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.accept.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accept.is_empty()
+    }
+
+    fn next(&self, state: usize, code: usize) -> Option<usize> {
+        self.t[state * self.columns + code]
+    }
+
+    // 先頭から読み進め、受理状態に入るたびに読んだ塩基の数を end に渡す
+    fn run<I, F>(&self, codes: I, mut end: F) -> Option<usize>
+    where
+        I: IntoIterator<Item = Option<usize>>,
+        F: FnMut(usize),
+    {
+        let mut state = self.start;
+        if self.accept.is_empty() {
+            return None;
+        }
+        if self.accept[state] {
+            end(0);
+        }
+        for (i, code) in codes.into_iter().enumerate() {
+            state = code
+                .filter(|&code| code < self.columns)
+                .and_then(|code| self.next(state, code))?;
+            if self.accept[state] {
+                end(i + 1);
+            }
+        }
+        Some(state)
+    }
+
+    // ASCII で書いた塩基の列全体を受理するか。表にない文字があれば受理しない
+    pub fn accept_bytes(&self, bases: &[u8]) -> bool {
+        let codes = bases.iter().map(|&c| base_index(c));
+        self.run(codes, |_| ()).is_some_and(|state| self.accept[state])
+    }
+
+    // pack で詰めた先頭の len 塩基の列全体を受理するか
+    pub fn accept_packed(&self, packed: &[u8], len: usize) -> bool {
+        let codes = (0..len).map(|i| Some(packed_code(packed, i)));
+        self.run(codes, |_| ()).is_some_and(|state| self.accept[state])
+    }
+
+    // searcher で作った DFA で、一致が終わる位置 (読んだ塩基の数) を全て返す
+    pub fn match_ends_packed(&self, packed: &[u8], len: usize) -> Vec<usize> {
+        let mut ends = Vec::new();
+        self.run((0..len).map(|i| Some(packed_code(packed, i))), |end| ends.push(end));
+        ends
+    }
+
+    pub fn match_ends_bytes(&self, bases: &[u8]) -> Vec<usize> {
+        let mut ends = Vec::new();
+        self.run(bases.iter().map(|&c| base_index(c)), |end| ends.push(end));
+        ends
+    }
+}
+
+#[test]
+fn dna_packing() {
+    let bases = b"GATTACAT";
+    let packed = pack(bases).unwrap();
+    assert_eq!(packed, vec![0b10_00_11_11, 0b00_01_00_11]);
+    assert_eq!(unpack(&packed, 7), b"GATTACA".to_vec());
+    assert!(pack(b"GANTC").is_none());
+
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(A|C|G|T)*TA(T|A)A").unwrap()).unwrap();
+    dfa.minimize();
+    let dna = DnaDfa::from_dfa(&dfa, false);
+    assert_eq!(dna.columns(), 4);
+    assert!(dna.accept_bytes(b"GGTATA"));
+    assert!(dna.accept_packed(&pack(b"GGTATA").unwrap(), 6));
+    assert!(!dna.accept_packed(&pack(b"GGTATAC").unwrap(), 7));
+    assert!(!dna.accept_bytes(b"GNTATA"));
+
+    let searcher = DnaDfa::searcher("TA(T|A)A", false).unwrap();
+    let genome = b"CCTATAAGTAAAT";
+    assert_eq!(searcher.match_ends_packed(&pack(genome).unwrap(), genome.len()), vec![6, 12]);
+    let searcher = DnaDfa::searcher("GN+C", true).unwrap();
+    assert_eq!(searcher.match_ends_bytes(b"AGNNCTGNC"), vec![5, 9]);
+}
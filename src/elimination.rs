@@ -0,0 +1,253 @@
+use dfa::Dfa;
+
+// 結合の強さ (大きいほど強い)
+const UNION: u8 = 0;
+const CONCAT: u8 = 1;
+const REPEAT: u8 = 2;
+const ATOM: u8 = 3;
+
+// 状態除去の途中で辺に載せる正規表現。空集合は None で表す
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Epsilon,
+    // 昇順に並んだ 1 文字のバイトの選択
+    Bytes(Vec<u8>),
+    Concat(Vec<Expr>),
+    Union(Vec<Expr>),
+    Star(Box<Expr>),
+}
+
+fn union(a: Option<Expr>, b: Option<Expr>) -> Option<Expr> {
+    let (a, b) = match (a, b) {
+        (None, e) | (e, None) => return e,
+        (Some(a), Some(b)) => (a, b),
+    };
+    let mut alternatives = Vec::new();
+    for e in [a, b] {
+        match e {
+            Expr::Union(es) => alternatives.extend(es),
+            e => alternatives.push(e),
+        }
+    }
+    // バイトの選択は一つにまとめる
+    let mut bytes = Vec::new();
+    let mut rest: Vec<Expr> = Vec::new();
+    for e in alternatives {
+        match e {
+            Expr::Bytes(bs) => bytes.extend(bs),
+            e => {
+                if !rest.contains(&e) {
+                    rest.push(e);
+                }
+            }
+        }
+    }
+    if !bytes.is_empty() {
+        bytes.sort();
+        bytes.dedup();
+        rest.insert(0, Expr::Bytes(bytes));
+    }
+    if rest.len() == 1 {
+        rest.pop()
+    } else {
+        Some(Expr::Union(rest))
+    }
+}
+
+fn concat(parts: Vec<Expr>) -> Expr {
+    let mut flat = Vec::new();
+    for e in parts {
+        match e {
+            Expr::Epsilon => {}
+            Expr::Concat(es) => flat.extend(es),
+            e => flat.push(e),
+        }
+    }
+    match flat.len() {
+        0 => Expr::Epsilon,
+        1 => flat.pop().unwrap(),
+        _ => Expr::Concat(flat),
+    }
+}
+
+fn star(e: Option<Expr>) -> Expr {
+    match e {
+        None | Some(Expr::Epsilon) => Expr::Epsilon,
+        Some(Expr::Star(e)) => Expr::Star(e),
+        Some(e) => Expr::Star(Box::new(e)),
+    }
+}
+
+fn precedence(e: &Expr) -> u8 {
+    match *e {
+        Expr::Union(ref es) if es.contains(&Expr::Epsilon) && es.len() == 2 => REPEAT,
+        Expr::Union(_) => UNION,
+        Expr::Concat(_) => CONCAT,
+        Expr::Star(_) => REPEAT,
+        Expr::Bytes(_) | Expr::Epsilon => ATOM,
+    }
+}
+
+fn write_byte(c: u8, in_class: bool, out: &mut String) {
+    let special: &[u8] = if in_class {
+        b"\\]-^"
+    } else {
+        b"\\|*+?().[]{}"
+    };
+    match c {
+        _ if special.contains(&c) => {
+            out.push('\\');
+            out.push(c as char);
+        }
+        0x20..=0x7e => out.push(c as char),
+        _ => out.push_str(&format!("\\x{:02x}", c)),
+    }
+}
+
+// 全バイトの選択を含むか (含むなら `(?s)` を付けて `.` で書く)
+fn has_any_byte(e: &Expr) -> bool {
+    match *e {
+        Expr::Epsilon => false,
+        Expr::Bytes(ref bytes) => bytes.len() == 256,
+        Expr::Concat(ref es) | Expr::Union(ref es) => es.iter().any(has_any_byte),
+        Expr::Star(ref e) => has_any_byte(e),
+    }
+}
+
+fn write_bytes(bytes: &[u8], dot_all: bool, out: &mut String) {
+    let dot = if dot_all {
+        bytes.len() == 256
+    } else {
+        bytes.len() == 255 && !bytes.contains(&b'\n')
+    };
+    if dot {
+        out.push('.');
+        return;
+    }
+    if bytes.len() == 1 {
+        write_byte(bytes[0], false, out);
+        return;
+    }
+    out.push('[');
+    let mut i = 0;
+    while i < bytes.len() {
+        // 連続するバイトは範囲にまとめる
+        let mut j = i;
+        while j + 1 < bytes.len() && bytes[j + 1] == bytes[j] + 1 {
+            j += 1;
+        }
+        write_byte(bytes[i], true, out);
+        if j >= i + 2 {
+            out.push('-');
+            write_byte(bytes[j], true, out);
+        } else if j == i + 1 {
+            write_byte(bytes[j], true, out);
+        }
+        i = j + 1;
+    }
+    out.push(']');
+}
+
+fn write_operand(e: &Expr, min_precedence: u8, dot_all: bool, out: &mut String) {
+    if precedence(e) < min_precedence {
+        out.push('(');
+        write(e, dot_all, out);
+        out.push(')');
+    } else {
+        write(e, dot_all, out);
+    }
+}
+
+fn write(e: &Expr, dot_all: bool, out: &mut String) {
+    match *e {
+        Expr::Epsilon => {}
+        Expr::Bytes(ref bytes) => write_bytes(bytes, dot_all, out),
+        Expr::Concat(ref es) => {
+            for e in es {
+                write_operand(e, CONCAT, dot_all, out);
+            }
+        }
+        Expr::Union(ref es) if es.contains(&Expr::Epsilon) => {
+            // ε を含む選択は `?` で書く
+            let rest: Vec<Expr> = es.iter().filter(|&e| *e != Expr::Epsilon).cloned().collect();
+            let rest = if rest.len() == 1 {
+                rest.into_iter().next().unwrap()
+            } else {
+                Expr::Union(rest)
+            };
+            write_operand(&rest, ATOM, dot_all, out);
+            out.push('?');
+        }
+        Expr::Union(ref es) => {
+            for (i, e) in es.iter().enumerate() {
+                if i > 0 {
+                    out.push('|');
+                }
+                write_operand(e, UNION, dot_all, out);
+            }
+        }
+        Expr::Star(ref e) => {
+            write_operand(e, ATOM, dot_all, out);
+            out.push('*');
+        }
+    }
+}
+
+// 状態除去法で DFA の受理言語を表すパターンを作る
+// 受理言語が空か空文字列だけの場合は None
+pub fn to_pattern(dfa: &Dfa) -> Option<String> {
+    let live = dfa.live_states();
+    let n = dfa.states.len();
+    // n 番を新しい開始状態、n + 1 番を新しい受理状態とする
+    let (start, accept) = (n, n + 1);
+    let mut edges: Vec<Vec<Option<Expr>>> = vec![vec![None; n + 2]; n + 2];
+    if n > 0 && live[dfa.start()] {
+        edges[start][dfa.start()] = Some(Expr::Epsilon);
+    }
+    for (p, state) in dfa.states.iter().enumerate().filter(|&(p, _)| live[p]) {
+        if state.accept {
+            edges[p][accept] = Some(Expr::Epsilon);
+        }
+        for (c, next) in dfa.transitions(p) {
+            if let Some(q) = next {
+                if live[q] {
+                    let e = edges[p][q].take();
+                    edges[p][q] = union(e, Some(Expr::Bytes(vec![c])));
+                }
+            }
+        }
+    }
+
+    for k in (0..n).filter(|&k| live[k]) {
+        let loop_expr = star(edges[k][k].take());
+        let sources: Vec<usize> = (0..n + 2).filter(|&i| edges[i][k].is_some()).collect();
+        let targets: Vec<usize> = (0..n + 2).filter(|&j| edges[k][j].is_some()).collect();
+        for &i in sources.iter() {
+            for &j in targets.iter() {
+                let path = concat(vec![
+                    edges[i][k].clone().unwrap(),
+                    loop_expr.clone(),
+                    edges[k][j].clone().unwrap(),
+                ]);
+                let e = edges[i][j].take();
+                edges[i][j] = union(e, Some(path));
+            }
+        }
+        for row in edges.iter_mut() {
+            row[k] = None;
+        }
+        for e in edges[k].iter_mut() {
+            *e = None;
+        }
+    }
+
+    match edges[start][accept].take() {
+        None | Some(Expr::Epsilon) => None,
+        Some(e) => {
+            let dot_all = has_any_byte(&e);
+            let mut out = if dot_all { "(?s)".to_owned() } else { String::new() };
+            write(&e, dot_all, &mut out);
+            Some(out)
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use binary::Options;
+use std::error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+#[derive(Debug)]
+pub enum Error {
+    // パターンを構文木にできなかった
+    Parse(String),
+    // パターンの構文の誤り (説明, パターン中のバイト位置)
+    Syntax(String, usize),
+    // 正規言語で表せない、または未対応の構文 (構文の名前, パターン中のバイト位置の範囲)
+    Unsupported {
+        feature: &'static str,
+        span: Range<usize>,
+    },
+    // DFA の状態数が上限を超えた
+    SizeLimit(usize),
+    // 回数指定の繰り返しを展開したパターンが長さの上限を超えた
+    RepeatLimit(usize),
+    // オートマトンの不変条件が満たされていない
+    InvalidAutomaton(String),
+    // DFA のバイナリ形式を読めない
+    Format(FormatError),
+    // Scanner のどの規則も入力のこの位置から 1 バイト以上一致しない
+    NoToken(usize),
+    Io(io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    // 先頭が DFA の magic でない
+    NotDfa,
+    // ヘッダの状態数に対して長さが合わない
+    Truncated,
+    // 読めない形式の版
+    Version(u16),
+    // 書いたときと読むときのコンパイル時の設定が違う
+    Options { expected: Options, found: Options },
+    Checksum { expected: u32, found: u32 },
+    // 先頭が rule pack の magic でない
+    NotRulePack,
+    // rule pack の manifest が本体と食い違う
+    Manifest(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::NotDfa => write!(f, "not a serialized DFA"),
+            FormatError::Truncated => write!(f, "serialized DFA is truncated"),
+            FormatError::Version(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            FormatError::Options { expected, found } => write!(
+                f,
+                "compiled with {:?} but {:?} was expected",
+                found, expected
+            ),
+            FormatError::Checksum { expected, found } => write!(
+                f,
+                "checksum mismatch: expected {:08x}, found {:08x}",
+                expected, found
+            ),
+            FormatError::NotRulePack => write!(f, "not a rule pack"),
+            FormatError::Manifest(ref reason) => {
+                write!(f, "manifest does not match the rule pack: {}", reason)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref pattern) => write!(f, "failed to parse pattern {:?}", pattern),
+            Error::Syntax(ref message, position) => {
+                write!(f, "{} at position {}", message, position)
+            }
+            Error::Unsupported { feature, ref span } => {
+                write!(f, "{} is not supported at position {}", feature, span.start)
+            }
+            Error::SizeLimit(limit) => write!(f, "DFA exceeds the limit of {} states", limit),
+            Error::RepeatLimit(limit) => write!(
+                f,
+                "counted repetition expands beyond the limit of {} characters",
+                limit
+            ),
+            Error::InvalidAutomaton(ref reason) => write!(f, "invalid automaton: {}", reason),
+            Error::Format(ref e) => write!(f, "{}", e),
+            Error::NoToken(position) => write!(f, "no rule matches at position {}", position),
+            Error::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,166 @@
+use dfa::{Dfa, StreamSpan};
+use error::Error;
+use nfa::Nfa;
+use std::marker::PhantomData;
+
+// イベント列を照合するためのアルファベット (各イベントを 1 バイトの記号に対応させる)
+pub trait Symbol {
+    fn to_byte(&self) -> u8;
+}
+
+#[derive(Debug)]
+pub struct EventPattern<S: Symbol> {
+    dfa: Dfa,
+    symbol: PhantomData<S>,
+}
+
+impl<S: Symbol> EventPattern<S> {
+    // パターンは各イベントの記号を文字として書く
+    pub fn new(regex: &str) -> Result<Self, Error> {
+        let nfa = Nfa::re2nfa(regex)?;
+        let mut dfa = Dfa::nfa2dfa(&nfa)?;
+        dfa.minimize();
+        Ok(EventPattern::from_dfa(dfa))
+    }
+
+    pub fn from_dfa(dfa: Dfa) -> Self {
+        EventPattern {
+            dfa,
+            symbol: PhantomData,
+        }
+    }
+
+    pub fn dfa(&self) -> &Dfa {
+        &self.dfa
+    }
+
+    pub fn accepts<I: IntoIterator<Item = S>>(&self, events: I) -> bool {
+        self.dfa.accept_iter(events.into_iter().map(|event| event.to_byte()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    // 受理できる続きがなくなった接頭辞の範囲
+    Violation(StreamSpan),
+}
+
+// イベントを一つずつ受け取り、安全性の違反を検出する
+#[derive(Debug)]
+pub struct Monitor<S: Symbol> {
+    pattern: EventPattern<S>,
+    live: Vec<bool>,
+    state: Option<usize>,
+    // これまでに受け取ったイベントの数
+    position: u64,
+    violation: Option<StreamSpan>,
+}
+
+impl<S: Symbol> Monitor<S> {
+    pub fn from_pattern(regex: &str) -> Result<Self, Error> {
+        Ok(Monitor::new(EventPattern::new(regex)?))
+    }
+
+    pub fn new(pattern: EventPattern<S>) -> Self {
+        let live = pattern.dfa().live_states();
+        let mut monitor = Monitor {
+            pattern,
+            live,
+            state: None,
+            position: 0,
+            violation: None,
+        };
+        monitor.reset();
+        monitor
+    }
+
+    pub fn reset(&mut self) {
+        self.position = 0;
+        let start = self.pattern.dfa().start();
+        self.state = Some(start).filter(|&s| s < self.live.len() && self.live[s]);
+        self.violation = if self.state.is_none() {
+            Some(StreamSpan { start: 0, end: 0 })
+        } else {
+            None
+        };
+    }
+
+    pub fn step(&mut self, event: S) -> Verdict {
+        if let Some(violation) = self.violation {
+            return Verdict::Violation(violation);
+        }
+        self.position += 1;
+        let state = self.state.unwrap();
+        self.state = self
+            .pattern
+            .dfa()
+            .transition(state, event.to_byte())
+            .filter(|&next| self.live[next]);
+        if self.state.is_none() {
+            self.violation = Some(StreamSpan {
+                start: 0,
+                end: self.position,
+            });
+            return Verdict::Violation(self.violation.unwrap());
+        }
+        Verdict::Ok
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // これまでの入力がパターン全体に一致しているか
+    pub fn is_accepting(&self) -> bool {
+        match self.state {
+            Some(s) => self.pattern.dfa().states[s].accept,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Copy)]
+enum FileEvent {
+    Open,
+    Read,
+    Close,
+}
+
+#[cfg(test)]
+impl Symbol for FileEvent {
+    fn to_byte(&self) -> u8 {
+        match *self {
+            FileEvent::Open => b'o',
+            FileEvent::Read => b'r',
+            FileEvent::Close => b'c',
+        }
+    }
+}
+
+#[test]
+fn event_pattern_accepts() {
+    use self::FileEvent::*;
+    let pattern: EventPattern<FileEvent> = EventPattern::new("(or*c)*").unwrap();
+    assert!(pattern.accepts(vec![Open, Read, Read, Close, Open, Close]));
+    assert!(!pattern.accepts(vec![Open, Read]));
+    assert!(!pattern.accepts(vec![Read]));
+}
+
+#[test]
+fn monitor_reports_violation() {
+    use self::FileEvent::*;
+    let mut monitor: Monitor<FileEvent> = Monitor::from_pattern("(or*c)*").unwrap();
+    assert_eq!(monitor.step(Open), Verdict::Ok);
+    assert_eq!(monitor.step(Read), Verdict::Ok);
+    assert!(!monitor.is_accepting());
+    assert_eq!(monitor.step(Close), Verdict::Ok);
+    assert!(monitor.is_accepting());
+    assert_eq!(monitor.position(), 3);
+    let violation = Verdict::Violation(StreamSpan { start: 0, end: 4 });
+    assert_eq!(monitor.step(Read), violation);
+    assert_eq!(monitor.step(Open), violation);
+    monitor.reset();
+    assert_eq!(monitor.step(Open), Verdict::Ok);
+}
@@ -0,0 +1,187 @@
+use ast::{Ast, Class};
+use error::Error;
+use std::fmt;
+
+// パターンを構文木にする前段。RegexBuilder::parser で差し替えると、正規表現の代わりに
+// glob や SQL の LIKE などの構文で書いたパターンを同じ NFA と DFA の構成で照合できる
+pub trait PatternParser: fmt::Debug + Send + Sync {
+    fn parse(&self, pattern: &str) -> Result<Ast, Error>;
+
+    // true ならパターンは入力全体と一致しなければならない (`^...$` と同じ)
+    fn full_match(&self) -> bool {
+        false
+    }
+}
+
+fn any_byte_except(excluded: &[u8]) -> Ast {
+    let mut class = Class::new();
+    for &c in excluded {
+        class.members[c as usize] = true;
+    }
+    class.negated = true;
+    Ast::Class(Box::new(class))
+}
+
+fn concat(mut asts: Vec<Ast>) -> Ast {
+    if asts.len() == 1 {
+        asts.pop().unwrap()
+    } else {
+        Ast::Concat(asts)
+    }
+}
+
+// シェルの glob。`*` は `/` 以外の 0 バイト以上、`?` は `/` 以外の 1 バイト、
+// `[a-z]` と `[!a-z]` (`[^a-z]` も可) はクラス、`\` は次の 1 文字をそのまま表す
+// 入力全体と一致する
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Glob;
+
+impl Glob {
+    // `[` の次から `]` までを読み、クラスと `]` の次の位置を返す
+    fn class(pattern: &[u8], open: usize) -> Result<(Class, usize), Error> {
+        let mut class = Class::new();
+        let mut i = open + 1;
+        if i < pattern.len() && (pattern[i] == b'!' || pattern[i] == b'^') {
+            class.negated = true;
+            i += 1;
+        }
+        let first = i;
+        loop {
+            let c = match pattern.get(i) {
+                Some(&b']') if i > first => return Ok((class, i + 1)),
+                Some(&c) => c,
+                None => return Err(Error::Syntax("unclosed character class".to_owned(), open)),
+            };
+            if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&c| c != b']') {
+                let hi = pattern[i + 2];
+                if hi < c {
+                    return Err(Error::Syntax("invalid range in character class".to_owned(), i));
+                }
+                for b in c..=hi {
+                    class.members[b as usize] = true;
+                }
+                i += 3;
+            } else {
+                class.members[c as usize] = true;
+                i += 1;
+            }
+        }
+    }
+}
+
+impl PatternParser for Glob {
+    fn parse(&self, pattern: &str) -> Result<Ast, Error> {
+        let pattern = pattern.as_bytes();
+        let mut asts = Vec::new();
+        let mut i = 0;
+        while i < pattern.len() {
+            match pattern[i] {
+                b'*' => {
+                    asts.push(Ast::Star(Box::new(any_byte_except(b"/"))));
+                    i += 1;
+                }
+                b'?' => {
+                    asts.push(any_byte_except(b"/"));
+                    i += 1;
+                }
+                b'[' => {
+                    let (class, next) = Glob::class(pattern, i)?;
+                    asts.push(Ast::Class(Box::new(class)));
+                    i = next;
+                }
+                b'\\' => match pattern.get(i + 1) {
+                    Some(&c) => {
+                        asts.push(Ast::Literal(c));
+                        i += 2;
+                    }
+                    None => return Err(Error::Syntax("trailing backslash".to_owned(), i)),
+                },
+                c => {
+                    asts.push(Ast::Literal(c));
+                    i += 1;
+                }
+            }
+        }
+        Ok(concat(asts))
+    }
+
+    fn full_match(&self) -> bool {
+        true
+    }
+}
+
+// SQL の LIKE。`%` は 0 バイト以上、`_` は 1 バイト、escape の次の 1 文字はそのまま表す
+// 入力全体と一致する
+#[derive(Debug, Clone, Copy)]
+pub struct SqlLike {
+    pub escape: Option<u8>,
+}
+
+impl Default for SqlLike {
+    fn default() -> Self {
+        SqlLike {
+            escape: Some(b'\\'),
+        }
+    }
+}
+
+impl PatternParser for SqlLike {
+    fn parse(&self, pattern: &str) -> Result<Ast, Error> {
+        let pattern = pattern.as_bytes();
+        let mut asts = Vec::new();
+        let mut i = 0;
+        while i < pattern.len() {
+            let c = pattern[i];
+            if Some(c) == self.escape {
+                match pattern.get(i + 1) {
+                    Some(&c) => asts.push(Ast::Literal(c)),
+                    None => return Err(Error::Syntax("trailing escape character".to_owned(), i)),
+                }
+                i += 2;
+                continue;
+            }
+            asts.push(match c {
+                b'%' => Ast::Star(Box::new(any_byte_except(&[]))),
+                b'_' => any_byte_except(&[]),
+                c => Ast::Literal(c),
+            });
+            i += 1;
+        }
+        Ok(concat(asts))
+    }
+
+    fn full_match(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+// glob の `[bc` は regex としては閉じていないクラスになる
+#[allow(clippy::invalid_regex)]
+fn glob_and_like_front_ends() {
+    use regex::RegexBuilder;
+
+    let glob = RegexBuilder::new("src/*.[ch]").parser(Glob).build().unwrap();
+    assert!(glob.is_match("src/main.c"));
+    assert!(glob.is_match("src/.h"));
+    assert!(!glob.is_match("src/sub/main.c"));
+    assert!(!glob.is_match("src/main.cpp"));
+    assert!(!glob.is_match("xsrc/main.c"));
+    let glob = RegexBuilder::new("[!0-9]?\\*").parser(Glob).build().unwrap();
+    assert!(glob.is_match("ab*") && !glob.is_match("1b*") && !glob.is_match("abc"));
+    match RegexBuilder::new("a[bc").parser(Glob).build() {
+        Err(Error::Syntax(_, 1)) => {}
+        other => panic!("{:?}", other.map(|r| r.as_str().to_owned())),
+    }
+
+    let like = RegexBuilder::new("100\\%_off%").parser(SqlLike::default()).build().unwrap();
+    assert!(like.is_match("100%!off today"));
+    assert!(!like.is_match("100x off"));
+    let like = RegexBuilder::new("a%")
+        .parser(SqlLike { escape: None })
+        .case_insensitive(true)
+        .build()
+        .unwrap();
+    assert!(like.is_match("ABC") && like.is_match("a\nb") && !like.is_match("ba"));
+    assert!(RegexBuilder::new("").parser(SqlLike::default()).build().unwrap().is_match(""));
+}
@@ -0,0 +1,229 @@
+use dfa::{Dfa, DfaBuilder};
+use error::Error;
+use nfa::Nfa;
+use std::ops::RangeInclusive;
+
+const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+// base 進数の数字 (小文字) とその値
+fn digits(base: usize) -> Result<&'static [u8], Error> {
+    if base < 2 || base > DIGITS.len() {
+        return Err(Error::InvalidAutomaton(format!(
+            "base must be between 2 and {}, got {}",
+            DIGITS.len(),
+            base
+        )));
+    }
+    Ok(&DIGITS[..base])
+}
+
+// base 進数で書かれた数のうち n で割った余りが remainder のものを受理する DFA
+// 空文字列は受理しない。先頭の 0 は許す
+pub fn residue(n: usize, base: usize, remainder: usize) -> Result<Dfa, Error> {
+    let digits = digits(base)?;
+    if n == 0 || remainder >= n {
+        return Err(Error::InvalidAutomaton(format!(
+            "remainder {} is not a residue modulo {}",
+            remainder, n
+        )));
+    }
+    let mut builder = DfaBuilder::new();
+    let start = builder.add_state(false);
+    // 余り r の状態は r + 1 番
+    for r in 0..n {
+        builder.add_state(r == remainder);
+    }
+    for (value, &digit) in digits.iter().enumerate() {
+        builder.set_transition(start, digit, value % n + 1)?;
+        for r in 0..n {
+            builder.set_transition(r + 1, digit, (r * base + value) % n + 1)?;
+        }
+    }
+    builder.finish()
+}
+
+// base 進数で書かれた n の倍数を受理する DFA
+pub fn divisible_by(n: usize, base: usize) -> Result<Dfa, Error> {
+    residue(n, base, 0)
+}
+
+fn digit_class(lo: u8, hi: u8) -> String {
+    if lo == hi {
+        return (lo as char).to_string();
+    }
+    let digits: Vec<String> = (lo..=hi).map(|d| (d as char).to_string()).collect();
+    format!("({})", digits.join("|"))
+}
+
+// 同じ桁数の 10 進数 lo..=hi に一致するパターンの選択肢
+fn same_length_range(lo: &[u8], hi: &[u8], out: &mut Vec<String>) {
+    if lo.len() == 1 {
+        out.push(digit_class(lo[0], hi[0]));
+        return;
+    }
+    let any = digit_class(b'0', b'9').repeat(lo.len() - 1);
+    if lo[0] == hi[0] {
+        let mut rest = Vec::new();
+        same_length_range(&lo[1..], &hi[1..], &mut rest);
+        out.extend(rest.into_iter().map(|r| format!("{}{}", lo[0] as char, r)));
+    } else if lo[1..].iter().all(|&d| d == b'0') && hi[1..].iter().all(|&d| d == b'9') {
+        out.push(format!("{}{}", digit_class(lo[0], hi[0]), any));
+    } else {
+        let nines = vec![b'9'; lo.len() - 1];
+        let zeros = vec![b'0'; lo.len() - 1];
+        same_length_range(lo, &[&lo[..1], &nines[..]].concat(), out);
+        if lo[0] + 1 < hi[0] {
+            out.push(format!("{}{}", digit_class(lo[0] + 1, hi[0] - 1), any));
+        }
+        same_length_range(&[&hi[..1], &zeros[..]].concat(), hi, out);
+    }
+}
+
+// 先頭に余分な 0 のない 10 進表記で lo..=hi の数に一致するパターン
+fn decimal_range_pattern(lo: u64, hi: u64) -> String {
+    let mut alternatives = Vec::new();
+    let mut lo = lo;
+    while lo <= hi {
+        let digits = lo.to_string().len() as u32;
+        let upper = match 10u64.checked_pow(digits) {
+            Some(power) => hi.min(power - 1),
+            None => hi,
+        };
+        same_length_range(
+            lo.to_string().as_bytes(),
+            upper.to_string().as_bytes(),
+            &mut alternatives,
+        );
+        match upper.checked_add(1) {
+            Some(next) => lo = next,
+            None => break,
+        }
+    }
+    alternatives.join("|")
+}
+
+fn compile(pattern: &str) -> Result<Dfa, Error> {
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa(pattern)?)?;
+    dfa.minimize();
+    Ok(dfa)
+}
+
+fn empty_language() -> Result<Dfa, Error> {
+    let mut builder = DfaBuilder::new();
+    builder.add_state(false);
+    builder.finish()
+}
+
+// 先頭に余分な 0 のない 10 進表記の数のうち min 以上 max 以下のものを受理する DFA
+pub fn numeric_range(min: u64, max: u64) -> Result<Dfa, Error> {
+    if min > max {
+        return empty_language();
+    }
+    compile(&decimal_range_pattern(min, max))
+}
+
+// numeric_range の DFA から状態除去法で作ったパターン。範囲が空なら None
+pub fn numeric_range_pattern(min: u64, max: u64) -> Result<Option<String>, Error> {
+    Ok(numeric_range(min, max)?.to_pattern())
+}
+
+// 10 進表記のポート番号のうち range に含まれるものを受理する DFA
+pub fn port_range(range: RangeInclusive<u16>) -> Result<Dfa, Error> {
+    numeric_range(u64::from(*range.start()), u64::from(*range.end()))
+}
+
+// "10.0.0.0/8" のような CIDR 表記の範囲に含まれる IPv4 アドレス (ドット区切りの 10 進表記) を受理する DFA
+pub fn ip_range(cidr: &str) -> Result<Dfa, Error> {
+    let parse_error = || Error::Parse(cidr.to_owned());
+    let (address, prefix) = match cidr.find('/') {
+        Some(slash) => (&cidr[..slash], &cidr[slash + 1..]),
+        None => (cidr, "32"),
+    };
+    let prefix: u32 = prefix.parse().map_err(|_| parse_error())?;
+    let octets: Vec<u8> = address
+        .split('.')
+        .map(|octet| octet.parse().map_err(|_| parse_error()))
+        .collect::<Result<_, _>>()?;
+    if octets.len() != 4 || prefix > 32 {
+        return Err(parse_error());
+    }
+    let mut octet_patterns = Vec::new();
+    for (i, &octet) in octets.iter().enumerate() {
+        // このオクテットで固定されるビット数
+        let fixed = prefix.saturating_sub(8 * i as u32).min(8);
+        let free = 8 - fixed;
+        let lo = u32::from(octet) >> free << free;
+        let hi = lo + (1 << free) - 1;
+        octet_patterns.push(format!(
+            "({})",
+            decimal_range_pattern(u64::from(lo), u64::from(hi))
+        ));
+    }
+    compile(&octet_patterns.join("\\."))
+}
+
+#[test]
+fn divisible_by_three() {
+    let dfa = divisible_by(3, 2).unwrap();
+    for i in 0..64usize {
+        assert_eq!(dfa.accept(&format!("{:b}", i)), i % 3 == 0);
+    }
+    assert!(!dfa.accept(""));
+    assert!(!dfa.accept("12"));
+
+    let dfa = residue(7, 16, 4).unwrap();
+    for i in 0..300usize {
+        assert_eq!(dfa.accept(&format!("{:x}", i)), i % 7 == 4);
+    }
+    assert!(divisible_by(0, 10).is_err());
+    assert!(divisible_by(3, 37).is_err());
+}
+
+#[test]
+fn port_and_ip_ranges() {
+    let dfa = port_range(1024..=2048).unwrap();
+    for port in 0..3000u32 {
+        assert_eq!(
+            dfa.accept(&port.to_string()),
+            (1024..=2048).contains(&port),
+            "port {}",
+            port
+        );
+    }
+    assert!(!dfa.accept("01024"));
+
+    let dfa = ip_range("10.0.0.0/8").unwrap();
+    assert!(dfa.accept("10.0.0.0"));
+    assert!(dfa.accept("10.255.1.2"));
+    assert!(!dfa.accept("11.0.0.0"));
+    assert!(!dfa.accept("10.01.0.0"));
+    assert!(!dfa.accept("10.256.0.0"));
+
+    let dfa = ip_range("192.168.1.0/25").unwrap();
+    assert!(dfa.accept("192.168.1.127"));
+    assert!(!dfa.accept("192.168.1.128"));
+    assert!(ip_range("192.168.1/24").is_err());
+
+    let user = compile("10\\.0\\..*").unwrap();
+    let dfa = ip_range("10.0.0.0/8").unwrap().intersection(&user);
+    assert!(dfa.accept("10.0.7.1"));
+    assert!(!dfa.accept("10.1.7.1"));
+    assert!(!dfa.accept("10.0.7.x"));
+}
+
+#[test]
+fn numeric_range_round_trip() {
+    let dfa = numeric_range(7, 1234).unwrap();
+    let pattern = numeric_range_pattern(7, 1234).unwrap().unwrap();
+    let round_trip = compile(&pattern).unwrap();
+    for i in 0..1500u64 {
+        let s = i.to_string();
+        assert_eq!(dfa.accept(&s), (7..=1234).contains(&i), "{}", i);
+        assert_eq!(round_trip.accept(&s), dfa.accept(&s), "{} via {}", i, pattern);
+    }
+    assert!(!dfa.accept("007"));
+    let dfa = numeric_range(u64::MAX - 1, u64::MAX).unwrap();
+    assert!(dfa.accept("18446744073709551615"));
+    assert!(!dfa.accept("18446744073709551613"));
+    assert_eq!(numeric_range_pattern(5, 4).unwrap(), None);
+}
@@ -0,0 +1,578 @@
+use dfa::{Anchors, Match, Matches, Search};
+use error::Error;
+use nfa::{split_anchors, split_flags, strip_verbose, Flags, Nfa, StateSet};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Mutex;
+
+// まだ求めていない遷移と、空集合への遷移
+const UNKNOWN: u32 = u32::MAX;
+const DEAD: u32 = u32::MAX - 1;
+
+// LazyDfa::new で覚える状態の数の上限
+pub const DEFAULT_MAX_STATES: usize = 10_000;
+
+// 一つの NFA について、照合中に求めた DFA の状態 (NFA 状態の集合) と遷移
+#[derive(Debug, Default)]
+struct States {
+    sets: Vec<Vec<usize>>,
+    accept: Vec<bool>,
+    // 状態ごとに 256 列
+    table: Vec<u32>,
+    ids: HashMap<Vec<usize>, u32>,
+    clears: usize,
+}
+
+impl States {
+    fn add(&mut self, nfa: &Nfa, set: Vec<usize>) -> u32 {
+        if let Some(&id) = self.ids.get(&set) {
+            return id;
+        }
+        let id = self.sets.len() as u32;
+        self.accept.push(set.iter().any(|&q| nfa.states[q].accept));
+        self.table.extend_from_slice(&[UNKNOWN; 256]);
+        self.ids.insert(set.clone(), id);
+        self.sets.push(set);
+        id
+    }
+
+    fn clear(&mut self) {
+        self.sets.clear();
+        self.accept.clear();
+        self.table.clear();
+        self.ids.clear();
+        self.clears += 1;
+    }
+
+    // 開始状態の番号 (いつも 0 番)
+    fn start(&mut self, nfa: &Nfa) -> u32 {
+        if self.sets.is_empty() {
+            self.add(nfa, nfa.start_states().sorted());
+        }
+        0
+    }
+
+    // state から c で進んだ先。まだ求めていなければ求めて覚える
+    // 覚えた状態が max_states に達したら今いる状態だけ残して作り直すので、それまでの番号は使えなくなる
+    fn next(&mut self, nfa: &Nfa, max_states: usize, mut state: u32, c: u8) -> u32 {
+        let next = self.table[state as usize * 256 + c as usize];
+        if next != UNKNOWN {
+            return next;
+        }
+        let mut set = StateSet::new();
+        for &q in self.sets[state as usize].iter() {
+            if let Some(targets) = nfa.t(q, c) {
+                set.extend(targets.iter().cloned());
+            }
+        }
+        let next = if set.is_empty() {
+            DEAD
+        } else {
+            if self.sets.len() >= max_states {
+                // 開始状態は 0 番に置き直す
+                let current = self.sets[state as usize].clone();
+                self.clear();
+                self.add(nfa, nfa.start_states().sorted());
+                state = self.add(nfa, current);
+            }
+            self.add(nfa, set.sorted())
+        };
+        self.table[state as usize * 256 + c as usize] = next;
+        next
+    }
+}
+
+// 照合一回分のキャッシュ。NFA ごとに別の状態を覚える
+#[derive(Debug, Default)]
+struct Cache {
+    full: States,
+    search: States,
+    reverse: States,
+}
+
+impl Cache {
+    fn len(&self) -> usize {
+        self.full.sets.len() + self.search.sets.len() + self.reverse.sets.len()
+    }
+
+    fn clears(&self) -> usize {
+        self.full.clears + self.search.clears + self.reverse.clears
+    }
+}
+
+// 部分集合構成を前もって行わず、入力を読みながら必要な状態だけを作る照合器
+// `.*a.{20}b` のように DFA の状態数が爆発するパターンでも、読んだ入力に現れた状態しか作らない
+// 作った状態が max_states に達したら覚えた状態を全て捨てて作り直す
+// キャッシュは照合の初めに借りて終わりに返すので、錠を持つのはその間だけ
+// 同時に照合するスレッドはそれぞれ別のキャッシュを使う
+#[derive(Debug)]
+pub struct LazyDfa {
+    pattern: String,
+    // 入力全体との一致を調べる NFA
+    nfa: Nfa,
+    // 入力の途中から始まる一致を探す NFA (`^` があれば nfa と同じ)
+    searcher: Nfa,
+    // 逆順の言語の NFA (入力の末尾からたどる)
+    reverse: Nfa,
+    anchors: Anchors,
+    max_states: usize,
+    // 貸し出していないキャッシュ
+    caches: Mutex<Vec<Cache>>,
+}
+
+impl LazyDfa {
+    pub fn new(pattern: &str) -> Result<LazyDfa, Error> {
+        LazyDfa::with_max_states(pattern, DEFAULT_MAX_STATES)
+    }
+
+    pub fn with_max_states(pattern: &str, max_states: usize) -> Result<LazyDfa, Error> {
+        let nfa = Nfa::re2nfa(pattern)?;
+        let (start, _, end) = split_anchors(&strip_verbose(pattern));
+        let flags = Flags::default().merge(split_flags(pattern.trim()).0);
+        let anchors = Anchors {
+            start,
+            end,
+            multi_line: flags.multi_line,
+        };
+        LazyDfa::from_nfa(pattern, nfa, anchors, max_states)
+    }
+
+    // `^` と `$` を anchors に分けた後の NFA から作る (Regex が DFA を作れなかったときに使う)
+    pub fn from_nfa(
+        pattern: &str,
+        nfa: Nfa,
+        anchors: Anchors,
+        max_states: usize,
+    ) -> Result<LazyDfa, Error> {
+        // 行頭の `^` は行の先頭ごとに開始状態を入れ直す必要があり、一つの状態の列では表せない
+        if anchors.start && anchors.multi_line {
+            return Err(Error::Unsupported {
+                feature: "multi-line start anchor in a lazy DFA",
+                span: 0..pattern.len(),
+            });
+        }
+        let searcher = if anchors.start {
+            nfa.clone()
+        } else {
+            nfa.unanchored()
+        };
+        Ok(LazyDfa {
+            pattern: pattern.to_owned(),
+            reverse: nfa.reverse(),
+            nfa,
+            searcher,
+            anchors,
+            // 開始状態と、そこから次の 1 状態を置けるだけは確保する
+            max_states: max_states.max(2),
+            caches: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // 入力のどこかにパターンと一致する部分があるか
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.is_match_bytes(haystack.as_bytes())
+    }
+
+    pub fn is_match_bytes(&self, haystack: &[u8]) -> bool {
+        self.with_cache(|cache| self.earliest_end(cache, haystack, 0).is_some())
+    }
+
+    // 入力全体がパターンと一致するか
+    pub fn is_full_match(&self, haystack: &str) -> bool {
+        let haystack = haystack.as_bytes();
+        self.with_cache(|cache| {
+            let ends = |end| end == haystack.len();
+            self.run(&self.nfa, &mut cache.full, haystack.iter().cloned(), ends).is_some()
+        })
+    }
+
+    // 入力の先頭から始まる部分がパターンと一致するか
+    pub fn matches_prefix(&self, haystack: &str) -> bool {
+        let haystack = haystack.as_bytes();
+        self.with_cache(|cache| {
+            let ends = |end| self.anchors.end_ok(haystack, end);
+            self.run(&self.nfa, &mut cache.full, haystack.iter().cloned(), ends).is_some()
+        })
+    }
+
+    // 入力の末尾で終わる部分がパターンと一致するか
+    pub fn matches_suffix(&self, haystack: &str) -> bool {
+        let haystack = haystack.as_bytes();
+        self.with_cache(|cache| {
+            let input = haystack.iter().rev().cloned();
+            let starts = |n| self.anchors.start_ok(haystack, haystack.len() - n);
+            self.run(&self.reverse, &mut cache.reverse, input, starts).is_some()
+        })
+    }
+
+    // 最も左から始まる最長の一致
+    pub fn find(&self, haystack: &str) -> Option<Match> {
+        let haystack = haystack.as_bytes();
+        self.find_bounded(haystack, 0..haystack.len() + 1, self.anchors, None)
+    }
+
+    // 重ならない全ての一致
+    pub fn find_iter<'h>(&self, haystack: &'h str) -> Matches<'_, 'h> {
+        Matches::new(self, haystack.as_bytes(), self.anchors, Some(0), None)
+    }
+
+    // 先頭に最長一致させ、一致範囲と残りの入力を返す (Dfa::strip_prefix_match と同じく `$` は見ない)
+    pub fn strip_prefix_match<'a>(&self, input: &'a str) -> Option<(Match, &'a str)> {
+        let bytes = input.as_bytes();
+        let end = self.with_cache(|cache| {
+            self.longest_at(cache, bytes, 0, None, |end| input.is_char_boundary(end))
+        })?;
+        Some((Match { start: 0, end }, &input[end..]))
+    }
+
+    // Regex::fill_template と同じ。埋められないと分かった (NFA 状態の集合, 位置) の組を覚えて探す
+    pub fn fill_template(&self, template: &str) -> Option<String> {
+        let template = template.as_bytes();
+        self.with_cache(|cache| {
+            let start = cache.full.start(&self.nfa);
+            let set = cache.full.sets[start as usize].clone();
+            let mut word = Vec::with_capacity(template.len());
+            let mut failed = HashSet::new();
+            if self.fill(&mut cache.full, template, set, &mut word, &mut failed) {
+                String::from_utf8(word).ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    // 一致する文字列が全て ASCII だけからなるか (Dfa::is_ascii_only と同じ判定を NFA の上で行う)
+    pub fn is_ascii_only(&self) -> bool {
+        let (accept, moves) = self.nfa.closure_moves();
+        let mut reachable = vec![false; accept.len()];
+        let mut stack = self.nfa.start_states().sorted();
+        for &q in stack.iter() {
+            reachable[q] = true;
+        }
+        while let Some(q) = stack.pop() {
+            for &r in moves[q].iter().flat_map(|targets| targets.iter()) {
+                if !reachable[r] {
+                    reachable[r] = true;
+                    stack.push(r);
+                }
+            }
+        }
+        // 受理状態へ到達できる状態
+        let mut live = accept;
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for q in 0..live.len() {
+                if !live[q] && moves[q].iter().any(|t| t.iter().any(|&r| live[r])) {
+                    live[q] = true;
+                    changed = true;
+                }
+            }
+        }
+        (0..live.len())
+            .filter(|&q| reachable[q] && live[q])
+            .all(|q| moves[q][0x80..].iter().all(|t| t.iter().all(|&r| !live[r])))
+    }
+
+    // 入力を断片ごとに与えて is_match と同じ判定をする照合器
+    pub fn stream(&self) -> LazyStream<'_> {
+        let mut cache = self.caches.lock().unwrap().pop().unwrap_or_default();
+        let state = cache.search.start(&self.searcher);
+        LazyStream {
+            lazy: self,
+            cache: Some(cache),
+            state,
+            pending: false,
+            matched: false,
+            position: 0,
+        }
+    }
+
+    // これまでに作り、今も覚えている状態の数 (照合中で貸し出しているキャッシュの分は数えない)
+    pub fn cached_states(&self) -> usize {
+        self.caches.lock().unwrap().iter().map(Cache::len).sum()
+    }
+
+    // 状態数が上限に達して覚えた状態を捨てた回数
+    pub fn cache_clears(&self) -> usize {
+        self.caches.lock().unwrap().iter().map(Cache::clears).sum()
+    }
+
+    // キャッシュを借りて f を呼び、終わったら返す。錠は借りるときと返すときにだけ持つ
+    fn with_cache<R, F: FnOnce(&mut Cache) -> R>(&self, f: F) -> R {
+        let mut cache = self.caches.lock().unwrap().pop().unwrap_or_default();
+        let result = f(&mut cache);
+        self.caches.lock().unwrap().push(cache);
+        result
+    }
+
+    // 受理状態に入った位置 (読んだバイト数) のうち、accept_at が true を返す最初のもの
+    fn run<I, F>(&self, nfa: &Nfa, states: &mut States, input: I, accept_at: F) -> Option<usize>
+    where
+        I: IntoIterator<Item = u8>,
+        F: Fn(usize) -> bool,
+    {
+        let mut state = states.start(nfa);
+        if states.accept[0] && accept_at(0) {
+            return Some(0);
+        }
+        for (i, c) in input.into_iter().enumerate() {
+            state = states.next(nfa, self.max_states, state, c);
+            if state == DEAD {
+                return None;
+            }
+            if states.accept[state as usize] && accept_at(i + 1) {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    // from 以降から始まる一致のうち、最も早く終わるものの終わり
+    fn earliest_end(&self, cache: &mut Cache, haystack: &[u8], from: usize) -> Option<usize> {
+        // `^` のあるパターンは入力の先頭からしか始まらない
+        if self.anchors.start && from > 0 {
+            return None;
+        }
+        let input = haystack[from..].iter().cloned();
+        let ends = |n| self.anchors.end_ok(haystack, from + n);
+        self.run(&self.searcher, &mut cache.search, input, ends).map(|n| from + n)
+    }
+
+    // from から始まる最長の一致の終わりのうち ok を満たすもの。max_len より長くは読まない
+    fn longest_at<F: Fn(usize) -> bool>(
+        &self,
+        cache: &mut Cache,
+        haystack: &[u8],
+        from: usize,
+        max_len: Option<usize>,
+        ok: F,
+    ) -> Option<usize> {
+        let states = &mut cache.full;
+        let mut state = states.start(&self.nfa);
+        let last = max_len.map_or(haystack.len(), |max| haystack.len().min(from + max));
+        let mut longest = None;
+        for end in from..=last {
+            if end > from {
+                state = states.next(&self.nfa, self.max_states, state, haystack[end - 1]);
+                if state == DEAD {
+                    break;
+                }
+            }
+            if states.accept[state as usize] && ok(end) {
+                longest = Some(end);
+            }
+        }
+        longest
+    }
+
+    // set から template の word.len() バイト目以降を埋める。埋められれば word に残して true
+    fn fill(
+        &self,
+        states: &mut States,
+        template: &[u8],
+        set: Vec<usize>,
+        word: &mut Vec<u8>,
+        failed: &mut HashSet<(Vec<usize>, usize)>,
+    ) -> bool {
+        let i = word.len();
+        if i == template.len() {
+            let id = states.add(&self.nfa, set);
+            return states.accept[id as usize];
+        }
+        if failed.contains(&(set.clone(), i)) {
+            return false;
+        }
+        let bytes = if template[i] == b'?' {
+            b' '..=b'~'
+        } else {
+            template[i]..=template[i]
+        };
+        for c in bytes {
+            // 状態を作り直すと番号が変わるので、集合から引き直す
+            let id = states.add(&self.nfa, set.clone());
+            let next = states.next(&self.nfa, self.max_states, id, c);
+            if next == DEAD {
+                continue;
+            }
+            let next = states.sets[next as usize].clone();
+            word.push(c);
+            if self.fill(states, template, next, word, failed) {
+                return true;
+            }
+            word.pop();
+        }
+        failed.insert((set, i));
+        false
+    }
+}
+
+// 開始位置が range の中にある最も左で最長の一致を、最も早く終わる一致より前の開始位置から順に探す
+impl Search for LazyDfa {
+    fn find_bounded(
+        &self,
+        haystack: &[u8],
+        range: Range<usize>,
+        anchors: Anchors,
+        max_len: Option<usize>,
+    ) -> Option<Match> {
+        self.with_cache(|cache| {
+            // 最も左の一致は、最も早く終わる一致の終わりより後からは始まらない
+            let earliest = self.earliest_end(cache, haystack, range.start)?;
+            let last = earliest.min(range.end.checked_sub(1)?);
+            for start in (range.start..=last).filter(|&at| anchors.start_ok(haystack, at)) {
+                let ends = |end| anchors.end_ok(haystack, end);
+                if let Some(end) = self.longest_at(cache, haystack, start, max_len, ends) {
+                    return Some(Match { start, end });
+                }
+            }
+            None
+        })
+    }
+}
+
+// LazyDfa::stream が返す照合器。照合が終わるまでキャッシュを借りておく
+// `$` で終わるパターンは入力の終わりが分かるまで判定できないため、finish で結果を確定する
+#[derive(Debug)]
+pub struct LazyStream<'l> {
+    lazy: &'l LazyDfa,
+    cache: Option<Cache>,
+    // 入力の途中から始まる一致を探す NFA の状態 (DEAD ならもう一致しない)
+    state: u32,
+    // ここで入力が終われば一致する
+    pending: bool,
+    matched: bool,
+    position: u64,
+}
+
+impl<'l> LazyStream<'l> {
+    // 続きの入力を読み、一致が確定していれば true を返す
+    pub fn feed(&mut self, chunk: &[u8]) -> bool {
+        self.feed_iter(chunk.iter().cloned())
+    }
+
+    // バイトを一つずつ作る入力を読む。一致が確定したら残りは読まない
+    pub fn feed_iter<I: IntoIterator<Item = u8>>(&mut self, input: I) -> bool {
+        let lazy = self.lazy;
+        let mut input = input.into_iter();
+        loop {
+            self.settle();
+            if self.matched {
+                break;
+            }
+            let c = match input.next() {
+                Some(c) => c,
+                None => break,
+            };
+            self.position += 1;
+            if self.pending && lazy.anchors.multi_line && c == b'\n' {
+                self.matched = true;
+                break;
+            }
+            if self.state != DEAD {
+                let states = &mut self.cache.as_mut().unwrap().search;
+                self.state = states.next(&lazy.searcher, lazy.max_states, self.state, c);
+            }
+        }
+        self.matched
+    }
+
+    // 今の状態が受理状態かを調べる
+    fn settle(&mut self) {
+        let states = &self.cache.as_ref().unwrap().search;
+        self.pending = self.state != DEAD && states.accept[self.state as usize];
+        if self.pending && !self.lazy.anchors.end {
+            self.matched = true;
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // 入力の終わりを知らせ、末尾のアンカーを解決した最終的な判定を返す
+    pub fn finish(mut self) -> bool {
+        if !self.matched {
+            self.settle();
+        }
+        self.matched || self.pending
+    }
+}
+
+impl<'l> Drop for LazyStream<'l> {
+    fn drop(&mut self) {
+        if let Some(cache) = self.cache.take() {
+            self.lazy.caches.lock().unwrap().push(cache);
+        }
+    }
+}
+
+#[test]
+fn lazy_matches_eager() {
+    use regex::Regex;
+    use rng::Rng;
+
+    let patterns = ["(a|b)*a(a|b){6}$", "^ab+c", "x[0-9]+y", "(?m)end$", "^a?$", "a?"];
+    let mut rng = Rng::new(9);
+    for pattern in patterns.iter() {
+        let regex = Regex::new(pattern).unwrap();
+        // 状態を捨てて作り直す経路も通るよう、上限を小さくする
+        let lazy = LazyDfa::with_max_states(pattern, 4).unwrap();
+        for _ in 0..200 {
+            let len = rng.below(12);
+            let input: String = (0..len).map(|_| *rng.choose(b"abcxy0end\n") as char).collect();
+            assert_eq!(lazy.is_match(&input), regex.is_match(&input), "{} {:?}", pattern, input);
+            assert_eq!(lazy.is_full_match(&input), regex.is_full_match(&input), "{}", pattern);
+            assert_eq!(lazy.find(&input), regex.find(&input), "{} {:?}", pattern, input);
+            let lazy_all: Vec<Match> = lazy.find_iter(&input).collect();
+            let all: Vec<Match> = regex.find_iter(&input).collect();
+            assert_eq!(lazy_all, all, "{} {:?}", pattern, input);
+            assert_eq!(lazy.matches_prefix(&input), regex.matches_prefix(&input));
+            assert_eq!(lazy.matches_suffix(&input), regex.matches_suffix(&input));
+            assert_eq!(lazy.strip_prefix_match(&input), regex.dfa().strip_prefix_match(&input));
+            let mut stream = lazy.stream();
+            let matched = input.as_bytes().chunks(3).any(|chunk| stream.feed(chunk));
+            assert_eq!(matched || stream.finish(), regex.is_match(&input), "{}", pattern);
+        }
+        for template in &["????", "a??", "x?y", "?"] {
+            assert_eq!(lazy.fill_template(template), regex.fill_template(template));
+        }
+        assert_eq!(lazy.is_ascii_only(), regex.is_ascii_only(), "{}", pattern);
+    }
+    assert!(!LazyDfa::new("a.b").unwrap().is_ascii_only());
+
+    // 前もって決定化すると 2^21 個の状態が要るパターン
+    let lazy = LazyDfa::with_max_states(".*a.{20}b", 64).unwrap();
+    let prefix: String = (0..400).map(|_| *rng.choose(b"ab") as char).collect();
+    assert!(!lazy.is_full_match(&format!("{}a{}c", prefix, "b".repeat(20))));
+    assert!(lazy.is_full_match(&format!("{}a{}b", prefix, "c".repeat(20))));
+    assert!(lazy.cache_clears() > 0);
+    assert!(lazy.cached_states() <= 64);
+}
+
+#[test]
+fn lazy_caches_per_thread() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let lazy = Arc::new(LazyDfa::new("a.{12}b").unwrap());
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let lazy = lazy.clone();
+            thread::spawn(move || {
+                let haystack = format!("{}a{}b", "b".repeat(i * 7), "c".repeat(12));
+                let m = lazy.find(&haystack).unwrap();
+                (m.start, lazy.is_match(&haystack[..haystack.len() - 1]))
+            })
+        })
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap(), (i * 7, false));
+    }
+    // 照合が終わったキャッシュは返っている
+    assert!(lazy.cached_states() > 0);
+}
@@ -1,3 +1,65 @@
-extern crate regparser;
+#[cfg(feature = "memmap2")]
+extern crate memmap2;
+#[cfg(feature = "petgraph")]
+extern crate petgraph;
+#[cfg(feature = "rustc-hash")]
+extern crate rustc_hash;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(test)]
+extern crate serde_json;
+#[cfg(feature = "serde")]
+extern crate toml;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
+#[macro_use]
+mod trace;
+#[macro_use]
+mod validate;
+mod annotation;
+pub mod ascii;
+pub mod ast;
+pub mod binary;
+pub mod classes;
+pub mod codegen;
+pub mod dense;
 pub mod dfa;
+pub mod dna;
+mod elimination;
+pub mod error;
+pub mod events;
+pub mod frontend;
+pub mod gen;
+mod lazy;
+pub mod lint;
+pub mod literal;
+pub mod monoid;
 pub mod nfa;
+pub mod parser;
+pub mod pikevm;
+pub mod profile;
+mod regex;
+pub mod rng;
+#[cfg(feature = "serde")]
+pub mod rulepack;
+pub mod roundtrip;
+pub mod sample;
+mod scanner;
+mod set;
+pub mod shiftor;
+mod simd;
+pub mod sketch;
+pub mod sparse;
+pub mod testutil;
+pub mod walk;
+
+pub use error::{Error, FormatError};
+pub use lazy::LazyDfa;
+pub use pikevm::Captures;
+pub use regex::{
+    CompileStats, EngineKind, LineMatch, LineMatches, MappedFile, OptLevel, Record, Regex,
+    RegexBuilder, Split, StreamMatcher,
+};
+pub use scanner::{Scanner, Tokens};
+pub use set::{PatternMetadata, RegexSet, SetMatches};
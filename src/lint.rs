@@ -0,0 +1,287 @@
+use ast::{self, Ast, GroupFlags};
+use dfa::{Dfa, Match};
+use error::Error;
+use nfa::{split_anchors, split_flags, strip_verbose, syntax_tree};
+use pikevm::PikeVm;
+use regex::Regex;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    // 部分一致で使うと、より長い入力の一部にも一致してしまう
+    Unanchored { example: Vec<u8> },
+    // 上限のない繰り返しが、コーパスでは observed 回までしか繰り返されない
+    UnboundedRepetition {
+        repetition: String,
+        observed: usize,
+        suggestion: String,
+    },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Lint::Unanchored { ref example } => write!(
+                f,
+                "pattern matches as a substring of longer inputs, e.g. {:?}",
+                String::from_utf8_lossy(example)
+            ),
+            Lint::UnboundedRepetition {
+                ref repetition,
+                observed,
+                ref suggestion,
+            } => write!(
+                f,
+                "{} repeats at most {} times in the corpus, consider {}",
+                repetition, observed, suggestion
+            ),
+        }
+    }
+}
+
+// 最短の受理文字列の前後に 1 バイト足した、受理されない上位文字列を探す
+pub fn unanchored(dfa: &Dfa) -> Option<Lint> {
+    let word = dfa.shortest_accepted()?;
+    let padding = [b'x', b'0', b' ', b'-', b'_'];
+    padding
+        .iter()
+        .cloned()
+        .chain(0..=255)
+        .map(|pad| {
+            let mut example = vec![pad];
+            example.extend_from_slice(&word);
+            example.push(pad);
+            example
+        })
+        .find(|example| !dfa.accept_bytes(example))
+        .map(|example| Lint::Unanchored { example })
+}
+
+pub fn check(dfa: &Dfa) -> Vec<Lint> {
+    unanchored(dfa).into_iter().collect()
+}
+
+// 上限のない繰り返しにコーパスで見た回数から上限を付けたパターンと、コーパスでの比較
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tightening {
+    pub pattern: String,
+    // 上限を付けた繰り返しごとの Lint::UnboundedRepetition
+    pub lints: Vec<Lint>,
+    // 元のパターンと一致の範囲が違ったコーパスの入力の添字
+    pub mismatches: Vec<usize>,
+    pub corpus_size: usize,
+}
+
+impl Tightening {
+    pub fn is_equivalent_on_corpus(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+// 繰り返しの本体が一致するバイト数が常に同じならその数
+fn fixed_width(ast: &Ast) -> Option<usize> {
+    match *ast {
+        Ast::Literal(_) | Ast::Dot | Ast::Class(_) => Some(1),
+        Ast::Group { ref ast, .. } | Ast::NonCapturing { ref ast, .. } => fixed_width(ast),
+        Ast::Concat(ref asts) => asts
+            .iter()
+            .try_fold(0, |sum, ast| fixed_width(ast).map(|width| sum + width)),
+        Ast::Union(ref asts) => {
+            let first = fixed_width(&asts[0])?;
+            if asts[1..].iter().all(|ast| fixed_width(ast) == Some(first)) {
+                Some(first)
+            } else {
+                None
+            }
+        }
+        Ast::Repeat { ref ast, min, max } if max == Some(min) => {
+            fixed_width(ast).map(|width| width * min)
+        }
+        _ => None,
+    }
+}
+
+// 上限のない繰り返しの本体と下限 (本体の幅が一定のものだけ。幅から回数を求めるため)
+fn unbounded(ast: &Ast) -> Option<(&Ast, usize)> {
+    let (body, min) = match *ast {
+        Ast::Star(ref body) => (&**body, 0),
+        Ast::Plus(ref body) => (&**body, 1),
+        Ast::Repeat {
+            ref ast,
+            min,
+            max: None,
+        } => (&**ast, min),
+        _ => return None,
+    };
+    match fixed_width(body) {
+        Some(width) if width > 0 => Some((body, min)),
+        _ => None,
+    }
+}
+
+// 他の繰り返しの中にない上限のない繰り返しを f で置き換えた構文木
+// 捕獲グループの範囲は最後の繰り返しの分しか分からないので、繰り返しの中のものは対象にしない
+// strip_groups なら元の捕獲グループは捕獲しないグループにする
+fn replace_unbounded<F>(ast: &Ast, repeated: bool, strip_groups: bool, f: &mut F) -> Ast
+where
+    F: FnMut(&Ast) -> Ast,
+{
+    if !repeated && unbounded(ast).is_some() {
+        return f(ast);
+    }
+    let mut inner =
+        |ast: &Ast, repeated| Box::new(replace_unbounded(ast, repeated, strip_groups, f));
+    match *ast {
+        Ast::Literal(_) | Ast::Dot | Ast::Class(_) => ast.clone(),
+        Ast::Group { ref ast, .. } if strip_groups => Ast::NonCapturing {
+            flags: GroupFlags::default(),
+            ast: inner(ast, repeated),
+        },
+        Ast::Group { ref name, ref ast } => Ast::Group {
+            name: name.clone(),
+            ast: inner(ast, repeated),
+        },
+        Ast::NonCapturing { flags, ref ast } => Ast::NonCapturing {
+            flags,
+            ast: inner(ast, repeated),
+        },
+        Ast::Concat(ref asts) => {
+            Ast::Concat(asts.iter().map(|ast| *inner(ast, repeated)).collect())
+        }
+        Ast::Union(ref asts) => Ast::Union(asts.iter().map(|ast| *inner(ast, repeated)).collect()),
+        Ast::ZeroOne(ref ast) => Ast::ZeroOne(inner(ast, repeated)),
+        Ast::Star(ref ast) => Ast::Star(inner(ast, true)),
+        Ast::Plus(ref ast) => Ast::Plus(inner(ast, true)),
+        Ast::Repeat { ref ast, min, max } => Ast::Repeat {
+            ast: inner(ast, true),
+            min,
+            max,
+        },
+    }
+}
+
+// 上限のない繰り返しに、コーパスの一致で繰り返された最大の回数を 2 の冪に切り上げた上限を付ける
+// 書き換えたパターンとの一致の範囲をコーパスの各入力で比べた結果も返す
+// 上限を付けられる繰り返しがコーパスの一致に一度も現れなければ None
+pub fn tighten(pattern: &str, corpus: &[&str]) -> Result<Option<Tightening>, Error> {
+    let regex = Regex::new(pattern)?;
+    let root = syntax_tree(pattern)?;
+    let flags = split_flags(pattern.trim()).0;
+
+    // 各繰り返しを捕獲グループで囲み、一致の中で繰り返しが占める範囲から回数を求める
+    let mut repetitions = Vec::new();
+    let probe = replace_unbounded(&root, false, true, &mut |ast: &Ast| {
+        repetitions.push(ast.clone());
+        let ast = replace_unbounded(ast, true, true, &mut |ast: &Ast| ast.clone());
+        Ast::Group {
+            name: None,
+            ast: Box::new(ast),
+        }
+    });
+    let vm = PikeVm::new(&probe, flags);
+    let mut observed: Vec<Option<usize>> = vec![None; repetitions.len()];
+    for input in corpus.iter() {
+        for m in regex.find_iter(input) {
+            let captures = match vm.captures(input.as_bytes(), m) {
+                Some(captures) => captures,
+                None => continue,
+            };
+            for (i, repetition) in repetitions.iter().enumerate() {
+                if let Some(Match { start, end }) = captures.get(i + 1) {
+                    let width = fixed_width(unbounded(repetition).unwrap().0).unwrap();
+                    observed[i] = observed[i].max(Some((end - start) / width));
+                }
+            }
+        }
+    }
+    if observed.iter().all(|observed| observed.is_none()) {
+        return Ok(None);
+    }
+
+    let mut lints = Vec::new();
+    let mut next = 0;
+    let tightened = replace_unbounded(&root, false, false, &mut |ast: &Ast| {
+        let i = next;
+        next += 1;
+        let observed = match observed[i] {
+            Some(observed) => observed,
+            None => return ast.clone(),
+        };
+        let (body, min) = unbounded(ast).unwrap();
+        let bounded = Ast::Repeat {
+            ast: Box::new(body.clone()),
+            min,
+            max: Some(observed.max(min).max(1).next_power_of_two()),
+        };
+        lints.push(Lint::UnboundedRepetition {
+            repetition: ast::to_pattern(ast),
+            observed,
+            suggestion: ast::to_pattern(&bounded),
+        });
+        bounded
+    });
+    let (start, _, end) = split_anchors(&strip_verbose(pattern));
+    let rewritten = format!(
+        "{}{}{}{}",
+        flags.to_inline(),
+        if start { "^" } else { "" },
+        ast::to_pattern(&tightened),
+        if end { "$" } else { "" }
+    );
+    let tightened = Regex::new(&rewritten)?;
+    let mismatches = corpus
+        .iter()
+        .enumerate()
+        .filter(|&(_, input)| !regex.find_iter(input).eq(tightened.find_iter(input)))
+        .map(|(i, _)| i)
+        .collect();
+    Ok(Some(Tightening {
+        pattern: rewritten,
+        lints,
+        mismatches,
+        corpus_size: corpus.len(),
+    }))
+}
+
+#[test]
+fn unanchored_lint() {
+    use nfa::Nfa;
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(a|b)c*").unwrap()).unwrap();
+    let lint = unanchored(&dfa).unwrap();
+    assert_eq!(
+        lint,
+        Lint::Unanchored {
+            example: b"xax".to_vec()
+        }
+    );
+    assert_eq!(
+        lint.to_string(),
+        "pattern matches as a substring of longer inputs, e.g. \"xax\""
+    );
+    assert!(check(&Dfa::nfa2dfa(&Nfa::re2nfa("(?s).*").unwrap()).unwrap()).is_empty());
+}
+
+#[test]
+fn tighten_repetitions() {
+    let corpus = ["key=value", "name=alice bob", "x=", "no match here", "id=42"];
+    let tightening = tighten("^[a-z]+=(.*)$", &corpus).unwrap().unwrap();
+    assert_eq!(tightening.pattern, "^[a-z]{1,4}=(.{0,16})$");
+    assert_eq!(
+        tightening.lints[0].to_string(),
+        "[a-z]+ repeats at most 4 times in the corpus, consider [a-z]{1,4}"
+    );
+    assert_eq!(tightening.lints.len(), 2);
+    assert!(tightening.is_equivalent_on_corpus());
+    assert_eq!(tightening.corpus_size, 5);
+
+    // コーパスに合わせた上限が、コーパス以外の入力で違いを生むこともある
+    let re = Regex::new(&tightening.pattern).unwrap();
+    assert!(!re.is_match("name=a very long value that goes past sixteen bytes"));
+
+    // 繰り返しの中の繰り返しと、本体の長さが変わる繰り返しには上限を付けない
+    assert_eq!(tighten("(a+b)*|(ab|c)*", &["aab", "abc"]).unwrap(), None);
+    // 見た回数は 2 の冪に切り上げる
+    let tightening = tighten("a*", &["aaa"]).unwrap().unwrap();
+    assert_eq!(tightening.pattern, "a{0,4}");
+    assert!(tightening.is_equivalent_on_corpus());
+}
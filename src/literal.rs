@@ -0,0 +1,154 @@
+use dfa::{Anchors, Dfa, Match};
+use std::cmp::Reverse;
+
+// 受理言語がただ一つの文字列からなるパターン向けの照合器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiteralMatcher {
+    needle: Vec<u8>,
+}
+
+impl LiteralMatcher {
+    pub fn new(needle: &[u8]) -> Self {
+        LiteralMatcher {
+            needle: needle.to_vec(),
+        }
+    }
+
+    // DFA の受理言語が一文字列のときのみ作る
+    pub fn from_dfa(dfa: &Dfa) -> Option<Self> {
+        match dfa.finite_language(1) {
+            Some(ref words) if words.len() == 1 => Some(LiteralMatcher::new(&words[0])),
+            _ => None,
+        }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    pub fn find(&self, haystack: &[u8]) -> Option<Match> {
+        if self.needle.is_empty() {
+            return Some(Match { start: 0, end: 0 });
+        }
+        haystack
+            .windows(self.needle.len())
+            .position(|window| window == &self.needle[..])
+            .map(|start| Match {
+                start,
+                end: start + self.needle.len(),
+            })
+    }
+
+    // anchors の制約を満たす最も左の一致
+    pub fn find_anchored(&self, haystack: &[u8], anchors: Anchors) -> Option<Match> {
+        let last = haystack.len().checked_sub(self.needle.len())?;
+        // 複数行でなければ `^` と `$` で一致の位置が一つに決まる
+        let first = if anchors.end && !anchors.multi_line { last } else { 0 };
+        let last = if anchors.start && !anchors.multi_line { 0 } else { last };
+        (first..=last)
+            .filter(|&start| haystack[start..].starts_with(&self.needle))
+            .map(|start| Match {
+                start,
+                end: start + self.needle.len(),
+            })
+            .find(|m| anchors.start_ok(haystack, m.start) && anchors.end_ok(haystack, m.end))
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+
+    // 入力全体が一致するか
+    pub fn is_full_match(&self, haystack: &[u8]) -> bool {
+        haystack == &self.needle[..]
+    }
+
+    pub fn is_prefix_of(&self, haystack: &[u8]) -> bool {
+        haystack.starts_with(&self.needle)
+    }
+}
+
+// 受理言語が少数の文字列からなるパターン向けの照合器。各位置で文字列を直接比べる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiteralSet {
+    // 同じ位置では最長の一致を選ぶので長い順に並べる
+    words: Vec<Vec<u8>>,
+}
+
+impl LiteralSet {
+    pub fn new(mut words: Vec<Vec<u8>>) -> Self {
+        words.sort_by_key(|word| Reverse(word.len()));
+        LiteralSet { words }
+    }
+
+    // DFA の受理言語が 1 個以上 limit 個以下の文字列のときのみ作る
+    pub fn from_dfa(dfa: &Dfa, limit: usize) -> Option<Self> {
+        match dfa.finite_language(limit) {
+            Some(words) if !words.is_empty() => Some(LiteralSet::new(words)),
+            _ => None,
+        }
+    }
+
+    pub fn words(&self) -> &[Vec<u8>] {
+        &self.words
+    }
+
+    // anchors の制約を満たす最も左で最長の一致
+    pub fn find_anchored(&self, haystack: &[u8], anchors: Anchors) -> Option<Match> {
+        let last = if anchors.start && !anchors.multi_line { 0 } else { haystack.len() };
+        (0..=last)
+            .filter(|&start| anchors.start_ok(haystack, start))
+            .find_map(|start| {
+                self.words
+                    .iter()
+                    .filter(|word| haystack[start..].starts_with(word))
+                    .map(|word| start + word.len())
+                    .find(|&end| anchors.end_ok(haystack, end))
+                    .map(|end| Match { start, end })
+            })
+    }
+}
+
+#[test]
+fn literal_matcher_from_dfa() {
+    use nfa::Nfa;
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(ab)(c)").unwrap()).unwrap();
+    let matcher = LiteralMatcher::from_dfa(&dfa).unwrap();
+    assert_eq!(matcher.needle(), b"abc");
+    assert_eq!(matcher.find(b"xxabcx"), Some(Match { start: 2, end: 5 }));
+    assert!(matcher.is_full_match(b"abc"));
+    assert!(matcher.is_prefix_of(b"abcd"));
+    assert!(!matcher.is_match(b"abd"));
+
+    let anchors = |start, end, multi_line| Anchors {
+        start,
+        end,
+        multi_line,
+    };
+    assert_eq!(matcher.find_anchored(b"abcabc", anchors(false, true, false)).unwrap().start, 3);
+    assert_eq!(matcher.find_anchored(b"abcabc", anchors(true, true, false)), None);
+    let m = matcher.find_anchored(b"x\nabc\nabc", anchors(true, true, true));
+    assert_eq!(m, Some(Match { start: 2, end: 5 }));
+
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a|b").unwrap()).unwrap();
+    assert!(LiteralMatcher::from_dfa(&dfa).is_none());
+}
+
+#[test]
+fn literal_set_from_dfa() {
+    use nfa::Nfa;
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("ab|abcd|cd").unwrap()).unwrap();
+    let set = LiteralSet::from_dfa(&dfa, 16).unwrap();
+    assert_eq!(set.words().len(), 3);
+    let unanchored = Anchors::default();
+    assert_eq!(set.find_anchored(b"xabcdx", unanchored), Some(Match { start: 1, end: 5 }));
+    assert_eq!(set.find_anchored(b"xabcx", unanchored), Some(Match { start: 1, end: 3 }));
+    let end = Anchors {
+        end: true,
+        ..Anchors::default()
+    };
+    assert_eq!(set.find_anchored(b"abcdab", end), Some(Match { start: 4, end: 6 }));
+    assert!(LiteralSet::from_dfa(&dfa, 2).is_none());
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a+").unwrap()).unwrap();
+    assert!(LiteralSet::from_dfa(&dfa, 16).is_none());
+}
@@ -1,18 +1,384 @@
-extern crate regparser;
-pub mod converter;
-pub mod dfa;
-pub mod monoid;
-pub mod nfa;
+extern crate regex;
 
-use dfa::Dfa;
-use monoid::{Monoid, TransitionPat};
-use nfa::Nfa;
+use regex::ast;
+use regex::dfa::Dfa;
+use regex::gen;
+use regex::monoid::Monoid;
+use regex::nfa::{split_flags, Nfa};
+use regex::roundtrip;
+use regex::sparse::SparseDfa;
+use regex::{Error, MappedFile, Regex};
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
+
+const USAGE: &str = "usage:
+    re match <pattern> [<input>]
+    re grep <pattern> <path>...
+    re dot <pattern> [-o <path>] [--no-minimize] [--all]
+    re minimize <pattern>
+    re monoid <pattern> [--no-minimize]
+    re explain <pattern>
+    re dump <pattern> --stage nfa|dfa|min|monoid [--format json]
+    re roundtrip <pattern>
+    re gen divisible-by <n> [--base <b>] [-o <path>]
+
+<pattern> or <input> may be `-` to read from stdin, but not both.
+A pattern read from stdin or a file may span several lines, which are joined.
+In `(?x)` mode whitespace and `#` comments up to the end of a line are ignored.
+`grep` prints the matching lines of each file with their line numbers.
+`dot --all` draws the NFA, DFA and minimized DFA side by side in one file.
+`gen` prints the generated DFA in dot format unless -o is given.
+`dump` prints the chosen stage of the compile pipeline as JSON.
+`roundtrip` converts the minimized DFA back to a pattern and checks that it is equivalent.
+Use `--pattern-file <path>` instead of <pattern> to read the pattern from a file.";
+
+struct Options {
+    command: String,
+    positional: Vec<String>,
+    pattern_file: Option<String>,
+    output: Option<String>,
+    base: Option<String>,
+    stage: Option<String>,
+    format: Option<String>,
+    minimize: bool,
+    all: bool,
+}
+
+fn usage_error(message: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}\n\n{}", message, USAGE),
+    ))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, Error> {
+    let mut args = args.iter();
+    let command = match args.next() {
+        Some(command) => command.clone(),
+        None => return Err(usage_error("missing subcommand")),
+    };
+    let mut options = Options {
+        command,
+        positional: Vec::new(),
+        pattern_file: None,
+        output: None,
+        base: None,
+        stage: None,
+        format: None,
+        minimize: true,
+        all: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => options.output = Some(path.clone()),
+                None => return Err(usage_error("-o requires a path")),
+            },
+            "--pattern-file" => match args.next() {
+                Some(path) => options.pattern_file = Some(path.clone()),
+                None => return Err(usage_error("--pattern-file requires a path")),
+            },
+            "--base" => match args.next() {
+                Some(base) => options.base = Some(base.clone()),
+                None => return Err(usage_error("--base requires a number")),
+            },
+            "--stage" => match args.next() {
+                Some(stage) => options.stage = Some(stage.clone()),
+                None => return Err(usage_error("--stage requires nfa, dfa, min or monoid")),
+            },
+            "--format" => match args.next() {
+                Some(format) => options.format = Some(format.clone()),
+                None => return Err(usage_error("--format requires a format")),
+            },
+            "--no-minimize" => options.minimize = false,
+            "--all" => options.all = true,
+            _ => options.positional.push(arg.clone()),
+        }
+    }
+    Ok(options)
+}
+
+fn read_stdin() -> io::Result<String> {
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+    Ok(source)
+}
+
+// 複数行のパターンを一行に連結する
+// `(?x)` のパターンでは `#` から行末までがコメントなので、改行を残して strip_verbose に任せる
+fn join_pattern_lines(source: &str) -> String {
+    if split_flags(source.trim()).0.verbose {
+        return source.trim().to_owned();
+    }
+    source.lines().collect()
+}
+
+// パターンを stdin から読むか
+fn reads_pattern_from_stdin(options: &Options) -> bool {
+    match options.pattern_file {
+        Some(ref path) => path == "-",
+        None => options.positional.first().is_some_and(|pattern| pattern == "-"),
+    }
+}
+
+// パターンを取り出し、残りの位置引数を返す
+fn read_pattern(options: &Options) -> Result<(String, &[String]), Error> {
+    if let Some(ref path) = options.pattern_file {
+        let source = if path == "-" {
+            read_stdin()?
+        } else {
+            fs::read_to_string(path)?
+        };
+        return Ok((join_pattern_lines(&source), &options.positional[..]));
+    }
+    match options.positional.split_first() {
+        Some((pattern, rest)) if pattern == "-" => Ok((join_pattern_lines(&read_stdin()?), rest)),
+        Some((pattern, rest)) => Ok((pattern.clone(), rest)),
+        None => Err(usage_error("missing pattern")),
+    }
+}
+
+fn compile(regex: &str, minimize: bool) -> Result<Dfa, Error> {
+    let nfa = Nfa::re2nfa(regex)?;
+    let mut dfa = Dfa::nfa2dfa(&nfa)?;
+    if minimize {
+        dfa.minimize();
+    }
+    Ok(dfa)
+}
+
+// NFA、DFA、最小化した DFA を一つの dot ファイルに並べて書く
+fn write_pipeline(regex: &str, path: &str) -> Result<(), Error> {
+    let nfa = Nfa::re2nfa(regex)?;
+    let dfa = Dfa::nfa2dfa(&nfa)?;
+    let minimized = compile(regex, true)?;
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    writeln!(out, "digraph G {{\nrankdir=LR;")?;
+    nfa.write_dot_cluster(&mut out, "nfa", "NFA")?;
+    dfa.write_dot_cluster(&mut out, "dfa", "DFA")?;
+    minimized.write_dot_cluster(&mut out, "min", "minimized DFA")?;
+    out.write_all(b"}\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn json_list<T, F: Fn(&T) -> String>(items: &[T], item: F) -> String {
+    let items: Vec<String> = items.iter().map(item).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_option(value: Option<usize>) -> String {
+    value.map_or("null".to_owned(), |value| value.to_string())
+}
+
+// NFA の遷移は入力バイト (ε 遷移は null) と遷移先の集合の組
+fn nfa_json(nfa: &Nfa) -> String {
+    let states = json_list(&nfa.states, |state| {
+        let transitions: Vec<String> = state
+            .transition
+            .iter()
+            .enumerate()
+            .filter_map(|(label, to)| to.as_ref().map(|to| (label, to)))
+            .map(|(label, to)| {
+                let label = if label == 256 { None } else { Some(label) };
+                let to = json_list(&to.sorted(), |id| id.to_string());
+                format!("{{\"label\":{},\"to\":{}}}", json_option(label), to)
+            })
+            .collect();
+        format!(
+            "{{\"id\":{},\"accept\":{},\"transitions\":[{}]}}",
+            state.id,
+            state.accept,
+            transitions.join(",")
+        )
+    });
+    format!("{{\"stage\":\"nfa\",\"start\":0,\"states\":{}}}", states)
+}
+
+// DFA の遷移は同じ遷移先へ続くバイトの区間 lo..=hi ごとにまとめる
+fn dfa_json(stage: &str, dfa: &Dfa) -> String {
+    let sparse = SparseDfa::from_dfa(dfa);
+    let states = json_list(&sparse.states, |state| {
+        let runs = json_list(&state.runs, |run| {
+            format!("{{\"lo\":{},\"hi\":{},\"to\":{}}}", run.lo, run.hi, run.next)
+        });
+        format!("{{\"accept\":{},\"transitions\":{}}}", state.accept, runs)
+    });
+    format!(
+        "{{\"stage\":\"{}\",\"start\":{},\"states\":{}}}",
+        stage,
+        dfa.start(),
+        states
+    )
+}
+
+fn monoid_json(monoid: &Monoid) -> String {
+    let generators: Vec<String> = monoid
+        .char_morphism()
+        .iter()
+        .enumerate()
+        .filter_map(|(c, element)| element.map(|element| (c, element)))
+        .map(|(c, element)| format!("{{\"byte\":{},\"element\":{}}}", c, element))
+        .collect();
+    let table = json_list(monoid.multiply_table(), |row| json_list(row, |x| x.to_string()));
+    format!(
+        "{{\"stage\":\"monoid\",\"size\":{},\"aperiodic\":{},\"generators\":[{}],\"multiply\":{}}}",
+        monoid.size(),
+        monoid.is_aperiodic(),
+        generators.join(","),
+        table
+    )
+}
+
+// コンパイルの途中の表現を外部のツール向けに書き出す
+fn dump(regex: &str, options: &Options) -> Result<String, Error> {
+    match options.format.as_deref() {
+        None | Some("json") => {}
+        Some(format) => return Err(usage_error(&format!("unknown format {:?}", format))),
+    }
+    match options.stage.as_deref() {
+        Some("nfa") => Ok(nfa_json(&Nfa::re2nfa(regex)?)),
+        Some("dfa") => Ok(dfa_json("dfa", &compile(regex, false)?)),
+        Some("min") => Ok(dfa_json("min", &compile(regex, true)?)),
+        Some("monoid") => Ok(monoid_json(&Monoid::construct(&compile(regex, true)?))),
+        Some(stage) => Err(usage_error(&format!("unknown stage {:?}", stage))),
+        None => Err(usage_error("dump requires --stage")),
+    }
+}
+
+fn parse_number(arg: &str) -> Result<usize, Error> {
+    arg.parse()
+        .map_err(|_| usage_error(&format!("expected a number, got {:?}", arg)))
+}
+
+fn generate(options: &Options) -> Result<(), Error> {
+    let dfa = match options.positional.split_first() {
+        Some((kind, rest)) if kind == "divisible-by" => {
+            let n = match rest.first() {
+                Some(n) => parse_number(n)?,
+                None => return Err(usage_error("divisible-by requires <n>")),
+            };
+            let base = match options.base {
+                Some(ref base) => parse_number(base)?,
+                None => 10,
+            };
+            gen::divisible_by(n, base)?
+        }
+        Some((kind, _)) => return Err(usage_error(&format!("unknown generator {:?}", kind))),
+        None => return Err(usage_error("missing generator")),
+    };
+    match options.output {
+        Some(ref output) => dfa.write(output)?,
+        None => println!("{}", dfa.dot()),
+    }
+    Ok(())
+}
+
+// 一致しなかった場合は Ok(false) を返す
+fn run(args: &[String]) -> Result<bool, Error> {
+    let options = parse_args(args)?;
+    if options.command == "gen" {
+        generate(&options)?;
+        return Ok(true);
+    }
+    if options.command == "match" && reads_pattern_from_stdin(&options) {
+        // 入力を省略した場合も stdin から読む
+        let input = if options.pattern_file.is_some() { 0 } else { 1 };
+        if !matches!(options.positional.get(input), Some(input) if input != "-") {
+            return Err(usage_error("<pattern> and <input> cannot both be read from stdin"));
+        }
+    }
+    let (regex, rest) = read_pattern(&options)?;
+    match options.command.as_str() {
+        "match" => {
+            let input = match rest.first() {
+                Some(input) if input != "-" => input.clone(),
+                _ => read_stdin()?,
+            };
+            let matched = Regex::new(&regex)?.is_match(&input);
+            println!("{}", if matched { "match" } else { "no match" });
+            return Ok(matched);
+        }
+        "grep" => {
+            if rest.is_empty() {
+                return Err(usage_error("missing <path>"));
+            }
+            let regex = Regex::new(&regex)?;
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            let mut matched = false;
+            for path in rest.iter() {
+                let file = MappedFile::open(path)?;
+                for line in regex.find_in_file(&file) {
+                    matched = true;
+                    if rest.len() > 1 {
+                        write!(out, "{}:", path)?;
+                    }
+                    writeln!(out, "{}:{}", line.number, String::from_utf8_lossy(line.text))?;
+                }
+            }
+            out.flush()?;
+            return Ok(matched);
+        }
+        "dot" if options.all => {
+            let output = options.output.as_ref().map_or("pipeline.dot", |o| o.as_str());
+            write_pipeline(&regex, output)?;
+        }
+        "dot" => {
+            let dfa = compile(&regex, options.minimize)?;
+            let output = options.output.as_ref().map_or("dfa.dot", |o| o.as_str());
+            dfa.write(output)?;
+        }
+        "minimize" => {
+            let dfa = compile(&regex, false)?;
+            let minimized = compile(&regex, true)?;
+            println!(
+                "{} states -> {} states",
+                dfa.states.len(),
+                minimized.states.len()
+            );
+        }
+        "monoid" => {
+            let monoid = Monoid::construct(&compile(&regex, options.minimize)?);
+            println!("size: {}", monoid.size());
+            println!("aperiodic: {}", monoid.is_aperiodic());
+        }
+        "dump" => println!("{}", dump(&regex, &options)?),
+        "roundtrip" => {
+            let result = roundtrip::check(&regex, 1000, 0)?;
+            match result.regenerated {
+                Some(ref regenerated) => println!("regenerated: {}", regenerated),
+                None => println!("regenerated: (empty or only the empty string)"),
+            }
+            if let Some(ref input) = result.counterexample {
+                println!("counterexample: {:?}", String::from_utf8_lossy(input));
+            }
+            println!(
+                "corpus: {} of {} inputs disagree",
+                result.corpus_mismatches, result.corpus_size
+            );
+            println!("{}", if result.is_equivalent() { "equivalent" } else { "not equivalent" });
+            return Ok(result.is_equivalent());
+        }
+        "explain" => match ast::explain_pattern(&regex) {
+            Some(explanation) => print!("{}", explanation),
+            None => return Err(Error::Parse(regex)),
+        },
+        command => return Err(usage_error(&format!("unknown subcommand {:?}", command))),
+    }
+    Ok(true)
+}
 
 fn main() {
-    let regex = "(a|ba)*";
-    let nfa = Nfa::re2nfa(regex);
-    let mut dfa = Dfa::nfa2dfa(&nfa);
-    dfa.minimize();
-    dfa.write("dfa.dot");
-    Monoid::construct(&dfa);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    }
 }
@@ -1,4 +1,6 @@
 use dfa::Dfa;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 
 #[derive(Hash, Clone, Eq, PartialEq, Debug)]
@@ -31,6 +33,7 @@ impl TransitionPat {
 
 type Element = usize;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Monoid {
     multiply_table: Vec<Vec<usize>>,
     char_morphism: Vec<Option<usize>>,
@@ -38,6 +41,7 @@ pub struct Monoid {
 
 impl Monoid {
     pub fn construct(dfa: &Dfa) -> Self {
+        let _span = trace_span!(DEBUG, "monoid", dfa_states = dfa.states.len());
         let mut queue = VecDeque::new();
         let ident = TransitionPat::identity(dfa.states.len());
         let mut transitions_map = HashMap::new();
@@ -53,7 +57,7 @@ impl Monoid {
                 for i in 0..dfa.states.len() + 1 {
                     next.pat[i] = if pat.pat[i] == dfa.states.len() {
                         dfa.states.len()
-                    } else if let Some(next_state) = dfa.states[pat.pat[i]].t[c] {
+                    } else if let Some(next_state) = dfa.transition(pat.pat[i], c as u8) {
                         next_state
                     } else {
                         dfa.states.len()
@@ -88,6 +92,7 @@ impl Monoid {
                 multiply_table[i][j] = calc_result;
             }
         }
+        trace_event!("built monoid", size = multiply_table.len());
         Monoid {
             multiply_table: multiply_table,
             char_morphism: char_morphism,
@@ -98,7 +103,17 @@ impl Monoid {
         self.multiply_table[x][y]
     }
 
-    fn size(&self) -> usize {
+    // multiply_table()[x][y] は元 x と y の積。0 番は単位元
+    pub fn multiply_table(&self) -> &[Vec<usize>] {
+        &self.multiply_table
+    }
+
+    // 各バイトが表す元。単位元と同じ変換になるバイトや、他のバイトと同じ元になるバイトは None
+    pub fn char_morphism(&self) -> &[Option<usize>] {
+        &self.char_morphism
+    }
+
+    pub fn size(&self) -> usize {
         self.multiply_table.len()
     }
 
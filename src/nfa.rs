@@ -1,9 +1,17 @@
 use self::Label::*;
-use regparser::parser::{Lexer, Node, NodeType, Parser};
+use ast::{self, Ast};
+use error::Error;
+use parser::{self, NamedClasses};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::io::{BufWriter, Write};
+#[cfg(feature = "rustc-hash")]
+use std::hash::BuildHasherDefault;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
 
 #[derive(PartialEq, Eq, Hash)]
 pub enum Label {
@@ -12,11 +20,14 @@ pub enum Label {
     Dot,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct State {
     pub transition: Vec<Option<StateSet>>,
     pub id: usize,
     pub accept: bool,
+    // 複数のパターンをまとめた NFA で、この受理状態がどのパターンのものか
+    pub pattern: Option<usize>,
 }
 
 impl State {
@@ -42,29 +53,328 @@ impl State {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Nfa {
     pub states: Vec<State>,
 }
 
+// パターン先頭の inline flag (`(?ix)` など) で指定するコンパイル時の設定
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    // `i`: ASCII の英字の大文字と小文字を区別しない
+    pub case_insensitive: bool,
+    // `x`: 空白と `#` から行末までのコメントを無視する
+    pub verbose: bool,
+    // `s`: `.` が改行にも一致する
+    pub dot_all: bool,
+    // `m`: `^` と `$` が各行の先頭と末尾にも一致する
+    pub multi_line: bool,
+}
+
+impl Flags {
+    // どちらかで有効な設定を有効にする
+    pub fn merge(self, other: Flags) -> Flags {
+        Flags {
+            case_insensitive: self.case_insensitive || other.case_insensitive,
+            verbose: self.verbose || other.verbose,
+            dot_all: self.dot_all || other.dot_all,
+            multi_line: self.multi_line || other.multi_line,
+        }
+    }
+
+    // パターン先頭に付ける `(?is)` のような inline flag。`x` は含めず、有効な flag がなければ空
+    pub fn to_inline(self) -> String {
+        let inline: String = [
+            (self.case_insensitive, 'i'),
+            (self.dot_all, 's'),
+            (self.multi_line, 'm'),
+        ]
+        .iter()
+        .filter(|&&(set, _)| set)
+        .map(|&(_, c)| c)
+        .collect();
+        if inline.is_empty() {
+            inline
+        } else {
+            format!("(?{})", inline)
+        }
+    }
+}
+
+// 先頭の `(?ix)` のような inline flag を読み、フラグと残りのパターンを返す
+pub fn split_flags(regex: &str) -> (Flags, &str) {
+    let mut flags = Flags::default();
+    if !regex.starts_with("(?") {
+        return (flags, regex);
+    }
+    for (i, c) in regex.char_indices().skip(2) {
+        match c {
+            'i' => flags.case_insensitive = true,
+            'x' => flags.verbose = true,
+            's' => flags.dot_all = true,
+            'm' => flags.multi_line = true,
+            ')' if i > 2 => return (flags, &regex[i + 1..]),
+            _ => break,
+        }
+    }
+    (Flags::default(), regex)
+}
+
+// 先頭の inline flag を取り除き、`x` があれば空白と `#` から行末までのコメントも取り除く
+pub fn strip_verbose(regex: &str) -> String {
+    let (flags, regex) = split_flags(regex.trim());
+    if !flags.verbose {
+        return regex.to_owned();
+    }
+    let mut stripped = String::new();
+    let mut chars = regex.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                stripped.push(c);
+                if let Some(escaped) = chars.next() {
+                    stripped.push(escaped);
+                }
+            }
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+// 先頭の `^` と末尾の `$` を取り除き、(`^` の有無, 残りのパターン, `$` の有無) を返す
+pub fn split_anchors(regex: &str) -> (bool, &str, bool) {
+    let (start, regex) = match regex.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, regex),
+    };
+    // 直前の `\` が奇数個なら `$` はエスケープされている
+    match regex.strip_suffix('$') {
+        Some(rest) if rest.chars().rev().take_while(|&c| c == '\\').count() % 2 == 0 => {
+            (start, rest, true)
+        }
+        _ => (start, regex, false),
+    }
+}
+
+// 大文字と小文字を区別しない場合に c と同一視するバイト
+pub fn case_variants(c: u8, flags: Flags) -> Vec<u8> {
+    if flags.case_insensitive && c.is_ascii_alphabetic() {
+        vec![c.to_ascii_lowercase(), c.to_ascii_uppercase()]
+    } else {
+        vec![c]
+    }
+}
+
+// 回数指定の繰り返しを展開した場合の 1 文字に一致する要素の数の上限
+pub const REPEAT_LIMIT: usize = 1 << 16;
+
+// リテラルと文字クラスだけを選択する構文木なら、一致するバイトを bytes に集めて true を返す
+fn literal_union(ast: &Ast, flags: Flags, bytes: &mut Vec<u8>) -> bool {
+    match *ast {
+        Ast::Group { ref ast, .. } => literal_union(ast, flags, bytes),
+        Ast::NonCapturing {
+            flags: group,
+            ref ast,
+        } => literal_union(ast, group.apply(flags), bytes),
+        Ast::Union(ref asts) => asts.iter().all(|ast| literal_union(ast, flags, bytes)),
+        Ast::Literal(c) => {
+            bytes.extend(case_variants(c, flags));
+            true
+        }
+        Ast::Class(ref class) => {
+            bytes.extend(class.bytes(flags.case_insensitive));
+            true
+        }
+        _ => false,
+    }
+}
+
+// inline flag と両端の `^` と `$` を除いたパターンの構文木
+// 誤りの位置は元のパターン中の位置に直す (`x` フラグで空白を除いた場合は除いた後の位置)
+pub fn syntax_tree(regex: &str) -> Result<Ast, Error> {
+    syntax_tree_with_classes(regex, &NamedClasses::default())
+}
+
+// syntax_tree と同じく、classes に登録した名前付きの文字クラスも使える
+pub fn syntax_tree_with_classes(regex: &str, classes: &NamedClasses) -> Result<Ast, Error> {
+    let (flags, rest) = split_flags(regex.trim());
+    let stripped = strip_verbose(regex);
+    let (start, body, _) = split_anchors(&stripped);
+    let offset = if flags.verbose {
+        0
+    } else {
+        rest.as_ptr() as usize - regex.as_ptr() as usize + start as usize
+    };
+    parser::parse_with_classes(body, classes).map_err(|e| match e {
+        Error::Syntax(message, position) => Error::Syntax(message, offset + position),
+        Error::Unsupported { feature, span } => Error::Unsupported {
+            feature,
+            span: offset + span.start..offset + span.end,
+        },
+        e => e,
+    })
+}
+
 impl Nfa {
-    pub fn re2nfa(regex: &str) -> Nfa {
-        let lexer = Lexer::new(regex.trim());
-        let parser = Parser::new(lexer);
-        let syntax_tree = parser.struct_syntax_tree();
-        if let Some(root) = syntax_tree.root {
-            let mut nfa = Nfa { states: Vec::new() };
-            nfa.add_state();
-            let states_num = nfa.states.len();
-            nfa.states[states_num - 1].insert_transition(Label::Epsilon, states_num);
-            nfa.construct(&root);
+    pub fn re2nfa(regex: &str) -> Result<Nfa, Error> {
+        Nfa::re2nfa_with_flags(regex, Flags::default())
+    }
+
+    // 回数指定の繰り返しを展開したパターンの長さの上限を指定する
+    pub fn re2nfa_with_limit(regex: &str, repeat_limit: usize) -> Result<Nfa, Error> {
+        let classes = NamedClasses::default();
+        Nfa::compile(regex, Flags::default(), &classes, repeat_limit)
+    }
+
+    // パターン先頭の inline flag に加えて flags の設定も有効にする
+    pub fn re2nfa_with_flags(regex: &str, flags: Flags) -> Result<Nfa, Error> {
+        Nfa::re2nfa_with_classes(regex, flags, &NamedClasses::default())
+    }
+
+    // classes に登録した名前付きの文字クラスを `[[:name:]]` で使えるようにする
+    pub fn re2nfa_with_classes(
+        regex: &str,
+        flags: Flags,
+        classes: &NamedClasses,
+    ) -> Result<Nfa, Error> {
+        Nfa::compile(regex, flags, classes, REPEAT_LIMIT)
+    }
+
+    fn compile(
+        regex: &str,
+        flags: Flags,
+        classes: &NamedClasses,
+        repeat_limit: usize,
+    ) -> Result<Nfa, Error> {
+        let flags = flags.merge(split_flags(regex.trim()).0);
+        let root = {
+            let _span = trace_span!(DEBUG, "parse", pattern_len = regex.len());
+            ast::simplify(syntax_tree_with_classes(regex, classes)?)
+        };
+        if root.expanded_len() > repeat_limit {
+            return Err(Error::RepeatLimit(repeat_limit));
+        }
+        Ok(Nfa::from_ast(&root, flags))
+    }
+
+    // 構文木から NFA を作る。パターン中の inline flag は反映済みとして flags だけを使う
+    pub fn from_ast(root: &Ast, flags: Flags) -> Nfa {
+        let _span = trace_span!(DEBUG, "nfa");
+        let mut nfa = Nfa { states: Vec::new() };
+        nfa.add_state();
+        let states_num = nfa.states.len();
+        nfa.states[states_num - 1].insert_transition(Label::Epsilon, states_num);
+        nfa.construct(root, flags);
+        nfa.add_state();
+        let states_num = nfa.states.len();
+        nfa.states[states_num - 1].accept = true;
+        trace_event!("built nfa", states = nfa.states.len());
+        debug_validate!(nfa, "nfa construction");
+        nfa
+    }
+
+    // 開始状態があり、状態番号が並びの位置と一致し、全ての遷移先が存在することを確かめる
+    // debug ビルドでは構文木からの構成の後に呼ばれる
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.states.is_empty() {
+            return Err(Error::InvalidAutomaton("no start state".to_owned()));
+        }
+        for (i, state) in self.states.iter().enumerate() {
+            if state.id != i {
+                return Err(Error::InvalidAutomaton(format!(
+                    "state at index {} has id {}",
+                    i, state.id
+                )));
+            }
+            // 256 番目は ε 遷移
+            if state.transition.len() != 257 {
+                return Err(Error::InvalidAutomaton(format!(
+                    "state {} has {} transition columns",
+                    state.id,
+                    state.transition.len()
+                )));
+            }
+            for next in state.transition.iter().flatten().flat_map(|set| set.iter()) {
+                if *next >= self.states.len() {
+                    return Err(Error::InvalidAutomaton(format!(
+                        "state {} has a transition to missing state {}",
+                        state.id, next
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 開始状態に全バイトの自己ループを加え、入力の途中からの一致も受理する NFA を返す
+    pub fn unanchored(&self) -> Nfa {
+        let mut nfa = self.clone();
+        nfa.states[0].insert_transition(Label::Dot, 0);
+        nfa
+    }
+
+    // 新しい開始状態 0 から各 NFA の開始状態へ ε 遷移する NFA を返す
+    // i 番目の NFA の受理状態には pattern = Some(i) を付け、部分集合構成で DFA の受理状態に引き継ぐ
+    pub fn union_tagged(nfas: &[Nfa]) -> Nfa {
+        let mut union = Nfa { states: Vec::new() };
+        union.add_state();
+        for (pattern, nfa) in nfas.iter().enumerate() {
+            let offset = union.states.len();
+            union.states[0].insert_transition(Label::Epsilon, offset);
+            for state in nfa.states.iter() {
+                union.states.push(State {
+                    transition: state
+                        .transition
+                        .iter()
+                        .map(|t| t.as_ref().map(|t| t.iter().map(|&id| id + offset).collect()))
+                        .collect(),
+                    id: state.id + offset,
+                    accept: state.accept,
+                    pattern: if state.accept { Some(pattern) } else { None },
+                });
+            }
+        }
+        union
+    }
+
+    // 受理言語の各文字列を逆順にした言語の NFA を返す
+    // 新しい開始状態 0 から元の受理状態へ ε 遷移し、元の状態 i は i + 1 番になる
+    pub fn reverse(&self) -> Nfa {
+        let mut nfa = Nfa { states: Vec::new() };
+        for _ in 0..=self.states.len() {
             nfa.add_state();
-            let states_num = nfa.states.len();
-            nfa.states[states_num - 1].accept = true;
-            nfa
-        } else {
-            panic!();
         }
+        for state in self.states.iter() {
+            if state.accept {
+                nfa.states[0].insert_transition(Label::Epsilon, state.id + 1);
+            }
+            for (label, targets) in state.transition.iter().enumerate() {
+                if let Some(ref targets) = *targets {
+                    for &target in targets.iter() {
+                        let label = if label == 256 {
+                            Label::Epsilon
+                        } else {
+                            Input(label as u8)
+                        };
+                        nfa.states[target + 1].insert_transition(label, state.id + 1);
+                    }
+                }
+            }
+        }
+        if !self.states.is_empty() {
+            nfa.states[1].accept = true;
+        }
+        nfa
     }
 
     //DFA側から呼ぶ
@@ -81,79 +391,117 @@ impl Nfa {
             transition: vec![None; 257],
             id: state_num,
             accept: false,
+            pattern: None,
         });
     }
 
-    fn construct(&mut self, node: &Node) {
-        use self::NodeType::*;
-        match node.node_type {
-            Group => {
-                let &Node { ref lhs, .. } = node;
-                self.construct(lhs.as_ref().unwrap());
+    fn construct(&mut self, ast: &Ast, flags: Flags) {
+        match *ast {
+            Ast::Group { ref ast, .. } => self.construct(ast, flags),
+            // グループの中だけ flag を切り替える
+            Ast::NonCapturing {
+                flags: group,
+                ref ast,
+            } => self.construct(ast, group.apply(flags)),
+            Ast::Union(_) if literal_union(ast, flags, &mut Vec::new()) => {
+                let mut bytes = Vec::new();
+                literal_union(ast, flags, &mut bytes);
+                self.construct_bytes(bytes);
             }
-            OpUnion => {
-                self.add_state();
-                let branch_node_id = self.states.len() - 1;
-                let &Node {
-                    ref lhs, ref rhs, ..
-                } = node;
-
-                let states_num = self.states.len();
-                self.states[branch_node_id].insert_transition(Label::Epsilon, states_num);
-                self.construct(lhs.as_ref().unwrap());
-                self.add_state();
-                let states_num = self.states.len();
-                let lhs_last_state_id = states_num - 1;
-
-                let states_num = self.states.len();
-                self.states[branch_node_id].insert_transition(Label::Epsilon, states_num);
-                self.construct(rhs.as_ref().unwrap());
-
-                let states_num = self.states.len();
-                self.states[lhs_last_state_id].insert_transition(Label::Epsilon, states_num);
-            }
-            OpConcat => {
-                let &Node {
-                    ref lhs, ref rhs, ..
-                } = node;
-                self.construct(lhs.as_ref().unwrap());
-                self.construct(rhs.as_ref().unwrap());
+            Ast::Union(ref asts) => self.construct_union(asts, flags),
+            Ast::Concat(ref asts) => {
+                for ast in asts {
+                    self.construct(ast, flags);
+                }
             }
-            OpStar => {
-                let &Node { ref lhs, .. } = node;
+            Ast::Star(ref ast) => self.construct_star(ast, flags),
+            Ast::Plus(ref ast) => {
                 self.add_state();
                 let loop_node_id = self.states.len() - 1;
                 self.states[loop_node_id].insert_transition(Label::Epsilon, loop_node_id + 1);
-                self.construct(lhs.as_ref().unwrap());
+                self.construct(ast, flags);
                 self.add_state();
                 let last_state_id = self.states.len() - 1;
                 self.states[last_state_id].insert_transition(Label::Epsilon, loop_node_id);
                 let next_state_id = self.states.len();
-                self.states[loop_node_id].insert_transition(Label::Epsilon, next_state_id);
-            }
-            OpZeroOne => {
-                let &Node { ref lhs, .. } = node;
-                let branch_node_id = self.states.len() - 1;
-                self.construct(lhs.as_ref().unwrap());
-                let last_state_id = self.states.len() - 1;
-                let next_state_id = self.states.len();
                 self.states[last_state_id].insert_transition(Label::Epsilon, next_state_id);
-                self.states[branch_node_id].insert_transition(Label::Epsilon, next_state_id);
             }
-            Dot => {
-                self.add_state();
-                let states_num = self.states.len();
-                self.states[states_num - 1].insert_transition(Label::Dot, states_num);
+            Ast::ZeroOne(ref ast) => self.construct_zero_one(ast, flags),
+            // X{m,n} は X を m 回連接し、省略可能な X を n - m 個続ける
+            Ast::Repeat { ref ast, min, max } => {
+                for _ in 0..min {
+                    self.construct(ast, flags);
+                }
+                match max {
+                    Some(max) => {
+                        for _ in min..max {
+                            self.construct_zero_one(ast, flags);
+                        }
+                    }
+                    None => self.construct_star(ast, flags),
+                }
             }
-            Literal => {
+            Ast::Dot if flags.dot_all => {
                 self.add_state();
                 let states_num = self.states.len();
-                let &Node { ref value, .. } = node;
-                self.states[states_num - 1].insert_transition(Input(value.unwrap()), states_num);
-            }
-            _ => {
-                panic!();
+                self.states[states_num - 1].insert_transition(Label::Dot, states_num);
             }
+            Ast::Dot => self.construct_bytes((0..=255u8).filter(|&c| c != b'\n')),
+            Ast::Literal(c) => self.construct_bytes(case_variants(c, flags)),
+            Ast::Class(ref class) => self.construct_bytes(class.bytes(flags.case_insensitive)),
+        }
+    }
+
+    // A|B|C は (A|B)|C として、左側の選択の後に右端の要素への分岐を加える
+    fn construct_union(&mut self, asts: &[Ast], flags: Flags) {
+        let (rhs, lhs) = asts.split_last().unwrap();
+        self.add_state();
+        let branch_node_id = self.states.len() - 1;
+
+        let states_num = self.states.len();
+        self.states[branch_node_id].insert_transition(Label::Epsilon, states_num);
+        match lhs.len() {
+            1 => self.construct(&lhs[0], flags),
+            _ => self.construct_union(lhs, flags),
+        }
+        self.add_state();
+        let lhs_last_state_id = self.states.len() - 1;
+
+        let states_num = self.states.len();
+        self.states[branch_node_id].insert_transition(Label::Epsilon, states_num);
+        self.construct(rhs, flags);
+
+        let states_num = self.states.len();
+        self.states[lhs_last_state_id].insert_transition(Label::Epsilon, states_num);
+    }
+
+    fn construct_star(&mut self, lhs: &Ast, flags: Flags) {
+        self.add_state();
+        let loop_node_id = self.states.len() - 1;
+        self.states[loop_node_id].insert_transition(Label::Epsilon, loop_node_id + 1);
+        self.construct(lhs, flags);
+        self.add_state();
+        let last_state_id = self.states.len() - 1;
+        self.states[last_state_id].insert_transition(Label::Epsilon, loop_node_id);
+        let next_state_id = self.states.len();
+        self.states[loop_node_id].insert_transition(Label::Epsilon, next_state_id);
+    }
+
+    fn construct_zero_one(&mut self, lhs: &Ast, flags: Flags) {
+        self.add_state();
+        let branch_node_id = self.states.len() - 1;
+        self.states[branch_node_id].insert_transition(Label::Epsilon, branch_node_id + 1);
+        self.construct(lhs, flags);
+        let next_state_id = self.states.len();
+        self.states[branch_node_id].insert_transition(Label::Epsilon, next_state_id);
+    }
+
+    // bytes のいずれか 1 バイトで次へ進む状態を加える
+    fn construct_bytes<I: IntoIterator<Item = u8>>(&mut self, bytes: I) {
+        self.add_state();
+        let states_num = self.states.len();
+        for c in bytes {
+            self.states[states_num - 1].insert_transition(Input(c), states_num);
         }
     }
 
@@ -216,27 +564,148 @@ impl Nfa {
         }
     }
 
+    // ε閉包を考慮した各状態の受理判定と文字ごとの遷移先
+    pub fn closure_moves(&self) -> (Vec<bool>, Vec<Vec<StateSet>>) {
+        let mut accepts = Vec::with_capacity(self.states.len());
+        let mut moves = Vec::with_capacity(self.states.len());
+        for state in self.states.iter() {
+            let mut start = StateSet::new();
+            start.insert(state.id);
+            let closure = self.epsilon_expand(&start);
+            accepts.push(closure.iter().any(|&id| self.states[id].accept));
+            let mut t = vec![StateSet::new(); 256];
+            for id in closure.iter() {
+                for (c, t_c) in t.iter_mut().enumerate() {
+                    if let Some(next) = self.t(*id, c as u8) {
+                        t_c.extend(next.iter().cloned());
+                    }
+                }
+            }
+            moves.push(t);
+        }
+        (accepts, moves)
+    }
+
+    // sim[p][q] が true のとき状態 q は状態 p を模倣する (L(p) ⊆ L(q))
+    pub fn simulation_preorder(&self) -> Vec<Vec<bool>> {
+        let (accepts, moves) = self.closure_moves();
+        let n = self.states.len();
+        let mut sim: Vec<Vec<bool>> = accepts
+            .iter()
+            .map(|&p_accept| accepts.iter().map(|&q_accept| !p_accept || q_accept).collect())
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for p in 0..n {
+                for q in 0..n {
+                    if !sim[p][q] {
+                        continue;
+                    }
+                    let simulated = moves[p].iter().zip(moves[q].iter()).all(|(p_t, q_t)| {
+                        p_t.iter()
+                            .all(|&p_next| q_t.iter().any(|&q_next| sim[p_next][q_next]))
+                    });
+                    if !simulated {
+                        sim[p][q] = false;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        sim
+    }
+
+    // 集合内の他の状態に模倣される状態を取り除く (同値な状態同士は id の小さい方を残す)
+    pub fn prune_simulated(&self, state_set: &StateSet, sim: &[Vec<bool>]) -> StateSet {
+        state_set
+            .iter()
+            .cloned()
+            .filter(|&p| {
+                !state_set
+                    .iter()
+                    .any(|&q| q != p && sim[p][q] && (!sim[q][p] || q < p))
+            })
+            .collect()
+    }
+
+    // 模倣同値な状態をまとめた ε 遷移なしの NFA を返す
+    pub fn reduce(&self) -> Nfa {
+        let sim = self.simulation_preorder();
+        let (accepts, moves) = self.closure_moves();
+        let representative: Vec<usize> = (0..self.states.len())
+            .map(|p| (0..=p).find(|&q| sim[p][q] && sim[q][p]).unwrap())
+            .collect();
+
+        let mut nfa = Nfa { states: Vec::new() };
+        let mut class_to_state: HashMap<usize, usize> = HashMap::new();
+        let mut queue = vec![representative[0]];
+        class_to_state.insert(representative[0], 0);
+        nfa.add_state();
+
+        while let Some(class) = queue.pop() {
+            let id = class_to_state[&class];
+            nfa.states[id].accept = accepts[class];
+            for (c, t) in moves[class].iter().enumerate() {
+                for next in t.iter() {
+                    let next_class = representative[*next];
+                    let next_id = match class_to_state.get(&next_class) {
+                        Some(&next_id) => next_id,
+                        None => {
+                            let next_id = nfa.states.len();
+                            class_to_state.insert(next_class, next_id);
+                            nfa.add_state();
+                            queue.push(next_class);
+                            next_id
+                        }
+                    };
+                    nfa.states[id].insert_transition(Input(c as u8), next_id);
+                }
+            }
+        }
+        nfa
+    }
+
     pub fn print(&self) {
         for state in self.states.iter() {
             println!("{:?}", state);
         }
     }
 
+    #[cfg(feature = "petgraph")]
+    pub fn to_petgraph(&self) -> ::petgraph::Graph<bool, Option<u8>> {
+        ::walk::to_petgraph(self)
+    }
+
     pub fn dot(&self) -> String {
-        let mut dot = r###"
+        let mut dot = Vec::new();
+        self.write_dot(&mut dot).unwrap();
+        String::from_utf8(dot).unwrap()
+    }
+
+    // dot 形式で out に書き出す
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(
+            r###"
             digraph G {
             rankdir=LR;
             empty [label = "" shape = plaintext];
         "###
-        .to_owned();
-
-        let mut ac_state_dot = "\nnode [shape = doublecircle]".to_owned();
-        for ac_state in self.states.iter().filter(|&state| state.accept == true) {
-            ac_state_dot.push_str(&("s".to_owned() + &ac_state.id.to_string() + " "));
+            .as_bytes(),
+        )?;
+        out.write_all(b"\nnode [shape = doublecircle]")?;
+        for ac_state in self.states.iter().filter(|&state| state.accept) {
+            write!(out, "s{} ", ac_state.id)?;
         }
-        dot.push_str(&(ac_state_dot + "\n"));
-        dot.push_str("node [shape = circle];\nempty -> s0 [label = \"start\"];\n");
+        out.write_all(b"\n")?;
+        out.write_all(b"node [shape = circle];\nempty -> s0 [label = \"start\"];\n")?;
 
+        self.write_dot_edges(out, "")?;
+        out.write_all(b"}")
+    }
+
+    fn write_dot_edges<W: Write>(&self, out: &mut W, prefix: &str) -> io::Result<()> {
         for s in self.states.iter() {
             for (label, t_state_set) in s.transition.iter().enumerate() {
                 if let Some(t_state_set) = t_state_set {
@@ -246,22 +715,131 @@ impl Nfa {
                         label.to_string()
                     };
                     for t_state in t_state_set.iter() {
-                        dot.push_str(&format!(
-                            "s{} -> s{} [label = \"{}\"]\n",
-                            s.id, t_state, label
-                        ));
+                        writeln!(
+                            out,
+                            "{}s{} -> {}s{} [label = \"{}\"]",
+                            prefix, s.id, prefix, t_state, label
+                        )?;
                     }
                 }
             }
         }
-        dot.push_str("}");
-        dot
+        Ok(())
+    }
+
+    // 他のグラフと一つの dot ファイルに並べるための subgraph を書く
+    // 状態の名前には name を前に付けて区別する
+    pub fn write_dot_cluster<W: Write>(
+        &self,
+        out: &mut W,
+        name: &str,
+        label: &str,
+    ) -> io::Result<()> {
+        writeln!(out, "subgraph cluster_{} {{\nlabel = \"{}\";", name, label)?;
+        writeln!(out, "{}_start [label = \"\" shape = plaintext];", name)?;
+        out.write_all(b"node [shape = doublecircle]")?;
+        for ac_state in self.states.iter().filter(|&state| state.accept) {
+            write!(out, " {}_s{}", name, ac_state.id)?;
+        }
+        out.write_all(b";\nnode [shape = circle];\n")?;
+        writeln!(out, "{}_start -> {}_s0 [label = \"start\"]", name, name)?;
+        self.write_dot_edges(out, &format!("{}_", name))?;
+        out.write_all(b"}\n")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = BufWriter::new(fs::File::create(path)?);
+        self.write_dot(&mut f)?;
+        f.flush()
     }
+}
+
+// 状態と遷移を直接指定して NFA を組み立てる (0 番が開始状態)
+#[derive(Debug, Default)]
+pub struct NfaBuilder {
+    states: Vec<State>,
+}
 
-    pub fn write(&self, file_name: &str) {
-        let dot = self.dot();
-        let mut f = BufWriter::new(fs::File::create(file_name).unwrap());
-        f.write(dot.as_bytes()).ok();
+impl From<Nfa> for NfaBuilder {
+    fn from(nfa: Nfa) -> Self {
+        NfaBuilder { states: nfa.states }
+    }
+}
+
+impl NfaBuilder {
+    pub fn new() -> Self {
+        NfaBuilder { states: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    fn check_state(&self, id: usize) -> Result<(), Error> {
+        if id < self.states.len() {
+            Ok(())
+        } else {
+            Err(Error::InvalidAutomaton(format!("state {} does not exist", id)))
+        }
+    }
+
+    pub fn add_state(&mut self, accept: bool) -> usize {
+        let id = self.states.len();
+        self.states.push(State {
+            transition: vec![None; 257],
+            id,
+            accept,
+            pattern: None,
+        });
+        id
+    }
+
+    pub fn set_accept(&mut self, id: usize, accept: bool) -> Result<(), Error> {
+        self.check_state(id)?;
+        self.states[id].accept = accept;
+        Ok(())
+    }
+
+    pub fn add_epsilon(&mut self, from: usize, to: usize) -> Result<(), Error> {
+        self.check_state(from)?;
+        self.check_state(to)?;
+        self.states[from].insert_transition(Label::Epsilon, to);
+        Ok(())
+    }
+
+    pub fn add_transition(&mut self, from: usize, c: u8, to: usize) -> Result<(), Error> {
+        self.check_state(from)?;
+        self.check_state(to)?;
+        self.states[from].insert_transition(Input(c), to);
+        Ok(())
+    }
+
+    // bytes の範囲の全てのバイトで from から to へ遷移する
+    pub fn add_range(
+        &mut self,
+        from: usize,
+        bytes: RangeInclusive<u8>,
+        to: usize,
+    ) -> Result<(), Error> {
+        self.check_state(from)?;
+        self.check_state(to)?;
+        for c in bytes {
+            self.states[from].insert_transition(Input(c), to);
+        }
+        Ok(())
+    }
+
+    // 開始状態があり、全ての遷移先が存在することを確かめて NFA を返す
+    pub fn finish(self) -> Result<Nfa, Error> {
+        let nfa = Nfa {
+            states: self.states,
+        };
+        nfa.validate()?;
+        Ok(nfa)
     }
 }
 
@@ -279,20 +857,46 @@ impl PartialEq for StateSet {
 
 impl Eq for StateSet {}
 
+impl StateSet {
+    // 要素を昇順に並べた列。同じ集合なら HashSet の中の順序によらず同じになる
+    pub fn sorted(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.0.iter().cloned().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+// 直列化した結果が HashSet の中の順序で変わらないよう、昇順の列として書き出す
+#[cfg(feature = "serde")]
+impl Serialize for StateSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.sorted().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for StateSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<usize>::deserialize(deserializer).map(|ids| StateSet(ids.into_iter().collect()))
+    }
+}
+
 impl Hash for StateSet {
-    fn hash<H>(&self, _state: &mut H)
+    fn hash<H>(&self, state: &mut H)
     where
         H: Hasher,
     {
-        self.0
-            .clone()
-            .into_iter()
-            .collect::<Vec<usize>>()
-            .sort()
-            .hash(_state);
+        self.sorted().hash(state);
     }
 }
 
+// 部分集合構成で NFA 状態の集合から DFA の状態を引く表
+// rustc-hash feature を有効にすると、DoS 耐性のない代わりに速い FxHash を使う
+#[cfg(feature = "rustc-hash")]
+pub type SubsetMap<V> = HashMap<StateSet, V, BuildHasherDefault<::rustc_hash::FxHasher>>;
+#[cfg(not(feature = "rustc-hash"))]
+pub type SubsetMap<V> = HashMap<StateSet, V>;
+
 impl Deref for StateSet {
     type Target = HashSet<usize>;
     fn deref(&self) -> &Self::Target {
@@ -0,0 +1,651 @@
+use ast::{Ast, Class, GroupFlags};
+use dfa::ByteSet;
+use error::Error;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Literal(u8),
+    Dot,
+    Class(Box<Class>),
+    Star,
+    Plus,
+    Question,
+    Repeat(usize, Option<usize>),
+    Pipe,
+    LParen,
+    // `(?:` と `(?i-s:`
+    NonCapturing(GroupFlags),
+    // `(?P<name>` と `(?<name>`
+    Named(String),
+    RParen,
+}
+
+// パターン中の字句と、そのバイト位置の範囲
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+fn syntax_error(message: &str, position: usize) -> Error {
+    Error::Syntax(message.to_owned(), position)
+}
+
+fn unsupported(feature: &'static str, start: usize, end: usize) -> Error {
+    Error::Unsupported {
+        feature,
+        span: start..end,
+    }
+}
+
+fn describe(c: u8) -> String {
+    match c {
+        0x21..=0x7e => format!("'{}'", c as char),
+        _ => format!("byte 0x{:02x}", c),
+    }
+}
+
+// `\d`, `\w`, `\s` と、大文字で書いたその否定
+fn shorthand_class(c: u8) -> Option<Class> {
+    let mut class = Class::new();
+    let members: Vec<u8> = match c.to_ascii_lowercase() {
+        b'd' => (b'0'..=b'9').collect(),
+        b'w' => (b'0'..=b'9')
+            .chain(b'A'..=b'Z')
+            .chain(b'a'..=b'z')
+            .chain(Some(b'_'))
+            .collect(),
+        b's' => vec![b' ', b'\t', b'\n', 0x0b, 0x0c, b'\r'],
+        _ => return None,
+    };
+    for c in members {
+        class.members[c as usize] = true;
+    }
+    class.negated = c.is_ascii_uppercase();
+    Some(class)
+}
+
+// クラスの中に `[:name:]` と書いて使う名前付きの文字クラス
+// 登録していない名前は POSIX の `alpha`, `digit` などとして解決する
+#[derive(Debug, Clone, Default)]
+pub struct NamedClasses {
+    classes: HashMap<String, ByteSet>,
+}
+
+impl NamedClasses {
+    pub fn new() -> Self {
+        NamedClasses::default()
+    }
+
+    // 同じ名前を登録し直した場合は後のバイトの集合で置き換える
+    pub fn insert(&mut self, name: &str, bytes: &[u8]) {
+        let mut members = [false; 256];
+        for &c in bytes {
+            members[c as usize] = true;
+        }
+        self.classes.insert(name.to_owned(), members);
+    }
+
+    pub fn get(&self, name: &str) -> Option<ByteSet> {
+        if let Some(members) = self.classes.get(name) {
+            return Some(*members);
+        }
+        let posix: fn(u8) -> bool = match name {
+            "alpha" => |c| c.is_ascii_alphabetic(),
+            "digit" => |c| c.is_ascii_digit(),
+            "alnum" => |c| c.is_ascii_alphanumeric(),
+            "upper" => |c| c.is_ascii_uppercase(),
+            "lower" => |c| c.is_ascii_lowercase(),
+            "xdigit" => |c| c.is_ascii_hexdigit(),
+            "punct" => |c| c.is_ascii_punctuation(),
+            "space" => |c| c == b' ' || (b'\t'..=b'\r').contains(&c),
+            _ => return None,
+        };
+        let mut members = [false; 256];
+        for c in 0..=255u8 {
+            members[c as usize] = posix(c);
+        }
+        Some(members)
+    }
+}
+
+struct Lexer<'p> {
+    pattern: &'p [u8],
+    pos: usize,
+    classes: &'p NamedClasses,
+    // これまでに現れたグループ名
+    names: Vec<String>,
+}
+
+impl<'p> Lexer<'p> {
+    fn new(pattern: &'p str, classes: &'p NamedClasses) -> Lexer<'p> {
+        Lexer {
+            pattern: pattern.as_bytes(),
+            pos: 0,
+            classes,
+            names: Vec::new(),
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, Error> {
+        let mut tokens = Vec::new();
+        while self.pos < self.pattern.len() {
+            let start = self.pos;
+            let kind = self.next_kind()?;
+            tokens.push(Token {
+                kind,
+                start,
+                end: self.pos,
+            });
+        }
+        Ok(tokens)
+    }
+
+    fn next_kind(&mut self) -> Result<TokenKind, Error> {
+        let c = self.pattern[self.pos];
+        self.pos += 1;
+        Ok(match c {
+            b'.' => TokenKind::Dot,
+            b'*' => TokenKind::Star,
+            b'+' => TokenKind::Plus,
+            b'?' => TokenKind::Question,
+            b'|' => TokenKind::Pipe,
+            b'(' if self.pattern.get(self.pos) == Some(&b'?') => {
+                if let Some(name) = self.group_name()? {
+                    return Ok(TokenKind::Named(name));
+                }
+                match self.group_flags()? {
+                    Some(flags) => TokenKind::NonCapturing(flags),
+                    None => return Err(self.group_syntax()),
+                }
+            }
+            b'(' => TokenKind::LParen,
+            b')' => TokenKind::RParen,
+            b'[' => TokenKind::Class(Box::new(self.class()?)),
+            b'{' => match self.counted() {
+                Some((min, max)) => TokenKind::Repeat(min, max),
+                None => TokenKind::Literal(c),
+            },
+            b'\\' => match self.hex() {
+                Some(c) => TokenKind::Literal(c),
+                None => {
+                    let escaped = self.escaped()?;
+                    let feature = match escaped {
+                        b'1'..=b'9' | b'k' => Some("backreference"),
+                        b'b' | b'B' => Some("word boundary"),
+                        b'A' | b'z' | b'Z' | b'G' => Some("anchor escape"),
+                        b'p' | b'P' => Some("Unicode property class"),
+                        _ => None,
+                    };
+                    if let Some(feature) = feature {
+                        return Err(unsupported(feature, self.pos - 2, self.pos));
+                    }
+                    match shorthand_class(escaped) {
+                        Some(class) => TokenKind::Class(Box::new(class)),
+                        None => TokenKind::Literal(escaped),
+                    }
+                }
+            },
+            // パターンの両端以外の `^` と `$` は未対応
+            b'^' | b'$' => {
+                return Err(unsupported("anchor inside a pattern", self.pos - 1, self.pos));
+            }
+            c => TokenKind::Literal(c),
+        })
+    }
+
+    // `(?` の `?` から `i-s:` のような flag と `:` までを読む。当てはまらなければ読み進めずに None を返す
+    fn group_flags(&mut self) -> Result<Option<GroupFlags>, Error> {
+        let mut flags = GroupFlags::default();
+        let mut on = true;
+        let mut scoped_only = None;
+        for (i, &c) in self.pattern[self.pos + 1..].iter().enumerate() {
+            match c {
+                b'i' => flags.case_insensitive = Some(on),
+                b's' => flags.dot_all = Some(on),
+                // `m` と `x` はパターン全体にしか指定できない
+                b'm' | b'x' => scoped_only = scoped_only.or(Some(self.pos + 1 + i)),
+                b'-' if on => on = false,
+                b':' => {
+                    if let Some(at) = scoped_only {
+                        return Err(unsupported("scoped flag", at, at + 1));
+                    }
+                    self.pos += i + 2;
+                    return Ok(Some(flags));
+                }
+                _ => break,
+            }
+        }
+        Ok(None)
+    }
+
+    // `(?` の `?` から `P<name>` または `<name>` を読む。当てはまらなければ読み進めずに None を返す
+    fn group_name(&mut self) -> Result<Option<String>, Error> {
+        let start = match self.pattern[self.pos + 1..] {
+            [b'P', b'<', ..] => self.pos + 3,
+            [b'<', c, ..] if c != b'=' && c != b'!' => self.pos + 2,
+            _ => return Ok(None),
+        };
+        let len = match self.pattern[start..].iter().position(|&c| c == b'>') {
+            Some(len) => len,
+            None => return Err(syntax_error("unclosed group name", start)),
+        };
+        let name = &self.pattern[start..start + len];
+        let valid = name.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+            && name.first().is_some_and(|c| !c.is_ascii_digit());
+        if !valid {
+            return Err(syntax_error("invalid group name", start));
+        }
+        let name = String::from_utf8_lossy(name).into_owned();
+        if self.names.contains(&name) {
+            return Err(syntax_error(&format!("duplicate group name \"{}\"", name), start));
+        }
+        self.names.push(name.clone());
+        self.pos = start + len + 1;
+        Ok(Some(name))
+    }
+
+    // `(?` で始まる構文を、`(` の次から見て分類する
+    fn group_syntax(&self) -> Error {
+        let start = self.pos - 1;
+        let rest = &self.pattern[self.pos + 1..];
+        let (feature, len) = match rest {
+            [b'=', ..] | [b'!', ..] => ("lookahead", 3),
+            [b'<', b'=', ..] | [b'<', b'!', ..] => ("lookbehind", 4),
+            [b'>', ..] => ("atomic group", 3),
+            _ => ("group option", 2),
+        };
+        unsupported(feature, start, (start + len).min(self.pattern.len()))
+    }
+
+    fn escaped(&mut self) -> Result<u8, Error> {
+        match self.pattern.get(self.pos) {
+            Some(&c) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            None => Err(syntax_error("unexpected end of pattern", self.pos)),
+        }
+    }
+
+    // `\xNN` の `x` から読む。当てはまらなければ読み進めずに None を返す
+    fn hex(&mut self) -> Option<u8> {
+        let digits = self.pattern.get(self.pos + 1..self.pos + 3)?;
+        if self.pattern[self.pos] != b'x' || !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let c = u8::from_str_radix(::std::str::from_utf8(digits).ok()?, 16).ok()?;
+        self.pos += 3;
+        Some(c)
+    }
+
+    // `{m}`, `{m,}`, `{m,n}` の `{` の次から読む。当てはまらなければ読み進めずに None を返す
+    fn counted(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest = &self.pattern[self.pos..];
+        let close = rest.iter().position(|&c| c == b'}')?;
+        let body = ::std::str::from_utf8(&rest[..close]).ok()?;
+        let number = |s: &str| -> Option<usize> {
+            if !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) {
+                s.parse().ok()
+            } else {
+                None
+            }
+        };
+        let (min, max) = match body.find(',') {
+            Some(comma) if comma + 1 == body.len() => (number(&body[..comma])?, None),
+            Some(comma) => (number(&body[..comma])?, Some(number(&body[comma + 1..])?)),
+            None => {
+                let count = number(body)?;
+                (count, Some(count))
+            }
+        };
+        self.pos += close + 1;
+        Some((min, max))
+    }
+
+    // クラスの中の `[:name:]` を `:` から読む。start は `[` の位置
+    fn named_class(&mut self, start: usize) -> Result<ByteSet, Error> {
+        let rest = &self.pattern[self.pos + 1..];
+        let close = rest
+            .windows(2)
+            .position(|pair| pair == b":]")
+            .ok_or_else(|| syntax_error("unclosed class name", start))?;
+        let name = String::from_utf8_lossy(&rest[..close]).into_owned();
+        let members = self
+            .classes
+            .get(&name)
+            .ok_or_else(|| syntax_error(&format!("unknown class name {:?}", name), start))?;
+        self.pos += close + 3;
+        Ok(members)
+    }
+
+    // `[` の次から `]` までを読む (先頭の `]` はクラスの要素)
+    fn class(&mut self) -> Result<Class, Error> {
+        let open = self.pos - 1;
+        let unclosed = || syntax_error("unclosed character class", open);
+        let mut class = Class::new();
+        if self.pattern.get(self.pos) == Some(&b'^') {
+            class.negated = true;
+            self.pos += 1;
+        }
+        let mut first = true;
+        loop {
+            let start = self.pos;
+            let mut c = *self.pattern.get(self.pos).ok_or_else(unclosed)?;
+            self.pos += 1;
+            if c == b']' && !first {
+                return Ok(class);
+            }
+            first = false;
+            if c == b'[' && self.pattern.get(self.pos) == Some(&b':') {
+                let members = self.named_class(start)?;
+                for (b, member) in class.members.iter_mut().enumerate() {
+                    *member |= members[b];
+                }
+                continue;
+            }
+            if c == b'\\' {
+                match self.hex() {
+                    Some(hex) => c = hex,
+                    None => {
+                        c = self.escaped().map_err(|_| unclosed())?;
+                        if let Some(shorthand) = shorthand_class(c) {
+                            for (b, member) in class.members.iter_mut().enumerate() {
+                                *member |= shorthand.members[b] != shorthand.negated;
+                            }
+                            continue;
+                        }
+                    }
+                }
+            } else if !c.is_ascii() {
+                // 複数バイトの文字はバイトの集合として表せない (`\xNN` で書いたバイトは使える)
+                return Err(unsupported("multibyte character in a class", start, self.pos));
+            }
+            let is_range = self.pattern.get(self.pos) == Some(&b'-')
+                && self.pos + 1 < self.pattern.len()
+                && self.pattern[self.pos + 1] != b']';
+            if !is_range {
+                class.members[c as usize] = true;
+                continue;
+            }
+            self.pos += 1;
+            let mut hi = self.pattern[self.pos];
+            self.pos += 1;
+            if hi == b'\\' {
+                hi = match self.hex() {
+                    Some(hex) => hex,
+                    None => self.escaped().map_err(|_| unclosed())?,
+                };
+            } else if !hi.is_ascii() {
+                return Err(unsupported("multibyte character in a class", self.pos - 1, self.pos));
+            }
+            if hi < c {
+                return Err(syntax_error("invalid class range", start));
+            }
+            for b in c..=hi {
+                class.members[b as usize] = true;
+            }
+        }
+    }
+}
+
+// 結合の強さ (大きいほど強い)
+const UNION: u8 = 0;
+const CONCAT: u8 = 1;
+
+// 字句の列を優先順位に従って構文木にする
+struct Parser<'p> {
+    pattern: &'p [u8],
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'p> Parser<'p> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn unexpected(&self) -> Error {
+        match self.peek() {
+            Some(token) => syntax_error(
+                &format!("unexpected {}", describe(self.pattern[token.start])),
+                token.start,
+            ),
+            None => syntax_error("unexpected end of pattern", self.pattern.len()),
+        }
+    }
+
+    // 結合の強さが min_precedence 以上の二項演算子だけを読み進める
+    // `a|`, `(|a)`, `()` のように何もない枝は空文字列 (要素のない連接) とする
+    fn expression(&mut self, min_precedence: u8) -> Result<Ast, Error> {
+        let mut lhs = match self.peek().map(|token| &token.kind) {
+            Some(&TokenKind::Pipe) | Some(&TokenKind::RParen) | None => Ast::Concat(Vec::new()),
+            Some(_) => self.repetition()?,
+        };
+        loop {
+            let precedence = match self.peek().map(|token| &token.kind) {
+                Some(&TokenKind::Pipe) => UNION,
+                Some(&TokenKind::RParen) | None => break,
+                Some(_) => CONCAT,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            if precedence == UNION {
+                self.pos += 1;
+            }
+            // 右辺は一段強い演算子だけを読むので、同じ演算子は左の要素の列に並ぶ
+            let rhs = self.expression(precedence + 1)?;
+            lhs = match (precedence, lhs) {
+                (UNION, Ast::Union(mut asts)) => {
+                    asts.push(rhs);
+                    Ast::Union(asts)
+                }
+                (UNION, lhs) => Ast::Union(vec![lhs, rhs]),
+                (_, lhs) => concat(lhs, rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn repetition(&mut self) -> Result<Ast, Error> {
+        let mut ast = self.atom()?;
+        while let Some(token) = self.peek().cloned() {
+            let operand = Box::new(ast);
+            ast = match token.kind {
+                TokenKind::Star => Ast::Star(operand),
+                TokenKind::Plus => Ast::Plus(operand),
+                TokenKind::Question => Ast::ZeroOne(operand),
+                TokenKind::Repeat(min, Some(max)) if max < min => {
+                    return Err(syntax_error("invalid repetition range", token.start));
+                }
+                TokenKind::Repeat(min, max) => Ast::Repeat {
+                    ast: operand,
+                    min,
+                    max,
+                },
+                _ => {
+                    ast = *operand;
+                    break;
+                }
+            };
+            self.pos += 1;
+        }
+        Ok(ast)
+    }
+
+    fn atom(&mut self) -> Result<Ast, Error> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(self.unexpected()),
+        };
+        let ast = match token.kind {
+            TokenKind::LParen | TokenKind::NonCapturing(_) | TokenKind::Named(_) => {
+                self.pos += 1;
+                let inner = Box::new(self.expression(UNION)?);
+                match self.peek() {
+                    Some(&Token {
+                        kind: TokenKind::RParen,
+                        ..
+                    }) => {}
+                    _ => return Err(syntax_error("unclosed group", token.start)),
+                }
+                match token.kind {
+                    TokenKind::NonCapturing(flags) => Ast::NonCapturing { flags, ast: inner },
+                    TokenKind::Named(name) => Ast::Group {
+                        name: Some(name),
+                        ast: inner,
+                    },
+                    _ => Ast::Group {
+                        name: None,
+                        ast: inner,
+                    },
+                }
+            }
+            TokenKind::Dot => Ast::Dot,
+            TokenKind::Class(ref class) => Ast::Class(class.clone()),
+            // 被演算子のない `{m,n}` は文字どおりに読む
+            TokenKind::Repeat(..) => {
+                self.pos += 1;
+                return Ok(self.literals(&self.pattern[token.start..token.end]));
+            }
+            TokenKind::Literal(c) if c >= 0xc0 => return Ok(self.multibyte(c)),
+            TokenKind::Literal(c) => Ast::Literal(c),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        Ok(ast)
+    }
+
+    fn literals(&self, bytes: &[u8]) -> Ast {
+        match bytes.len() {
+            1 => Ast::Literal(bytes[0]),
+            _ => Ast::Concat(bytes.iter().map(|&c| Ast::Literal(c)).collect()),
+        }
+    }
+
+    // 複数バイトの文字は繰り返しの単位になるよう、続くバイトとまとめて一つの連接にする
+    fn multibyte(&mut self, lead: u8) -> Ast {
+        let mut bytes = vec![lead];
+        self.pos += 1;
+        while let Some(&Token {
+            kind: TokenKind::Literal(c),
+            ..
+        }) = self.peek()
+        {
+            if c & 0xc0 != 0x80 {
+                break;
+            }
+            bytes.push(c);
+            self.pos += 1;
+        }
+        self.literals(&bytes)
+    }
+}
+
+// 連接を一つの要素の列にまとめる
+fn concat(lhs: Ast, rhs: Ast) -> Ast {
+    let mut asts = match lhs {
+        Ast::Concat(asts) => asts,
+        lhs => vec![lhs],
+    };
+    match rhs {
+        Ast::Concat(rhs) => asts.extend(rhs),
+        rhs => asts.push(rhs),
+    }
+    Ast::Concat(asts)
+}
+
+// パターンを構文木にする。誤りの位置はパターン中のバイト位置で表す
+pub fn parse(pattern: &str) -> Result<Ast, Error> {
+    parse_with_classes(pattern, &NamedClasses::default())
+}
+
+// classes に登録した名前付きの文字クラスも使えるようにしてパターンを構文木にする
+// 空のパターンは空文字列だけに一致する (要素のない連接)
+pub fn parse_with_classes(pattern: &str, classes: &NamedClasses) -> Result<Ast, Error> {
+    if pattern.is_empty() {
+        return Ok(Ast::Concat(Vec::new()));
+    }
+    let mut parser = Parser {
+        pattern: pattern.as_bytes(),
+        tokens: Lexer::new(pattern, classes).tokenize()?,
+        pos: 0,
+    };
+    let root = parser.expression(UNION)?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    Ok(root)
+}
+
+#[test]
+fn parse_errors() {
+    for &(pattern, message) in &[
+        ("(ab|c))", "unexpected ')' at position 6"),
+        ("(ab", "unclosed group at position 0"),
+        ("a|*", "unexpected '*' at position 2"),
+        ("a|)", "unexpected ')' at position 2"),
+        ("[a-", "unclosed character class at position 0"),
+        ("x[z-a]", "invalid class range at position 2"),
+        ("a{3,2}", "invalid repetition range at position 1"),
+    ] {
+        assert_eq!(parse(pattern).unwrap_err().to_string(), message);
+    }
+    for (pattern, feature, span) in [
+        ("a^b", "anchor inside a pattern", 1..2),
+        ("(a)\\1", "backreference", 3..5),
+        ("\\bword", "word boundary", 0..2),
+        ("x(?<=a)b", "lookbehind", 1..5),
+        ("(?!a)b", "lookahead", 0..3),
+        ("(?>ab)", "atomic group", 0..3),
+        ("[\u{e9}]", "multibyte character in a class", 1..2),
+    ] {
+        match parse(pattern) {
+            Err(Error::Unsupported {
+                feature: f,
+                span: s,
+            }) => assert_eq!((f, s), (feature, span), "{}", pattern),
+            other => panic!("{}: {:?}", pattern, other),
+        }
+    }
+    // inline flag と先頭の `^` を除いた分も位置に数える
+    let e = ::nfa::syntax_tree("(?i)^a)").unwrap_err();
+    assert_eq!(e.to_string(), "unexpected ')' at position 6");
+    let e = ::nfa::syntax_tree("(?i)a\\1").unwrap_err();
+    assert_eq!(e.to_string(), "backreference is not supported at position 5");
+}
+
+#[test]
+fn empty_branches() {
+    let empty = || Ast::Concat(Vec::new());
+    let group = |ast| Ast::Group {
+        name: None,
+        ast: Box::new(ast),
+    };
+    assert_eq!(parse("a|").unwrap(), Ast::Union(vec![Ast::Literal(b'a'), empty()]));
+    assert_eq!(parse("|").unwrap(), Ast::Union(vec![empty(), empty()]));
+    assert_eq!(
+        parse("a||b").unwrap(),
+        Ast::Union(vec![Ast::Literal(b'a'), empty(), Ast::Literal(b'b')])
+    );
+    assert_eq!(
+        parse("(a|)").unwrap(),
+        group(Ast::Union(vec![Ast::Literal(b'a'), empty()]))
+    );
+    assert_eq!(parse("()").unwrap(), group(empty()));
+    // `{0}` は本体を一度も繰り返さないので空文字列に一致する
+    assert_eq!(
+        parse("ab{0}").unwrap(),
+        Ast::Concat(vec![
+            Ast::Literal(b'a'),
+            Ast::Repeat {
+                ast: Box::new(Ast::Literal(b'b')),
+                min: 0,
+                max: Some(0),
+            },
+        ])
+    );
+}
@@ -0,0 +1,280 @@
+use ast::Ast;
+use dfa::{ByteSet, Match};
+use nfa::{case_variants, Flags};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Thompson の構成による命令列
+#[derive(Debug, Clone)]
+enum Inst {
+    // 集合に含まれる 1 バイトを読む
+    Bytes(Box<ByteSet>),
+    // 両方へ進む (先の方を優先する)
+    Split(usize, usize),
+    Jump(usize),
+    // 現在の位置を捕獲スロットに書く
+    Save(usize),
+    Match,
+}
+
+// 捕獲グループの位置を求める仮想機械 (Pike VM)
+// DFA は捕獲を扱わないので、グループの範囲が要るときだけこちらを動かす
+#[derive(Debug, Clone)]
+pub struct PikeVm {
+    program: Vec<Inst>,
+    // 暗黙のグループ 0 を含むグループの数
+    groups: usize,
+    // グループの名前から番号への対応 (一致ごとの Captures と共有する)
+    names: Arc<HashMap<String, usize>>,
+}
+
+// 各グループが一致した範囲 (参加しなかったグループは None)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures {
+    spans: Vec<Option<Match>>,
+    names: Arc<HashMap<String, usize>>,
+}
+
+impl Captures {
+    pub fn get(&self, group: usize) -> Option<Match> {
+        self.spans.get(group).cloned().and_then(|span| span)
+    }
+
+    // `(?P<name>...)` で名前を付けたグループの範囲
+    pub fn name(&self, name: &str) -> Option<Match> {
+        self.names.get(name).and_then(|&group| self.get(group))
+    }
+
+    // グループ 0 を含むグループの数
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match>> + '_ {
+        self.spans.iter().cloned()
+    }
+}
+
+impl PikeVm {
+    // flags はパターン全体の設定で、`(?i:...)` などのグループの中では切り替える
+    pub fn new(ast: &Ast, flags: Flags) -> Self {
+        let mut vm = PikeVm {
+            program: vec![Inst::Save(0)],
+            groups: 1,
+            names: Arc::new(HashMap::new()),
+        };
+        vm.compile(ast, flags);
+        vm.program.push(Inst::Save(1));
+        vm.program.push(Inst::Match);
+        vm
+    }
+
+    // 名前の付いたグループの番号
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    fn push(&mut self, inst: Inst) -> usize {
+        self.program.push(inst);
+        self.program.len() - 1
+    }
+
+    fn push_bytes<I: IntoIterator<Item = u8>>(&mut self, bytes: I) {
+        let mut set = [false; 256];
+        for c in bytes {
+            set[c as usize] = true;
+        }
+        self.push(Inst::Bytes(Box::new(set)));
+    }
+
+    fn compile(&mut self, ast: &Ast, flags: Flags) {
+        match *ast {
+            Ast::Literal(c) => self.push_bytes(case_variants(c, flags)),
+            Ast::Dot => self.push_bytes((0..=255u8).filter(|&c| flags.dot_all || c != b'\n')),
+            Ast::Class(ref class) => self.push_bytes(class.bytes(flags.case_insensitive)),
+            Ast::Group { ref name, ref ast } => {
+                if let Some(ref name) = *name {
+                    Arc::make_mut(&mut self.names).insert(name.clone(), self.groups);
+                }
+                let slot = 2 * self.groups;
+                self.groups += 1;
+                self.push(Inst::Save(slot));
+                self.compile(ast, flags);
+                self.push(Inst::Save(slot + 1));
+            }
+            Ast::NonCapturing {
+                flags: group,
+                ref ast,
+            } => self.compile(ast, group.apply(flags)),
+            Ast::Concat(ref asts) => {
+                for ast in asts {
+                    self.compile(ast, flags);
+                }
+            }
+            Ast::Union(ref asts) => {
+                let mut jumps = Vec::new();
+                for ast in &asts[..asts.len() - 1] {
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile(ast, flags);
+                    jumps.push(self.push(Inst::Jump(0)));
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+                self.compile(&asts[asts.len() - 1], flags);
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            Ast::Star(ref ast) => self.compile_star(ast, flags),
+            Ast::Plus(ref ast) => {
+                let start = self.program.len();
+                self.compile(ast, flags);
+                let split = self.push(Inst::Split(start, 0));
+                self.program[split] = Inst::Split(start, split + 1);
+            }
+            Ast::ZeroOne(ref ast) => self.compile_zero_one(ast, flags),
+            Ast::Repeat { ref ast, min, max } => {
+                for _ in 0..min {
+                    self.compile(ast, flags);
+                }
+                match max {
+                    Some(max) => {
+                        for _ in min..max {
+                            self.compile_zero_one(ast, flags);
+                        }
+                    }
+                    None => self.compile_star(ast, flags),
+                }
+            }
+        }
+    }
+
+    fn compile_star(&mut self, ast: &Ast, flags: Flags) {
+        let split = self.push(Inst::Split(0, 0));
+        self.compile(ast, flags);
+        self.push(Inst::Jump(split));
+        self.program[split] = Inst::Split(split + 1, self.program.len());
+    }
+
+    fn compile_zero_one(&mut self, ast: &Ast, flags: Flags) {
+        let split = self.push(Inst::Split(0, 0));
+        self.compile(ast, flags);
+        self.program[split] = Inst::Split(split + 1, self.program.len());
+    }
+
+    // pc から ε 遷移でたどれる命令をスレッドとして優先順に threads に加える
+    fn add_thread(
+        &self,
+        threads: &mut Vec<(usize, Vec<Option<usize>>)>,
+        visited: &mut [bool],
+        pc: usize,
+        at: usize,
+        mut slots: Vec<Option<usize>>,
+    ) {
+        if visited[pc] {
+            return;
+        }
+        visited[pc] = true;
+        match self.program[pc] {
+            Inst::Split(first, second) => {
+                self.add_thread(threads, visited, first, at, slots.clone());
+                self.add_thread(threads, visited, second, at, slots);
+            }
+            Inst::Jump(next) => self.add_thread(threads, visited, next, at, slots),
+            Inst::Save(slot) => {
+                slots[slot] = Some(at);
+                self.add_thread(threads, visited, pc + 1, at, slots);
+            }
+            Inst::Bytes(_) | Inst::Match => threads.push((pc, slots)),
+        }
+    }
+
+    // haystack の span の範囲全体が一致するときの各グループの範囲 (位置は haystack の中での添字)
+    // 同じ範囲に複数の読み方があれば、選択は左の選択肢を、繰り返しは多い方を優先する
+    pub fn captures(&self, haystack: &[u8], span: Match) -> Option<Captures> {
+        let mut visited = vec![false; self.program.len()];
+        let mut threads = Vec::new();
+        let slots = vec![None; 2 * self.groups];
+        self.add_thread(&mut threads, &mut visited, 0, span.start, slots);
+        for (i, &c) in haystack[..span.end].iter().enumerate().skip(span.start) {
+            let mut visited = vec![false; self.program.len()];
+            let mut next = Vec::new();
+            for (pc, slots) in threads {
+                if let Inst::Bytes(ref set) = self.program[pc] {
+                    if set[c as usize] {
+                        self.add_thread(&mut next, &mut visited, pc + 1, i + 1, slots);
+                    }
+                }
+            }
+            if next.is_empty() {
+                return None;
+            }
+            threads = next;
+        }
+        let (_, slots) = threads
+            .into_iter()
+            .find(|&(pc, _)| matches!(self.program[pc], Inst::Match))?;
+        let spans = slots
+            .chunks(2)
+            .map(|pair| match (pair[0], pair[1]) {
+                (Some(start), Some(end)) => Some(Match { start, end }),
+                _ => None,
+            })
+            .collect();
+        Some(Captures {
+            spans,
+            names: self.names.clone(),
+        })
+    }
+}
+
+#[test]
+fn pike_vm_captures() {
+    use parser::parse;
+    let spans = |regex: &str, haystack: &str| {
+        let vm = PikeVm::new(&parse(regex).unwrap(), Flags::default());
+        let span = Match {
+            start: 0,
+            end: haystack.len(),
+        };
+        vm.captures(haystack.as_bytes(), span).map(|captures| {
+            captures
+                .iter()
+                .map(|m| m.map(|m| (m.start, m.end)))
+                .collect::<Vec<_>>()
+        })
+    };
+    assert_eq!(
+        spans("(a+)(b|c)?d", "aacd"),
+        Some(vec![Some((0, 4)), Some((0, 2)), Some((2, 3))])
+    );
+    assert_eq!(
+        spans("(a+)(b|c)?d", "ad"),
+        Some(vec![Some((0, 2)), Some((0, 1)), None])
+    );
+    assert_eq!(spans("(a+)(b|c)?d", "abb"), None);
+    // 繰り返しの中のグループは最後の一回の範囲
+    assert_eq!(
+        spans("(?:(a)|(b))*", "ab"),
+        Some(vec![Some((0, 2)), Some((0, 1)), Some((1, 2))])
+    );
+    assert_eq!(
+        spans("(a|ab)(c|bcd)", "abcd"),
+        Some(vec![Some((0, 4)), Some((0, 1)), Some((1, 4))])
+    );
+    assert_eq!(spans("(a*)*", "aa"), Some(vec![Some((0, 2)), Some((0, 2))]));
+    let vm = PikeVm::new(&parse("x(?i:(b))").unwrap(), Flags::default());
+    assert_eq!(vm.groups(), 2);
+    let span = Match { start: 1, end: 3 };
+    let captures = vm.captures(b"-xB-", span).unwrap();
+    assert_eq!(captures.get(1), Some(Match { start: 2, end: 3 }));
+    assert!(vm.captures(b"-XB-", span).is_none());
+}
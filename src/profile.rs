@@ -0,0 +1,121 @@
+use dfa::Dfa;
+use std::collections::HashMap;
+
+// コーパスを DFA で読んだときに、各状態を訪れた回数と各遷移を通った回数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    // visits[状態番号]
+    visits: Vec<u64>,
+    transitions: HashMap<(usize, u8), u64>,
+    // 入力の末尾まで読んで受理した数
+    accepted: u64,
+    inputs: u64,
+}
+
+impl Profile {
+    pub fn visits(&self, state: usize) -> u64 {
+        self.visits.get(state).cloned().unwrap_or(0)
+    }
+
+    pub fn transitions(&self, state: usize, c: u8) -> u64 {
+        self.transitions.get(&(state, c)).cloned().unwrap_or(0)
+    }
+
+    // 読んだバイトごとの回数 (遷移がなくて止まったバイトは数えない)
+    pub fn byte_histogram(&self) -> [u64; 256] {
+        let mut histogram = [0; 256];
+        for (&(_, c), &n) in self.transitions.iter() {
+            histogram[c as usize] += n;
+        }
+        histogram
+    }
+
+    // 訪れた回数の多い順に並べた状態番号。同じ回数なら番号の小さい順
+    pub fn hot_states(&self) -> Vec<usize> {
+        let mut states: Vec<usize> = (0..self.visits.len()).collect();
+        states.sort_by_key(|&id| (!self.visits[id], id));
+        states
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.accepted
+    }
+
+    pub fn inputs(&self) -> u64 {
+        self.inputs
+    }
+}
+
+impl Dfa {
+    // corpus の各入力を開始状態から全体一致で読み、遷移がなくなったところで打ち切る
+    pub fn profile<I, T>(&self, corpus: I) -> Profile
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let mut profile = Profile {
+            visits: vec![0; self.states.len()],
+            ..Profile::default()
+        };
+        if self.states.is_empty() {
+            return profile;
+        }
+        for input in corpus {
+            profile.inputs += 1;
+            let mut state = self.start();
+            profile.visits[state] += 1;
+            let mut alive = true;
+            for &c in input.as_ref() {
+                match self.transition(state, c) {
+                    Some(next) => {
+                        *profile.transitions.entry((state, c)).or_insert(0) += 1;
+                        profile.visits[next] += 1;
+                        state = next;
+                    }
+                    None => {
+                        alive = false;
+                        break;
+                    }
+                }
+            }
+            if alive && self.states[state].accept {
+                profile.accepted += 1;
+            }
+        }
+        profile
+    }
+
+    // よく訪れる状態ほど小さい番号にして、遷移表の中で近くに並ぶようにする
+    // profile はこの DFA (並べ替える前) で取ったもの
+    pub fn reorder_by_heat(&mut self, profile: &Profile) {
+        assert_eq!(profile.visits.len(), self.states.len(), "profile of another DFA");
+        let order = profile.hot_states();
+        self.renumber(&order);
+    }
+}
+
+#[test]
+fn profile_heat() {
+    use nfa::Nfa;
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a(b|c)*d|x+").unwrap()).unwrap();
+    dfa.minimize();
+    let corpus = ["abcbd", "ad", "xxx", "abq", "z"];
+    let profile = dfa.profile(corpus.iter());
+    assert_eq!(profile.inputs(), 5);
+    assert_eq!(profile.accepted(), 3);
+    assert_eq!(profile.visits(dfa.start()), 5);
+    assert_eq!(profile.transitions(dfa.start(), b'a'), 3);
+    let histogram = profile.byte_histogram();
+    assert_eq!(histogram[b'b' as usize], 3);
+    assert_eq!(histogram[b'x' as usize], 3);
+    assert_eq!(histogram[b'z' as usize], 0);
+
+    let before: Vec<bool> = corpus.iter().map(|s| dfa.accept(s)).collect();
+    dfa.reorder_by_heat(&profile);
+    let after: Vec<bool> = corpus.iter().map(|s| dfa.accept(s)).collect();
+    assert_eq!(before, after);
+    // 並べ替えた後の DFA で取り直すと、訪れた回数は番号の順に減っていく
+    let profile = dfa.profile(corpus.iter());
+    let visits: Vec<u64> = (0..dfa.states.len()).map(|id| profile.visits(id)).collect();
+    assert!(visits.windows(2).all(|pair| pair[0] >= pair[1]));
+}
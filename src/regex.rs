@@ -0,0 +1,1672 @@
+use ascii::AsciiDfa;
+use ast::{self, Ast};
+use dense::DenseDfa;
+use dfa::{quick_reject, Anchors, ByteMask, ByteSet, Dfa, DfaBuilder, Match, Matches, Search};
+use error::Error;
+use frontend::PatternParser;
+use lazy::{LazyDfa, LazyStream, DEFAULT_MAX_STATES};
+use literal::{LiteralMatcher, LiteralSet};
+use nfa::{split_anchors, split_flags, strip_verbose, syntax_tree_with_classes, Flags, Nfa};
+use nfa::REPEAT_LIMIT;
+use parser::NamedClasses;
+use pikevm::{Captures, PikeVm};
+#[cfg(feature = "serde")]
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use shiftor::ShiftOr;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+// パターンをコンパイルした照合器
+#[derive(Debug)]
+pub struct Regex {
+    pattern: String,
+    nfa: Nfa,
+    dfa: Dfa,
+    // ASCII だけのパターンなら dfa を 128 列に縮めたもの (入力全体の照合に使う)
+    ascii: Option<AsciiDfa>,
+    // dfa の遷移表を 1 本に詰めたもの (find と find_iter の探索に使う)
+    dense: DenseDfa,
+    // 入力の途中から始まる一致があるかを調べるための DFA の詰めた遷移表
+    searcher: DenseDfa,
+    // 逆順の言語の DFA (入力の末尾からたどる)
+    reverse: Dfa,
+    required: ByteSet,
+    // quick_reject に渡すため required をビットの集合にしたもの
+    required_mask: ByteMask,
+    anchors: Anchors,
+    // is_match と find に使う照合器
+    engine: Engine,
+    // 一致の長さの下限と上限 (下限が None なら一致はない、上限が None なら上限はない)
+    min_len: Option<usize>,
+    max_len: Option<usize>,
+    // 捕獲グループの範囲を求めるときだけ使う
+    vm: PikeVm,
+    // 暗黙のグループ 0 を含む捕獲グループの名前
+    capture_names: Vec<Option<String>>,
+    static_captures_len: Option<usize>,
+    // 逆順の言語の照合器を作るときに使う
+    root: Ast,
+    flags: Flags,
+    opt_level: OptLevel,
+    stats: CompileStats,
+}
+
+// コンパイルの各段階で作ったオートマトンの大きさ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompileStats {
+    // 構文木から作った NFA の状態数
+    pub nfa_states: usize,
+    // Nfa::reduce で模倣同値な状態をまとめた後の状態数 (reduce しない OptLevel::O3 未満では None)
+    pub reduced_nfa_states: Option<usize>,
+    // 照合に使う DFA の状態数 (EngineKind::Lazy では前もって作らないので 0)
+    pub dfa_states: usize,
+    // is_match と find に選んだ照合器
+    pub engine: EngineKind,
+}
+
+// is_match と find に使う照合器の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineKind {
+    // DFA で一致を探す
+    Dfa,
+    // 受理言語がただ一つの文字列なので、その文字列を直接探す
+    Literal,
+    // 受理言語が FINITE_SET_LIMIT 個以下の文字列なので、各位置で文字列を直接比べる
+    FiniteSet,
+    // アンカーのない 64 バイト以下の固定長のパターンなので、ビット並列で探す
+    ShiftOr,
+    // DFA の状態数が STATE_LIMIT を超えたので、照合しながら必要な状態だけを作る
+    Lazy,
+}
+
+// 文字列を直接比べる照合器を選ぶ受理言語の大きさの上限
+const FINITE_SET_LIMIT: usize = 16;
+
+#[derive(Debug)]
+enum Engine {
+    Dfa,
+    Literal(LiteralMatcher),
+    FiniteSet(LiteralSet),
+    ShiftOr(Box<ShiftOr>),
+    Lazy(Box<LazyDfa>),
+}
+
+impl Engine {
+    // OptLevel::O2 以上で、DFA より速く照合できる単純な言語なら専用の照合器を選ぶ
+    fn select(
+        dfa: &Dfa,
+        root: &Ast,
+        flags: Flags,
+        anchors: Anchors,
+        opt_level: OptLevel,
+    ) -> Engine {
+        if opt_level < OptLevel::O2 {
+            return Engine::Dfa;
+        }
+        match dfa.finite_language(FINITE_SET_LIMIT) {
+            Some(ref words) if words.len() == 1 => {
+                return Engine::Literal(LiteralMatcher::new(&words[0]))
+            }
+            Some(ref words) if words.is_empty() => return Engine::Dfa,
+            Some(words) => return Engine::FiniteSet(LiteralSet::new(words)),
+            None => {}
+        }
+        if anchors != Anchors::default() {
+            return Engine::Dfa;
+        }
+        match ShiftOr::from_ast(root, flags) {
+            Some(shift_or) => Engine::ShiftOr(Box::new(shift_or)),
+            None => Engine::Dfa,
+        }
+    }
+
+    fn kind(&self) -> EngineKind {
+        match *self {
+            Engine::Dfa => EngineKind::Dfa,
+            Engine::Literal(_) => EngineKind::Literal,
+            Engine::FiniteSet(_) => EngineKind::FiniteSet,
+            Engine::ShiftOr(_) => EngineKind::ShiftOr,
+            Engine::Lazy(_) => EngineKind::Lazy,
+        }
+    }
+}
+
+impl CompileStats {
+    // reduce の後の状態数の、前の状態数に対する比 (reduce しなければ None)
+    pub fn reduction_ratio(&self) -> Option<f64> {
+        self.reduced_nfa_states.map(|reduced| reduced as f64 / self.nfa_states as f64)
+    }
+}
+
+// コンパイルにかける手間と照合の速さの釣り合い
+// 上の段は下の段の処理を全て含む
+//   O0: 構文木をそのまま NFA にし、DFA も最小化しない。コンパイルは最も速いが DFA の状態が多い
+//   O1: 構文木を simplify で整理し、DFA を最小化する。状態が減り照合時のメモリが少ない
+//   O2: ASCII だけのパターンに 128 列の遷移表を作り、入力に必須のバイトで先に弾く。
+//       受理言語が単純なら is_match と find に DFA より速い照合器を選ぶ (既定)
+//   O3: 模倣同値な NFA 状態をまとめてから DFA を作り、部分集合構成でも他の状態に模倣される状態を
+//       刈り込む。大きなパターンで部分集合構成が軽くなるが、模倣関係の計算は状態数の 2 乗以上かかる
+// examples/opt_levels.rs で段ごとのコンパイル時間と照合時間を測れる
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    #[default]
+    O2,
+    O3,
+}
+
+impl OptLevel {
+    // 0 から 3 の数で指定する。3 より大きい数は O3 とみなす
+    pub fn from_level(level: u8) -> Self {
+        match level {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            2 => OptLevel::O2,
+            _ => OptLevel::O3,
+        }
+    }
+}
+
+// コンパイル時の設定を指定して Regex を作る
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    pattern: String,
+    flags: Flags,
+    classes: NamedClasses,
+    opt_level: OptLevel,
+    // None なら正規表現の構文で読む
+    parser: Option<Arc<dyn PatternParser>>,
+}
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> Self {
+        RegexBuilder {
+            pattern: pattern.to_owned(),
+            flags: Flags::default(),
+            classes: NamedClasses::default(),
+            opt_level: OptLevel::default(),
+            parser: None,
+        }
+    }
+
+    // パターン先頭の `(?i)` と同じ
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    // パターン先頭の `(?s)` と同じ
+    pub fn dot_all(&mut self, yes: bool) -> &mut Self {
+        self.flags.dot_all = yes;
+        self
+    }
+
+    // パターン先頭の `(?m)` と同じ
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    // パターン中のクラスに `[:name:]` と書くと bytes のいずれかに一致する
+    pub fn class(&mut self, name: &str, bytes: &[u8]) -> &mut Self {
+        self.classes.insert(name, bytes);
+        self
+    }
+
+    pub fn opt_level(&mut self, level: OptLevel) -> &mut Self {
+        self.opt_level = level;
+        self
+    }
+
+    // 正規表現の代わりに parser の構文でパターンを読む
+    // inline flag や `^` と `$` は parser の構文には含まれないので、flag は builder で指定する
+    pub fn parser<P: PatternParser + 'static>(&mut self, parser: P) -> &mut Self {
+        self.parser = Some(Arc::new(parser));
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, Error> {
+        match self.parser {
+            Some(ref parser) => {
+                Regex::with_parser(&self.pattern, &**parser, self.flags, self.opt_level)
+            }
+            None => Regex::with_flags(&self.pattern, self.flags, &self.classes, self.opt_level),
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    fn with_flags(
+        pattern: &str,
+        flags: Flags,
+        classes: &NamedClasses,
+        opt_level: OptLevel,
+    ) -> Result<Regex, Error> {
+        let _span = trace_span!(DEBUG, "compile", pattern = pattern);
+        let flags = flags.merge(split_flags(pattern.trim()).0);
+        let root = syntax_tree_with_classes(pattern, classes)?;
+        let (nfa, unreduced) = Regex::compile_tree(&root, flags, opt_level)?;
+        let (start, _, end) = split_anchors(&strip_verbose(pattern));
+        let anchors = Anchors {
+            start,
+            end,
+            multi_line: flags.multi_line,
+        };
+        Regex::assemble(pattern.to_owned(), root, nfa, unreduced, flags, anchors, opt_level)
+    }
+
+    fn with_parser(
+        pattern: &str,
+        parser: &dyn PatternParser,
+        flags: Flags,
+        opt_level: OptLevel,
+    ) -> Result<Regex, Error> {
+        let _span = trace_span!(DEBUG, "compile", pattern = pattern);
+        let root = parser.parse(pattern)?;
+        let (nfa, unreduced) = Regex::compile_tree(&root, flags, opt_level)?;
+        let anchors = Anchors {
+            start: parser.full_match(),
+            end: parser.full_match(),
+            multi_line: flags.multi_line,
+        };
+        Regex::assemble(pattern.to_owned(), root, nfa, unreduced, flags, anchors, opt_level)
+    }
+
+    // 構文木を NFA にする。reduce した場合は reduce する前の状態数も返す
+    fn compile_tree(
+        root: &Ast,
+        flags: Flags,
+        opt_level: OptLevel,
+    ) -> Result<(Nfa, Option<usize>), Error> {
+        let tree = if opt_level >= OptLevel::O1 {
+            ast::simplify(root.clone())
+        } else {
+            root.clone()
+        };
+        if tree.expanded_len() > REPEAT_LIMIT {
+            return Err(Error::RepeatLimit(REPEAT_LIMIT));
+        }
+        let nfa = Nfa::from_ast(&tree, flags);
+        if opt_level >= OptLevel::O3 {
+            Ok((nfa.reduce(), Some(nfa.states.len())))
+        } else {
+            Ok((nfa, None))
+        }
+    }
+
+    // 構文木と NFA から照合に使う DFA などを揃える
+    // unreduced は NFA を reduce したときの reduce する前の状態数
+    fn assemble(
+        pattern: String,
+        root: Ast,
+        nfa: Nfa,
+        unreduced: Option<usize>,
+        flags: Flags,
+        anchors: Anchors,
+        opt_level: OptLevel,
+    ) -> Result<Regex, Error> {
+        let determinize = |nfa: &Nfa| -> Result<Dfa, Error> {
+            let mut dfa = if opt_level >= OptLevel::O3 {
+                Dfa::nfa2dfa_pruned(nfa)?
+            } else {
+                Dfa::nfa2dfa(nfa)?
+            };
+            if opt_level >= OptLevel::O1 {
+                dfa.minimize();
+            }
+            Ok(dfa)
+        };
+        let built = determinize(&nfa).and_then(|dfa| {
+            let searcher = determinize(&nfa.unanchored())?.to_dense();
+            Ok((dfa, searcher, determinize(&nfa.reverse())?))
+        });
+        let (dfa, searcher, reverse, lazy) = match built {
+            Ok((dfa, searcher, reverse)) => (dfa, searcher, reverse, None),
+            // 状態数の上限を超えたら前もって決定化せず、照合しながら状態を作る
+            // DFA を使う照合は全て LazyDfa に任せ、dfa などは状態のない DFA にしておく
+            Err(e @ Error::SizeLimit(_)) => {
+                let lazy = LazyDfa::from_nfa(&pattern, nfa.clone(), anchors, DEFAULT_MAX_STATES)
+                    .map_err(|_| e)?;
+                (Dfa::new(), Dfa::new().to_dense(), Dfa::new(), Some(lazy))
+            }
+            Err(e) => return Err(e),
+        };
+        let (required, ascii) = if opt_level >= OptLevel::O2 && lazy.is_none() {
+            (dfa.required_bytes(), AsciiDfa::from_dfa(&dfa))
+        } else {
+            ([false; 256], None)
+        };
+        let (min_len, max_len) = match lazy {
+            Some(_) => (Some(0), None),
+            None => (dfa.min_match_len(), dfa.max_match_len()),
+        };
+        let engine = match lazy {
+            Some(lazy) => Engine::Lazy(Box::new(lazy)),
+            None => Engine::select(&dfa, &root, flags, anchors, opt_level),
+        };
+        let vm = PikeVm::new(&root, flags);
+        let mut names = vec![None];
+        names.extend(ast::capture_names(&root));
+        let static_groups = ast::static_capture_groups(&root);
+        let stats = CompileStats {
+            nfa_states: unreduced.unwrap_or(nfa.states.len()),
+            reduced_nfa_states: unreduced.map(|_| nfa.states.len()),
+            dfa_states: dfa.states.len(),
+            engine: engine.kind(),
+        };
+        Ok(Regex {
+            pattern,
+            nfa,
+            ascii,
+            dense: dfa.to_dense(),
+            dfa,
+            searcher,
+            reverse,
+            required,
+            required_mask: ByteMask::from_set(&required),
+            anchors,
+            engine,
+            min_len,
+            max_len,
+            vm,
+            capture_names: names,
+            static_captures_len: static_groups.map(|n| n + 1),
+            root,
+            flags,
+            opt_level,
+            stats,
+        })
+    }
+
+    // 一致する各バイト列を逆順にした言語の照合器。`^` と `$` は入れ替わる
+    // OptLevel など builder で指定した設定は元の照合器と同じにする
+    // 捕獲グループは逆順の構文木で `(` が現れる順に番号を付け直す
+    pub fn reversed(&self) -> Result<Regex, Error> {
+        let root = ast::reverse(&self.root);
+        let anchors = Anchors {
+            start: self.anchors.end,
+            end: self.anchors.start,
+            multi_line: self.anchors.multi_line,
+        };
+        let pattern = format!(
+            "{}{}{}{}",
+            self.flags.to_inline(),
+            if anchors.start { "^" } else { "" },
+            ast::to_pattern(&root),
+            if anchors.end { "$" } else { "" }
+        );
+        let nfa = self.nfa.reverse();
+        Regex::assemble(pattern, root, nfa, None, self.flags, anchors, self.opt_level)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn nfa(&self) -> &Nfa {
+        &self.nfa
+    }
+
+    // EngineKind::Lazy の照合器は DFA を作らないので、状態のない DFA を返す
+    pub fn dfa(&self) -> &Dfa {
+        &self.dfa
+    }
+
+    // 最小化する前と後の DFA の組。最小化の効果を比べたり、monoid をそれぞれに作ったりするのに使う
+    // (OptLevel にかかわらず両方をこの NFA から作り直す。EngineKind::Lazy の照合器では panic する)
+    pub fn dfa_pair(&self) -> (Dfa, Dfa) {
+        let dfa = Dfa::nfa2dfa(&self.nfa).expect("determinized when the regex was built");
+        let minimized = dfa.minimized();
+        (dfa, minimized)
+    }
+
+    pub fn stats(&self) -> &CompileStats {
+        &self.stats
+    }
+
+    pub fn engine_kind(&self) -> EngineKind {
+        self.stats.engine
+    }
+
+    // 一致に必ず含まれるバイト (OptLevel::O2 未満では求めないので空)
+    pub fn required_bytes(&self) -> &ByteSet {
+        &self.required
+    }
+
+    // 一致する文字列が全て ASCII だけからなるか
+    pub fn is_ascii_only(&self) -> bool {
+        match self.engine {
+            Engine::Lazy(ref lazy) => lazy.is_ascii_only(),
+            _ => self.dfa.is_ascii_only(),
+        }
+    }
+
+    // 一致全体を表すグループ 0 を含む捕獲グループの数
+    pub fn captures_len(&self) -> usize {
+        self.capture_names.len()
+    }
+
+    // 各捕獲グループの名前 (名前のないグループは None)
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.capture_names.iter().map(|name| name.as_ref().map(|name| name.as_str()))
+    }
+
+    // 名前の付いた捕獲グループの番号
+    pub fn capture_index(&self, name: &str) -> Option<usize> {
+        self.vm.group_index(name)
+    }
+
+    // どの一致でもグループ 0 を含めて必ず参加する捕獲グループの数。一致によって変わるなら None
+    pub fn static_captures_len(&self) -> Option<usize> {
+        self.static_captures_len
+    }
+
+    // 入力のどこかにパターンと一致する部分があるか
+    pub fn is_match(&self, haystack: &str) -> bool {
+        let _span = trace_span!(TRACE, "is_match", haystack_len = haystack.len());
+        self.is_match_bytes(haystack.as_bytes())
+    }
+
+    fn is_match_bytes(&self, haystack: &[u8]) -> bool {
+        if quick_reject(&self.required_mask, haystack) {
+            return false;
+        }
+
+        match self.engine {
+            Engine::Literal(ref literal) => literal.find_anchored(haystack, self.anchors).is_some(),
+            Engine::FiniteSet(ref set) => set.find_anchored(haystack, self.anchors).is_some(),
+            Engine::ShiftOr(ref shift_or) => shift_or.is_match(haystack),
+            Engine::Lazy(ref lazy) => lazy.is_match_bytes(haystack),
+            Engine::Dfa if self.anchors != Anchors::default() => {
+                self.dense.find_anchored(haystack, 0, self.anchors).is_some()
+            }
+            Engine::Dfa => self.searcher.shortest_match(haystack).is_some(),
+        }
+    }
+
+    // 最も左から始まる最長の一致
+    pub fn find(&self, haystack: &str) -> Option<Match> {
+        let _span = trace_span!(TRACE, "find", haystack_len = haystack.len());
+        let bytes = haystack.as_bytes();
+        match self.engine {
+            Engine::Literal(ref literal) => literal.find_anchored(bytes, self.anchors),
+            Engine::FiniteSet(ref set) => set.find_anchored(bytes, self.anchors),
+            Engine::ShiftOr(ref shift_or) => {
+                shift_or.find(bytes).map(|(start, end)| Match { start, end })
+            }
+            Engine::Lazy(ref lazy) => lazy.find(haystack),
+            Engine::Dfa if !self.is_match(haystack) => None,
+            Engine::Dfa => self.dense.find_anchored(bytes, 0, self.anchors),
+        }
+    }
+
+    // find と同じ一致と、その中で各捕獲グループが一致した範囲
+    pub fn captures(&self, haystack: &str) -> Option<Captures> {
+        let m = self.find(haystack)?;
+        self.vm.captures(haystack.as_bytes(), m)
+    }
+
+    // 重ならない全ての一致
+    pub fn find_iter<'h>(&self, haystack: &'h str) -> Matches<'_, 'h> {
+        Matches::new(self.search(), haystack.as_bytes(), self.anchors, self.min_len, self.max_len)
+    }
+
+    // find_iter などで一致を探す照合器
+    fn search(&self) -> &dyn Search {
+        match self.engine {
+            Engine::Lazy(ref lazy) => &**lazy,
+            _ => &self.dense,
+        }
+    }
+
+    // 一致と一致の間の部分文字列
+    pub fn split<'r, 'h>(&'r self, haystack: &'h str) -> Split<'r, 'h> {
+        Split {
+            haystack,
+            matches: self.find_iter(haystack),
+            last: Some(0),
+            limit: None,
+        }
+    }
+
+    // split と同じだが、最大 limit 個に分け、最後の部分には残りの全てを含める
+    pub fn splitn<'r, 'h>(&'r self, haystack: &'h str, limit: usize) -> Split<'r, 'h> {
+        Split {
+            limit: Some(limit),
+            ..self.split(haystack)
+        }
+    }
+
+    // find_iter の各一致で、名前付きグループの名前と一致した部分文字列を組にする
+    // 一致しなかったグループと、文字の途中で始まるか終わる部分は組に含めない
+    pub fn extract_records<'r, 'h>(
+        &'r self,
+        haystack: &'h str,
+    ) -> impl Iterator<Item = Record<'r, 'h>> + 'r
+    where
+        'h: 'r,
+    {
+        let bytes = haystack.as_bytes();
+        self.find_iter(haystack)
+            .filter(move |m| haystack.is_char_boundary(m.start) && haystack.is_char_boundary(m.end))
+            .filter_map(move |m| self.vm.captures(bytes, m))
+            .map(move |caps| Record {
+                fields: self
+                    .capture_names
+                    .iter()
+                    .zip(caps.iter())
+                    .filter_map(|(name, span)| {
+                        let span = span?;
+                        Some((name.as_ref()?.as_str(), haystack.get(span.start..span.end)?))
+                    })
+                    .collect(),
+            })
+    }
+
+    // find_iter の一致した部分文字列ごとの出現回数
+    // 文字の途中で始まるか終わる一致は部分文字列にできないので数えない
+    pub fn match_histogram<'h>(&self, haystack: &'h str) -> HashMap<&'h str, usize> {
+        let mut histogram = HashMap::new();
+        for m in self.find_iter(haystack) {
+            if let Some(text) = haystack.get(m.start..m.end) {
+                *histogram.entry(text).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+
+    // find_iter と同じ一致を、入力を chunk_size バイトずつに分けて複数のスレッドで探す
+    // 各区間ではその区間から始まる一致を区間の先頭から探し、前の区間の一致が区間の先頭を越えていれば、
+    // 越えた位置から区間の結果と揃うまで探し直す
+    pub fn find_iter_parallel(&self, haystack: &str, chunk_size: usize) -> Vec<Match> {
+        let bytes = haystack.as_bytes();
+        let chunk_size = chunk_size.max(1);
+        // 空の一致は入力の末尾にもあり得るので、開始位置は haystack.len() まで
+        let chunks: Vec<Range<usize>> = (0..=bytes.len())
+            .step_by(chunk_size)
+            .map(|lo| lo..(lo + chunk_size).min(bytes.len() + 1))
+            .collect();
+        let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(chunks.len());
+        if chunks.len() <= 1 || self.min_len.is_none() {
+            return self.find_iter(haystack).collect();
+        }
+
+        let mut found: Vec<Vec<Match>> = vec![Vec::new(); chunks.len()];
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|worker| {
+                    let chunks = &chunks;
+                    scope.spawn(move || {
+                        (worker..chunks.len())
+                            .step_by(workers)
+                            .map(|i| (i, self.find_starting_in(bytes, chunks[i].clone())))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            for handle in handles {
+                for (i, matches) in handle.join().unwrap() {
+                    found[i] = matches;
+                }
+            }
+        });
+
+        let next_pos = |m: &Match| if m.start == m.end { m.end + 1 } else { m.end };
+        let mut matches = Vec::new();
+        // 前の区間までの最後の一致の次に探し始める位置
+        let mut pos = 0;
+        for (chunk, local) in chunks.into_iter().zip(found) {
+            // 区間の結果を作る途中で探し始めた位置
+            let restarts: Vec<usize> = Some(chunk.start)
+                .into_iter()
+                .chain(local.iter().map(next_pos))
+                .collect();
+            while pos < chunk.end {
+                if pos <= chunk.start {
+                    matches.extend_from_slice(&local);
+                    pos = local.last().map_or(pos, next_pos);
+                    break;
+                }
+                if let Some(k) = restarts.iter().position(|&at| at == pos) {
+                    matches.extend_from_slice(&local[k..]);
+                    pos = local.last().map_or(pos, next_pos);
+                    break;
+                }
+                let m = match self.find_next(bytes, pos..chunk.end) {
+                    Some(m) => m,
+                    None => break,
+                };
+                pos = next_pos(&m);
+                matches.push(m);
+            }
+        }
+        matches
+    }
+
+    // 開始位置が range の中にある最も左で最長の一致
+    fn find_next(&self, haystack: &[u8], range: Range<usize>) -> Option<Match> {
+        self.search().find_bounded(haystack, range, self.anchors, self.max_len)
+    }
+
+    // range から始まる重ならない一致を find_iter と同じ規則で左から順に求める
+    fn find_starting_in(&self, haystack: &[u8], range: Range<usize>) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let mut pos = range.start;
+        while pos < range.end {
+            let m = match self.find_next(haystack, pos..range.end) {
+                Some(m) => m,
+                None => break,
+            };
+            pos = if m.start == m.end { m.end + 1 } else { m.end };
+            matches.push(m);
+        }
+        matches
+    }
+
+    // MappedFile::open で開いたファイルを行ごとに調べ、一致する部分を持つ行を順に返す
+    pub fn find_in_file<'h>(&self, file: &'h MappedFile) -> LineMatches<'_, 'h> {
+        self.find_in_lines(file)
+    }
+
+    // contents を行ごとに調べ、一致する部分を持つ行とその中の重ならない一致を順に返す
+    // `^` と `$` は行の先頭と末尾 (CRLF なら `\r` の前) に一致する。位置は全て contents の先頭から
+    pub fn find_in_lines<'h>(&self, contents: &'h [u8]) -> LineMatches<'_, 'h> {
+        // 末尾の改行の後は行として数えない
+        let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+        LineMatches {
+            regex: self,
+            contents,
+            pos: 0,
+            number: 0,
+            done: contents.is_empty(),
+        }
+    }
+
+    // 入力の先頭から始まる部分がパターンと一致するか
+    pub fn matches_prefix(&self, haystack: &str) -> bool {
+        if let Engine::Lazy(ref lazy) = self.engine {
+            return lazy.matches_prefix(haystack);
+        }
+        let haystack = haystack.as_bytes();
+        reaches_accept(&self.dfa, haystack.iter(), |n| self.anchors.end_ok(haystack, n))
+    }
+
+    // 入力の末尾で終わる部分がパターンと一致するか
+    pub fn matches_suffix(&self, haystack: &str) -> bool {
+        if let Engine::Lazy(ref lazy) = self.engine {
+            return lazy.matches_suffix(haystack);
+        }
+        let haystack = haystack.as_bytes();
+        reaches_accept(&self.reverse, haystack.iter().rev(), |n| {
+            self.anchors.start_ok(haystack, haystack.len() - n)
+        })
+    }
+
+    // 入力の先頭に最長一致させ、一致範囲と残りの入力を返す
+    // `$` で終わるパターンは、一致の直後が入力 (multi-line なら行) の終わりのときだけ返す
+    pub fn strip_prefix_match<'a>(&self, input: &'a str) -> Option<(Match, &'a str)> {
+        let (m, rest) = match self.engine {
+            Engine::Lazy(ref lazy) => lazy.strip_prefix_match(input)?,
+            _ => self.dfa.strip_prefix_match(input)?,
+        };
+        if self.anchors.end_ok(input.as_bytes(), m.end) {
+            Some((m, rest))
+        } else {
+            None
+        }
+    }
+
+    // 入力全体がパターンと一致するか
+    pub fn is_full_match(&self, haystack: &str) -> bool {
+        if let Some(ref ascii) = self.ascii {
+            return ascii.accept_bytes(haystack.as_bytes());
+        }
+        match self.engine {
+            Engine::Lazy(ref lazy) => lazy.is_full_match(haystack),
+            _ => self.dfa.accept(haystack),
+        }
+    }
+
+    // template の `?` を表示可能な ASCII 文字 1 個ずつで埋め、全体がパターンと一致する文字列を返す
+    // `?` 以外はそのまま残す。埋め方が複数あれば辞書順で最小のもの、なければ None
+    pub fn fill_template(&self, template: &str) -> Option<String> {
+        if let Engine::Lazy(ref lazy) = self.engine {
+            return lazy.fill_template(template);
+        }
+        // template の言語: i 番目の状態から i バイト目を読んで i + 1 番目へ進む
+        let mut builder = DfaBuilder::new();
+        for i in 0..=template.len() {
+            builder.add_state(i == template.len());
+        }
+        for (i, &c) in template.as_bytes().iter().enumerate() {
+            if c == b'?' {
+                for c in b' '..=b'~' {
+                    builder.set_transition(i, c, i + 1).ok()?;
+                }
+            } else {
+                builder.set_transition(i, c, i + 1).ok()?;
+            }
+        }
+        let template = builder.finish().ok()?;
+        let filled = self.dfa.intersection(&template).shortest_accepted()?;
+        String::from_utf8(filled).ok()
+    }
+
+    // 遅延して作られる入力に対する is_match
+    pub fn is_match_iter<I: IntoIterator<Item = u8>>(&self, input: I) -> bool {
+        let mut stream = self.stream();
+        stream.feed_iter(input) || stream.finish()
+    }
+
+    // 入力を断片ごとに与えて is_match と同じ判定をする照合器
+    pub fn stream(&self) -> StreamMatcher<'_> {
+        let lazy = match self.engine {
+            Engine::Lazy(ref lazy) => Some(lazy.stream()),
+            _ => None,
+        };
+        StreamMatcher {
+            regex: self,
+            lazy,
+            active: vec![None; self.dfa.states.len()],
+            scratch: vec![None; self.dfa.states.len()],
+            last: None,
+            pending: false,
+            matched: false,
+            position: 0,
+        }
+    }
+}
+
+// 入力全体を保持せずに一致を探す照合器
+// `$` で終わるパターンは入力の終わりが分かるまで判定できないため、finish で結果を確定する
+#[derive(Debug)]
+pub struct StreamMatcher<'r> {
+    regex: &'r Regex,
+    // EngineKind::Lazy の照合器なら、読んだ入力は全てこちらで調べる
+    lazy: Option<LazyStream<'r>>,
+    // 一致の途中にある DFA の状態と、そこにいる一致候補のうち最も遅い開始位置
+    // 同じ状態にいる候補は先の振る舞いが同じなので、長さの上限に最も余裕のあるものだけを残す
+    active: Vec<Option<u64>>,
+    // 次のバイトを読んだ後の active を作る作業領域 (バイトごとに確保しないように使い回す)
+    scratch: Vec<Option<u64>>,
+    // 直前のバイト (行頭の判定に使う)
+    last: Option<u8>,
+    // ここで入力が終われば一致する
+    pending: bool,
+    matched: bool,
+    position: u64,
+}
+
+impl<'r> StreamMatcher<'r> {
+    // 続きの入力を読み、一致が確定していれば true を返す
+    pub fn feed(&mut self, chunk: &[u8]) -> bool {
+        self.feed_iter(chunk.iter().cloned())
+    }
+
+    // バイトを一つずつ作る入力を読む。一致が確定したら残りは読まない
+    pub fn feed_iter<I: IntoIterator<Item = u8>>(&mut self, input: I) -> bool {
+        if let Some(ref mut lazy) = self.lazy {
+            return lazy.feed_iter(input);
+        }
+        let anchors = self.regex.anchors;
+        let states = &self.regex.dfa.states;
+        let max_len = self.regex.max_len;
+        let mut input = input.into_iter();
+        loop {
+            self.settle();
+            if self.matched {
+                break;
+            }
+            let c = match input.next() {
+                Some(c) => c,
+                None => break,
+            };
+            self.position += 1;
+            if self.pending && anchors.multi_line && c == b'\n' {
+                self.matched = true;
+                break;
+            }
+            // このバイトを読む前に読んだバイト数
+            let read = self.position - 1;
+            let class = self.regex.dfa.classes().get(c);
+            let next = &mut self.scratch;
+            for slot in next.iter_mut() {
+                *slot = None;
+            }
+            for (id, &start) in self.active.iter().enumerate() {
+                let start = match start {
+                    Some(start) => start,
+                    None => continue,
+                };
+                // 既に最長の一致と同じだけ読んだ候補はもう受理されない
+                if max_len.is_some_and(|max| read - start >= max as u64) {
+                    continue;
+                }
+                if let Some(to) = states[id].t[class] {
+                    next[to] = next[to].max(Some(start));
+                }
+            }
+            ::std::mem::swap(&mut self.active, &mut self.scratch);
+            self.last = Some(c);
+        }
+        self.matched
+    }
+
+    // 現在の位置で一致を始められれば開始状態を加え、受理状態にあるかを調べる
+    fn settle(&mut self) {
+        let anchors = self.regex.anchors;
+        let line_start = self.last.is_none() || (anchors.multi_line && self.last == Some(b'\n'));
+        if !anchors.start || line_start {
+            self.active[self.regex.dfa.start()] = Some(self.position);
+        }
+        let states = &self.regex.dfa.states;
+        self.pending = self
+            .active
+            .iter()
+            .enumerate()
+            .any(|(id, active)| active.is_some() && states[id].accept);
+        if self.pending && !anchors.end {
+            self.matched = true;
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        match self.lazy {
+            Some(ref lazy) => lazy.position(),
+            None => self.position,
+        }
+    }
+
+    // 入力の終わりを知らせ、末尾のアンカーを解決した最終的な判定を返す
+    pub fn finish(mut self) -> bool {
+        if let Some(lazy) = self.lazy.take() {
+            return lazy.finish();
+        }
+        // 最短の一致より短い入力は調べるまでもない
+        match self.regex.min_len {
+            Some(min) if self.position >= min as u64 => {}
+            _ => return false,
+        }
+        if !self.matched {
+            self.settle();
+        }
+        self.matched || self.pending
+    }
+}
+
+// Regex::find_in_file に渡すファイルの中身
+// memmap2 feature があればファイルをメモリに読み込まずに mmap する
+#[derive(Debug)]
+pub struct MappedFile {
+    #[cfg(feature = "memmap2")]
+    map: Option<memmap2::Mmap>,
+    #[cfg(not(feature = "memmap2"))]
+    bytes: Vec<u8>,
+}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedFile, Error> {
+        #[cfg(feature = "memmap2")]
+        {
+            let file = fs::File::open(path)?;
+            // 長さ 0 のファイルは mmap できない
+            if file.metadata()?.len() == 0 {
+                return Ok(MappedFile { map: None });
+            }
+            // 照合の途中で他のプロセスがファイルを書き換えると結果は不定になる
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            Ok(MappedFile { map: Some(map) })
+        }
+        #[cfg(not(feature = "memmap2"))]
+        Ok(MappedFile {
+            bytes: fs::read(path)?,
+        })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    #[cfg(feature = "memmap2")]
+    fn deref(&self) -> &[u8] {
+        self.map.as_ref().map_or(&[], |map| map)
+    }
+
+    #[cfg(not(feature = "memmap2"))]
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+// Regex::find_in_lines の返す、一致を含む行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch<'h> {
+    // 1 から数えた行番号
+    pub number: usize,
+    // 改行と行末の `\r` を含まない行の範囲
+    pub span: Match,
+    pub text: &'h [u8],
+    pub matches: Vec<Match>,
+}
+
+// Regex::find_in_lines の返すイテレータ
+#[derive(Debug)]
+pub struct LineMatches<'r, 'h> {
+    regex: &'r Regex,
+    contents: &'h [u8],
+    // 次の行の先頭
+    pos: usize,
+    number: usize,
+    done: bool,
+}
+
+impl<'r, 'h> Iterator for LineMatches<'r, 'h> {
+    type Item = LineMatch<'h>;
+
+    fn next(&mut self) -> Option<LineMatch<'h>> {
+        let regex = self.regex;
+        while !self.done {
+            let rest = &self.contents[self.pos..];
+            let len = match rest.iter().position(|&c| c == b'\n') {
+                Some(len) => len,
+                None => {
+                    self.done = true;
+                    rest.len()
+                }
+            };
+            let line = &rest[..len];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let start = self.pos;
+            self.pos += len + 1;
+            self.number += 1;
+            // 行を丸ごと弾けるかを先に調べ、一致のある行だけで位置を求める
+            if !regex.is_match_bytes(line) {
+                continue;
+            }
+            let search = regex.search();
+            let matches = Matches::new(search, line, regex.anchors, regex.min_len, regex.max_len)
+                .map(|m| Match {
+                    start: start + m.start,
+                    end: start + m.end,
+                })
+                .collect();
+            return Some(LineMatch {
+                number: self.number,
+                span: Match {
+                    start,
+                    end: start + line.len(),
+                },
+                text: line,
+                matches,
+            });
+        }
+        None
+    }
+}
+
+// Regex::extract_records の返す、グループの名前と一致した部分文字列の組 (グループの順)
+// serde feature では名前をキーとする map として直列化する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<'r, 'h> {
+    fields: Vec<(&'r str, &'h str)>,
+}
+
+impl<'r, 'h> Record<'r, 'h> {
+    pub fn get(&self, name: &str) -> Option<&'h str> {
+        self.fields.iter().find(|&&(n, _)| n == name).map(|&(_, text)| text)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'r str, &'h str)> + '_ {
+        self.fields.iter().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'r, 'h> Serialize for Record<'r, 'h> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for &(name, text) in self.fields.iter() {
+            map.serialize_entry(name, text)?;
+        }
+        map.end()
+    }
+}
+
+// Regex::split と Regex::splitn の返す部分文字列の列
+pub struct Split<'r, 'h> {
+    haystack: &'h str,
+    matches: Matches<'r, 'h>,
+    // まだ返していない部分の始まり (None なら全て返した)
+    last: Option<usize>,
+    // あと何個返せるか
+    limit: Option<usize>,
+}
+
+impl<'r, 'h> Iterator for Split<'r, 'h> {
+    type Item = &'h str;
+
+    fn next(&mut self) -> Option<&'h str> {
+        let last = self.last?;
+        match self.limit {
+            Some(0) => {
+                self.last = None;
+                return None;
+            }
+            Some(1) => {
+                self.last = None;
+                return Some(&self.haystack[last..]);
+            }
+            Some(ref mut limit) => *limit -= 1,
+            None => {}
+        }
+        // 空の一致は文字の途中にも現れるので、文字の境界にあるものだけで分ける
+        let haystack = self.haystack;
+        let m = self
+            .matches
+            .find(|m| haystack.is_char_boundary(m.start) && haystack.is_char_boundary(m.end));
+        match m {
+            Some(m) => {
+                self.last = Some(m.end);
+                Some(&haystack[last..m.start])
+            }
+            None => {
+                self.last = None;
+                Some(&haystack[last..])
+            }
+        }
+    }
+}
+
+// 入力を先頭から読む途中で、読んだバイト数 n が at(n) を満たす位置で受理状態を通るか
+fn reaches_accept<'a, I, F>(dfa: &Dfa, haystack: I, at: F) -> bool
+where
+    I: Iterator<Item = &'a u8>,
+    F: Fn(usize) -> bool,
+{
+    let mut state = match dfa.states.get(dfa.start()) {
+        Some(state) => state,
+        None => return false,
+    };
+    if state.accept && at(0) {
+        return true;
+    }
+    for (i, &c) in haystack.enumerate() {
+        match dfa.transition(state.id, c) {
+            Some(next) => state = &dfa.states[next],
+            None => return false,
+        }
+        if state.accept && at(i + 1) {
+            return true;
+        }
+    }
+    false
+}
+
+#[test]
+fn regex_is_match() {
+    let re = Regex::new("a(b|c)*d").unwrap();
+    assert_eq!(re.as_str(), "a(b|c)*d");
+    assert!(re.is_match("xxabcbdyy"));
+    assert!(re.is_match("ad"));
+    assert!(!re.is_match("abcb"));
+    assert!(!re.is_match("xxdbca"));
+    assert_eq!(re.find("xxabcbdyy"), Some(Match { start: 2, end: 7 }));
+    assert_eq!(re.find_iter("adxabd").count(), 2);
+    assert!(re.is_full_match("abd"));
+    assert!(!re.is_full_match("xabd"));
+    assert!(re.is_ascii_only());
+    assert!(!Regex::new("a.b").unwrap().is_ascii_only());
+    assert!(!Regex::new("caf\\xe9").unwrap().is_ascii_only());
+    // 空のパターンはどの位置でも空文字列に一致する
+    let empty = Regex::new("").unwrap();
+    assert_eq!(empty.find("ab"), Some(Match { start: 0, end: 0 }));
+    let spans: Vec<_> = empty.find_iter("ab").map(|m| m.start..m.end).collect();
+    assert_eq!(spans, [0..0, 1..1, 2..2]);
+    assert!(empty.is_full_match("") && !empty.is_full_match("a"));
+    assert!(Regex::new("^$").unwrap().is_full_match(""));
+}
+
+#[test]
+fn capture_spans() {
+    let re = Regex::new("([a-z]+)=([0-9]+)(;)?").unwrap();
+    let caps = re.captures("x: key=42, y").unwrap();
+    assert_eq!(caps.len(), re.captures_len());
+    assert_eq!(caps.get(0), Some(Match { start: 3, end: 9 }));
+    assert_eq!(caps.get(1), Some(Match { start: 3, end: 6 }));
+    assert_eq!(caps.get(2), Some(Match { start: 7, end: 9 }));
+    assert_eq!(caps.get(3), None);
+    assert_eq!(caps.get(4), None);
+    assert!(re.captures("no match").is_none());
+    let re = Regex::new("^(a|ab)(b*)$").unwrap();
+    let caps = re.captures("abbb").unwrap();
+    assert_eq!(caps.get(1), Some(Match { start: 0, end: 1 }));
+    assert_eq!(caps.get(2), Some(Match { start: 1, end: 4 }));
+}
+
+#[test]
+// 対応していない後読みをわざと渡す
+#[allow(clippy::invalid_regex)]
+fn named_captures() {
+    let re = Regex::new("(?P<year>[0-9]{4})-(?<month>[0-9]{2})(-([0-9]{2}))?").unwrap();
+    assert_eq!(
+        re.capture_names().collect::<Vec<_>>(),
+        vec![None, Some("year"), Some("month"), None, None]
+    );
+    assert_eq!(re.capture_index("month"), Some(2));
+    assert_eq!(re.capture_index("day"), None);
+    let caps = re.captures("on 2024-06").unwrap();
+    assert_eq!(caps.name("year"), Some(Match { start: 3, end: 7 }));
+    assert_eq!(caps.name("month"), Some(Match { start: 8, end: 10 }));
+    assert_eq!(caps.name("day"), None);
+    assert_eq!(ast::to_pattern(&::nfa::syntax_tree("(?<y>a)b").unwrap()), "(?P<y>a)b");
+    for (pattern, message) in [
+        ("(?P<1x>a)", "invalid group name"),
+        ("(?<>a)", "invalid group name"),
+        ("(?P<x", "unclosed group name"),
+        ("(?P<x>a)(?<x>b)", "duplicate group name \"x\""),
+    ] {
+        match Regex::new(pattern) {
+            Err(Error::Syntax(found, _)) => assert_eq!(found, message),
+            other => panic!("{}: {:?}", pattern, other),
+        }
+    }
+    assert!(Regex::new("(?<=a)").is_err());
+}
+
+#[test]
+fn unminimized_and_minimized() {
+    use monoid::Monoid;
+    let re = Regex::new("(a|b)*abb").unwrap();
+    let (dfa, minimized) = re.dfa_pair();
+    assert_eq!((dfa.states.len(), minimized.states.len()), (5, 4));
+    assert_eq!(minimized.states.len(), re.dfa().states.len());
+    assert!(minimized.is_homomorphic_image_of(&dfa));
+    for haystack in ["abb", "babb", "ab", ""].iter() {
+        assert_eq!(dfa.accept(haystack), minimized.accept(haystack));
+    }
+    assert_eq!(Monoid::construct(&dfa).size(), Monoid::construct(&minimized).size());
+}
+
+#[test]
+fn reversed_language() {
+    let re = Regex::new("^(ab|c)+d[0-9]{2}").unwrap();
+    let rev = re.reversed().unwrap();
+    assert_eq!(rev.as_str(), "[0-9]{2}d(ba|c)+$");
+    assert!(rev.is_full_match("21dbacba"));
+    assert!(rev.is_match("xx21dcba"));
+    assert!(!rev.is_match("21dbacbax"));
+    assert_eq!(rev.find("x12dc"), Some(Match { start: 1, end: 5 }));
+    let caps = rev.captures("12dcba").unwrap();
+    assert_eq!(caps.get(1), Some(Match { start: 4, end: 6 }));
+    assert!(rev.reversed().unwrap().is_full_match("abcd12"));
+
+    let rev = Regex::new("(?i)é+x").unwrap().reversed().unwrap();
+    assert_eq!(rev.as_str(), "(?i)x(?:\\xa9\\xc3)+");
+    let haystack: Vec<u8> = "ééX".bytes().rev().collect();
+    assert!(rev.dfa().accept_bytes(&haystack));
+
+    let re = RegexBuilder::new("ab+c").opt_level(OptLevel::O0).build().unwrap();
+    let rev = re.reversed().unwrap();
+    assert!(rev.is_full_match("cbba"));
+    assert_eq!(rev.required_bytes(), &[false; 256]);
+}
+
+#[test]
+fn opt_levels() {
+    let pattern = "(foo|foobar|fo+)[0-9]{2}(x|y|x)*";
+    let haystacks = ["foobar42xyx", "xx fooo12 yy", "foo1", "", "bar99"];
+    let mut sizes = Vec::new();
+    for level in 0..=3 {
+        let re = RegexBuilder::new(pattern)
+            .opt_level(OptLevel::from_level(level))
+            .build()
+            .unwrap();
+        for haystack in haystacks.iter() {
+            let expected = Regex::new(pattern).unwrap();
+            assert_eq!(re.is_match(haystack), expected.is_match(haystack));
+            assert_eq!(re.find(haystack), expected.find(haystack));
+            assert_eq!(re.is_full_match(haystack), expected.is_full_match(haystack));
+        }
+        sizes.push((re.nfa().states.len(), re.dfa().states.len()));
+        let stats = re.stats();
+        assert_eq!(stats.dfa_states, re.dfa().states.len());
+        if level < 3 {
+            assert_eq!(stats.nfa_states, re.nfa().states.len());
+            assert_eq!(stats.reduction_ratio(), None);
+        } else {
+            assert_eq!(stats.nfa_states, sizes[2].0);
+            assert_eq!(stats.reduced_nfa_states, Some(re.nfa().states.len()));
+            assert!(stats.reduction_ratio().unwrap() < 1.0);
+        }
+    }
+    assert!(sizes[0].1 > sizes[1].1);
+    assert_eq!(sizes[1], sizes[2]);
+    assert!(sizes[3].0 < sizes[2].0);
+    // 刈り込みながら作った DFA も最小化すれば同じ大きさになる
+    assert_eq!(sizes[3].1, sizes[2].1);
+    let re = RegexBuilder::new("ab").opt_level(OptLevel::O1).build().unwrap();
+    assert!(!re.required_bytes()[b'a' as usize]);
+    assert!(Regex::new("ab").unwrap().required_bytes()[b'a' as usize]);
+    assert_eq!(OptLevel::from_level(9), OptLevel::O3);
+}
+
+#[test]
+fn split_between_matches() {
+    let re = Regex::new("[,;] *").unwrap();
+    let pieces: Vec<_> = re.split("a, b;c,,d").collect();
+    assert_eq!(pieces, vec!["a", "b", "c", "", "d"]);
+    assert_eq!(re.split("").collect::<Vec<_>>(), vec![""]);
+    assert_eq!(re.split(",x,").collect::<Vec<_>>(), vec!["", "x", ""]);
+    assert_eq!(re.splitn("a, b;c,,d", 2).collect::<Vec<_>>(), vec!["a", "b;c,,d"]);
+    assert_eq!(re.splitn("a, b", 5).collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(re.splitn("a, b", 0).count(), 0);
+    let re = Regex::new("x*").unwrap();
+    assert_eq!(re.split("aé").collect::<Vec<_>>(), vec!["", "a", "é", ""]);
+}
+
+#[test]
+fn prefix_and_suffix() {
+    let re = Regex::new("\\.(rs|toml)").unwrap();
+    assert!(re.matches_suffix("src/main.rs"));
+    assert!(re.matches_suffix("Cargo.toml"));
+    assert!(!re.matches_suffix("main.rs.bak"));
+    assert!(!re.matches_prefix("main.rs"));
+    let re = Regex::new("ab+").unwrap();
+    assert!(re.matches_prefix("abbbxyz"));
+    assert!(!re.matches_prefix("xab"));
+    assert!(re.matches_suffix("xxab"));
+    assert!(!re.matches_suffix("abx"));
+    assert_eq!(re.strip_prefix_match("abbxab"), Some((Match { start: 0, end: 3 }, "xab")));
+    assert_eq!(re.strip_prefix_match("xab"), None);
+    let re = Regex::new("[0-9]+$").unwrap();
+    assert_eq!(re.strip_prefix_match("42"), Some((Match { start: 0, end: 2 }, "")));
+    assert_eq!(re.strip_prefix_match("42a"), None);
+}
+
+#[test]
+fn capture_metadata() {
+    let re = Regex::new("(a)(b|c)[0-9]").unwrap();
+    assert_eq!(re.captures_len(), 3);
+    assert_eq!(re.static_captures_len(), Some(3));
+    assert_eq!(re.capture_names().collect::<Vec<_>>(), vec![None, None, None]);
+    let re = Regex::new("x(a)?").unwrap();
+    assert_eq!(re.captures_len(), 2);
+    assert_eq!(re.static_captures_len(), None);
+}
+
+#[test]
+fn case_insensitive() {
+    let re = Regex::new("(?i)hello [a-c]").unwrap();
+    assert!(re.is_full_match("HeLLo B"));
+    assert!(re.is_full_match("hello a"));
+    assert!(!re.is_full_match("hello d"));
+    let re = RegexBuilder::new("ab").case_insensitive(true).build().unwrap();
+    assert!(re.is_full_match("aB"));
+    assert!(!Regex::new("ab").unwrap().is_full_match("aB"));
+    let re = Regex::new("(?ix) a b # comment").unwrap();
+    assert!(re.is_full_match("AB"));
+}
+
+#[test]
+// 閉じていないクラスをわざと渡す
+#[allow(clippy::invalid_regex)]
+fn named_classes() {
+    let re = RegexBuilder::new("[[:dna:]]+-[[:digit:]_]")
+        .class("dna", b"ACGT")
+        .build()
+        .unwrap();
+    assert!(re.is_full_match("GATTACA-7"));
+    assert!(re.is_full_match("AC-_"));
+    assert!(!re.is_full_match("GATTXCA-7"));
+    let re = RegexBuilder::new("[^[:hex:]]").class("hex", b"0123456789abcdef").build().unwrap();
+    assert!(re.is_full_match("g"));
+    assert!(!re.is_full_match("a"));
+    // 登録した名前は POSIX の名前より優先する
+    let re = RegexBuilder::new("[[:alpha:]]").class("alpha", b"ab").build().unwrap();
+    assert!(!re.is_full_match("c"));
+    match Regex::new("x[[:dna:]]") {
+        Err(Error::Syntax(message, position)) => {
+            assert_eq!(message, "unknown class name \"dna\"");
+            assert_eq!(position, 2);
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(Regex::new("[[:alpha]").is_err());
+}
+
+#[test]
+fn scoped_flags() {
+    let re = Regex::new("a(?i:b)c").unwrap();
+    assert!(re.is_full_match("aBc"));
+    assert!(!re.is_full_match("ABc"));
+    let re = Regex::new("(?i)a(?-i:b)").unwrap();
+    assert!(re.is_full_match("Ab"));
+    assert!(!re.is_full_match("AB"));
+    let re = Regex::new("(?s:a.)b.").unwrap();
+    assert!(re.is_full_match("a\nbx"));
+    assert!(!re.is_full_match("a\nb\n"));
+    let re = Regex::new("(?:ab)+(c)").unwrap();
+    assert!(re.is_full_match("ababc"));
+    assert_eq!(re.captures_len(), 2);
+    assert_eq!(
+        ast::to_pattern(&ast::simplify(::parser::parse("(?i-s:ab)(?:c)").unwrap())),
+        "(?i-s:ab)c"
+    );
+    match Regex::new("(?m:a)") {
+        Err(Error::Unsupported { feature, .. }) => assert_eq!(feature, "scoped flag"),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn dot_all_and_multi_line() {
+    let re = Regex::new("a.b").unwrap();
+    assert!(re.is_full_match("a-b"));
+    assert!(!re.is_full_match("a\nb"));
+    assert!(Regex::new("(?s)a.b").unwrap().is_full_match("a\nb"));
+    assert!(RegexBuilder::new("a.b").dot_all(true).build().unwrap().is_full_match("a\nb"));
+
+    let re = Regex::new("^ab$").unwrap();
+    assert!(re.is_match("ab"));
+    assert!(!re.is_match("xab"));
+    assert!(!re.is_match("ab\ncd"));
+    let re = Regex::new("(?m)^cd$").unwrap();
+    assert!(re.is_match("ab\ncd\nef"));
+    assert!(!re.is_match("ab\nxcd"));
+    assert_eq!(re.find("ab\ncd"), Some(Match { start: 3, end: 5 }));
+    let re = RegexBuilder::new("^a+").multi_line(true).build().unwrap();
+    assert_eq!(re.find_iter("aa\nba\na").count(), 2);
+    assert!(!re.matches_suffix("ba"));
+    assert!(Regex::new("b$").unwrap().matches_prefix("b"));
+    assert!(!Regex::new("b$").unwrap().matches_prefix("bc"));
+    assert!(Regex::new("a\\$").unwrap().is_full_match("a$"));
+    assert!(Regex::new("a^b").is_err());
+}
+
+#[test]
+fn stream_resolves_end_anchor() {
+    let re = Regex::new("ab$").unwrap();
+    let mut stream = re.stream();
+    assert!(!stream.feed(b"xxa"));
+    assert!(!stream.feed(b"b"));
+    assert_eq!(stream.position(), 4);
+    assert!(stream.finish());
+    let mut stream = re.stream();
+    stream.feed(b"xab");
+    stream.feed(b"c");
+    assert!(!stream.finish());
+
+    let re = Regex::new("(?m)^cd$").unwrap();
+    let mut stream = re.stream();
+    assert!(!stream.feed(b"ab\nc"));
+    assert!(!stream.feed(b"d"));
+    assert!(stream.feed(b"\nef"));
+    let mut stream = re.stream();
+    stream.feed(b"xcd\n");
+    assert!(!stream.finish());
+
+    let re = Regex::new("b+c").unwrap();
+    let mut stream = re.stream();
+    assert!(stream.feed(b"abbbcd"));
+    assert!(stream.finish());
+}
+
+#[test]
+fn match_byte_iterators() {
+    // 一致が確定した後の入力は作られない
+    let mut produced = 0;
+    let input = (0..1000u32).map(|i| {
+        produced += 1;
+        b"xxabcx"[i as usize % 6]
+    });
+    let re = Regex::new("ab+c").unwrap();
+    assert!(re.is_match_iter(input));
+    assert_eq!(produced, 5);
+    assert!(!re.is_match_iter(b"abd".iter().cloned()));
+    assert!(Regex::new("c$").unwrap().is_match_iter("abc".bytes()));
+    assert!(!Regex::new("c$").unwrap().is_match_iter("abcd".bytes()));
+    // 一致の長さの上限を超えた候補を捨てても、後から始まる候補は残る
+    let digits = Regex::new("x[0-9]{2}y").unwrap();
+    let mut stream = digits.stream();
+    assert!(!stream.feed(b"x1x2") && stream.feed(b"3y"));
+    assert!(!digits.is_match_iter("x123y".bytes()));
+    assert!(!digits.is_match_iter("x1y".bytes()));
+
+    let dfa = re.dfa();
+    assert!(dfa.accept_iter("abbc".bytes()));
+    assert!(!dfa.accept_iter("abbc".bytes().chain(Some(b'c'))));
+    assert!(!Dfa::new().accept_iter("".bytes()));
+}
+
+#[test]
+fn fill_template() {
+    let id = Regex::new("[A-Z]{3}-[0-9]{4}-(dev|prod)").unwrap();
+    assert_eq!(id.fill_template("AB?-12??-???").as_deref(), Some("ABA-1200-dev"));
+    assert_eq!(id.fill_template("???-????-pro?").as_deref(), Some("AAA-0000-prod"));
+    assert_eq!(id.fill_template("ab?-1234-dev"), None);
+    assert_eq!(id.fill_template("AB?-1234-de"), None);
+    let filled = id.fill_template("X?Z-9??9-????").unwrap();
+    assert!(id.is_full_match(&filled));
+}
+
+#[test]
+fn engine_selection() {
+    let haystacks = ["xxabcx", "abc", "abcabc", "ab\nabc\nx", "ABC", "", "aBcd", "xab\ncd"];
+    for &(pattern, kind) in &[
+        ("abc", EngineKind::Literal),
+        ("(a)(b)c", EngineKind::Literal),
+        ("^abc", EngineKind::Literal),
+        ("abc$", EngineKind::Literal),
+        ("^abc$", EngineKind::Literal),
+        ("(?m)^abc$", EngineKind::Literal),
+        ("", EngineKind::Literal),
+        ("(?i)abc", EngineKind::FiniteSet),
+        ("ab|abcd|cd", EngineKind::FiniteSet),
+        ("(?m)^(ab|cd)$", EngineKind::FiniteSet),
+        ("a?b?c?", EngineKind::FiniteSet),
+        ("[a-c]b.", EngineKind::ShiftOr),
+        ("(?s)[a-z]{2}.", EngineKind::ShiftOr),
+        ("^[a-c]b.", EngineKind::Dfa),
+        ("(?i)[a-c]bc", EngineKind::Dfa),
+        ("ab+c", EngineKind::Dfa),
+    ] {
+        let re = Regex::new(pattern).unwrap();
+        assert_eq!(re.engine_kind(), kind, "{}", pattern);
+        assert_eq!(re.stats().engine, kind);
+        let dfa = RegexBuilder::new(pattern).opt_level(OptLevel::O1).build().unwrap();
+        assert_eq!(dfa.engine_kind(), EngineKind::Dfa);
+        for haystack in haystacks.iter() {
+            assert_eq!(re.is_match(haystack), dfa.is_match(haystack), "{} {:?}", pattern, haystack);
+            assert_eq!(re.find(haystack), dfa.find(haystack), "{} {:?}", pattern, haystack);
+        }
+    }
+}
+
+#[test]
+fn find_iter_parallel_matches_find_iter() {
+    let haystack = "ab 123 abcd\nxay 45678 b\nabcdab 9 xyyy\n\nlast 0";
+    let patterns = [
+        "[0-9]+",
+        "ab|abcd",
+        "a*",
+        "x.*y",
+        "^ab",
+        "[0-9]$",
+        "(?m)^[a-z]+",
+        "(?m)[a-z]+$",
+        "",
+        "zzz",
+    ];
+    for pattern in patterns.iter() {
+        let re = Regex::new(pattern).unwrap();
+        let expected: Vec<Match> = re.find_iter(haystack).collect();
+        for chunk_size in [1, 2, 3, 5, 8, 64].iter() {
+            assert_eq!(
+                re.find_iter_parallel(haystack, *chunk_size),
+                expected,
+                "{} {}",
+                pattern,
+                chunk_size
+            );
+        }
+    }
+}
+
+#[test]
+fn find_in_file_by_lines() {
+    use std::env;
+
+    let path = env::temp_dir().join(format!("re-find-in-file-{}.log", std::process::id()));
+    fs::write(&path, "GET /a 200\nPOST /b 500\n\nGET /c 404 GET\n").unwrap();
+    let file = MappedFile::open(&path).unwrap();
+    let re = Regex::new("GET").unwrap();
+    let found: Vec<_> = re.find_in_file(&file).collect();
+    assert_eq!(found.len(), 2);
+    assert_eq!((found[0].number, found[0].span), (1, Match { start: 0, end: 10 }));
+    assert_eq!(found[0].text, b"GET /a 200");
+    assert_eq!(found[1].number, 4);
+    assert_eq!(
+        found[1].matches,
+        vec![Match { start: 24, end: 27 }, Match { start: 35, end: 38 }]
+    );
+
+    // `^` と `$` は行ごとに効く
+    let re = Regex::new("^[A-Z]+ /[a-z] [0-9]+$").unwrap();
+    let numbers = |re: &Regex, contents: &[u8]| {
+        re.find_in_lines(contents).map(|l| l.number).collect::<Vec<_>>()
+    };
+    assert_eq!(numbers(&re, &file), vec![1, 2]);
+    assert_eq!(numbers(&Regex::new("^$").unwrap(), &file), vec![3]);
+
+    // CRLF の行末の `\r` は行に含めない
+    let crlf = b"GET /a 200\r\nPOST /b 500\r\n";
+    assert_eq!(numbers(&re, crlf), vec![1, 2]);
+    let line = re.find_in_lines(crlf).nth(1).unwrap();
+    assert_eq!((line.text, line.span), (&b"POST /b 500"[..], Match { start: 12, end: 23 }));
+    // 最後の行は改行で終わらなくてもよい
+    assert_eq!(numbers(&re, b"\nGET /a 1"), vec![2]);
+
+    fs::write(&path, "").unwrap();
+    assert_eq!(re.find_in_file(&MappedFile::open(&path).unwrap()).count(), 0);
+    fs::remove_file(&path).unwrap();
+    assert!(MappedFile::open(&path).is_err());
+}
+
+#[test]
+fn match_histogram_counts_texts() {
+    let re = Regex::new("(GET|POST) /[a-z]*").unwrap();
+    let histogram = re.match_histogram("GET /a POST /b GET /a GET / GET /a");
+    assert_eq!(histogram.len(), 3);
+    assert_eq!(histogram["GET /a"], 3);
+    assert_eq!(histogram["POST /b"], 1);
+    assert_eq!(histogram["GET /"], 1);
+    assert!(re.match_histogram("nothing").is_empty());
+
+    // 文字の途中の空の一致は数えない
+    let histogram = Regex::new("x*").unwrap().match_histogram("xxaéx");
+    assert_eq!(histogram["xx"], 1);
+    assert_eq!(histogram["x"], 1);
+    assert_eq!(histogram[""], 3);
+}
+
+#[test]
+fn extract_records_by_name() {
+    let re = Regex::new("(?P<method>[A-Z]+) (?P<path>/[a-z]*)( (?P<status>[0-9]{3}))?").unwrap();
+    let log = "GET /index 200\nPOST /login\nDELETE / 404";
+    let records: Vec<Record> = re.extract_records(log).collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].get("method"), Some("GET"));
+    assert_eq!(records[0].get("status"), Some("200"));
+    assert_eq!(
+        records[1].iter().collect::<Vec<_>>(),
+        vec![("method", "POST"), ("path", "/login")]
+    );
+    assert_eq!(records[1].get("status"), None);
+    assert_eq!(records[2].len(), 3);
+    assert!(re.extract_records("nothing").next().is_none());
+
+    // 名前のないグループは組にしない
+    let re = Regex::new("([a-z]+)=(?P<value>[0-9]+)").unwrap();
+    let record = re.extract_records("x=1").next().unwrap();
+    assert_eq!(record.iter().collect::<Vec<_>>(), vec![("value", "1")]);
+    assert!(Regex::new("[a-z]+").unwrap().extract_records("ab").next().unwrap().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serialize_records() {
+    use serde_json;
+
+    let re = Regex::new("(?P<key>[a-z]+)=(?P<value>[0-9]+)").unwrap();
+    let json: Vec<String> = re
+        .extract_records("b=2 a=1")
+        .map(|record| serde_json::to_string(&record).unwrap())
+        .collect();
+    assert_eq!(json, vec![r#"{"key":"b","value":"2"}"#, r#"{"key":"a","value":"1"}"#]);
+}
+
+#[test]
+fn empty_alternatives() {
+    let re = Regex::new("^(ab|)c$").unwrap();
+    assert!(re.is_match("abc") && re.is_match("c") && !re.is_match("bc"));
+    assert_eq!(re.captures("c").unwrap().get(1), Some(Match { start: 0, end: 0 }));
+    let re = Regex::new("x(|y)z").unwrap();
+    assert_eq!(re.find_iter("xz xyz").collect::<Vec<_>>().len(), 2);
+    assert!(Regex::new("a||b").unwrap().is_match(""));
+    assert!(Regex::new("^()$").unwrap().is_full_match(""));
+    assert!(Regex::new("()*").unwrap().is_full_match(""));
+
+    // x{0} は空文字列に一致し、中のグループは参加しない
+    let re = Regex::new("^a(b){0}c$").unwrap();
+    assert!(re.is_match("ac") && !re.is_match("abc"));
+    assert_eq!(re.captures_len(), 2);
+    assert_eq!(re.static_captures_len(), Some(1));
+    assert_eq!(re.captures("ac").unwrap().get(1), None);
+}
+
+#[test]
+fn lazy_fallback_on_size_limit() {
+    // 入力の途中から探す DFA は 2^21 個の状態が要るので、照合しながら状態を作る
+    let re = Regex::new("a.{20}b").unwrap();
+    assert_eq!(re.engine_kind(), EngineKind::Lazy);
+    assert_eq!(re.stats().dfa_states, 0);
+    let haystack = format!("xxa{}byy{}b", "c".repeat(20), "a".repeat(22));
+    assert!(re.is_match(&haystack) && !re.is_match(&haystack[3..48]));
+    assert_eq!(re.find(&haystack), Some(Match { start: 2, end: 24 }));
+    let all: Vec<Match> = re.find_iter(&haystack).collect();
+    assert_eq!(all, vec![Match { start: 2, end: 24 }, Match { start: 27, end: 49 }]);
+    assert_eq!(re.captures(&haystack).unwrap().get(0), Some(Match { start: 2, end: 24 }));
+    assert!(re.is_full_match(&haystack[2..24]) && !re.is_full_match(&haystack));
+    assert!(re.matches_prefix(&haystack[2..]) && !re.matches_prefix(&haystack));
+    assert!(re.matches_suffix(&haystack) && !re.matches_suffix(&haystack[..48]));
+    assert_eq!(re.strip_prefix_match(&haystack[2..]).unwrap().1, &haystack[24..]);
+    assert!(re.is_match_iter(haystack.bytes()) && !re.is_match_iter(haystack[3..48].bytes()));
+    let mut stream = re.stream();
+    assert!(!stream.feed(&haystack.as_bytes()[..23]) && stream.feed(b"b"));
+    assert_eq!(stream.position(), 24);
+    let filled = re.fill_template(&format!("a{}?", "?".repeat(20))).unwrap();
+    assert_eq!(filled, format!("a{}b", " ".repeat(20)));
+    assert!(!re.is_ascii_only());
+    let lines: Vec<usize> = re
+        .find_in_lines(format!("x\n{}\nab", &haystack[2..24]).as_bytes())
+        .map(|line| line.number)
+        .collect();
+    assert_eq!(lines, vec![2]);
+}
@@ -0,0 +1,155 @@
+use dfa::Dfa;
+use error::Error;
+use nfa::Nfa;
+use rng::Rng;
+use std::collections::{HashMap, VecDeque};
+
+// パターン → DFA → 状態除去法で作り直したパターン → DFA と一周させた結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundTrip {
+    pub pattern: String,
+    // 作り直したパターン。受理言語が空か空文字列だけなら None
+    pub regenerated: Option<String>,
+    // 二つの DFA の一方だけが受理する最短の入力 (同じ長さでは辞書順で最小)。等価なら None
+    pub counterexample: Option<Vec<u8>>,
+    // 試した入力のうち、二つの DFA で判定が食い違ったものの数
+    pub corpus_mismatches: usize,
+    pub corpus_size: usize,
+}
+
+impl RoundTrip {
+    pub fn is_equivalent(&self) -> bool {
+        self.counterexample.is_none() && self.corpus_mismatches == 0
+    }
+}
+
+fn compile(pattern: &str) -> Result<Dfa, Error> {
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa(pattern)?)?;
+    dfa.minimize();
+    Ok(dfa)
+}
+
+// 空文字列だけを受理するか、何も受理しない DFA (to_pattern が None を返す言語)
+fn epsilon_or_empty(accepts_empty: bool) -> Dfa {
+    let mut dfa = Dfa::new();
+    dfa.states.push(::dfa::State {
+        t: vec![None; 256],
+        id: 0,
+        accept: accepts_empty,
+        patterns: Vec::new(),
+    });
+    dfa.minimized()
+}
+
+// 積の状態。遷移のない側は None
+type Pair = (Option<usize>, Option<usize>);
+
+// 二つの DFA の積を幅優先でたどり、一方だけが受理する最短の入力を探す
+// 遷移のない側は受理しない死状態にいるものとして続ける
+pub fn distinguishing_input(a: &Dfa, b: &Dfa) -> Option<Vec<u8>> {
+    let start = |dfa: &Dfa| {
+        if dfa.states.is_empty() {
+            None
+        } else {
+            Some(dfa.start())
+        }
+    };
+    let accept = |dfa: &Dfa, state: Option<usize>| state.is_some_and(|s| dfa.states[s].accept);
+    let first = (start(a), start(b));
+    let mut parent: HashMap<Pair, Option<(usize, u8)>> = HashMap::new();
+    let mut pairs = vec![first];
+    parent.insert(first, None);
+    let mut queue = VecDeque::new();
+    queue.push_back(0);
+    while let Some(i) = queue.pop_front() {
+        let (p, q) = pairs[i];
+        if accept(a, p) != accept(b, q) {
+            let mut input = Vec::new();
+            let mut pair = pairs[i];
+            while let Some(Some((prev, c))) = parent.get(&pair) {
+                input.push(*c);
+                pair = pairs[*prev];
+            }
+            input.reverse();
+            return Some(input);
+        }
+        for c in 0..=255u8 {
+            let next = (
+                p.and_then(|p| a.transition(p, c)),
+                q.and_then(|q| b.transition(q, c)),
+            );
+            if next == (None, None) || parent.contains_key(&next) {
+                continue;
+            }
+            parent.insert(next, Some((i, c)));
+            pairs.push(next);
+            queue.push_back(pairs.len() - 1);
+        }
+    }
+    None
+}
+
+// pattern を一周させ、積の探索と corpus_size 個の入力の両方で言語が変わらないことを確かめる
+// 入力は両方の DFA から標本を取ったものと、パターン中のバイトを並べたもの
+pub fn check(pattern: &str, corpus_size: usize, seed: u64) -> Result<RoundTrip, Error> {
+    let original = compile(pattern)?;
+    let regenerated = original.to_pattern();
+    let round_tripped = match regenerated {
+        Some(ref regenerated) => compile(regenerated)?,
+        None => epsilon_or_empty(original.accept("")),
+    };
+    let counterexample = distinguishing_input(&original, &round_tripped);
+
+    let mut rng = Rng::new(seed);
+    let mut alphabet: Vec<u8> = pattern.bytes().collect();
+    alphabet.sort_unstable();
+    alphabet.dedup();
+    let mut corpus_mismatches = 0;
+    for i in 0..corpus_size {
+        let input = match i % 3 {
+            0 => original.sample(&mut rng, 16),
+            1 => round_tripped.sample(&mut rng, 16),
+            _ => None,
+        };
+        let input = input.unwrap_or_else(|| {
+            let len = rng.below(17);
+            (0..len).map(|_| *rng.choose(&alphabet)).collect()
+        });
+        if original.accept_bytes(&input) != round_tripped.accept_bytes(&input) {
+            corpus_mismatches += 1;
+        }
+    }
+    Ok(RoundTrip {
+        pattern: pattern.to_owned(),
+        regenerated,
+        counterexample,
+        corpus_mismatches,
+        corpus_size,
+    })
+}
+
+#[test]
+fn round_trip_preserves_language() {
+    let patterns = [
+        "(a|b)*abb",
+        "(0|1(01*0)*1)*",
+        "[0-9]+(\\.[0-9]+)?",
+        "a*",
+        "(ab|a)(bc|c)*",
+        "x?y?z?",
+        "[^a-y]+z",
+        "(?i)hello|world",
+        "a?b?",
+    ];
+    for pattern in patterns.iter() {
+        let result = check(pattern, 300, 1).unwrap();
+        assert!(result.is_equivalent(), "{:?}", result);
+    }
+    let epsilon = epsilon_or_empty(true);
+    assert!(epsilon.accept("") && !epsilon.accept("a"));
+
+    let a = compile("ab*").unwrap();
+    let b = compile("ab*|b").unwrap();
+    assert_eq!(distinguishing_input(&a, &b), Some(b"b".to_vec()));
+    assert_eq!(distinguishing_input(&a, &a), None);
+}
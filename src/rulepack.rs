@@ -0,0 +1,488 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use binary::{checksum, DfaRef, Options};
+use dfa::Anchors;
+use error::{Error, FormatError};
+use nfa::{split_flags, Flags};
+use serde::{Deserialize, Serialize};
+use set::{PatternMetadata, RegexSet};
+use toml::Spanned;
+
+// 名前付きの規則の一覧を一つの DFA にまとめた rule pack
+//
+// 規則の一覧 (compile が読む) は、規則を書いた順に [[rules]] で並べた TOML
+//   [[rules]]
+//   name = "sql-union"            規則の名前 (必須、重複不可)
+//   pattern = 'union\s+select'    パターン (必須)
+//   flags = "i"                   パターンに付ける inline flag (`i`, `s`, `m`, `x` の並び)
+//   severity = "high"             PatternMetadata::severity
+//   description = "..."           PatternMetadata::description
+//   cwe = "22"                    それ以外の文字列の値は PatternMetadata::extra
+// manifest (compile が書く) も同じ形式で、flags をパターンに付けて書き、先頭に version と
+// 本体の checksum を書く
+//
+// 本体の形式 (数値は全てリトルエンディアン)
+//
+// ヘッダ
+//   magic     4 バイト  b"RPAK"
+//   version   u16      RULEPACK_VERSION
+//   reserved  u16      0
+//   rules     u32      規則の数
+//   dfa       u32      DFA 部分のバイト数
+//   checksum  u32      ヘッダより後ろ全体の FNV-1a
+// 本体
+//   DFA      Dfa::to_bytes の形式で、パターン数は規則の数
+//   anchors  規則ごとに u8 (bit 0: 先頭のアンカー, bit 1: 末尾のアンカー, bit 2: 複数行)
+//   offsets  状態ごとに、その状態で一致する規則が ids のどこから始まるかの u32 と、最後に ids の長さ
+//   ids      一致する規則の番号の u32
+
+const MAGIC: &[u8; 4] = b"RPAK";
+pub const RULEPACK_VERSION: u16 = 1;
+const HEADER_LEN: usize = 20;
+
+const ANCHOR_START: u8 = 1;
+const ANCHOR_END: u8 = 1 << 1;
+const MULTI_LINE: u8 = 1 << 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub name: String,
+    // flags を inline flag として付けたパターン
+    pub pattern: String,
+    // rule_id は name と同じ
+    pub metadata: PatternMetadata,
+}
+
+// compile の結果。artifact と manifest は別々のファイルに書いて load に渡す
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub artifact: Vec<u8>,
+    pub manifest: String,
+    pub rules: Vec<Rule>,
+}
+
+// artifact のバイト列をそのまま遷移表として使う rule pack
+#[derive(Debug, Clone)]
+pub struct RulePack<'a> {
+    dfa: DfaRef<'a>,
+    anchors: Vec<Anchors>,
+    offsets: &'a [u8],
+    ids: &'a [u8],
+    rules: Vec<Rule>,
+}
+
+// 規則の一覧と manifest の [[rules]] の一つ
+#[derive(Debug, Deserialize)]
+struct RuleEntry {
+    name: Spanned<String>,
+    pattern: Option<String>,
+    flags: Option<Spanned<String>>,
+    severity: Option<String>,
+    description: Option<String>,
+    #[serde(flatten)]
+    extra: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleList {
+    version: Option<u16>,
+    checksum: Option<String>,
+    #[serde(default)]
+    rules: Vec<RuleEntry>,
+}
+
+#[derive(Serialize)]
+struct ManifestRule<'a> {
+    name: &'a str,
+    pattern: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    #[serde(flatten)]
+    extra: &'a BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    version: u16,
+    checksum: String,
+    rules: Vec<ManifestRule<'a>>,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from(bytes[at]) | u16::from(bytes[at + 1]) << 8
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    (0..4).fold(0, |n, i| n | u32::from(bytes[at + i]) << (8 * i))
+}
+
+// TOML として読む。エラーの位置は source 中のバイト位置
+fn parse(source: &str) -> Result<RuleList, Error> {
+    toml::from_str(source).map_err(|e| {
+        let at = e.line_col().map_or(0, |(line, col)| {
+            source.split('\n').take(line).map(|l| l.len() + 1).sum::<usize>() + col
+        });
+        let message = e.to_string();
+        // 位置は Error::Syntax の表示に任せる
+        let message = message.split(" at line ").next().unwrap_or(&message);
+        Error::Syntax(message.to_owned(), at)
+    })
+}
+
+// flags をパターン先頭の inline flag とまとめる
+// at は flags の行の位置
+fn apply_flags(flags: &str, at: usize, pattern: &str) -> Result<String, Error> {
+    if flags.is_empty() {
+        return Ok(pattern.to_owned());
+    }
+    let inline = format!("(?{})", flags);
+    let (extra, rest) = split_flags(&inline);
+    if !rest.is_empty() {
+        return Err(Error::Syntax(format!("unknown flags {:?}", flags), at));
+    }
+    let (own, body) = split_flags(pattern.trim_start());
+    let flags = Flags::default().merge(own).merge(extra);
+    let letters: String = [
+        (flags.case_insensitive, 'i'),
+        (flags.verbose, 'x'),
+        (flags.dot_all, 's'),
+        (flags.multi_line, 'm'),
+    ]
+    .iter()
+    .filter(|&&(set, _)| set)
+    .map(|&(_, c)| c)
+    .collect();
+    Ok(format!("(?{}){}", letters, body))
+}
+
+fn to_rules(entries: Vec<RuleEntry>) -> Result<Vec<Rule>, Error> {
+    let mut rules = Vec::with_capacity(entries.len());
+    let mut names = HashSet::new();
+    for entry in entries {
+        let at = entry.name.start();
+        let name = entry.name.into_inner();
+        if !names.insert(name.clone()) {
+            return Err(Error::Syntax(format!("duplicate rule {:?}", name), at));
+        }
+        let pattern = match (entry.pattern, entry.flags) {
+            (Some(pattern), Some(flags)) => apply_flags(flags.get_ref(), flags.start(), &pattern)?,
+            (Some(pattern), None) => pattern,
+            (None, _) => return Err(Error::Syntax(format!("rule {:?} has no pattern", name), at)),
+        };
+        let metadata = PatternMetadata {
+            rule_id: Some(name.clone()),
+            severity: entry.severity,
+            description: entry.description,
+            extra: entry.extra,
+        };
+        rules.push(Rule {
+            name,
+            pattern,
+            metadata,
+        });
+    }
+    Ok(rules)
+}
+
+fn write_manifest(rules: &[Rule], artifact_checksum: u32) -> String {
+    let manifest = Manifest {
+        version: RULEPACK_VERSION,
+        checksum: format!("{:08x}", artifact_checksum),
+        rules: rules
+            .iter()
+            .map(|rule| ManifestRule {
+                name: &rule.name,
+                pattern: &rule.pattern,
+                severity: rule.metadata.severity.as_deref(),
+                description: rule.metadata.description.as_deref(),
+                extra: &rule.metadata.extra,
+            })
+            .collect(),
+    };
+    toml::to_string(&manifest).expect("manifest has only strings and integers")
+}
+
+// 規則の一覧のファイルを読んで compile_str する
+pub fn compile<P: AsRef<Path>>(path: P) -> Result<Compiled, Error> {
+    compile_str(&fs::read_to_string(path)?)
+}
+
+pub fn compile_str(source: &str) -> Result<Compiled, Error> {
+    let rules = to_rules(parse(source)?.rules)?;
+    let set = RegexSet::with_metadata(
+        rules
+            .iter()
+            .map(|rule| (rule.pattern.as_str(), rule.metadata.clone())),
+    )?;
+
+    let dfa = set.dfa();
+    let options = Options {
+        anchors: Anchors::default(),
+        patterns: rules.len() as u32,
+    };
+    let mut body = dfa.to_bytes(&options);
+    let dfa_len = body.len() as u32;
+    for anchors in set.anchors() {
+        let flags = [
+            (anchors.start, ANCHOR_START),
+            (anchors.end, ANCHOR_END),
+            (anchors.multi_line, MULTI_LINE),
+        ]
+        .iter()
+        .filter(|&&(set, _)| set)
+        .fold(0, |flags, &(_, bit)| flags | bit);
+        body.push(flags);
+    }
+    // to_bytes と同じく開始状態と 0 番を入れ替えた順に書く
+    let start = dfa.start();
+    let swapped = |id: usize| match id {
+        id if id == start => 0,
+        0 => start,
+        id => id,
+    };
+    let mut ids = Vec::new();
+    for id in (0..dfa.states.len()).map(swapped) {
+        body.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+        ids.extend(dfa.states[id].patterns.iter().map(|&p| p as u32));
+    }
+    body.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+    for id in ids {
+        body.extend_from_slice(&id.to_le_bytes());
+    }
+
+    let mut artifact = Vec::with_capacity(HEADER_LEN + body.len());
+    artifact.extend_from_slice(MAGIC);
+    artifact.extend_from_slice(&RULEPACK_VERSION.to_le_bytes());
+    artifact.extend_from_slice(&0u16.to_le_bytes());
+    artifact.extend_from_slice(&options.patterns.to_le_bytes());
+    artifact.extend_from_slice(&dfa_len.to_le_bytes());
+    artifact.extend_from_slice(&checksum(&body).to_le_bytes());
+    artifact.extend_from_slice(&body);
+    let manifest = write_manifest(&rules, checksum(&artifact));
+    Ok(Compiled {
+        artifact,
+        manifest,
+        rules,
+    })
+}
+
+// manifest が artifact と同じ compile の結果であることと、本体の形を確かめてから読み込む
+// 遷移表と一致する規則の表は artifact を指したまま使う
+pub fn load<'a>(artifact: &'a [u8], manifest: &str) -> Result<RulePack<'a>, Error> {
+    let mismatch = |reason: String| Error::Format(FormatError::Manifest(reason));
+    let manifest = parse(manifest)?;
+    if manifest.version != Some(RULEPACK_VERSION) {
+        return Err(mismatch(format!("version is not {}", RULEPACK_VERSION)));
+    }
+    let expected = format!("{:08x}", checksum(artifact));
+    if manifest.checksum.as_ref() != Some(&expected) {
+        return Err(mismatch(format!("checksum is not {}", expected)));
+    }
+    let rules = to_rules(manifest.rules)?;
+
+    if artifact.len() < MAGIC.len() || &artifact[..MAGIC.len()] != MAGIC {
+        return Err(Error::Format(FormatError::NotRulePack));
+    }
+    if artifact.len() < HEADER_LEN {
+        return Err(Error::Format(FormatError::Truncated));
+    }
+    let version = u16_at(artifact, 4);
+    if version != RULEPACK_VERSION {
+        return Err(Error::Format(FormatError::Version(version)));
+    }
+    let count = u32_at(artifact, 8) as usize;
+    if count != rules.len() {
+        return Err(mismatch(format!("{} rules for {} patterns", rules.len(), count)));
+    }
+    let body = &artifact[HEADER_LEN..];
+    let found = checksum(body);
+    if found != u32_at(artifact, 16) {
+        return Err(Error::Format(FormatError::Checksum {
+            expected: u32_at(artifact, 16),
+            found,
+        }));
+    }
+    let dfa_len = u32_at(artifact, 12) as usize;
+    if body.len() < dfa_len + count {
+        return Err(Error::Format(FormatError::Truncated));
+    }
+    let options = Options {
+        anchors: Anchors::default(),
+        patterns: count as u32,
+    };
+    let dfa = DfaRef::new(&body[..dfa_len], &options)?;
+    let anchors = body[dfa_len..dfa_len + count]
+        .iter()
+        .map(|&flags| Anchors {
+            start: flags & ANCHOR_START != 0,
+            end: flags & ANCHOR_END != 0,
+            multi_line: flags & MULTI_LINE != 0,
+        })
+        .collect();
+    let tables = &body[dfa_len + count..];
+    let offsets_len = 4 * (dfa.len() + 1);
+    if tables.len() < offsets_len {
+        return Err(Error::Format(FormatError::Truncated));
+    }
+    let (offsets, ids) = tables.split_at(offsets_len);
+    if ids.len() != 4 * u32_at(offsets, offsets_len - 4) as usize {
+        return Err(Error::Format(FormatError::Truncated));
+    }
+    for state in 0..dfa.len() {
+        if u32_at(offsets, 4 * state) > u32_at(offsets, 4 * state + 4) {
+            return Err(Error::InvalidAutomaton(format!(
+                "state {} has a negative number of rules",
+                state
+            )));
+        }
+    }
+    if let Some(id) = ids.chunks(4).map(|id| u32_at(id, 0)).find(|&id| id as usize >= count) {
+        return Err(Error::InvalidAutomaton(format!("missing rule {}", id)));
+    }
+    Ok(RulePack {
+        dfa,
+        anchors,
+        offsets,
+        ids,
+        rules,
+    })
+}
+
+impl<'a> RulePack<'a> {
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        !self.matches(haystack).is_empty()
+    }
+
+    // この状態で一致する規則の番号
+    fn accepted(&self, state: usize) -> impl Iterator<Item = usize> + '_ {
+        let from = u32_at(self.offsets, 4 * state) as usize;
+        let to = u32_at(self.offsets, 4 * state + 4) as usize;
+        (from..to).map(move |i| u32_at(self.ids, 4 * i) as usize)
+    }
+
+    // 入力のどこかに一致する部分を持つ規則を、一覧に書いた順に全て求める
+    pub fn matches(&self, haystack: &str) -> Vec<&Rule> {
+        let haystack = haystack.as_bytes();
+        let mut matched = vec![false; self.rules.len()];
+        let mut remaining = self.rules.len();
+        let mut state = self.dfa.start();
+        let mut end = 0;
+        loop {
+            for rule in self.accepted(state) {
+                if !matched[rule] && self.anchors[rule].end_ok(haystack, end) {
+                    matched[rule] = true;
+                    remaining -= 1;
+                }
+            }
+            if remaining == 0 || end == haystack.len() {
+                break;
+            }
+            match self.dfa.next(state, haystack[end]) {
+                Some(next) => state = next,
+                None => break,
+            }
+            end += 1;
+        }
+        self.rules
+            .iter()
+            .zip(matched)
+            .filter(|&(_, m)| m)
+            .map(|(rule, _)| rule)
+            .collect()
+    }
+}
+
+#[test]
+fn compile_and_load_rule_pack() {
+    let source = r#"
+# 攻撃の痕跡
+[[rules]]
+name = "sql-union"
+pattern = 'union\s+select'
+flags = "i"
+severity = "high"
+description = "UNION based SQL injection"
+
+[[rules]]
+name = "path-traversal"
+pattern = '\.\./'
+severity = "medium"
+cwe = "22"
+
+[[rules]]
+name = "admin"
+pattern = "^GET /admin"
+"#;
+    let compiled = compile_str(source).unwrap();
+    assert_eq!(compiled.rules.len(), 3);
+    assert_eq!(compiled.rules[0].pattern, "(?i)union\\s+select");
+    assert_eq!(compiled.rules[1].metadata.extra.get("cwe").map(String::as_str), Some("22"));
+
+    let pack = load(&compiled.artifact, &compiled.manifest).unwrap();
+    assert_eq!(pack.rules(), &compiled.rules[..]);
+    let names = |haystack: &str| {
+        pack.matches(haystack)
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names("GET /admin?id=1 UNION  SELECT"), vec!["sql-union", "admin"]);
+    assert_eq!(names("POST /admin/../../etc/passwd"), vec!["path-traversal"]);
+    assert!(!pack.is_match("GET /index.html"));
+    let matched = pack.matches("1 union select");
+    assert_eq!(matched[0].metadata.severity.as_deref(), Some("high"));
+    assert_eq!(matched[0].metadata.rule_id.as_deref(), Some("sql-union"));
+
+    // manifest を読み直しても同じ規則になる
+    assert_eq!(to_rules(parse(&compiled.manifest).unwrap().rules).unwrap(), compiled.rules);
+
+    // 別の compile の manifest や壊れた本体は弾く
+    let other = compile_str("[[rules]]\nname = 'a'\npattern = 'a'\n").unwrap();
+    match load(&compiled.artifact, &other.manifest) {
+        Err(Error::Format(FormatError::Manifest(_))) => {}
+        other => panic!("{:?}", other),
+    }
+    let mut broken = compiled.artifact.clone();
+    broken[HEADER_LEN] ^= 1;
+    let manifest = write_manifest(&compiled.rules, checksum(&broken));
+    match load(&broken, &manifest) {
+        Err(Error::Format(FormatError::Checksum { .. })) => {}
+        other => panic!("{:?}", other),
+    }
+
+    let rule = |name: &str, extra: &str| format!("[[rules]]\nname = '{}'\n{}\n", name, extra);
+    let duplicate = rule("a", "pattern = 'a'") + &rule("a", "pattern = 'b'");
+    match compile_str(&duplicate) {
+        Err(Error::Syntax(ref message, 52)) if message.contains("duplicate rule") => {}
+        other => panic!("{:?}", other),
+    }
+    match compile_str(&rule("a", "severity = 'low'")) {
+        Err(Error::Syntax(ref message, 17)) if message.contains("has no pattern") => {}
+        other => panic!("{:?}", other),
+    }
+    match compile_str(&rule("a", "pattern = 'a'\nflags = 'q'")) {
+        Err(Error::Syntax(_, 43)) => {}
+        other => panic!("{:?}", other),
+    }
+    match compile_str("[[rules]]\nname = 'a'\npattern (\n") {
+        Err(Error::Syntax(_, 29)) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(compile_str(&rule("a", "pattern = 'a'\ncwe = 22")).is_err());
+}
@@ -0,0 +1,302 @@
+use ast::Ast;
+use error::Error;
+use nfa::{self, Flags};
+use rng::Rng;
+use std::collections::HashMap;
+
+// パターンの構文木をたどって一致する文字列を作る生成器
+// Dfa::sample と違い、繰り返しを何回回すかや選択肢のどれを選ぶかを構文ごとに重み付けできる
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    root: Ast,
+    flags: Flags,
+    star: f64,
+    optional: f64,
+    max_repeat: usize,
+    // 選択の番号 (パターン中で `|` を含む部分が現れる順) ごとの各選択肢の重み
+    branches: HashMap<usize, Vec<f64>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SamplerBuilder {
+    pattern: String,
+    star: f64,
+    optional: f64,
+    max_repeat: usize,
+    branches: HashMap<usize, Vec<f64>>,
+}
+
+fn probability(name: &str, p: f64) -> Result<f64, Error> {
+    if !(0.0..=1.0).contains(&p) {
+        return Err(Error::InvalidAutomaton(format!(
+            "{} must be a probability, got {}",
+            name, p
+        )));
+    }
+    Ok(p)
+}
+
+// 選択を現れる順に集める
+fn unions<'a>(ast: &'a Ast, out: &mut Vec<&'a [Ast]>) {
+    match *ast {
+        Ast::Literal(_) | Ast::Dot | Ast::Class(_) => {}
+        Ast::Group { ref ast, .. }
+        | Ast::NonCapturing { ref ast, .. }
+        | Ast::Star(ref ast)
+        | Ast::Plus(ref ast)
+        | Ast::ZeroOne(ref ast)
+        | Ast::Repeat { ref ast, .. } => unions(ast, out),
+        Ast::Concat(ref items) => items.iter().for_each(|item| unions(item, out)),
+        Ast::Union(ref items) => {
+            out.push(items);
+            items.iter().for_each(|item| unions(item, out));
+        }
+    }
+}
+
+impl SamplerBuilder {
+    pub fn new(pattern: &str) -> Self {
+        SamplerBuilder {
+            pattern: pattern.to_owned(),
+            star: 0.5,
+            optional: 0.5,
+            max_repeat: 8,
+            branches: HashMap::new(),
+        }
+    }
+
+    // `*`, `+` と上限のない `{m,}` で、もう一周回る確率
+    // 上限のある `{m,n}` で m 回を超えて繰り返す確率にも使う
+    pub fn star(&mut self, p: f64) -> &mut Self {
+        self.star = p;
+        self
+    }
+
+    // `?` の中身を使う確率
+    pub fn optional(&mut self, p: f64) -> &mut Self {
+        self.optional = p;
+        self
+    }
+
+    // 上限のない繰り返しで、必要な回数を超えて回る回数の上限
+    pub fn max_repeat(&mut self, n: usize) -> &mut Self {
+        self.max_repeat = n;
+        self
+    }
+
+    // index 番目の選択 (0 から数える) の各選択肢を weights の比で選ぶ
+    // 指定のない選択は一様に選ぶ
+    pub fn branch_weights(&mut self, index: usize, weights: &[f64]) -> &mut Self {
+        self.branches.insert(index, weights.to_vec());
+        self
+    }
+
+    pub fn build(&self) -> Result<Sampler, Error> {
+        let (flags, _) = nfa::split_flags(self.pattern.trim());
+        let root = nfa::syntax_tree(&self.pattern)?;
+        let mut found = Vec::new();
+        unions(&root, &mut found);
+        for (&index, weights) in self.branches.iter() {
+            let branches = match found.get(index) {
+                Some(branches) => branches.len(),
+                None => {
+                    return Err(Error::InvalidAutomaton(format!(
+                        "the pattern has {} alternations, no alternation {}",
+                        found.len(),
+                        index
+                    )))
+                }
+            };
+            if weights.len() != branches
+                || weights.iter().any(|&w| !(w >= 0.0 && w.is_finite()))
+                || weights.iter().sum::<f64>() <= 0.0
+            {
+                return Err(Error::InvalidAutomaton(format!(
+                    "alternation {} needs {} non-negative weights, got {:?}",
+                    index, branches, weights
+                )));
+            }
+        }
+        Ok(Sampler {
+            root,
+            flags,
+            star: probability("star", self.star)?,
+            optional: probability("optional", self.optional)?,
+            max_repeat: self.max_repeat,
+            branches: self.branches.clone(),
+        })
+    }
+}
+
+// 表示可能な ASCII があればその中から選ぶ
+fn pick(rng: &mut Rng, bytes: &[u8]) -> Option<u8> {
+    let printable: Vec<u8> = bytes
+        .iter()
+        .cloned()
+        .filter(|&c| c == b' ' || c.is_ascii_graphic())
+        .collect();
+    if !printable.is_empty() {
+        return Some(*rng.choose(&printable));
+    }
+    if bytes.is_empty() {
+        return None;
+    }
+    Some(*rng.choose(bytes))
+}
+
+impl Sampler {
+    pub fn new(pattern: &str) -> Result<Sampler, Error> {
+        SamplerBuilder::new(pattern).build()
+    }
+
+    // パターンに一致する文字列を一つ作る。どのバイトにも一致しないクラスを含むなら None
+    pub fn sample(&self, rng: &mut Rng) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut union = 0;
+        self.walk(&self.root, self.flags, rng, &mut union, &mut out)?;
+        Some(out)
+    }
+
+    // 回数の上限 (None は max_repeat まで) の範囲で、star の確率で回り続ける
+    fn extra(&self, rng: &mut Rng, limit: Option<usize>) -> usize {
+        let limit = limit.unwrap_or(self.max_repeat);
+        let mut n = 0;
+        while n < limit && rng.next_f64() < self.star {
+            n += 1;
+        }
+        n
+    }
+
+    fn repeat(
+        &self,
+        ast: &Ast,
+        times: usize,
+        flags: Flags,
+        rng: &mut Rng,
+        union: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Option<()> {
+        // 選択の番号は構文木の上での順なので、何回回しても中の選択は同じ番号にする
+        let first = *union;
+        let mut after = first;
+        for _ in 0..times {
+            after = first;
+            self.walk(ast, flags, rng, &mut after, out)?;
+        }
+        if times == 0 {
+            let mut skipped = Vec::new();
+            unions(ast, &mut skipped);
+            after = first + skipped.len();
+        }
+        *union = after;
+        Some(())
+    }
+
+    fn walk(
+        &self,
+        ast: &Ast,
+        flags: Flags,
+        rng: &mut Rng,
+        union: &mut usize,
+        out: &mut Vec<u8>,
+    ) -> Option<()> {
+        match *ast {
+            Ast::Literal(c) => out.push(c),
+            Ast::Dot => {
+                let mut bytes: Vec<u8> = (0..=255u8).collect();
+                if !flags.dot_all {
+                    bytes.retain(|&c| c != b'\n');
+                }
+                out.push(pick(rng, &bytes)?);
+            }
+            Ast::Class(ref class) => out.push(pick(rng, &class.bytes(flags.case_insensitive))?),
+            Ast::Group { ref ast, .. } => self.walk(ast, flags, rng, union, out)?,
+            Ast::NonCapturing {
+                flags: group,
+                ref ast,
+            } => self.walk(ast, group.apply(flags), rng, union, out)?,
+            Ast::Concat(ref items) => {
+                for item in items {
+                    self.walk(item, flags, rng, union, out)?;
+                }
+            }
+            Ast::Union(ref items) => {
+                let index = *union;
+                *union += 1;
+                let chosen = match self.branches.get(&index) {
+                    Some(weights) => {
+                        let mut r = rng.next_f64() * weights.iter().sum::<f64>();
+                        let mut chosen = weights.len() - 1;
+                        for (i, &w) in weights.iter().enumerate() {
+                            if r < w {
+                                chosen = i;
+                                break;
+                            }
+                            r -= w;
+                        }
+                        chosen
+                    }
+                    None => rng.below(items.len()),
+                };
+                // 選ばなかった選択肢の中の選択も番号を進める
+                for (i, item) in items.iter().enumerate() {
+                    let times = if i == chosen { 1 } else { 0 };
+                    self.repeat(item, times, flags, rng, union, out)?;
+                }
+            }
+            Ast::Star(ref ast) => {
+                let times = self.extra(rng, None);
+                self.repeat(ast, times, flags, rng, union, out)?;
+            }
+            Ast::Plus(ref ast) => {
+                let times = 1 + self.extra(rng, None);
+                self.repeat(ast, times, flags, rng, union, out)?;
+            }
+            Ast::ZeroOne(ref ast) => {
+                let times = if rng.next_f64() < self.optional { 1 } else { 0 };
+                self.repeat(ast, times, flags, rng, union, out)?;
+            }
+            Ast::Repeat {
+                ref ast,
+                min,
+                max,
+            } => {
+                let times = min + self.extra(rng, max.map(|max| max - min));
+                self.repeat(ast, times, flags, rng, union, out)?;
+            }
+        }
+        Some(())
+    }
+}
+
+#[test]
+fn weighted_samples() {
+    use dfa::Dfa;
+    use nfa::Nfa;
+
+    let pattern = "(?i)(GET|POST|DELETE) /[a-z]+(/[0-9]{1,3})*( HTTP/1\\.(0|1))?";
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa(pattern).unwrap()).unwrap();
+    let sampler = Sampler::new(pattern).unwrap();
+    let mut rng = Rng::new(3);
+    for _ in 0..200 {
+        assert!(dfa.accept_bytes(&sampler.sample(&mut rng).unwrap()));
+    }
+
+    let sampler = SamplerBuilder::new(pattern)
+        .star(0.0)
+        .optional(1.0)
+        .branch_weights(0, &[0.0, 1.0, 0.0])
+        .branch_weights(1, &[0.0, 1.0])
+        .build()
+        .unwrap();
+    for _ in 0..20 {
+        let sample = String::from_utf8(sampler.sample(&mut rng).unwrap()).unwrap();
+        assert!(sample.starts_with("POST /"), "{}", sample);
+        assert!(sample.ends_with(" HTTP/1.1"), "{}", sample);
+        assert!(dfa.accept(&sample));
+    }
+
+    assert!(SamplerBuilder::new("a|b").branch_weights(1, &[1.0, 1.0]).build().is_err());
+    assert!(SamplerBuilder::new("a|b").branch_weights(0, &[1.0]).build().is_err());
+    assert!(SamplerBuilder::new("a*").star(1.5).build().is_err());
+}
@@ -0,0 +1,149 @@
+use dfa::{Dfa, Match};
+use error::Error;
+use nfa::{split_anchors, strip_verbose, Nfa};
+
+// 規則の列から作る字句解析器
+// 各位置で最も長く一致する規則を選び、同じ長さなら先に書いた規則を優先する
+#[derive(Debug)]
+pub struct Scanner {
+    rules: Vec<String>,
+    // 受理状態に一致した規則の番号を持つ、位置を固定した DFA
+    dfa: Dfa,
+}
+
+impl Scanner {
+    pub fn new<I, S>(rules: I) -> Result<Scanner, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let rules: Vec<String> = rules.into_iter().map(|r| r.as_ref().to_owned()).collect();
+        let mut nfas = Vec::with_capacity(rules.len());
+        for rule in rules.iter() {
+            // 規則は常に現在の位置から一致させるので `^` と `$` は意味を持たない
+            let stripped = strip_verbose(rule);
+            let (start, body, end) = split_anchors(&stripped);
+            if start || end {
+                let at = if start { 0 } else { body.len() };
+                return Err(Error::Unsupported {
+                    feature: "anchor in a scanner rule",
+                    span: at..at + 1,
+                });
+            }
+            nfas.push(Nfa::re2nfa(rule)?);
+        }
+        let mut dfa = Dfa::nfa2dfa(&Nfa::union_tagged(&nfas))?;
+        dfa.minimize();
+        Ok(Scanner { rules, dfa })
+    }
+
+    pub fn rules(&self) -> &[String] {
+        &self.rules
+    }
+
+    // input の from バイト目から始まる最長の字句 (規則の番号と範囲)。空の一致は字句にしない
+    pub fn longest_at(&self, input: &[u8], from: usize) -> Option<(usize, Match)> {
+        let mut state = self.dfa.states.get(self.dfa.start())?;
+        let mut best = None;
+        for (i, &c) in input[from..].iter().enumerate() {
+            match self.dfa.transition(state.id, c) {
+                Some(next) => state = &self.dfa.states[next],
+                None => break,
+            }
+            if let Some(&rule) = state.patterns.first() {
+                best = Some((
+                    rule,
+                    Match {
+                        start: from,
+                        end: from + i + 1,
+                    },
+                ));
+            }
+        }
+        best
+    }
+
+    // 入力を先頭から字句に分ける。どの規則にも一致しない位置で NoToken を返して終わる
+    pub fn tokens<'s, 'i>(&'s self, input: &'i str) -> Tokens<'s, 'i> {
+        Tokens {
+            scanner: self,
+            input: input.as_bytes(),
+            pos: Some(0),
+        }
+    }
+}
+
+// Scanner::tokens の返す字句の列
+#[derive(Debug)]
+pub struct Tokens<'s, 'i> {
+    scanner: &'s Scanner,
+    input: &'i [u8],
+    // 次の字句の始まり (誤りの後は None)
+    pos: Option<usize>,
+}
+
+impl<'s, 'i> Iterator for Tokens<'s, 'i> {
+    type Item = Result<(usize, Match), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = self.pos?;
+        if pos == self.input.len() {
+            self.pos = None;
+            return None;
+        }
+        match self.scanner.longest_at(self.input, pos) {
+            Some((rule, m)) => {
+                self.pos = Some(m.end);
+                Some(Ok((rule, m)))
+            }
+            None => {
+                self.pos = None;
+                Some(Err(Error::NoToken(pos)))
+            }
+        }
+    }
+}
+
+#[test]
+fn scan_tokens() {
+    const IF: usize = 0;
+    const IDENT: usize = 1;
+    const NUMBER: usize = 2;
+    const OP: usize = 3;
+    const SPACE: usize = 4;
+    let rules = ["if", "[a-z_][a-z0-9_]*", "[0-9]+(\\.[0-9]+)?", "[=<>]=?|\\+", "[ ]+"];
+    let scanner = Scanner::new(rules).unwrap();
+    let input = "if iffy <= 3.25 + x";
+    let tokens: Vec<(usize, &str)> = scanner
+        .tokens(input)
+        .map(|token| token.unwrap())
+        .filter(|&(rule, _)| rule != SPACE)
+        .map(|(rule, m)| (rule, &input[m.start..m.end]))
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            (IF, "if"),
+            (IDENT, "iffy"),
+            (OP, "<="),
+            (NUMBER, "3.25"),
+            (OP, "+"),
+            (IDENT, "x"),
+        ]
+    );
+    // `3.` は NUMBER の途中までしか一致しないので 3 で切れる
+    let mut tokens = scanner.tokens("3.x");
+    assert_eq!(tokens.next().unwrap().unwrap(), (NUMBER, Match { start: 0, end: 1 }));
+    assert!(tokens.next().unwrap().is_err());
+
+    let mut tokens = scanner.tokens("a ? b");
+    assert_eq!(tokens.next().unwrap().unwrap().0, IDENT);
+    assert_eq!(tokens.next().unwrap().unwrap().0, SPACE);
+    match tokens.next() {
+        Some(Err(Error::NoToken(2))) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(tokens.next().is_none());
+    assert!(scanner.tokens("").next().is_none());
+    assert!(Scanner::new(["a", "^b"]).is_err());
+}
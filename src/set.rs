@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+
+use dfa::{Anchors, Dfa};
+use error::Error;
+use nfa::{split_anchors, split_flags, strip_verbose, Flags, Nfa};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// 複数のパターンを一つの DFA にまとめ、どのパターンが一致したかを一度の走査で調べる
+#[derive(Debug)]
+pub struct RegexSet {
+    patterns: Vec<String>,
+    // 受理状態に一致したパターンの番号を持つ、入力の途中からの一致を探す DFA
+    dfa: Dfa,
+    anchors: Vec<Anchors>,
+    metadata: Vec<PatternMetadata>,
+}
+
+// パターンに付けておき、一致したときにパターンと一緒に受け取る情報
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PatternMetadata {
+    pub rule_id: Option<String>,
+    pub severity: Option<String>,
+    pub description: Option<String>,
+    // 上のどれにも当てはまらない情報
+    pub extra: BTreeMap<String, String>,
+}
+
+impl PatternMetadata {
+    pub fn new<S: Into<String>>(rule_id: S) -> PatternMetadata {
+        PatternMetadata {
+            rule_id: Some(rule_id.into()),
+            ..PatternMetadata::default()
+        }
+    }
+
+    pub fn severity<S: Into<String>>(mut self, severity: S) -> PatternMetadata {
+        self.severity = Some(severity.into());
+        self
+    }
+
+    pub fn description<S: Into<String>>(mut self, description: S) -> PatternMetadata {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn extra<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> PatternMetadata {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+}
+
+// RegexSet::matches の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetMatches {
+    matched: Vec<bool>,
+}
+
+impl SetMatches {
+    pub fn matched(&self, pattern: usize) -> bool {
+        self.matched.get(pattern).cloned().unwrap_or(false)
+    }
+
+    pub fn matched_any(&self) -> bool {
+        self.matched.iter().any(|&m| m)
+    }
+
+    // 一致したパターンの番号
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.matched.iter().enumerate().filter(|&(_, &m)| m).map(|(i, _)| i)
+    }
+
+    // 集合に含まれるパターンの数
+    pub fn len(&self) -> usize {
+        self.matched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matched.is_empty()
+    }
+}
+
+impl RegexSet {
+    pub fn new<I, S>(patterns: I) -> Result<RegexSet, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns: Vec<String> = patterns.into_iter().map(|p| p.as_ref().to_owned()).collect();
+        let mut nfas = Vec::with_capacity(patterns.len());
+        let mut anchors = Vec::with_capacity(patterns.len());
+        for pattern in patterns.iter() {
+            let nfa = Nfa::re2nfa(pattern)?;
+            let (start, _, end) = split_anchors(&strip_verbose(pattern));
+            let flags = Flags::default().merge(split_flags(pattern.trim()).0);
+            // 行頭の `^` は開始状態を行の先頭ごとに入れ直す必要があり、一つの DFA では表せない
+            if start && flags.multi_line {
+                return Err(Error::Unsupported {
+                    feature: "multi-line start anchor in a set",
+                    span: 0..pattern.len(),
+                });
+            }
+            nfas.push(if start { nfa } else { nfa.unanchored() });
+            anchors.push(Anchors {
+                start,
+                end,
+                multi_line: flags.multi_line,
+            });
+        }
+        let mut dfa = Dfa::nfa2dfa(&Nfa::union_tagged(&nfas))?;
+        dfa.minimize();
+        let metadata = vec![PatternMetadata::default(); patterns.len()];
+        Ok(RegexSet {
+            patterns,
+            dfa,
+            anchors,
+            metadata,
+        })
+    }
+
+    // パターンとその情報の組から作る
+    pub fn with_metadata<I, S>(rules: I) -> Result<RegexSet, Error>
+    where
+        I: IntoIterator<Item = (S, PatternMetadata)>,
+        S: AsRef<str>,
+    {
+        let (patterns, metadata): (Vec<S>, Vec<PatternMetadata>) = rules.into_iter().unzip();
+        let mut set = RegexSet::new(patterns)?;
+        set.metadata = metadata;
+        Ok(set)
+    }
+
+    pub fn metadata(&self, pattern: usize) -> Option<&PatternMetadata> {
+        self.metadata.get(pattern)
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    // 受理状態に一致したパターンの番号を持つ DFA
+    pub fn dfa(&self) -> &Dfa {
+        &self.dfa
+    }
+
+    pub fn anchors(&self) -> &[Anchors] {
+        &self.anchors
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.matches(haystack).matched_any()
+    }
+
+    // 入力のどこかに一致する部分を持つパターンを全て求める
+    // 全てのパターンが一致した時点で読むのをやめる
+    pub fn matches(&self, haystack: &str) -> SetMatches {
+        let haystack = haystack.as_bytes();
+        let mut matched = vec![false; self.patterns.len()];
+        let mut remaining = self.patterns.len();
+        let mut state = match self.dfa.states.get(self.dfa.start()) {
+            Some(state) => state,
+            None => return SetMatches { matched },
+        };
+        let mut end = 0;
+        loop {
+            for &pattern in state.patterns.iter() {
+                if !matched[pattern] && self.anchors[pattern].end_ok(haystack, end) {
+                    matched[pattern] = true;
+                    remaining -= 1;
+                }
+            }
+            if remaining == 0 || end == haystack.len() {
+                break;
+            }
+            match self.dfa.transition(state.id, haystack[end]) {
+                Some(next) => state = &self.dfa.states[next],
+                None => break,
+            }
+            end += 1;
+        }
+        SetMatches { matched }
+    }
+
+    // 一致したパターンの番号をその情報と一緒に返す
+    pub fn matches_with_metadata(&self, haystack: &str) -> Vec<(usize, &PatternMetadata)> {
+        self.matches(haystack)
+            .iter()
+            .map(|pattern| (pattern, &self.metadata[pattern]))
+            .collect()
+    }
+}
+
+#[test]
+// 閉じていない `(` をわざと渡す
+#[allow(clippy::invalid_regex)]
+fn regex_set_matches() {
+    let set = RegexSet::new(["[0-9]+", "^GET ", "\\.png$", "(?i)error", "x{3}"]).unwrap();
+    assert_eq!(set.len(), 5);
+    let found = |haystack: &str| set.matches(haystack).iter().collect::<Vec<_>>();
+    assert_eq!(found("GET /img/42.png"), vec![0, 1, 2]);
+    assert_eq!(found("POST /GET 42.png.bak"), vec![0]);
+    assert_eq!(found("ERROR: xxxx"), vec![3, 4]);
+    assert_eq!(found("nothing"), Vec::<usize>::new());
+    assert!(!set.is_match("nothing"));
+    let matches = set.matches("GET 1");
+    assert!(matches.matched(1) && !matches.matched(2) && !matches.matched(9));
+    assert_eq!(matches.len(), 5);
+
+    // 同じ言語のパターンも区別する
+    let set = RegexSet::new(["ab|cd", "cd|ab", "a"]).unwrap();
+    assert_eq!(set.matches("xcd").iter().collect::<Vec<_>>(), vec![0, 1]);
+    assert!(RegexSet::new(Vec::<String>::new()).unwrap().matches("a").is_empty());
+    match RegexSet::new(["(?m)^a"]) {
+        Err(Error::Unsupported { feature, .. }) => {
+            assert_eq!(feature, "multi-line start anchor in a set")
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(RegexSet::new(["a", "("]).is_err());
+}
+
+#[test]
+fn regex_set_metadata() {
+    let set = RegexSet::with_metadata(vec![
+        (
+            "(?i)password=",
+            PatternMetadata::new("secret-001")
+                .severity("high")
+                .description("plain text password"),
+        ),
+        ("AKIA[0-9A-Z]{16}", PatternMetadata::new("secret-002").extra("provider", "aws")),
+        ("TODO", PatternMetadata::default()),
+    ])
+    .unwrap();
+    assert_eq!(set.len(), 3);
+    let found = set.matches_with_metadata("PASSWORD=hunter2 TODO");
+    let ids: Vec<_> = found.iter().map(|&(i, m)| (i, m.rule_id.as_deref())).collect();
+    assert_eq!(ids, vec![(0, Some("secret-001")), (2, None)]);
+    assert_eq!(found[0].1.severity.as_deref(), Some("high"));
+    let key = set.metadata(1).unwrap();
+    assert_eq!(key.extra.get("provider").map(String::as_str), Some("aws"));
+    assert!(set.metadata(3).is_none());
+    assert!(set.matches_with_metadata("nothing").is_empty());
+
+    // new で作った集合の情報は空
+    let set = RegexSet::new(["a"]).unwrap();
+    assert_eq!(set.metadata(0), Some(&PatternMetadata::default()));
+    assert!(RegexSet::with_metadata(vec![("(", PatternMetadata::default())]).is_err());
+}
@@ -0,0 +1,126 @@
+use ast::Ast;
+use dfa::ByteSet;
+use nfa::{split_anchors, split_flags, strip_verbose, syntax_tree, Flags};
+use simd;
+
+// ビット並列 (Shift-Or) による固定長パターンの探索
+#[derive(Debug)]
+pub struct ShiftOr {
+    masks: [u64; 256],
+    len: usize,
+    // 先頭の位置に一致するバイトが 3 個以下ならその一覧
+    // find は途中まで一致している位置がない間、次にこのどれかが現れるところまで読み飛ばす
+    first: Option<Vec<u8>>,
+}
+
+impl ShiftOr {
+    // リテラル、`.`、文字クラスと固定回数の繰り返しの連接からなる 64 文字以下のパターンのみ対象とする
+    pub fn new(regex: &str) -> Option<ShiftOr> {
+        let flags = split_flags(regex.trim()).0;
+        match split_anchors(&strip_verbose(regex)) {
+            (false, _, false) => {}
+            _ => return None,
+        }
+        let root = syntax_tree(regex).ok()?;
+        ShiftOr::from_ast(&root, flags)
+    }
+
+    // 構文木から作る。`^` と `$` は構文木に含まれないので、アンカーのないパターンにだけ使う
+    pub fn from_ast(root: &Ast, flags: Flags) -> Option<ShiftOr> {
+        if flags.case_insensitive {
+            return None;
+        }
+        let mut dot = [true; 256];
+        dot[b'\n' as usize] = flags.dot_all;
+        let mut positions = Vec::new();
+        if !ShiftOr::flatten(root, &dot, &mut positions) || positions.is_empty() {
+            return None;
+        }
+
+        let mut masks = [!0u64; 256];
+        for (i, position) in positions.iter().enumerate() {
+            for (c, mask) in masks.iter_mut().enumerate() {
+                if position[c] {
+                    *mask &= !(1 << i);
+                }
+            }
+        }
+        let first: Vec<u8> = (0..=255u8).filter(|&c| positions[0][c as usize]).collect();
+        Some(ShiftOr {
+            masks,
+            len: positions.len(),
+            first: if first.len() <= 3 { Some(first) } else { None },
+        })
+    }
+
+    // 各位置で一致するバイトの集合を positions に並べる。64 を超えたら false を返す
+    fn flatten(ast: &Ast, dot: &ByteSet, positions: &mut Vec<ByteSet>) -> bool {
+        let mut set = [false; 256];
+        match *ast {
+            Ast::Group { ref ast, .. } => return ShiftOr::flatten(ast, dot, positions),
+            Ast::NonCapturing { flags, ref ast } if flags.is_empty() => {
+                return ShiftOr::flatten(ast, dot, positions);
+            }
+            Ast::Concat(ref asts) => {
+                return asts.iter().all(|ast| ShiftOr::flatten(ast, dot, positions));
+            }
+            Ast::Repeat { ref ast, min, max } if max == Some(min) => {
+                return (0..min).all(|_| ShiftOr::flatten(ast, dot, positions));
+            }
+            Ast::Literal(c) => set[c as usize] = true,
+            Ast::Class(ref class) => {
+                for c in class.bytes(false) {
+                    set[c as usize] = true;
+                }
+            }
+            Ast::Dot => set = *dot,
+            _ => return false,
+        }
+        positions.push(set);
+        positions.len() <= 64
+    }
+
+    // 最左の一致の (開始位置, 終了位置) を返す
+    pub fn find(&self, haystack: &[u8]) -> Option<(usize, usize)> {
+        let accept = 1 << (self.len - 1);
+        let mut state = !0u64;
+        let mut at = 0;
+        while at < haystack.len() {
+            if state == !0 {
+                if let Some(ref first) = self.first {
+                    at += simd::find_any(first, &haystack[at..])?;
+                }
+            }
+            state = (state << 1) | self.masks[haystack[at] as usize];
+            at += 1;
+            if state & accept == 0 {
+                return Some((at - self.len, at));
+            }
+        }
+        None
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find(haystack).is_some()
+    }
+}
+
+#[test]
+fn shift_or_find() {
+    let matcher = ShiftOr::new("a.c").unwrap();
+    assert_eq!(matcher.find(b"xxabxcabcz"), Some((6, 9)));
+    assert!(!matcher.is_match(b"acab"));
+    assert!(ShiftOr::new("a*").is_none());
+    let matcher = ShiftOr::new("[0-9]{3}-x").unwrap();
+    assert_eq!(matcher.find(b"12-x 345-x"), Some((5, 10)));
+    // 先頭のバイトまで読み飛ばす。一致が 32 バイトの区切りをまたぐ場合も含む
+    let matcher = ShiftOr::new("abc").unwrap();
+    for at in 0..70 {
+        let haystack = format!("{}abc{}", "x".repeat(at), "ab".repeat(3));
+        assert_eq!(matcher.find(haystack.as_bytes()), Some((at, at + 3)), "{}", haystack);
+        let haystack = format!("{}aabababc", "x".repeat(at));
+        assert_eq!(matcher.find(haystack.as_bytes()), Some((at + 5, at + 8)), "{}", haystack);
+    }
+    assert_eq!(matcher.find(b"abababab"), None);
+    assert!(matcher.first.is_some() && ShiftOr::new("[a-d]x").unwrap().first.is_none());
+}
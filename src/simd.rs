@@ -0,0 +1,62 @@
+// 入力の中で needles (3 バイトまで) のどれかが最初に現れる位置を探す
+// DenseDfa と ShiftOr が、一致の始まりになりえないバイトを読み飛ばすのに使う
+// simd feature を有効にすると、x86_64 で AVX2 が使えるときは 32 バイトずつまとめて比べる
+pub fn find_any(needles: &[u8], haystack: &[u8]) -> Option<usize> {
+    debug_assert!(needles.len() <= 3);
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if !needles.is_empty() && is_x86_feature_detected!("avx2") {
+            // AVX2 が使えることは実行時に確かめた
+            return unsafe { avx2::find_any(needles, haystack) };
+        }
+    }
+    haystack.iter().position(|b| needles.contains(b))
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    // needles は空でないこと。3 バイトより少なければ先頭のバイトを繰り返して 3 つの比較を埋める
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_any(needles: &[u8], haystack: &[u8]) -> Option<usize> {
+        let needle = |i: usize| *needles.get(i).unwrap_or(&needles[0]) as i8;
+        let (n0, n1, n2) = (
+            _mm256_set1_epi8(needle(0)),
+            _mm256_set1_epi8(needle(1)),
+            _mm256_set1_epi8(needle(2)),
+        );
+        let mut at = 0;
+        while at + 32 <= haystack.len() {
+            let chunk = _mm256_loadu_si256(haystack.as_ptr().add(at) as *const __m256i);
+            let eq = _mm256_or_si256(
+                _mm256_or_si256(_mm256_cmpeq_epi8(chunk, n0), _mm256_cmpeq_epi8(chunk, n1)),
+                _mm256_cmpeq_epi8(chunk, n2),
+            );
+            let mask = _mm256_movemask_epi8(eq) as u32;
+            if mask != 0 {
+                return Some(at + mask.trailing_zeros() as usize);
+            }
+            at += 32;
+        }
+        haystack[at..].iter().position(|b| needles.contains(b)).map(|i| at + i)
+    }
+}
+
+#[test]
+fn find_any_bytes() {
+    // 32 バイトの区切りの前後と、区切りに満たない末尾
+    for &len in &[0, 1, 31, 32, 33, 64, 100] {
+        for at in 0..len {
+            let mut haystack = vec![b'.'; len];
+            haystack[at] = b'z';
+            assert_eq!(find_any(b"z", &haystack), Some(at));
+            assert_eq!(find_any(b"xyz", &haystack), Some(at));
+            haystack[len - 1] = b'y';
+            assert_eq!(find_any(b"yz", &haystack), Some(at));
+        }
+        assert_eq!(find_any(b"abc", &vec![b'.'; len]), None);
+    }
+    assert_eq!(find_any(b"", b"abc"), None);
+    assert_eq!(find_any(b"\xff", b"ab\xffc"), Some(2));
+}
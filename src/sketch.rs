@@ -0,0 +1,116 @@
+use dfa::Dfa;
+
+// 256 バイトの集合を 1 ビットずつに詰めたもの
+type Bits = [u64; 4];
+
+fn contains(bits: &Bits, c: u8) -> bool {
+    bits[(c >> 6) as usize] >> (c & 63) & 1 == 1
+}
+
+// 先頭の depth バイトから「一致する文字列の先頭になり得るか」を答える近似的な集合
+// 位置ごとに現れ得るバイトの集合だけを持つので、あり得ない先頭を通すことはあっても、
+// 一致し得る先頭を落とすことはない
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixSketch {
+    positions: Vec<Bits>,
+    // 一致する文字列の先頭になる depth バイトの列の数 (DFA から正確に数えたもの)
+    live_prefixes: f64,
+}
+
+impl PrefixSketch {
+    pub fn depth(&self) -> usize {
+        self.positions.len()
+    }
+
+    // prefix の先頭 depth バイトまでを調べる。false なら prefix で始まる文字列はどれも一致しない
+    pub fn may_match(&self, prefix: &[u8]) -> bool {
+        prefix
+            .iter()
+            .zip(self.positions.iter())
+            .all(|(&c, bits)| contains(bits, c))
+    }
+
+    pub fn live_prefixes(&self) -> f64 {
+        self.live_prefixes
+    }
+
+    // may_match が true を返す depth バイトの列の数
+    pub fn admitted_prefixes(&self) -> f64 {
+        self.positions
+            .iter()
+            .map(|bits| bits.iter().map(|word| word.count_ones()).sum::<u32>() as f64)
+            .product()
+    }
+
+    // may_match が true を返す depth バイトの列のうち、実際にはどの一致の先頭にもならないものの割合
+    // 一様に選んだ列に対する偽陽性率の上限で、depth バイトより短い問い合わせには当てはまらない
+    pub fn false_positive_rate(&self) -> f64 {
+        let admitted = self.admitted_prefixes();
+        if admitted == 0.0 {
+            return 0.0;
+        }
+        1.0 - self.live_prefixes / admitted
+    }
+
+    // 表の大きさ (バイト数)
+    pub fn size(&self) -> usize {
+        self.positions.len() * 32
+    }
+}
+
+impl Dfa {
+    /// 一致する文字列の先頭 depth バイトとしてあり得るかを答える近似的な集合を作る。
+    pub fn prefix_sketch(&self, depth: usize) -> PrefixSketch {
+        let live = self.live_states();
+        let mut positions = Vec::with_capacity(depth);
+        // 各状態に先頭から何通りの読み方で着くか
+        let mut counts = vec![0.0; self.states.len()];
+        if live.get(self.start()) == Some(&true) {
+            counts[self.start()] = 1.0;
+        }
+        for _ in 0..depth {
+            let mut bits = [0u64; 4];
+            let mut next = vec![0.0; self.states.len()];
+            for (id, &count) in counts.iter().enumerate().filter(|&(_, &count)| count > 0.0) {
+                for c in 0..=255u8 {
+                    if let Some(to) = self.transition(id, c).filter(|&to| live[to]) {
+                        bits[(c >> 6) as usize] |= 1 << (c & 63);
+                        next[to] += count;
+                    }
+                }
+            }
+            positions.push(bits);
+            counts = next;
+        }
+        PrefixSketch {
+            positions,
+            live_prefixes: counts.iter().sum(),
+        }
+    }
+}
+
+#[test]
+fn prefix_sketch() {
+    use nfa::Nfa;
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("(GET|PUT) /api/[a-z]+").unwrap()).unwrap();
+    let sketch = dfa.prefix_sketch(4);
+    assert_eq!(sketch.depth(), 4);
+    assert_eq!(sketch.size(), 128);
+    assert!(sketch.may_match(b"GET /api/users"));
+    assert!(sketch.may_match(b"PUT "));
+    assert!(sketch.may_match(b"PU"));
+    assert!(!sketch.may_match(b"POST /api"));
+    assert!(!sketch.may_match(b"GET/"));
+    // GUT と PET は通ってしまうが、その割合は正確に分かる
+    assert!(sketch.may_match(b"GUT "));
+    assert_eq!(sketch.live_prefixes(), 2.0);
+    assert_eq!(sketch.admitted_prefixes(), 4.0);
+    assert_eq!(sketch.false_positive_rate(), 0.5);
+
+    let dfa = Dfa::nfa2dfa(&Nfa::re2nfa("a[0-9]*").unwrap()).unwrap();
+    let sketch = dfa.prefix_sketch(3);
+    assert_eq!(sketch.live_prefixes(), 100.0);
+    assert_eq!(sketch.false_positive_rate(), 0.0);
+    let empty = Dfa::nfa2dfa(&Nfa::re2nfa("[^\\x00-\\xff]").unwrap()).unwrap();
+    assert!(!empty.prefix_sketch(2).may_match(b"ab"));
+}
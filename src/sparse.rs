@@ -0,0 +1,147 @@
+use dfa::{Automaton, Dfa, DfaBuilder};
+use error::Error;
+use std::mem;
+
+// 同じ遷移先へ続くバイトの区間 lo..=hi
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Run {
+    pub lo: u8,
+    pub hi: u8,
+    pub next: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseState {
+    // lo の昇順で、区間は重ならない
+    pub runs: Vec<Run>,
+    pub accept: bool,
+}
+
+// 各状態の遷移を区間の列で持つ DFA
+// 遷移の少ない状態が多いとき (UTF-8 の多バイト文字を読む途中の状態など) に 256 列の表より小さい
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseDfa {
+    pub states: Vec<SparseState>,
+    start: usize,
+}
+
+impl SparseDfa {
+    pub fn from_dfa(dfa: &Dfa) -> SparseDfa {
+        let states = dfa
+            .states
+            .iter()
+            .map(|state| {
+                let mut runs: Vec<Run> = Vec::new();
+                for (c, next) in dfa.transitions(state.id) {
+                    let next = match next {
+                        Some(next) => next,
+                        None => continue,
+                    };
+                    match runs.last_mut() {
+                        Some(run) if run.next == next && run.hi as usize + 1 == c as usize => {
+                            run.hi = c
+                        }
+                        _ => runs.push(Run { lo: c, hi: c, next }),
+                    }
+                }
+                SparseState {
+                    runs,
+                    accept: state.accept,
+                }
+            })
+            .collect();
+        SparseDfa {
+            states,
+            start: dfa.start(),
+        }
+    }
+
+    pub fn to_dfa(&self) -> Result<Dfa, Error> {
+        let mut builder = DfaBuilder::new();
+        for state in self.states.iter() {
+            builder.add_state(state.accept);
+        }
+        for (from, state) in self.states.iter().enumerate() {
+            for run in state.runs.iter() {
+                for c in run.lo..=run.hi {
+                    builder.set_transition(from, c, run.next)?;
+                }
+            }
+        }
+        let mut dfa = builder.finish()?;
+        dfa.set_start(self.start);
+        dfa.validate()?;
+        Ok(dfa)
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn num_runs(&self) -> usize {
+        self.states.iter().map(|state| state.runs.len()).sum()
+    }
+
+    // 遷移の区間が占めるおおよそのバイト数 (密な表は 1 状態あたり 256 個の Option<usize>)
+    pub fn transition_size(&self) -> usize {
+        self.num_runs() * mem::size_of::<Run>()
+    }
+}
+
+impl Automaton for SparseDfa {
+    fn start(&self) -> usize {
+        self.start
+    }
+
+    fn next(&self, state: usize, c: u8) -> Option<usize> {
+        let runs = &self.states[state].runs;
+        // c 以下の lo を持つ最後の区間
+        let i = match runs.binary_search_by_key(&c, |run| run.lo) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        if c <= runs[i].hi {
+            Some(runs[i].next)
+        } else {
+            None
+        }
+    }
+
+    fn is_accept(&self, state: usize) -> bool {
+        self.states[state].accept
+    }
+
+    fn num_states(&self) -> usize {
+        self.states.len()
+    }
+}
+
+#[test]
+fn sparse_matches_dense() {
+    use nfa::Nfa;
+
+    let mut dfa = Dfa::nfa2dfa(&Nfa::re2nfa("[a-z0-9_]+@(ex|test)\\.(com|org)").unwrap()).unwrap();
+    dfa.minimize();
+    let sparse = SparseDfa::from_dfa(&dfa);
+    assert_eq!(sparse.len(), dfa.states.len());
+    assert!(sparse.transition_size() < dfa.states.len() * 256 * mem::size_of::<Option<usize>>());
+    for s in &["me@ex.com", "a_1@test.org", "me@ex.net", "@ex.com", "ME@ex.com", ""] {
+        assert_eq!(Automaton::accept_bytes(&sparse, s.as_bytes()), dfa.accept(s), "{}", s);
+    }
+    // 最初の状態は [0-9], _, [a-z] の 3 区間
+    let start = &sparse.states[sparse.start()];
+    assert_eq!(start.runs.len(), 3);
+    assert_eq!((start.runs[0].lo, start.runs[0].hi), (b'0', b'9'));
+
+    let dense = sparse.to_dfa().unwrap();
+    assert_eq!(SparseDfa::from_dfa(&dense), sparse);
+}
@@ -0,0 +1,61 @@
+use rng::Rng;
+
+// 性質テスト向けに、この crate がコンパイルできるパターンと入力を乱数で作る
+
+const ATOMS: &[&str] = &["a", "b", "c", "."];
+
+// 深さ depth 以下のランダムなパターン
+pub fn random_pattern(rng: &mut Rng, depth: usize) -> String {
+    if depth == 0 {
+        return (*rng.choose(ATOMS)).to_owned();
+    }
+    match rng.below(6) {
+        0 => format!(
+            "{}|{}",
+            random_pattern(rng, depth - 1),
+            random_pattern(rng, depth - 1)
+        ),
+        1 | 2 => format!(
+            "{}{}",
+            random_operand(rng, depth - 1),
+            random_operand(rng, depth - 1)
+        ),
+        3 => format!("{}{}", random_operand(rng, depth - 1), rng.choose(&["*", "+", "?"])),
+        _ => (*rng.choose(ATOMS)).to_owned(),
+    }
+}
+
+// 連接や繰り返しの被演算子になれるよう、選択は括弧で囲む
+fn random_operand(rng: &mut Rng, depth: usize) -> String {
+    let pattern = random_pattern(rng, depth);
+    if pattern.len() == 1 {
+        pattern
+    } else {
+        format!("({})", pattern)
+    }
+}
+
+// alphabet の文字からなる長さ max_len 以下のランダムな文字列
+pub fn random_input(rng: &mut Rng, alphabet: &[u8], max_len: usize) -> Vec<u8> {
+    let len = rng.below(max_len + 1);
+    (0..len).map(|_| *rng.choose(alphabet)).collect()
+}
+
+#[test]
+fn random_patterns_compile() {
+    use dfa::Dfa;
+    use nfa::Nfa;
+
+    let mut rng = Rng::new(7);
+    for _ in 0..50 {
+        let pattern = random_pattern(&mut rng, 3);
+        let dfa = Dfa::nfa2dfa(&Nfa::re2nfa(&pattern).unwrap()).unwrap();
+        let input = random_input(&mut rng, b"abc", 6);
+        let mut minimized = Dfa::nfa2dfa(&Nfa::re2nfa(&pattern).unwrap()).unwrap();
+        minimized.minimize();
+        assert_eq!(dfa.accept_bytes(&input), minimized.accept_bytes(&input), "{}", pattern);
+    }
+    let mut a = Rng::new(3);
+    let mut b = Rng::new(3);
+    assert_eq!(random_pattern(&mut a, 4), random_pattern(&mut b, 4));
+}
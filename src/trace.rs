@@ -0,0 +1,34 @@
+// `tracing` feature が有効なときだけ span と event を出す
+// 無効なときは値の式も評価しない
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_span {
+    ($level:ident, $name:expr $(, $field:ident = $value:expr)*) => {
+        ::tracing::span!(::tracing::Level::$level, $name $(, $field = $value)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span {
+    ($level:ident, $name:expr $(, $field:ident = $value:expr)*) => {
+        ::trace::Disabled
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($message:expr $(, $field:ident = $value:expr)*) => {
+        ::tracing::debug!($($field = $value,)* $message)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($message:expr $(, $field:ident = $value:expr)*) => {
+        ()
+    };
+}
+
+// trace_span! が無効なときに返す値
+#[cfg(not(feature = "tracing"))]
+pub struct Disabled;
@@ -0,0 +1,11 @@
+// debug ビルドでは各段階の後にオートマトンの不変条件を確かめる
+// release ビルドでは validate を呼ばない
+macro_rules! debug_validate {
+    ($automaton:expr, $stage:expr) => {
+        if cfg!(debug_assertions) {
+            if let Err(e) = $automaton.validate() {
+                panic!("{} produced an invalid automaton: {}", $stage, e);
+            }
+        }
+    };
+}
@@ -0,0 +1,188 @@
+use dfa::Dfa;
+use nfa::Nfa;
+use std::collections::VecDeque;
+
+// 開始状態を持つ有向グラフ (ラベル None は ε 遷移)
+pub trait Graph {
+    fn num_states(&self) -> usize;
+    // 開始状態の番号
+    fn start(&self) -> usize {
+        0
+    }
+    fn is_accepting(&self, id: usize) -> bool;
+    fn edges(&self, id: usize) -> Vec<(Option<u8>, usize)>;
+}
+
+impl Graph for Dfa {
+    fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    fn start(&self) -> usize {
+        Dfa::start(self)
+    }
+
+    fn is_accepting(&self, id: usize) -> bool {
+        self.states[id].accept
+    }
+
+    fn edges(&self, id: usize) -> Vec<(Option<u8>, usize)> {
+        self.transitions(id)
+            .filter_map(|(c, next)| next.map(|next| (Some(c), next)))
+            .collect()
+    }
+}
+
+impl Graph for Nfa {
+    fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    fn is_accepting(&self, id: usize) -> bool {
+        self.states[id].accept
+    }
+
+    fn edges(&self, id: usize) -> Vec<(Option<u8>, usize)> {
+        let mut edges = Vec::new();
+        for (label, t) in self.states[id].transition.iter().enumerate() {
+            if let Some(ref t) = *t {
+                let label = if label == 256 {
+                    None
+                } else {
+                    Some(label as u8)
+                };
+                let mut targets: Vec<usize> = t.iter().cloned().collect();
+                targets.sort();
+                edges.extend(targets.into_iter().map(|next| (label, next)));
+            }
+        }
+        edges
+    }
+}
+
+pub trait Visitor {
+    // 状態に初めて到達したとき
+    fn enter_state(&mut self, _id: usize) {}
+    // 到達した状態から出る全ての遷移について (遷移先が訪問済みでも呼ばれる)
+    fn on_transition(&mut self, _from: usize, _label: Option<u8>, _to: usize) {}
+}
+
+// 開始状態から深さ優先で到達可能な状態をたどる
+pub fn walk_dfs<G: Graph, V: Visitor>(graph: &G, visitor: &mut V) {
+    if graph.num_states() == 0 {
+        return;
+    }
+    let mut visited = vec![false; graph.num_states()];
+    let mut stack = vec![graph.start()];
+    while let Some(id) = stack.pop() {
+        if visited[id] {
+            continue;
+        }
+        visited[id] = true;
+        visitor.enter_state(id);
+        let edges = graph.edges(id);
+        for &(label, next) in edges.iter() {
+            visitor.on_transition(id, label, next);
+        }
+        for &(_, next) in edges.iter().rev() {
+            if !visited[next] {
+                stack.push(next);
+            }
+        }
+    }
+}
+
+// 開始状態から幅優先で到達可能な状態をたどる
+pub fn walk_bfs<G: Graph, V: Visitor>(graph: &G, visitor: &mut V) {
+    if graph.num_states() == 0 {
+        return;
+    }
+    let mut visited = vec![false; graph.num_states()];
+    let mut queue = VecDeque::new();
+    visited[graph.start()] = true;
+    queue.push_back(graph.start());
+    while let Some(id) = queue.pop_front() {
+        visitor.enter_state(id);
+        for (label, next) in graph.edges(id) {
+            visitor.on_transition(id, label, next);
+            if !visited[next] {
+                visited[next] = true;
+                queue.push_back(next);
+            }
+        }
+    }
+}
+
+// ノードの重みは受理状態かどうか、辺の重みは遷移のラベル (ε は None)
+// ノード番号は状態番号と一致する
+#[cfg(feature = "petgraph")]
+pub fn to_petgraph<G: Graph>(graph: &G) -> ::petgraph::Graph<bool, Option<u8>> {
+    let mut export = ::petgraph::Graph::new();
+    let nodes: Vec<_> = (0..graph.num_states())
+        .map(|id| export.add_node(graph.is_accepting(id)))
+        .collect();
+    for id in 0..graph.num_states() {
+        for (label, next) in graph.edges(id) {
+            export.add_edge(nodes[id], nodes[next], label);
+        }
+    }
+    export
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Recorder {
+    order: Vec<usize>,
+    transitions: usize,
+}
+
+#[cfg(test)]
+impl Visitor for Recorder {
+    fn enter_state(&mut self, id: usize) {
+        self.order.push(id);
+    }
+
+    fn on_transition(&mut self, _from: usize, _label: Option<u8>, _to: usize) {
+        self.transitions += 1;
+    }
+}
+
+#[test]
+fn walk_orders() {
+    let mut builder = ::dfa::DfaBuilder::new();
+    for _ in 0..4 {
+        builder.add_state(false);
+    }
+    builder.set_transition(0, b'a', 1).unwrap();
+    builder.set_transition(0, b'b', 2).unwrap();
+    builder.set_transition(1, b'a', 3).unwrap();
+    builder.set_transition(3, b'a', 0).unwrap();
+    let dfa = builder.finish().unwrap();
+
+    let mut dfs = Recorder::default();
+    walk_dfs(&dfa, &mut dfs);
+    assert_eq!(dfs.order, vec![0, 1, 3, 2]);
+    assert_eq!(dfs.transitions, 4);
+
+    let mut bfs = Recorder::default();
+    walk_bfs(&dfa, &mut bfs);
+    assert_eq!(bfs.order, vec![0, 1, 2, 3]);
+
+    let nfa = Nfa::re2nfa("a|b").unwrap();
+    let mut nfa_bfs = Recorder::default();
+    walk_bfs(&nfa, &mut nfa_bfs);
+    assert_eq!(nfa_bfs.order.len(), nfa.states.len());
+}
+
+#[cfg(feature = "petgraph")]
+#[test]
+fn petgraph_export() {
+    let nfa = Nfa::re2nfa("ab").unwrap();
+    let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    dfa.minimize();
+    let graph = dfa.to_petgraph();
+    assert_eq!(graph.node_count(), dfa.states.len());
+    assert_eq!(graph.edge_count(), 2);
+    assert!(!::petgraph::algo::is_cyclic_directed(&graph));
+    assert_eq!(nfa.to_petgraph().node_count(), nfa.states.len());
+}
@@ -0,0 +1,54 @@
+extern crate regex;
+
+use regex::dfa::Dfa;
+use regex::monoid::Monoid;
+use regex::nfa::Nfa;
+use std::fs;
+use std::path::Path;
+
+fn summary(pattern: &str) -> String {
+    let nfa = Nfa::re2nfa(pattern).unwrap();
+    let mut dfa = Dfa::nfa2dfa(&nfa).unwrap();
+    dfa.minimize();
+    let monoid = Monoid::construct(&dfa);
+    format!(
+        "pattern: {}\nstates: {}\nmonoid: {}\n",
+        pattern,
+        dfa.states.len(),
+        monoid.size()
+    )
+}
+
+// tests/snapshots/<name>.snap と一致するか確かめる
+fn assert_snapshot(name: &str, pattern: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{}.snap", name));
+    let expected = fs::read_to_string(&path).unwrap();
+    assert_eq!(summary(pattern), expected, "snapshot {} differs", name);
+}
+
+#[test]
+fn even_number_of_a() {
+    assert_snapshot("even_a", "b*(ab*ab*)*");
+}
+
+#[test]
+fn binary_multiples_of_three() {
+    assert_snapshot("div3", "(0|1(01*0)*1)*");
+}
+
+#[test]
+fn ab_star() {
+    assert_snapshot("ab_star", "(ab)*");
+}
+
+#[test]
+fn a_star_b_star() {
+    assert_snapshot("a_star_b_star", "a*b*");
+}
+
+#[test]
+fn ends_with_ab() {
+    assert_snapshot("ends_with_ab", "(a|b)*ab");
+}